@@ -0,0 +1,23 @@
+use std::env;
+use std::path::PathBuf;
+
+fn main() {
+    println!("cargo:rerun-if-changed=src/libs/capi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+
+    // Generate the C header of the C API. The header is only rewritten when
+    // its contents change.
+    let crate_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml"))
+        .expect("Failed to read cbindgen.toml");
+    match cbindgen::Builder::new()
+        .with_config(config)
+        .with_src(crate_dir.join("src/libs/capi.rs"))
+        .generate()
+    {
+        Ok(bindings) => {
+            bindings.write_to_file(crate_dir.join("include/nfp_tools.h"));
+        }
+        Err(e) => println!("cargo:warning=Failed to generate the C header: {}", e),
+    }
+}
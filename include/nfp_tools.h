@@ -0,0 +1,357 @@
+/* C API of rust_nfp_tools. Generated by cbindgen from src/libs/capi.rs, do not edit. */
+
+#ifndef NFP_TOOLS_H
+#define NFP_TOOLS_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define NFP_CPP_LEN32 0
+
+#define NFP_CPP_LEN64 1
+
+#define NFP_CPP_NOLEN 3
+
+#define NFP_MEM_EMEM 0
+
+#define NFP_MEM_IMEM 1
+
+#define NFP_MEM_CTM 2
+
+#define NFP_MEM_CLS 3
+
+#define NFP_MEM_PCIE_SRAM 4
+
+#define NFP_MEM_ARM 5
+
+#define NFP_MEM_ENGINE_ATOMIC32 0
+
+#define NFP_MEM_ENGINE_BULK32 1
+
+#define NFP_MEM_ENGINE_BULK64 2
+
+/**
+ * Result of a C API call. Each error code matches a variant of the Rust
+ * `NfpError`. The values are part of the ABI and never change.
+ */
+typedef enum NfpStatus {
+  NFP_STATUS_OK = 0,
+  /**
+   * A device resource, such as an expansion BAR, is held by someone else.
+   */
+  NFP_STATUS_LOCK_CONTENTION = -1,
+  /**
+   * An access to the device backend failed.
+   */
+  NFP_STATUS_IO = -2,
+  /**
+   * An address or length does not fit where it is used.
+   */
+  NFP_STATUS_ADDRESS_OUT_OF_RANGE = -3,
+  /**
+   * An argument is not valid, including a NULL pointer.
+   */
+  NFP_STATUS_INVALID_ARGUMENT = -4,
+  /**
+   * No NFP matches the requested device.
+   */
+  NFP_STATUS_DEVICE_NOT_FOUND = -5,
+  /**
+   * A RISC-V debug module abstract command failed.
+   */
+  NFP_STATUS_DM_CMD_ERR = -6,
+  /**
+   * The device did not reach the expected state in time.
+   */
+  NFP_STATUS_TIMEOUT = -7,
+  /**
+   * Data read back from the device does not match what was expected.
+   */
+  NFP_STATUS_VERIFY_FAILED = -8,
+  /**
+   * The operation needs firmware, but none is loaded on the NFP.
+   */
+  NFP_STATUS_FIRMWARE_NOT_LOADED = -9,
+  /**
+   * The library hit an internal error. The device should be closed.
+   */
+  NFP_STATUS_PANIC = -100,
+} NfpStatus;
+
+/**
+ * Handle of an opened NFP. Handles may be shared between threads.
+ */
+typedef struct NfpDevice NfpDevice;
+
+/**
+ * Expansion BAR taken from an `NfpDevice` for exclusive use. A BAR must
+ * only be used by one thread at a time.
+ */
+typedef struct NfpExpBar NfpExpBar;
+
+/**
+ * RFPC core, by island ID and cluster, group and core number.
+ */
+typedef struct NfpRfpc {
+  uint8_t island;
+  uint8_t cluster;
+  uint8_t group;
+  uint8_t core;
+} NfpRfpc;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Return the message of the last error returned to the calling thread.
+ *
+ * The string stays valid until the next failing call on the thread.
+ */
+const char *nfp_last_error(void);
+
+/**
+ * Open the NFP at PCIe BDF `pci_bdf`, e.g. "0000:65:00.0", and initialize
+ * its PCIe BARs.
+ *
+ * # Safety
+ *
+ * `pci_bdf` must be a NUL-terminated string and `device` must point to
+ * writable storage for the handle, which is set to NULL on failure.
+ */
+enum NfpStatus nfp_device_open(const char *pci_bdf, struct NfpDevice **device);
+
+/**
+ * Open an in-process simulated NFP.
+ *
+ * # Safety
+ *
+ * See `nfp_device_open`.
+ */
+enum NfpStatus nfp_device_open_sim(struct NfpDevice **device);
+
+/**
+ * Open the NFP served by the `nfp-cpp-server` at `addr` ("HOST:PORT"),
+ * authenticating with the token in `NFP_REMOTE_TOKEN`.
+ *
+ * # Safety
+ *
+ * See `nfp_device_open`.
+ */
+enum NfpStatus nfp_device_open_remote(const char *addr, struct NfpDevice **device);
+
+/**
+ * Close a device handle. BARs taken from it stay valid until freed.
+ *
+ * # Safety
+ *
+ * `device` must be NULL or a handle returned by an `nfp_device_open`
+ * function that is not used afterwards.
+ */
+void nfp_device_close(struct NfpDevice *device);
+
+/**
+ * Take an expansion BAR of `device` for exclusive use. Waits for another
+ * thread to free a BAR if all of them are in use.
+ *
+ * # Safety
+ *
+ * `device` must be a handle returned by an `nfp_device_open` function and
+ * `bar` must point to writable storage for the BAR, which is set to NULL
+ * on failure.
+ */
+enum NfpStatus nfp_exp_bar_alloc(const struct NfpDevice *device, struct NfpExpBar **bar);
+
+/**
+ * Return an expansion BAR to its device.
+ *
+ * # Safety
+ *
+ * `bar` must be NULL or a BAR returned by `nfp_exp_bar_alloc` that is not
+ * used afterwards.
+ */
+void nfp_exp_bar_free(struct NfpExpBar *bar);
+
+/**
+ * Look up the ID of the island called `name` in the chip description, or
+ * parse an island ID.
+ *
+ * # Safety
+ *
+ * `name` must be a NUL-terminated string and `id` must point to writable
+ * storage.
+ */
+enum NfpStatus nfp_island_id(const char *name, uint8_t *id);
+
+/**
+ * Look up the ID of the CPP target called `name` in the chip description,
+ * or parse a target ID.
+ *
+ * # Safety
+ *
+ * See `nfp_island_id`.
+ */
+enum NfpStatus nfp_target_id(const char *name, uint8_t *id);
+
+/**
+ * Read `length_words` 32-bit words from the CPP bus into `words`.
+ *
+ * # Parameters
+ *
+ * * `cpp_len` - One of the `NFP_CPP_*` length values.
+ *
+ * # Safety
+ *
+ * `bar` must be a BAR returned by `nfp_exp_bar_alloc` and `words` must
+ * point to `length_words` writable words.
+ */
+enum NfpStatus nfp_cpp_read(struct NfpExpBar *bar,
+                            uint8_t island,
+                            uint8_t target,
+                            uint8_t action,
+                            uint8_t token,
+                            uint8_t cpp_len,
+                            uint64_t address,
+                            uint32_t *words,
+                            size_t length_words);
+
+/**
+ * Write `length_words` 32-bit words from `words` to the CPP bus.
+ *
+ * # Safety
+ *
+ * `bar` must be a BAR returned by `nfp_exp_bar_alloc` and `words` must
+ * point to `length_words` words.
+ */
+enum NfpStatus nfp_cpp_write(struct NfpExpBar *bar,
+                             uint8_t island,
+                             uint8_t target,
+                             uint8_t action,
+                             uint8_t token,
+                             uint8_t cpp_len,
+                             uint64_t address,
+                             const uint32_t *words,
+                             size_t length_words);
+
+/**
+ * Read `length_words` XPB registers starting at `address` of `island`, or
+ * of the XPB master of the island if `xpbm` is set.
+ *
+ * # Safety
+ *
+ * See `nfp_cpp_read`.
+ */
+enum NfpStatus nfp_xpb_read(struct NfpExpBar *bar,
+                            uint8_t island,
+                            uint32_t address,
+                            bool xpbm,
+                            uint32_t *words,
+                            size_t length_words);
+
+/**
+ * Write `length_words` XPB registers starting at `address` of `island`.
+ *
+ * # Safety
+ *
+ * See `nfp_cpp_write`.
+ */
+enum NfpStatus nfp_xpb_write(struct NfpExpBar *bar,
+                             uint8_t island,
+                             uint32_t address,
+                             bool xpbm,
+                             const uint32_t *words,
+                             size_t length_words);
+
+/**
+ * Read `length_words` 32-bit words at byte `address` of a memory of
+ * `island`.
+ *
+ * # Parameters
+ *
+ * * `mem_type` - One of the `NFP_MEM_*` memory types.
+ * * `engine` - One of the `NFP_MEM_ENGINE_*` engines, used for MU memories.
+ *
+ * # Safety
+ *
+ * See `nfp_cpp_read`.
+ */
+enum NfpStatus nfp_mem_read(struct NfpExpBar *bar,
+                            uint8_t island,
+                            uint8_t mem_type,
+                            uint8_t engine,
+                            uint64_t address,
+                            uint32_t *words,
+                            size_t length_words);
+
+/**
+ * Write `length_words` 32-bit words at byte `address` of a memory of
+ * `island`.
+ *
+ * # Safety
+ *
+ * See `nfp_cpp_write`.
+ */
+enum NfpStatus nfp_mem_write(struct NfpExpBar *bar,
+                             uint8_t island,
+                             uint8_t mem_type,
+                             uint8_t engine,
+                             uint64_t address,
+                             const uint32_t *words,
+                             size_t length_words);
+
+/**
+ * Halt an RFPC core through its debug module.
+ *
+ * # Safety
+ *
+ * `device` must be a handle returned by an `nfp_device_open` function and
+ * `rfpc` must point to an `NfpRfpc`.
+ */
+enum NfpStatus nfp_rfpc_halt(const struct NfpDevice *device, const struct NfpRfpc *rfpc);
+
+/**
+ * Resume a halted RFPC core.
+ *
+ * # Safety
+ *
+ * See `nfp_rfpc_halt`.
+ */
+enum NfpStatus nfp_rfpc_resume(const struct NfpDevice *device, const struct NfpRfpc *rfpc);
+
+/**
+ * Read a register of a halted RFPC core.
+ *
+ * # Parameters
+ *
+ * * `reg_addr` - Debug module register number: 0x1000 + n for GPR xn, or
+ *   the CSR number.
+ *
+ * # Safety
+ *
+ * See `nfp_rfpc_halt`. `value` must point to writable storage.
+ */
+enum NfpStatus nfp_rfpc_reg_read(const struct NfpDevice *device,
+                                 const struct NfpRfpc *rfpc,
+                                 uint16_t reg_addr,
+                                 uint64_t *value);
+
+/**
+ * Write a register of a halted RFPC core.
+ *
+ * # Safety
+ *
+ * See `nfp_rfpc_halt`.
+ */
+enum NfpStatus nfp_rfpc_reg_write(const struct NfpDevice *device,
+                                  const struct NfpRfpc *rfpc,
+                                  uint16_t reg_addr,
+                                  uint64_t value);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* NFP_TOOLS_H */
@@ -0,0 +1,110 @@
+use clap::{ArgAction, Parser, Subcommand};
+
+use rust_nfp_tools::cli::cpp::CppCommand;
+use rust_nfp_tools::cli::exit::{exit, EXIT_CODES_HELP};
+use rust_nfp_tools::cli::gdb::GdbArgs;
+use rust_nfp_tools::cli::global::GlobalArgs;
+use rust_nfp_tools::cli::list::ListArgs;
+use rust_nfp_tools::cli::mem::MemCommand;
+use rust_nfp_tools::cli::rfpc::RfpcCommand;
+use rust_nfp_tools::cli::rtsym::RtsymCommand;
+use rust_nfp_tools::cli::server::ServerArgs;
+use rust_nfp_tools::cli::virt_term::VirtTermArgs;
+use rust_nfp_tools::cli::xpb::XpbCommand;
+use rust_nfp_tools::libs::error::NfpError;
+
+// Device selection and example usage, shown in the help.
+const EXAMPLES: &str = "The NFP is given with -Z, --remote or --sim, or else by the \
+    NFP_BDF environment variable, or else by the config file. Example config \
+    file:\n\n  \
+    pci_bdf = \"0000:65:00.0\"\n  \
+    elf = [\"/lib/firmware/netronome/app.elf\"]\n\n\
+    Example usage - write and read back a word of rfpc0 CTM:\n\n  \
+    nfp -Z 0000:65:00.0 mem write -i rfpc0 -m ctm -a 0x0 -v 0x12345678\n  \
+    nfp -Z 0000:65:00.0 mem read -i rfpc0 -m ctm -a 0x0\n\n\
+    Example usage - enable tracing in the PA control register of rfpc0 \
+    cluster 0 group 0:\n\n  \
+    NFP_BDF=0000:65:00.0 nfp xpb reg rfpc0.cl0.grp0.PAControl --field trace_en=1\n\n\
+    Example usage - halt the first RFPC and read its program counter:\n\n  \
+    nfp rfpc halt -i rfpc0 -u 0 -r 0 -c 0\n  \
+    nfp rfpc read -i rfpc0 -u 0 -r 0 -c 0 --csr dpc";
+
+/// Struct representing the CLI arguments
+#[derive(Parser, Debug)]
+#[command(
+    name = "nfp",
+    about = "Access an NFP over the CPP bus, XPB and RFPC debug modules.",
+    long_about = None,
+    after_help = format!("{}\n\n{}", EXAMPLES, EXIT_CODES_HELP)
+)]
+struct Cli {
+    #[command(flatten)]
+    global: GlobalArgs,
+
+    #[command(subcommand)]
+    command: NfpCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum NfpCommand {
+    /// List the NFP devices attached to this host.
+    List(ListArgs),
+
+    /// Read and write data with CPP commands.
+    #[command(subcommand)]
+    Cpp(CppCommand),
+
+    /// Read and write XPB registers.
+    Xpb {
+        /// Access the registers with explicit commands through an explicit
+        /// BAR instead of through an expansion BAR.
+        #[arg(long = "explicit", global = true, action = ArgAction::SetTrue)]
+        explicit: bool,
+
+        #[command(subcommand)]
+        command: XpbCommand,
+    },
+
+    /// Read, write and test NFP memory.
+    #[command(subcommand)]
+    Mem(MemCommand),
+
+    /// Debug and trace RFPC cores.
+    #[command(subcommand)]
+    Rfpc(RfpcCommand),
+
+    /// List, read and write the run-time symbols of the NFP firmware.
+    #[command(subcommand)]
+    Rtsym(RtsymCommand),
+
+    /// Read from an RFPC virtual terminal.
+    VirtTerm(VirtTermArgs),
+
+    /// Start an RSP debug server to connect to an NFP RISC-V debugger.
+    Gdb(GdbArgs),
+
+    /// Serve CPP bus, XPB and explicit command access to the NFP over TCP.
+    Server(ServerArgs),
+}
+
+fn run(cli: Cli) -> Result<(), NfpError> {
+    let global = &cli.global;
+    match cli.command {
+        NfpCommand::List(args) => args.run(),
+        NfpCommand::Cpp(command) => command.run(global),
+        NfpCommand::Xpb { explicit, command } => command.run(global, explicit),
+        NfpCommand::Mem(command) => command.run(global),
+        NfpCommand::Rfpc(command) => command.run(global),
+        NfpCommand::Rtsym(command) => command.run(global),
+        NfpCommand::VirtTerm(args) => args.run(global),
+        NfpCommand::Gdb(args) => args.run(global),
+        NfpCommand::Server(args) => args.run(global),
+    }
+}
+
+fn main() {
+    GlobalArgs::load_descriptions().unwrap_or_else(|e| exit(Err(e)));
+    let cli = Cli::parse();
+
+    exit(run(cli));
+}
@@ -1,9 +1,8 @@
-use clap::{ArgAction, Parser};
-use clap_num::maybe_hex;
+use clap::Parser;
 
-use rust_nfp_tools::libs::common::{hex_parser, validate_nfp_bdf};
-use rust_nfp_tools::libs::cpp_bus::{CppBus, CppIsland, CppLength, CppTarget};
-use rust_nfp_tools::libs::expansion_bar::{init_device_bars, ExpansionBar};
+use rust_nfp_tools::cli::cpp::CppArgs;
+use rust_nfp_tools::cli::exit::exit;
+use rust_nfp_tools::cli::global::GlobalArgs;
 
 /// Struct representing the CLI arguments
 #[derive(Parser, Debug)]
@@ -13,73 +12,24 @@ use rust_nfp_tools::libs::expansion_bar::{init_device_bars, ExpansionBar};
     after_help = "Example usage - atomic write two 32-bit words to RFPC0 CTM:\n
                   nfp-cpp --pci-bdf=0000:65:00.0 --island=9 --target=7 \
                   --action=4 --token=0 --cpp-len=Len32 --address=0 --length=2 \
-                  -v 5 6 7 --pci-bdf=0000:65:00.0"
+                  -v 5 6 7 --pci-bdf=0000:65:00.0\n
+                  Example usage - explicit MU test-and-add of 1 to an EMEM \
+                  word, printing its previous value:\n
+                  nfp-cpp -Z 0000:65:00.0 --island=emu0 --target=mem \
+                  --action=7 --token=1 --address=0x100 -v 1 --explicit \
+                  --push-length=1"
 )]
 struct Cli {
-    #[arg(short = 'Z', long = "pci-bdf", required = true, value_parser = validate_nfp_bdf)]
-    pci_bdf: String,
+    #[command(flatten)]
+    global: GlobalArgs,
 
-    #[arg(short = 'i', long = "island", required = true)]
-    island: CppIsland,
-
-    #[arg(short = 't', long = "target", required = true)]
-    target: CppTarget,
-
-    #[arg(short = 'c', long = "action", required = true)]
-    action: u8,
-
-    #[arg(short = 'o', long = "token", required = true)]
-    token: u8,
-
-    #[arg(short = 'p', long = "cpp-len", default_value_t = CppLength::Len32)]
-    cpp_len: CppLength,
-
-    #[arg(short = 'a', long = "address", required = true, value_parser = maybe_hex::<u64>)]
-    address: u64,
-
-    #[arg(short = 'l', long = "length", default_value_t = 1, value_parser = maybe_hex::<u64>)]
-    length: u64,
-
-    #[arg(short = 'v', long = "value", action = ArgAction::Append, num_args = 1.., value_parser = hex_parser)]
-    values: Vec<u32>,
+    #[command(flatten)]
+    args: CppArgs,
 }
 
 fn main() {
+    GlobalArgs::load_descriptions().unwrap_or_else(|e| exit(Err(e)));
     let cli = Cli::parse();
 
-    // Initialize the PCIe BARs in the PCIe config. space.
-    init_device_bars(&cli.pci_bdf);
-
-    // Allocate a new expansion BAR for the PCIe device.
-    let mut exp_bar = ExpansionBar::new(&cli.pci_bdf, None);
-
-    // Instantiate Cpp bus with allocated expansion BAR.
-    let mut cpp_bus = CppBus::new(&mut exp_bar);
-
-    if cli.values.is_empty() {
-        // Read over CPP bus.
-        let read_words = cpp_bus.read(
-            cli.island,
-            cli.target,
-            cli.action,
-            cli.token,
-            cli.cpp_len,
-            cli.address,
-            cli.length,
-        );
-        for value in read_words {
-            println!("0x{:08x}", value);
-        }
-    } else {
-        // Write over CPP bus.
-        cpp_bus.write(
-            cli.island,
-            cli.target,
-            cli.action,
-            cli.token,
-            cli.cpp_len,
-            cli.address,
-            cli.values,
-        );
-    }
+    exit(cli.args.run(&cli.global));
 }
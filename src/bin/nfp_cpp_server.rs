@@ -0,0 +1,34 @@
+use clap::Parser;
+
+use rust_nfp_tools::cli::exit::exit;
+use rust_nfp_tools::cli::global::GlobalArgs;
+use rust_nfp_tools::cli::server::ServerArgs;
+
+/// Struct representing the CLI arguments
+#[derive(Parser, Debug)]
+#[command(
+    about = "Serve CPP bus, XPB and explicit command access to an NFP over TCP.",
+    long_about = None,
+    after_help = " Clients authenticate with a token shared with the server, read from \
+                  --token-file or the NFP_REMOTE_TOKEN environment variable. The \
+                  protocol is not encrypted.\n
+                  Example usage - serve an NFP to the lab network, then read an XPB \
+                  register from another machine:\n
+                  nfp-cpp-server -Z 0000:65:00.0 --listen 0.0.0.0:4242 \
+                  --token-file /etc/nfp_tools/token\n
+                  NFP_REMOTE_TOKEN=... nfp-xpb --remote lab1:4242 -i rfpc0 -a 0x0"
+)]
+struct Cli {
+    #[command(flatten)]
+    global: GlobalArgs,
+
+    #[command(flatten)]
+    args: ServerArgs,
+}
+
+fn main() {
+    GlobalArgs::load_descriptions().unwrap_or_else(|e| exit(Err(e)));
+    let cli = Cli::parse();
+
+    exit(cli.args.run(&cli.global));
+}
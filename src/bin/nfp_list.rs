@@ -0,0 +1,23 @@
+use clap::Parser;
+
+use rust_nfp_tools::cli::exit::exit;
+use rust_nfp_tools::cli::list::ListArgs;
+
+/// Struct representing the CLI arguments
+#[derive(Parser, Debug)]
+#[command(
+    about = "List the NFP devices attached to this host.",
+    long_about = None,
+    after_help = "The index or serial shown for a device can be passed to \
+                  the other tools in place of a PCIe BDF, e.g. `-Z 1`."
+)]
+struct Cli {
+    #[command(flatten)]
+    args: ListArgs,
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    exit(cli.args.run());
+}
@@ -1,10 +1,8 @@
-use clap::{ArgAction, Parser};
-use clap_num::maybe_hex;
+use clap::Parser;
 
-use rust_nfp_tools::libs::common::{hex_parser, validate_nfp_bdf};
-use rust_nfp_tools::libs::cpp_bus::CppIsland;
-use rust_nfp_tools::libs::expansion_bar::{init_device_bars, ExpansionBar};
-use rust_nfp_tools::libs::mem_access::{mem_read, mem_write, MemoryType, MuMemoryEngine};
+use rust_nfp_tools::cli::exit::exit;
+use rust_nfp_tools::cli::global::GlobalArgs;
+use rust_nfp_tools::cli::mem::MemArgs;
 
 /// Struct representing the CLI arguments
 #[derive(Parser, Debug)]
@@ -13,66 +11,31 @@ use rust_nfp_tools::libs::mem_access::{mem_read, mem_write, MemoryType, MuMemory
     long_about = None,
     after_help =  " Example usage - atomic write one 32-bit word to rfpc0 CTM:\n
                     nfp-mem -Z 0000:65:00.0 --mem-type=ctm --isl=rfpc0 \
-                    -a 0x00000000 -v 0x12345678"
+                    -a 0x00000000 -v 0x12345678\n
+                    Example usage - increment a counter in EMEM and print its previous value:\n
+                    nfp-mem -Z 0000:65:00.0 --mem-type=emem --island=emu0 \
+                    -a 0x00001000 --atomic incr --test\n
+                    Example usage - save 1 MiB of EMEM to a file and check it reads back the same:\n
+                    nfp-mem -Z 0000:65:00.0 --mem-type=emem --island=emu0 \
+                    -a 0x00000000 -l 0x40000 --dump-file emem.bin --verify\n
+                    Example usage - read the local memory of a halted RFPC core:\n
+                    nfp-mem -Z 0000:65:00.0 --mem-type=lmem --island=rfpc0 \
+                    -u 0 -r 0 -c 0 -a 0x00000000 -l 4\n
+                    Example usage - print changes to 8 words of EMEM counters every second:\n
+                    nfp-mem -Z 0000:65:00.0 --mem-type=emem --island=emu0 \
+                    -a 0x00001000 -l 8 --watch 1s"
 )]
 struct Cli {
-    #[arg(short = 'Z', long = "pci-bdf", required = true, value_parser = validate_nfp_bdf)]
-    pci_bdf: String,
+    #[command(flatten)]
+    global: GlobalArgs,
 
-    #[arg(short = 'i', long = "island", required = true)]
-    island: CppIsland,
-
-    #[arg(short = 'm', long = "mem-type", required = true)]
-    mem_type: MemoryType,
-
-    #[arg(short = 'e', long = "mem-engine", default_value_t = MuMemoryEngine::Bulk32)]
-    mem_engine: MuMemoryEngine,
-
-    #[arg(short = 'a', long = "address", required = true, value_parser = maybe_hex::<u64>)]
-    address: u64,
-
-    #[arg(short = 'l', long = "length", default_value_t = 1, value_parser = maybe_hex::<u64>)]
-    length: u64,
-
-    #[arg(short = 'v', long = "value", action = ArgAction::Append, num_args = 1.., value_parser = hex_parser)]
-    values: Vec<u32>,
+    #[command(flatten)]
+    args: MemArgs,
 }
 
 fn main() {
+    GlobalArgs::load_descriptions().unwrap_or_else(|e| exit(Err(e)));
     let cli = Cli::parse();
 
-    // Initialize the PCIe BARs in the PCIe config. space.
-    init_device_bars(&cli.pci_bdf);
-
-    // Allocate a new expansion BAR for the PCIe device.
-    let mut exp_bar = ExpansionBar::new(&cli.pci_bdf, None);
-
-    if cli.values.is_empty() {
-        let read_words = mem_read(
-            &mut exp_bar,
-            cli.island,
-            cli.mem_type,
-            cli.mem_engine,
-            cli.address,
-            cli.length,
-        );
-        for (index, value) in read_words.iter().enumerate() {
-            println!(
-                "address 0x{:08x}: 0x{:08x}",
-                cli.address + (index * 4) as u64,
-                value
-            );
-        }
-    } else {
-        let mut values_to_write: Vec<u32> = Vec::new();
-        values_to_write.extend(cli.values.iter().cycle().take(cli.length as usize));
-        mem_write(
-            &mut exp_bar,
-            cli.island,
-            cli.mem_type,
-            cli.mem_engine,
-            cli.address,
-            values_to_write,
-        );
-    }
+    exit(cli.args.run(&cli.global));
 }
@@ -0,0 +1,32 @@
+use clap::Parser;
+
+use rust_nfp_tools::cli::exit::exit;
+use rust_nfp_tools::cli::global::GlobalArgs;
+use rust_nfp_tools::cli::memtest::MemTestArgs;
+
+/// Struct representing the CLI arguments
+#[derive(Parser, Debug)]
+#[command(
+    about = "Test NFP memory with walking-bit, address and random patterns.",
+    long_about = None,
+    after_help = "Example usage - run all tests over the first 1 MiB of EMEM:\n
+                  nfp-memtest -Z 0000:65:00.0 --mem-type=emem --island=emu0 \
+                  -a 0x00000000 -l 0x40000\n
+                  Example usage - rerun a failed random test with Bulk64 only:\n
+                  nfp-memtest -Z 0000:65:00.0 --mem-type=ctm --island=rfpc0 \
+                  -a 0x00000000 -l 0x10000 -t random -e bulk64 --seed 0x1234abcd"
+)]
+struct Cli {
+    #[command(flatten)]
+    global: GlobalArgs,
+
+    #[command(flatten)]
+    args: MemTestArgs,
+}
+
+fn main() {
+    GlobalArgs::load_descriptions().unwrap_or_else(|e| exit(Err(e)));
+    let cli = Cli::parse();
+
+    exit(cli.args.run(&cli.global));
+}
@@ -0,0 +1,30 @@
+use clap::Parser;
+
+use rust_nfp_tools::cli::exit::exit;
+use rust_nfp_tools::cli::global::GlobalArgs;
+use rust_nfp_tools::cli::rtsym::RtsymArgs;
+
+/// Struct representing the CLI arguments
+#[derive(Parser, Debug)]
+#[command(
+    about = "List, read and write the run-time symbols of the NFP firmware.",
+    long_about = None,
+    after_help = "Example usage - list the run-time symbols:\n
+                  nfp-rtsym -Z 0000:65:00.0\n
+                  Example usage - read the first 4 words of symbol `_pkt_counters`:\n
+                  nfp-rtsym -Z 0000:65:00.0 -n _pkt_counters -l 4"
+)]
+struct Cli {
+    #[command(flatten)]
+    global: GlobalArgs,
+
+    #[command(flatten)]
+    args: RtsymArgs,
+}
+
+fn main() {
+    GlobalArgs::load_descriptions().unwrap_or_else(|e| exit(Err(e)));
+    let cli = Cli::parse();
+
+    exit(cli.args.run(&cli.global));
+}
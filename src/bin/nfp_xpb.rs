@@ -1,11 +1,8 @@
-use clap::{ArgAction, Parser};
-use clap_num::maybe_hex;
+use clap::Parser;
 
-use rust_nfp_tools::libs::common::{hex_parser, validate_nfp_bdf};
-use rust_nfp_tools::libs::cpp_bus::CppIsland;
-use rust_nfp_tools::libs::expansion_bar::init_device_bars;
-use rust_nfp_tools::libs::explicit_bar::ExplicitBar;
-use rust_nfp_tools::libs::xpb_bus::{xpb_explicit_read32, xpb_explicit_write32};
+use rust_nfp_tools::cli::exit::exit;
+use rust_nfp_tools::cli::global::GlobalArgs;
+use rust_nfp_tools::cli::xpb::XpbArgs;
 
 /// Struct representing the CLI arguments
 #[derive(Parser, Debug)]
@@ -14,43 +11,35 @@ use rust_nfp_tools::libs::xpb_bus::{xpb_explicit_read32, xpb_explicit_write32};
     long_about = None,
     after_help = "Example usage - Read PCIE0 `pcie_pf0_k_pciconf0` register \
                  (address: `0x00B00040`): \n
-                 nfp-xpb -Z 0000:65:00.0 --i=4 -a 0x00B00040 -l 1"
+                 nfp-xpb -Z 0000:65:00.0 --i=4 -a 0x00B00040 -l 1\n
+                 Example usage - print changes to 4 registers from `0x00B00040` \
+                 every 250 ms: \n
+                 nfp-xpb -Z 0000:65:00.0 --i=4 -a 0x00B00040 -l 4 --watch 250ms\n
+                 Example usage - read 16 registers from `0x00B00040` with \
+                 explicit commands: \n
+                 nfp-xpb -Z 0000:65:00.0 --i=4 -a 0x00B00040 -l 16 --explicit\n
+                 Example usage - print the fields of the PA control register of \
+                 rfpc0 cluster 0 group 0, then enable tracing: \n
+                 nfp-xpb -Z 0000:65:00.0 --reg rfpc0.cl0.grp0.PAControl\n
+                 nfp-xpb -Z 0000:65:00.0 --reg rfpc0.cl0.grp0.PAControl --field trace_en=1\n
+                 Example usage - save the PA and group control registers of \
+                 rfpc0 and rfpc1, then compare them with the device later: \n
+                 nfp-xpb -Z 0000:65:00.0 snapshot -i rfpc0 -i rfpc1 -b pa \
+                 -b rfpc_group -o good.json\n
+                 nfp-xpb -Z 0000:65:00.0 diff good.json",
+    subcommand_negates_reqs = true
 )]
 struct Cli {
-    #[arg(short = 'Z', long = "pci-bdf", required = true, value_parser = validate_nfp_bdf)]
-    pci_bdf: String,
+    #[command(flatten)]
+    global: GlobalArgs,
 
-    #[arg(short = 'i', long = "island", required = true)]
-    island: CppIsland,
-
-    #[arg(short = 'a', long = "address", required = true, value_parser = maybe_hex::<u32>)]
-    address: u32,
-
-    #[arg(short = 'l', long = "length", default_value_t = 1, value_parser = maybe_hex::<u64>)]
-    length: u64,
-
-    #[arg(short = 'v', long = "value", action = ArgAction::Append, num_args = 1.., value_parser = hex_parser)]
-    values: Vec<u32>,
-
-    #[arg(short = 'x', long = "xpbm", action = ArgAction::SetTrue)]
-    xpbm: bool,
+    #[command(flatten)]
+    args: XpbArgs,
 }
 
 fn main() {
+    GlobalArgs::load_descriptions().unwrap_or_else(|e| exit(Err(e)));
     let cli = Cli::parse();
 
-    // Initialize the PCIe BARs in the PCIe config. space.
-    init_device_bars(&cli.pci_bdf);
-
-    // Allocate a new explicit BAR for the PCIe device.
-    let mut expl_bar = ExplicitBar::new(&cli.pci_bdf, 0);
-
-    if cli.values.is_empty() {
-        // Read over Xpb bus.
-        let read_word = xpb_explicit_read32(&mut expl_bar, &cli.island, cli.address, cli.xpbm);
-        println!("0x{:08x}", read_word);
-    } else {
-        // Write over Xpb bus.
-        xpb_explicit_write32(&mut expl_bar, &cli.island, cli.address, cli.values, cli.xpbm);
-    }
+    exit(cli.args.run(&cli.global));
 }
@@ -0,0 +1,78 @@
+#![allow(dead_code)]
+
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::libs::error::NfpError;
+
+/// Defaults of the command line options, read from a TOML file such as:
+///
+/// ```toml
+/// pci_bdf = "0000:65:00.0"
+/// elf = ["/lib/firmware/netronome/app.elf"]
+/// ```
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// PCIe BDF, index or serial number of the NFP to use when no device is
+    /// given on the command line or in `NFP_BDF`.
+    pub pci_bdf: Option<String>,
+
+    /// Address of an `nfp-cpp-server` to use instead of a local NFP.
+    pub remote: Option<String>,
+
+    /// Use the simulated NFP instead of a local NFP.
+    #[serde(default)]
+    pub sim: bool,
+
+    /// Firmware ELFs to resolve symbols from when no `--elf` is given.
+    #[serde(default)]
+    pub elf: Vec<PathBuf>,
+}
+
+impl Config {
+    /// Path of the config file read when `--config` is not given:
+    /// `$XDG_CONFIG_HOME/nfp/config.toml`, or `~/.config/nfp/config.toml`.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_home = match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        };
+        Some(config_home.join("nfp").join("config.toml"))
+    }
+
+    /// Load the config file at `path`, or the default config file if no
+    /// path is given.
+    ///
+    /// # Returns
+    ///
+    /// The defaults of the file, or no defaults if no path is given and the
+    /// default config file does not exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::InvalidArgument` if the file cannot be parsed or
+    /// holds an unknown option.
+    pub fn load(path: Option<&Path>) -> Result<Config, NfpError> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match Config::default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents).map_err(|e| {
+                NfpError::InvalidArgument(format!("Invalid config file {}: {}", path.display(), e))
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !required => Ok(Config::default()),
+            Err(e) => Err(NfpError::io(
+                format!("Failed to read config file {}", path.display()),
+                e,
+            )),
+        }
+    }
+}
@@ -0,0 +1,344 @@
+#![allow(dead_code)]
+
+use clap::{ArgAction, Args, Subcommand};
+use clap_num::{maybe_hex, maybe_hex_range};
+use std::path::PathBuf;
+
+use crate::cli::global::GlobalArgs;
+use crate::libs::common::hex_parser;
+use crate::libs::cpp_bus::{CppBus, CppIsland, CppLength, CppTarget};
+use crate::libs::elf_symbols::{AddressExpr, ElfSymbols};
+use crate::libs::error::NfpError;
+use crate::libs::explicit_bar::{ExplicitBar, ExplicitCmdFields, EXPL_MAX_DATA_WORDS};
+use crate::libs::output_format::{words_to_bytes, write_region, OutputArgs, OutputFormat};
+
+/// CPP command issued by the `cpp` commands.
+#[derive(Args, Debug)]
+pub struct CppCommandArgs {
+    #[arg(short = 'i', long = "island", required = true)]
+    pub island: CppIsland,
+
+    #[arg(short = 't', long = "target", required = true)]
+    pub target: CppTarget,
+
+    #[arg(short = 'c', long = "action", required = true)]
+    pub action: u8,
+
+    #[arg(short = 'o', long = "token", required = true)]
+    pub token: u8,
+
+    #[arg(short = 'p', long = "cpp-len", default_value_t = CppLength::Len32)]
+    pub cpp_len: CppLength,
+
+    /// Address, or symbol with an optional offset, e.g. `counters+0x10`.
+    #[arg(short = 'a', long = "address", required = true)]
+    pub address: AddressExpr,
+
+    /// Firmware ELF to resolve symbols from. May be given more than once.
+    #[arg(long = "elf", value_name = "PATH", action = ArgAction::Append)]
+    pub elf: Vec<PathBuf>,
+}
+
+// Parsers of the explicit command fields narrower than a byte.
+fn field2_parser(s: &str) -> Result<u8, String> {
+    maybe_hex_range(s, 0, 0x3)
+}
+
+fn field5_parser(s: &str) -> Result<u8, String> {
+    maybe_hex_range(s, 0, 0x1F)
+}
+
+fn field7_parser(s: &str) -> Result<u8, String> {
+    maybe_hex_range(s, 0, 0x7F)
+}
+
+/// Fields of a CPP command issued as an explicit command.
+#[derive(Args, Debug)]
+pub struct ExplicitArgs {
+    /// Issue the command as an explicit command through an explicit BAR.
+    /// `--cpp-len` is not used; the options below set the command fields.
+    #[arg(long = "explicit", action = ArgAction::SetTrue)]
+    pub explicit: bool,
+
+    /// Signal type of the explicit command. Defaults to 1 unless a master
+    /// or reference is given.
+    #[arg(long = "sig-type", requires = "explicit", value_parser = field2_parser)]
+    pub sig_type: Option<u8>,
+
+    /// Byte mask of the explicit command.
+    #[arg(long = "byte-mask", default_value = "0xff", requires = "explicit", value_parser = maybe_hex::<u8>)]
+    pub byte_mask: u8,
+
+    /// Raw CPP length field of the explicit command. Defaults to the number
+    /// of 32-bit words transferred minus one.
+    #[arg(long = "cmd-length", requires = "explicit", value_parser = maybe_hex::<u8>)]
+    pub cmd_length: Option<u8>,
+
+    /// Master island of the explicit command.
+    #[arg(long = "master-island", requires = "explicit", value_parser = field7_parser)]
+    pub master_island: Option<u8>,
+
+    /// Data master of the explicit command.
+    #[arg(long = "data-master", requires = "explicit", value_parser = field5_parser)]
+    pub data_master: Option<u8>,
+
+    /// Data reference of the explicit command.
+    #[arg(long = "data-ref", requires = "explicit", value_parser = maybe_hex::<u16>)]
+    pub data_ref: Option<u16>,
+
+    /// Signal master of the explicit command.
+    #[arg(long = "signal-master", requires = "explicit", value_parser = field5_parser)]
+    pub signal_master: Option<u8>,
+
+    /// Signal reference of the explicit command.
+    #[arg(long = "signal-ref", requires = "explicit", value_parser = field7_parser)]
+    pub signal_ref: Option<u8>,
+}
+
+impl ExplicitArgs {
+    /// Signal, byte mask and master fields of an explicit command.
+    fn fields(&self) -> ExplicitCmdFields {
+        let master_or_ref = self.master_island.is_some()
+            || self.data_master.is_some()
+            || self.data_ref.is_some()
+            || self.signal_master.is_some()
+            || self.signal_ref.is_some();
+
+        ExplicitCmdFields {
+            sig_type: self.sig_type.or((!master_or_ref).then_some(1)),
+            byte_mask: self.byte_mask,
+            master_island: self.master_island,
+            data_master: self.data_master,
+            data_ref: self.data_ref,
+            signal_master: self.signal_master,
+            signal_ref: self.signal_ref,
+        }
+    }
+}
+
+/// Commands of `nfp cpp`.
+#[derive(Subcommand, Debug)]
+pub enum CppCommand {
+    /// Read data with a CPP command.
+    Read {
+        #[command(flatten)]
+        command: CppCommandArgs,
+
+        #[arg(short = 'l', long = "length", default_value_t = 1, value_parser = maybe_hex::<u64>)]
+        length: u64,
+
+        #[command(flatten)]
+        explicit: ExplicitArgs,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+
+    /// Write data with a CPP command.
+    Write {
+        #[command(flatten)]
+        command: CppCommandArgs,
+
+        #[arg(short = 'v', long = "value", required = true, action = ArgAction::Append, num_args = 1.., value_parser = hex_parser)]
+        values: Vec<u32>,
+
+        #[command(flatten)]
+        explicit: ExplicitArgs,
+
+        /// Number of 32-bit words pushed back by an explicit write command,
+        /// such as the previous value of a test atomic. Printed like a read.
+        #[arg(long = "push-length", requires = "explicit", value_parser = maybe_hex::<u64>)]
+        push_length: Option<u64>,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+}
+
+/// Arguments of `nfp-cpp`, which writes if values are given and reads
+/// otherwise.
+#[derive(Args, Debug)]
+pub struct CppArgs {
+    #[command(flatten)]
+    command: CppCommandArgs,
+
+    #[arg(short = 'l', long = "length", default_value_t = 1, value_parser = maybe_hex::<u64>)]
+    length: u64,
+
+    #[arg(short = 'v', long = "value", action = ArgAction::Append, num_args = 1.., value_parser = hex_parser)]
+    values: Vec<u32>,
+
+    #[command(flatten)]
+    output: OutputArgs,
+
+    #[command(flatten)]
+    explicit: ExplicitArgs,
+
+    /// Number of 32-bit words pushed back by an explicit write command, such
+    /// as the previous value of a test atomic. Printed like a read.
+    #[arg(long = "push-length", requires = "explicit", requires = "values", value_parser = maybe_hex::<u64>)]
+    push_length: Option<u64>,
+}
+
+impl CppArgs {
+    /// Run the read or write on the NFP selected by `global`.
+    pub fn run(self, global: &GlobalArgs) -> Result<(), NfpError> {
+        let command = if self.values.is_empty() {
+            CppCommand::Read {
+                command: self.command,
+                length: self.length,
+                explicit: self.explicit,
+                output: self.output,
+            }
+        } else {
+            CppCommand::Write {
+                command: self.command,
+                values: self.values,
+                explicit: self.explicit,
+                push_length: self.push_length,
+                output: self.output,
+            }
+        };
+
+        command.run(global)
+    }
+}
+
+impl CppCommand {
+    /// Run the command on the NFP selected by `global`.
+    pub fn run(self, global: &GlobalArgs) -> Result<(), NfpError> {
+        let (command, explicit, output) = match &self {
+            CppCommand::Read {
+                command,
+                explicit,
+                output,
+                ..
+            }
+            | CppCommand::Write {
+                command,
+                explicit,
+                output,
+                ..
+            } => (command, explicit, output),
+        };
+
+        // Open the selected NFP.
+        let nfp = global.open()?;
+
+        // Resolve the address against the firmware symbols.
+        let symbols = ElfSymbols::load(&global.elf(&command.elf)?)?;
+        let address = symbols.resolve(&command.address, command.island)?;
+
+        let read_words = if explicit.explicit {
+            // Take an explicit BAR of the PCIe device.
+            let mut expl_bar = nfp.expl_bar()?;
+            self.run_explicit(&mut expl_bar, address)?
+        } else {
+            // Take an expansion BAR of the PCIe device.
+            let mut exp_bar = nfp.exp_bar()?;
+
+            // Instantiate Cpp bus with allocated expansion BAR.
+            let mut cpp_bus = CppBus::new(&mut exp_bar);
+
+            match &self {
+                // Read over CPP bus.
+                CppCommand::Read { length, .. } => Some(cpp_bus.read(
+                    command.island,
+                    command.target,
+                    command.action,
+                    command.token,
+                    command.cpp_len,
+                    address,
+                    *length,
+                )?),
+                // Write over CPP bus.
+                CppCommand::Write { values, .. } => {
+                    cpp_bus.write(
+                        command.island,
+                        command.target,
+                        command.action,
+                        command.token,
+                        command.cpp_len,
+                        address,
+                        values.clone(),
+                    )?;
+                    None
+                }
+            }
+        };
+
+        if let Some(read_words) = read_words {
+            if output.format == OutputFormat::Text {
+                for value in read_words {
+                    println!("0x{:08x}", value);
+                }
+            } else {
+                let annotate = |offset| symbols.symbolize_offset(Some(command.island.id()), offset);
+                write_region(
+                    &mut std::io::stdout().lock(),
+                    output.format,
+                    output.width,
+                    address,
+                    &words_to_bytes(&read_words),
+                    (!symbols.is_empty()).then_some(&annotate),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run the command as an explicit command.
+    ///
+    /// # Returns
+    ///
+    /// The data pushed by the command, if it reads or `--push-length` is
+    /// given.
+    fn run_explicit(
+        &self,
+        expl_bar: &mut ExplicitBar,
+        address: u64,
+    ) -> Result<Option<Vec<u32>>, NfpError> {
+        let (command, explicit, words, pull_data, push_data_len) = match self {
+            CppCommand::Read {
+                command,
+                length,
+                explicit,
+                ..
+            } => (command, explicit, *length, None, Some(*length)),
+            CppCommand::Write {
+                command,
+                values,
+                explicit,
+                push_length,
+                ..
+            } => (
+                command,
+                explicit,
+                values.len() as u64,
+                Some(values.clone()),
+                *push_length,
+            ),
+        };
+
+        if words == 0 || words > EXPL_MAX_DATA_WORDS {
+            return Err(NfpError::InvalidArgument(format!(
+                "Explicit commands transfer 1 to {} words, not {}",
+                EXPL_MAX_DATA_WORDS, words
+            )));
+        }
+        let length = explicit.cmd_length.unwrap_or((words - 1) as u8);
+
+        expl_bar.explicit_cmd(
+            command.island,
+            command.target,
+            command.action,
+            command.token,
+            address,
+            length,
+            &explicit.fields(),
+            pull_data,
+            push_data_len,
+        )
+    }
+}
@@ -0,0 +1,55 @@
+#![allow(dead_code)]
+
+use crate::libs::error::NfpError;
+
+// Exit codes of the tools. Command line errors reported by clap also exit
+// with EXIT_USAGE.
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_DEVICE_NOT_FOUND: i32 = 3;
+pub const EXIT_LOCK_CONTENTION: i32 = 4;
+pub const EXIT_TIMEOUT: i32 = 5;
+pub const EXIT_VERIFY_FAILED: i32 = 6;
+pub const EXIT_ADDRESS_OUT_OF_RANGE: i32 = 7;
+pub const EXIT_DM_CMD_ERR: i32 = 8;
+pub const EXIT_FIRMWARE_NOT_LOADED: i32 = 9;
+
+/// Description of the exit codes, for the help of the tools.
+pub const EXIT_CODES_HELP: &str = "Exit status:\n  \
+    0  success\n  \
+    1  I/O error\n  \
+    2  invalid command line or argument\n  \
+    3  device not found\n  \
+    4  device resource held by another process\n  \
+    5  timeout\n  \
+    6  verify or memory test failed\n  \
+    7  address out of range\n  \
+    8  RFPC debug module command failed\n  \
+    9  no firmware loaded";
+
+/// Exit code a tool returns for `error`.
+pub fn exit_code(error: &NfpError) -> i32 {
+    match error {
+        NfpError::Io { .. } => EXIT_FAILURE,
+        NfpError::InvalidArgument(_) => EXIT_USAGE,
+        NfpError::DeviceNotFound(_) => EXIT_DEVICE_NOT_FOUND,
+        NfpError::LockContention(_) => EXIT_LOCK_CONTENTION,
+        NfpError::Timeout(_) => EXIT_TIMEOUT,
+        NfpError::VerifyFailed(_) => EXIT_VERIFY_FAILED,
+        NfpError::AddressOutOfRange(_) => EXIT_ADDRESS_OUT_OF_RANGE,
+        NfpError::DmCmdErr(_) => EXIT_DM_CMD_ERR,
+        NfpError::FirmwareNotLoaded(_) => EXIT_FIRMWARE_NOT_LOADED,
+    }
+}
+
+/// Print the error a tool failed with, if any, and exit with its exit code.
+pub fn exit(result: Result<(), NfpError>) -> ! {
+    match result {
+        Ok(()) => std::process::exit(EXIT_SUCCESS),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(exit_code(&e));
+        }
+    }
+}
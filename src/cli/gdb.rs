@@ -0,0 +1,45 @@
+#![allow(dead_code)]
+
+use clap::Args;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::cli::global::GlobalArgs;
+use crate::libs::error::NfpError;
+use crate::libs::gdb_server_stub::RspServer;
+
+/// Arguments of `nfp gdb` and `nfp-gdb`.
+#[derive(Args, Debug)]
+pub struct GdbArgs {}
+
+impl GdbArgs {
+    /// Serve RSP debug clients for the NFP selected by `global` until
+    /// Ctrl-C is pressed.
+    pub fn run(&self, global: &GlobalArgs) -> Result<(), NfpError> {
+        // Open the selected NFP.
+        let nfp = global.open()?;
+
+        // Use an atomic flag to handle ctrl+c termination.
+        let running = Arc::new(AtomicBool::new(true));
+
+        // Handle ctrl+c to gracefully exit.
+        ctrlc::set_handler({
+            let running = running.clone();
+            move || {
+                println!("\n\nKeyboard interrupt received (ctrl+C). Exiting.");
+                running.store(false, Ordering::SeqCst);
+            }
+        })
+        .expect("Error setting Ctrl-C handler");
+
+        // Create an instance of RspServer.
+        let mut rsp_server = RspServer::new(nfp);
+
+        // Run the server in the main thread.
+        rsp_server.run(running);
+
+        println!("Server shutting down.");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+#![allow(dead_code)]
+
+use clap::{ArgAction, Args};
+use std::path::PathBuf;
+
+use crate::cli::config::Config;
+use crate::libs::chip_desc::install_env_chip_desc;
+use crate::libs::device_backend::BUS_REPLAY_ENV;
+use crate::libs::device_enum::DeviceEnumerator;
+use crate::libs::error::NfpError;
+use crate::libs::nfp::Nfp;
+use crate::libs::xpb_regs::install_env_reg_desc;
+
+/// Environment variable selecting the NFP when no device option is given.
+pub const NFP_BDF_ENV: &str = "NFP_BDF";
+
+/// Options selecting the NFP, shared by all tools.
+///
+/// A device given on the command line takes precedence over `NFP_BDF`,
+/// which takes precedence over the config file.
+#[derive(Args, Debug, Clone, Default)]
+#[command(next_help_heading = "Device options")]
+pub struct GlobalArgs {
+    /// PCIe BDF, index or serial number of the NFP (see `nfp list`).
+    /// Defaults to NFP_BDF, then to the config file.
+    #[arg(short = 'Z', long = "pci-bdf", global = true, value_name = "DEVICE")]
+    pub pci_bdf: Option<String>,
+
+    /// Use an in-process simulated NFP.
+    #[arg(long = "sim", global = true, action = ArgAction::SetTrue, conflicts_with = "pci_bdf")]
+    pub sim: bool,
+
+    /// Access the NFP through the nfp-cpp-server at this address. The
+    /// server token is taken from NFP_REMOTE_TOKEN.
+    #[arg(long = "remote", global = true, value_name = "HOST:PORT", conflicts_with_all = ["pci_bdf", "sim"])]
+    pub remote: Option<String>,
+
+    /// Config file with defaults for these options and `--elf`. Defaults
+    /// to `$XDG_CONFIG_HOME/nfp/config.toml`.
+    #[arg(long = "config", global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+}
+
+impl GlobalArgs {
+    /// Load the chip description named by `NFP_CHIP_DESC` and the register
+    /// description named by `NFP_REG_DESC`, if set. Tools call this before
+    /// parsing their command line, as parsing island and target names uses
+    /// the chip description.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::Io` if a file cannot be read, and
+    /// `NfpError::InvalidArgument` if it is not a valid description.
+    pub fn load_descriptions() -> Result<(), NfpError> {
+        install_env_chip_desc()?;
+        install_env_reg_desc()
+    }
+
+    /// Load the config file given with `--config`, or the default one.
+    pub fn config(&self) -> Result<Config, NfpError> {
+        Config::load(self.config.as_deref())
+    }
+
+    /// Open the selected NFP and initialize its PCIe BARs.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::InvalidArgument` if no NFP is selected or an NFP is
+    /// selected on the command line while `NFP_BUS_REPLAY` is set, and
+    /// `NfpError::DeviceNotFound` if no NFP matches the PCIe BDF, index or
+    /// serial number given.
+    pub fn open(&self) -> Result<Nfp, NfpError> {
+        if std::env::var_os(BUS_REPLAY_ENV).is_some() {
+            if self.pci_bdf.is_some() || self.remote.is_some() || self.sim {
+                return Err(NfpError::InvalidArgument(format!(
+                    "{} replays a bus trace in place of the NFP, do not also give -Z, --remote or --sim",
+                    BUS_REPLAY_ENV
+                )));
+            }
+            // A replayed trace stands in for the NFP selected by NFP_BDF or
+            // the config file.
+            return Nfp::open(None, None, false);
+        }
+        if let Some(remote) = &self.remote {
+            return Nfp::open(None, Some(remote), false);
+        }
+        if self.sim {
+            return Nfp::open(None, None, true);
+        }
+
+        let selector = match &self.pci_bdf {
+            Some(selector) => Some(selector.clone()),
+            None => std::env::var(NFP_BDF_ENV)
+                .ok()
+                .filter(|selector| !selector.is_empty()),
+        };
+        if let Some(selector) = selector {
+            let pci_bdf = DeviceEnumerator::new().select(&selector)?;
+            return Nfp::open(Some(&pci_bdf), None, false);
+        }
+
+        let config = self.config()?;
+        if let Some(selector) = &config.pci_bdf {
+            let pci_bdf = DeviceEnumerator::new().select(selector)?;
+            Nfp::open(Some(&pci_bdf), None, false)
+        } else if config.remote.is_some() || config.sim {
+            Nfp::open(None, config.remote.as_deref(), config.sim)
+        } else {
+            Err(NfpError::InvalidArgument(format!(
+                "No NFP selected, give -Z, --remote or --sim, set {} or set a default in the config file",
+                NFP_BDF_ENV
+            )))
+        }
+    }
+
+    /// Firmware ELFs to resolve symbols from: `elf` if given on the command
+    /// line, otherwise those of the config file.
+    pub fn elf(&self, elf: &[PathBuf]) -> Result<Vec<PathBuf>, NfpError> {
+        if !elf.is_empty() {
+            return Ok(elf.to_vec());
+        }
+        Ok(self.config()?.elf)
+    }
+}
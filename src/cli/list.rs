@@ -0,0 +1,82 @@
+#![allow(dead_code)]
+
+use clap::Args;
+
+use crate::libs::device_backend::LOCK_FILE_ROOT;
+use crate::libs::device_enum::{DeviceEnumerator, NfpDeviceInfo};
+use crate::libs::error::NfpError;
+
+/// Arguments of `nfp list` and `nfp-list`.
+#[derive(Args, Debug)]
+pub struct ListArgs {
+    #[arg(long = "sysfs-root", default_value = "/sys", hide = true)]
+    sysfs_root: String,
+
+    #[arg(long = "lock-root", default_value = LOCK_FILE_ROOT, hide = true)]
+    lock_root: String,
+}
+
+/// Format the implemented BARs as e.g. "0:2M,2:128M,4:64K".
+fn format_bar_sizes(device: &NfpDeviceInfo) -> String {
+    device
+        .bar_sizes
+        .iter()
+        .map(|(bar, size)| {
+            let (value, unit) = match size {
+                s if s >> 30 != 0 && s % (1 << 30) == 0 => (s >> 30, "G"),
+                s if s >> 20 != 0 && s % (1 << 20) == 0 => (s >> 20, "M"),
+                s if s >> 10 != 0 && s % (1 << 10) == 0 => (s >> 10, "K"),
+                s => (*s, ""),
+            };
+            format!("{}:{}{}", bar, value, unit)
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Format the held expansion BAR locks as e.g. "2.0,2.1".
+fn format_locks(device: &NfpDeviceInfo) -> String {
+    if device.locked_exp_bars.is_empty() {
+        return "-".to_string();
+    }
+
+    device
+        .locked_exp_bars
+        .iter()
+        .map(|(phys_bar, exp_bar)| format!("{}.{}", phys_bar, exp_bar))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+impl ListArgs {
+    /// Print the NFPs attached to this host.
+    pub fn run(&self) -> Result<(), NfpError> {
+        let devices = DeviceEnumerator::with_roots(&self.sysfs_root, &self.lock_root).list()?;
+
+        if devices.is_empty() {
+            println!("No NFP devices found.");
+            return Ok(());
+        }
+
+        println!(
+            "{:<5} {:<12} {:<17} {:<4} {:<10} {:<24} LOCKS",
+            "INDEX", "BDF", "SERIAL", "NUMA", "DRIVER", "BARS"
+        );
+        for (index, device) in devices.iter().enumerate() {
+            println!(
+                "{:<5} {:<12} {:<17} {:<4} {:<10} {:<24} {}",
+                index,
+                device.pci_bdf,
+                device.serial.as_deref().unwrap_or("-"),
+                device
+                    .numa_node
+                    .map_or("-".to_string(), |node| node.to_string()),
+                device.driver.as_deref().unwrap_or("-"),
+                format_bar_sizes(device),
+                format_locks(device)
+            );
+        }
+
+        Ok(())
+    }
+}
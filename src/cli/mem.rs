@@ -0,0 +1,472 @@
+#![allow(dead_code)]
+
+use clap::{ArgAction, ArgGroup, Args, Subcommand};
+use clap_num::maybe_hex;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::cli::global::GlobalArgs;
+use crate::cli::memtest::MemTestArgs;
+use crate::libs::common::{duration_parser, hex_parser};
+use crate::libs::cpp_bus::CppIsland;
+use crate::libs::elf_symbols::{AddressExpr, ElfSymbols};
+use crate::libs::error::NfpError;
+use crate::libs::expansion_bar::ExpansionBar;
+use crate::libs::mem_access::{
+    lmem_read, lmem_write, mem_atomic, mem_atomic_test_range, mem_cpp_address, mem_dump, mem_load,
+    mem_read, mem_write, word_bytes, MemoryType, MuAtomicOp, MuMemoryEngine,
+};
+use crate::libs::nfp::Nfp;
+use crate::libs::output_format::{progress_reporter, words_to_bytes, write_region, OutputArgs};
+use crate::libs::rfpc::Rfpc;
+use crate::libs::watch::{ctrlc_running_flag, watch_words};
+
+/// Memory region accessed by the `mem` commands.
+#[derive(Args, Debug)]
+pub struct MemRegionArgs {
+    #[arg(short = 'i', long = "island", required = true)]
+    pub island: CppIsland,
+
+    #[arg(short = 'm', long = "mem-type", required = true)]
+    pub mem_type: MemoryType,
+
+    #[arg(short = 'e', long = "mem-engine", default_value = "bulk32")]
+    pub mem_engine: MuMemoryEngine,
+
+    /// RFPC cluster of the core whose local memory to access with `-m lmem`.
+    #[arg(short = 'u', long = "cluster", required_if_eq("mem_type", "lmem"))]
+    pub cluster: Option<u8>,
+
+    /// RFPC group of the core whose local memory to access with `-m lmem`.
+    #[arg(short = 'r', long = "group", required_if_eq("mem_type", "lmem"))]
+    pub group: Option<u8>,
+
+    /// RFPC core whose local memory to access with `-m lmem`.
+    #[arg(short = 'c', long = "core", required_if_eq("mem_type", "lmem"))]
+    pub core: Option<u8>,
+
+    /// Address, or symbol with an optional offset, e.g. `counters+0x10`.
+    #[arg(short = 'a', long = "address", required = true)]
+    pub address: AddressExpr,
+
+    #[arg(short = 'l', long = "length", default_value_t = 1, value_parser = maybe_hex::<u64>)]
+    pub length: u64,
+
+    /// Firmware ELF to resolve symbols from. May be given more than once.
+    #[arg(long = "elf", value_name = "PATH", action = ArgAction::Append)]
+    pub elf: Vec<PathBuf>,
+}
+
+impl MemRegionArgs {
+    /// Return the RFPC core whose local memory `-m lmem` accesses.
+    fn lmem_core(&self) -> Option<Rfpc> {
+        if self.mem_type != MemoryType::Lmem {
+            return None;
+        }
+
+        // Clap requires the cluster, group and core for LMEM.
+        Some(Rfpc {
+            island: self.island,
+            cluster: self.cluster?,
+            group: self.group?,
+            core: self.core?,
+        })
+    }
+}
+
+/// Commands of `nfp mem`.
+#[derive(Subcommand, Debug)]
+pub enum MemCommand {
+    /// Read words of memory.
+    Read {
+        #[command(flatten)]
+        region: MemRegionArgs,
+
+        #[command(flatten)]
+        output: OutputArgs,
+
+        /// Read the region every INTERVAL, e.g. `500ms` or `2s`, and print
+        /// the words that change until Ctrl-C is pressed.
+        #[arg(long = "watch", value_name = "INTERVAL", value_parser = duration_parser)]
+        watch: Option<Duration>,
+    },
+
+    /// Write words to memory, repeating the values up to `length` words.
+    Write {
+        #[command(flatten)]
+        region: MemRegionArgs,
+
+        #[arg(short = 'v', long = "value", required = true, action = ArgAction::Append, num_args = 1.., value_parser = hex_parser)]
+        values: Vec<u32>,
+    },
+
+    /// Save `length` words of memory to a binary file.
+    Dump {
+        #[command(flatten)]
+        region: MemRegionArgs,
+
+        /// File to save the memory to.
+        #[arg(value_name = "PATH")]
+        file: PathBuf,
+
+        /// Read every chunk of memory twice and check both reads match.
+        #[arg(long = "verify", action = ArgAction::SetTrue)]
+        verify: bool,
+    },
+
+    /// Write the contents of a binary file to memory.
+    Load {
+        #[command(flatten)]
+        region: MemRegionArgs,
+
+        /// File to write to memory.
+        #[arg(value_name = "PATH")]
+        file: PathBuf,
+
+        /// Read back the memory and check it matches.
+        #[arg(long = "verify", action = ArgAction::SetTrue)]
+        verify: bool,
+    },
+
+    /// Apply an MU atomic operation to `length` words.
+    Atomic {
+        #[command(flatten)]
+        region: MemRegionArgs,
+
+        #[arg(value_name = "OP")]
+        op: MuAtomicOp,
+
+        #[arg(short = 'v', long = "value", action = ArgAction::Append, num_args = 1.., value_parser = hex_parser)]
+        values: Vec<u32>,
+
+        /// Use the test variant of the operation and print the previous
+        /// value of each word.
+        #[arg(long = "test", action = ArgAction::SetTrue)]
+        test: bool,
+    },
+
+    /// Test memory with walking-bit, address and random patterns.
+    Test(MemTestArgs),
+}
+
+/// Arguments of `nfp-mem`, which selects the operation with flags.
+#[derive(Args, Debug)]
+#[command(group(ArgGroup::new("transfer").args(["dump_file", "load_file"])))]
+pub struct MemArgs {
+    #[command(flatten)]
+    region: MemRegionArgs,
+
+    #[arg(short = 'v', long = "value", action = ArgAction::Append, num_args = 1.., value_parser = hex_parser)]
+    values: Vec<u32>,
+
+    #[arg(long = "atomic", value_name = "OP")]
+    atomic: Option<MuAtomicOp>,
+
+    #[arg(long = "test", action = ArgAction::SetTrue, requires = "atomic")]
+    test: bool,
+
+    #[command(flatten)]
+    output: OutputArgs,
+
+    /// Save `length` words of memory to a binary file.
+    #[arg(long = "dump-file", value_name = "PATH", conflicts_with_all = ["values", "atomic", "load_file"])]
+    dump_file: Option<PathBuf>,
+
+    /// Write the contents of a binary file to memory.
+    #[arg(long = "load-file", value_name = "PATH", conflicts_with_all = ["values", "atomic"])]
+    load_file: Option<PathBuf>,
+
+    /// Check a dump by reading every chunk of memory twice, or a load by
+    /// reading it back.
+    #[arg(long = "verify", action = ArgAction::SetTrue, requires = "transfer")]
+    verify: bool,
+
+    /// Read the region every INTERVAL, e.g. `500ms` or `2s`, and print the
+    /// words that change until Ctrl-C is pressed.
+    #[arg(long = "watch", value_name = "INTERVAL", value_parser = duration_parser,
+          conflicts_with_all = ["values", "atomic", "dump_file", "load_file"])]
+    watch: Option<Duration>,
+}
+
+impl MemArgs {
+    /// Run the operation selected by the flags on the NFP selected by
+    /// `global`.
+    pub fn run(self, global: &GlobalArgs) -> Result<(), NfpError> {
+        let region = self.region;
+        let command = if let Some(file) = self.dump_file {
+            MemCommand::Dump {
+                region,
+                file,
+                verify: self.verify,
+            }
+        } else if let Some(file) = self.load_file {
+            MemCommand::Load {
+                region,
+                file,
+                verify: self.verify,
+            }
+        } else if let Some(op) = self.atomic {
+            MemCommand::Atomic {
+                region,
+                op,
+                values: self.values,
+                test: self.test,
+            }
+        } else if self.values.is_empty() {
+            MemCommand::Read {
+                region,
+                output: self.output,
+                watch: self.watch,
+            }
+        } else {
+            MemCommand::Write {
+                region,
+                values: self.values,
+            }
+        };
+
+        command.run(global)
+    }
+}
+
+impl MemCommand {
+    /// Run the command on the NFP selected by `global`.
+    pub fn run(self, global: &GlobalArgs) -> Result<(), NfpError> {
+        let region = match &self {
+            MemCommand::Test(args) => return args.run(global),
+            MemCommand::Read { region, .. }
+            | MemCommand::Write { region, .. }
+            | MemCommand::Dump { region, .. }
+            | MemCommand::Load { region, .. }
+            | MemCommand::Atomic { region, .. } => region,
+        };
+
+        if region.mem_type == MemoryType::Lmem
+            && matches!(
+                self,
+                MemCommand::Dump { .. } | MemCommand::Load { .. } | MemCommand::Atomic { .. }
+            )
+        {
+            return Err(NfpError::InvalidArgument(
+                "dump, load and atomic operations are not supported on LMEM".to_string(),
+            ));
+        }
+
+        // Open the selected NFP.
+        let nfp = global.open()?;
+
+        // Take an expansion BAR of the PCIe device.
+        let mut exp_bar = nfp.exp_bar()?;
+
+        // Resolve the address against the firmware symbols.
+        let symbols = ElfSymbols::load(&global.elf(&region.elf)?)?;
+        let address = symbols.resolve(&region.address, region.island)?;
+
+        match &self {
+            MemCommand::Read { output, watch, .. } => run_read(
+                region,
+                &nfp,
+                &mut exp_bar,
+                &symbols,
+                address,
+                output,
+                *watch,
+            ),
+            MemCommand::Write { values, .. } => {
+                run_write(region, &nfp, &mut exp_bar, address, values)
+            }
+            MemCommand::Dump { file, verify, .. } => {
+                run_dump(region, &mut exp_bar, address, file, *verify)
+            }
+            MemCommand::Load { file, verify, .. } => {
+                run_load(region, &mut exp_bar, address, file, *verify)
+            }
+            MemCommand::Atomic {
+                op, values, test, ..
+            } => run_atomic(region, &nfp, &mut exp_bar, address, *op, values, *test),
+            MemCommand::Test(_) => unreachable!(),
+        }
+    }
+}
+
+/// Print the words of the region, once or every `watch` interval.
+fn run_read(
+    region: &MemRegionArgs,
+    nfp: &Nfp,
+    exp_bar: &mut ExpansionBar,
+    symbols: &ElfSymbols,
+    address: u64,
+    output: &OutputArgs,
+    watch: Option<Duration>,
+) -> Result<(), NfpError> {
+    let annotate = |offset| symbols.symbolize_offset(Some(region.island.id()), offset);
+    let mut expl_bar = match region.lmem_core() {
+        Some(_) => Some(nfp.expl_bar()?),
+        None => None,
+    };
+    let mut read_words = || match (region.lmem_core(), expl_bar.as_mut()) {
+        (Some(rfpc), Some(expl_bar)) => lmem_read(expl_bar, &rfpc, address, region.length),
+        _ => mem_read(
+            exp_bar,
+            region.island,
+            region.mem_type,
+            region.mem_engine,
+            address,
+            region.length,
+        ),
+    };
+
+    if let Some(interval) = watch {
+        let running = ctrlc_running_flag()?;
+        return watch_words(
+            &mut std::io::stdout().lock(),
+            interval,
+            address,
+            &mut read_words,
+            (!symbols.is_empty()).then_some(&annotate),
+            &running,
+        );
+    }
+
+    let read_words = read_words()?;
+    write_region(
+        &mut std::io::stdout().lock(),
+        output.format,
+        output.width,
+        address,
+        &words_to_bytes(&read_words),
+        (!symbols.is_empty()).then_some(&annotate),
+    )
+}
+
+/// Write `values` to the region, repeated up to its length.
+fn run_write(
+    region: &MemRegionArgs,
+    nfp: &Nfp,
+    exp_bar: &mut ExpansionBar,
+    address: u64,
+    values: &[u32],
+) -> Result<(), NfpError> {
+    let mut values_to_write: Vec<u32> = Vec::new();
+    values_to_write.extend(values.iter().cycle().take(region.length as usize));
+    if let Some(rfpc) = region.lmem_core() {
+        let mut expl_bar = nfp.expl_bar()?;
+        lmem_write(&mut expl_bar, &rfpc, address, values_to_write)
+    } else {
+        mem_write(
+            exp_bar,
+            region.island,
+            region.mem_type,
+            region.mem_engine,
+            address,
+            values_to_write,
+        )
+    }
+}
+
+/// Save memory to the file at `path`.
+fn run_dump(
+    region: &MemRegionArgs,
+    exp_bar: &mut ExpansionBar,
+    address: u64,
+    path: &Path,
+    verify: bool,
+) -> Result<(), NfpError> {
+    // Check the region before creating the file, so that a bad region does
+    // not leave an empty file behind.
+    let length = word_bytes(region.length)?;
+    mem_cpp_address(region.island, region.mem_type, address, length)?;
+
+    let file = File::create(path)
+        .map_err(|e| NfpError::io(format!("Failed to create {}", path.display()), e))?;
+    let mut writer = BufWriter::new(file);
+
+    mem_dump(
+        exp_bar,
+        region.island,
+        region.mem_type,
+        region.mem_engine,
+        address,
+        length,
+        &mut writer,
+        verify,
+        &mut progress_reporter("Dumping"),
+    )
+}
+
+/// Write the file at `path` to memory.
+fn run_load(
+    region: &MemRegionArgs,
+    exp_bar: &mut ExpansionBar,
+    address: u64,
+    path: &Path,
+    verify: bool,
+) -> Result<(), NfpError> {
+    let file = File::open(path)
+        .map_err(|e| NfpError::io(format!("Failed to open {}", path.display()), e))?;
+    let length = file
+        .metadata()
+        .map_err(|e| NfpError::io(format!("Failed to read {}", path.display()), e))?
+        .len();
+    let mut reader = BufReader::new(file);
+
+    mem_load(
+        exp_bar,
+        region.island,
+        region.mem_type,
+        region.mem_engine,
+        address,
+        length,
+        &mut reader,
+        verify,
+        &mut progress_reporter("Loading"),
+    )
+}
+
+/// Apply the MU atomic operation `op`.
+fn run_atomic(
+    region: &MemRegionArgs,
+    nfp: &Nfp,
+    exp_bar: &mut ExpansionBar,
+    address: u64,
+    op: MuAtomicOp,
+    values: &[u32],
+    test: bool,
+) -> Result<(), NfpError> {
+    // Compare-and-write only exists in its test variant.
+    if !test && op != MuAtomicOp::CompareWrite {
+        return mem_atomic(
+            exp_bar,
+            region.island,
+            region.mem_type,
+            op,
+            address,
+            values,
+            region.length,
+        );
+    }
+
+    // Take an explicit BAR of the PCIe device.
+    let mut expl_bar = nfp.expl_bar()?;
+
+    let previous = mem_atomic_test_range(
+        &mut expl_bar,
+        region.island,
+        region.mem_type,
+        op,
+        address,
+        values,
+        region.length,
+    )?;
+    for (index, previous) in previous.iter().enumerate() {
+        println!(
+            "address 0x{:08x}: 0x{:08x}",
+            address + index as u64 * 4,
+            previous
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,158 @@
+#![allow(dead_code)]
+
+use clap::{ArgAction, Args};
+use clap_num::maybe_hex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::cli::global::GlobalArgs;
+use crate::libs::cpp_bus::CppIsland;
+use crate::libs::error::NfpError;
+use crate::libs::mem_access::{MemoryType, MuMemoryEngine};
+use crate::libs::mem_test::{mem_test, MemTestPattern};
+use crate::libs::output_format::progress_reporter;
+
+/// Arguments of `nfp mem test` and `nfp-memtest`.
+#[derive(Args, Debug)]
+pub struct MemTestArgs {
+    #[arg(short = 'i', long = "island", required = true)]
+    island: CppIsland,
+
+    #[arg(short = 'm', long = "mem-type", required = true)]
+    mem_type: MemoryType,
+
+    /// Start address of the region to test.
+    #[arg(short = 'a', long = "address", required = true, value_parser = maybe_hex::<u64>)]
+    address: u64,
+
+    /// Length of the region to test in 32-bit words.
+    #[arg(short = 'l', long = "length", required = true, value_parser = maybe_hex::<u64>)]
+    length: u64,
+
+    /// Test to run. May be given more than once. Runs all tests if not given.
+    #[arg(short = 't', long = "test", action = ArgAction::Append)]
+    tests: Vec<MemTestPattern>,
+
+    /// MU engine to test with. May be given more than once. Tests with
+    /// Bulk32 and Bulk64 if not given.
+    #[arg(short = 'e', long = "mem-engine", action = ArgAction::Append)]
+    mem_engines: Vec<MuMemoryEngine>,
+
+    /// Seed of the random test, by default taken from the clock.
+    #[arg(long = "seed", value_parser = maybe_hex::<u32>)]
+    seed: Option<u32>,
+
+    /// Number of failing words to print per test.
+    #[arg(long = "max-errors", default_value_t = 16)]
+    max_errors: usize,
+}
+
+impl MemTestArgs {
+    /// Test the region on the NFP selected by `global`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::VerifyFailed` if any test fails.
+    pub fn run(&self, global: &GlobalArgs) -> Result<(), NfpError> {
+        if self.mem_type == MemoryType::Lmem {
+            return Err(NfpError::InvalidArgument(
+                "LMEM cannot be tested over the CPP bus".to_string(),
+            ));
+        }
+
+        let tests = if self.tests.is_empty() {
+            vec![
+                MemTestPattern::WalkingOnes,
+                MemTestPattern::WalkingZeros,
+                MemTestPattern::AddressInAddress,
+                MemTestPattern::Random,
+            ]
+        } else {
+            self.tests.clone()
+        };
+
+        // Only MU memories have engines; test other memories once.
+        let engines = if !self.mem_type.is_mu() {
+            vec![MuMemoryEngine::Bulk32]
+        } else if self.mem_engines.is_empty() {
+            vec![MuMemoryEngine::Bulk32, MuMemoryEngine::Bulk64]
+        } else {
+            self.mem_engines.clone()
+        };
+
+        let end = self
+            .length
+            .checked_mul(4)
+            .and_then(|bytes| self.address.checked_add(bytes))
+            .ok_or_else(|| {
+                NfpError::AddressOutOfRange(format!(
+                    "Test region of {:#x} words at {:#x} does not fit in 64 bits of address",
+                    self.length, self.address
+                ))
+            })?;
+
+        let seed = self.seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(1, |time| time.subsec_nanos() ^ time.as_secs() as u32)
+        });
+
+        // Open the selected NFP.
+        let nfp = global.open()?;
+
+        // Take an expansion BAR of the PCIe device.
+        let mut exp_bar = nfp.exp_bar()?;
+
+        println!(
+            "Testing {} {} 0x{:08x}-0x{:08x}, seed 0x{:08x}",
+            self.island, self.mem_type, self.address, end, seed
+        );
+
+        let mut failed_tests = 0;
+        for &engine in &engines {
+            for &test in &tests {
+                let report = mem_test(
+                    &mut exp_bar,
+                    self.island,
+                    self.mem_type,
+                    engine,
+                    test,
+                    self.address,
+                    self.length,
+                    seed,
+                    self.max_errors,
+                    &mut progress_reporter("Testing"),
+                )?;
+
+                if report.passed() {
+                    println!("{} {}: PASS ({} words)", test, engine, report.words_tested);
+                    continue;
+                }
+
+                failed_tests += 1;
+                println!(
+                    "{} {}: FAIL ({} of {} words, failing bits 0x{:08x})",
+                    test, engine, report.failure_count, report.words_tested, report.failing_bits
+                );
+                for failure in &report.failures {
+                    println!("  {}", failure);
+                }
+                if report.failure_count > report.failures.len() as u64 {
+                    println!(
+                        "  ... {} more",
+                        report.failure_count - report.failures.len() as u64
+                    );
+                }
+            }
+        }
+
+        if failed_tests != 0 {
+            return Err(NfpError::VerifyFailed(format!(
+                "{} of {} tests failed",
+                failed_tests,
+                tests.len() * engines.len()
+            )));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,305 @@
+#![allow(dead_code)]
+
+use clap::{ArgAction, Args, Subcommand};
+use clap_num::maybe_hex;
+use std::convert::TryInto;
+use std::path::PathBuf;
+
+use crate::cli::global::GlobalArgs;
+use crate::libs::cpp_bus::CppIsland;
+use crate::libs::elf_symbols::ElfSymbols;
+use crate::libs::error::NfpError;
+use crate::libs::output_format::{write_registers, GroupWidth, OutputFormat, RegisterValue};
+use crate::libs::rfpc::{Rfpc, RfpcCsr, RfpcGpr, RfpcReg};
+use crate::libs::rfpc_debugger::{read_rfpc_reg, rfpc_dbg_halt, rfpc_dbg_resume, write_rfpc_reg};
+use crate::libs::rfpc_trace::{format_uncomp_trace, pa_trigger_on_uncomp_trace, read_trace};
+
+/// RFPC core accessed by the `rfpc` commands.
+#[derive(Args, Debug)]
+pub struct RfpcCoreArgs {
+    #[arg(short = 'i', long = "island", required = true)]
+    pub island: CppIsland,
+
+    #[arg(short = 'u', long = "cluster", required = true)]
+    pub cluster: u8,
+
+    #[arg(short = 'r', long = "group", required = true)]
+    pub group: u8,
+
+    #[arg(short = 'c', long = "core", required = true)]
+    pub core: u8,
+}
+
+impl RfpcCoreArgs {
+    /// Return the selected core.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::InvalidArgument` if the cluster, group or core
+    /// does not exist on the chip.
+    pub fn rfpc(&self) -> Result<Rfpc, NfpError> {
+        Rfpc::new(self.island, self.cluster, self.group, self.core)
+    }
+}
+
+/// Register accessed by `rfpc read` and `rfpc write`.
+#[derive(Args, Debug)]
+#[group(id = "register", required = true, multiple = false)]
+pub struct RfpcRegisterArgs {
+    #[arg(short = 's', long = "csr")]
+    pub csr: Option<RfpcCsr>,
+
+    #[arg(short = 'p', long = "gpr")]
+    pub gpr: Option<RfpcGpr>,
+}
+
+impl RfpcRegisterArgs {
+    /// Return the selected CSR or GPR.
+    fn reg(&self) -> Box<dyn RfpcReg> {
+        // Clap requires exactly one of the CSR and GPR.
+        match (&self.csr, &self.gpr) {
+            (Some(csr), _) => Box::new(csr.clone()),
+            (None, Some(gpr)) => Box::new(gpr.clone()),
+            (None, None) => unreachable!("Either CSR or GPR must be provided"),
+        }
+    }
+}
+
+/// Commands of `nfp rfpc`.
+#[derive(Subcommand, Debug)]
+pub enum RfpcCommand {
+    /// Read a GPR or CSR, halting the core around the access.
+    Read {
+        #[command(flatten)]
+        core: RfpcCoreArgs,
+
+        #[command(flatten)]
+        register: RfpcRegisterArgs,
+
+        /// Firmware ELF used to show the symbol a register value points to,
+        /// e.g. for `dpc`. May be given more than once.
+        #[arg(long = "elf", value_name = "PATH", action = ArgAction::Append)]
+        elf: Vec<PathBuf>,
+
+        /// Format of the register value printed.
+        #[arg(short = 'f', long = "format", default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+
+    /// Write a GPR or CSR, halting the core around the access.
+    Write {
+        #[command(flatten)]
+        core: RfpcCoreArgs,
+
+        #[command(flatten)]
+        register: RfpcRegisterArgs,
+
+        #[arg(short = 'v', long = "value", required = true, value_parser = maybe_hex::<u64>)]
+        value: u64,
+    },
+
+    /// Halt a core.
+    Halt {
+        #[command(flatten)]
+        core: RfpcCoreArgs,
+    },
+
+    /// Resume a halted core.
+    Resume {
+        #[command(flatten)]
+        core: RfpcCoreArgs,
+    },
+
+    /// Capture trace information of a core.
+    Trace(RfpcTraceArgs),
+}
+
+impl RfpcCommand {
+    /// Run the command on the NFP selected by `global`.
+    pub fn run(self, global: &GlobalArgs) -> Result<(), NfpError> {
+        let core = match &self {
+            RfpcCommand::Trace(args) => return args.run(global),
+            RfpcCommand::Read { core, .. }
+            | RfpcCommand::Write { core, .. }
+            | RfpcCommand::Halt { core }
+            | RfpcCommand::Resume { core } => core,
+        };
+        let rfpc = core.rfpc()?;
+
+        // Open the selected NFP.
+        let nfp = global.open()?;
+
+        // Take an explicit BAR of the PCIe device.
+        let mut expl_bar = nfp.expl_bar()?;
+
+        match self {
+            RfpcCommand::Read {
+                register,
+                elf,
+                format,
+                ..
+            } => {
+                let reg = register.reg();
+                let val = read_rfpc_reg(&mut expl_bar, &rfpc, &reg)?;
+                let register = RegisterValue {
+                    name: format!("{}:{}", rfpc, reg),
+                    value: val,
+                    width: GroupWidth::W64,
+                    symbol: ElfSymbols::load(&global.elf(&elf)?)?.symbolize(val),
+                };
+                write_registers(&mut std::io::stdout().lock(), format, &[register])
+            }
+            RfpcCommand::Write {
+                register, value, ..
+            } => write_rfpc_reg(&mut expl_bar, &rfpc, &register.reg(), value),
+            RfpcCommand::Halt { .. } => rfpc_dbg_halt(&mut expl_bar, &rfpc),
+            RfpcCommand::Resume { .. } => rfpc_dbg_resume(&mut expl_bar, &rfpc),
+            RfpcCommand::Trace(_) => unreachable!(),
+        }
+    }
+}
+
+/// Arguments of `nfp-rfpc-reg`, which writes if a value is given and reads
+/// otherwise.
+#[derive(Args, Debug)]
+pub struct RfpcRegArgs {
+    #[command(flatten)]
+    core: RfpcCoreArgs,
+
+    #[command(flatten)]
+    register: RfpcRegisterArgs,
+
+    #[arg(short = 'v', long = "value", value_parser = maybe_hex::<u64>)]
+    value: Option<u64>,
+
+    /// Firmware ELF used to show the symbol a register value points to,
+    /// e.g. for `dpc`. May be given more than once.
+    #[arg(long = "elf", value_name = "PATH", action = ArgAction::Append)]
+    elf: Vec<PathBuf>,
+
+    /// Format of the register value printed.
+    #[arg(short = 'f', long = "format", default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+}
+
+impl RfpcRegArgs {
+    /// Run the read or write on the NFP selected by `global`.
+    pub fn run(self, global: &GlobalArgs) -> Result<(), NfpError> {
+        let command = match self.value {
+            Some(value) => RfpcCommand::Write {
+                core: self.core,
+                register: self.register,
+                value,
+            },
+            None => RfpcCommand::Read {
+                core: self.core,
+                register: self.register,
+                elf: self.elf,
+                format: self.format,
+            },
+        };
+
+        command.run(global)
+    }
+}
+
+/// Arguments of `nfp rfpc trace` and `nfp-rfpc-trace`.
+#[derive(Args, Debug)]
+pub struct RfpcTraceArgs {
+    #[command(flatten)]
+    core: RfpcCoreArgs,
+
+    #[arg(long = "tp", action = ArgAction::SetFalse)]
+    trace_pc: bool,
+
+    #[arg(long = "ts", action = ArgAction::SetFalse)]
+    trace_seq: bool,
+
+    #[arg(long = "tb", action = ArgAction::SetFalse)]
+    trace_bp: bool,
+
+    #[arg(long = "tr", action = ArgAction::SetFalse)]
+    trace_reg: bool,
+
+    #[arg(short = 'n', long = "num-samples", required = true)]
+    num_samples: u32,
+
+    #[arg(short = 'b', long = "bus-words", required = true)]
+    bus_words: u32,
+
+    #[arg(short = 'w', long = "word-index", required = true)]
+    word_index: u32,
+
+    #[arg(long = "t", long = "timestamp", action = ArgAction::SetFalse)]
+    timestamp: bool,
+
+    /// Firmware ELF used to show the symbol of traced program counters.
+    /// May be given more than once.
+    #[arg(long = "elf", value_name = "PATH", action = ArgAction::Append)]
+    elf: Vec<PathBuf>,
+}
+
+impl RfpcTraceArgs {
+    /// Capture and print the trace on the NFP selected by `global`.
+    pub fn run(&self, global: &GlobalArgs) -> Result<(), NfpError> {
+        if self.timestamp && (self.bus_words == 2) {
+            return Err(NfpError::InvalidArgument(
+                "Cannot sample only 2 bus words and a timestamp. \
+                    A timestamp is only available when sampling either 1 or 3 words \
+                    on the Performance bus."
+                    .to_string(),
+            ));
+        }
+
+        if !self.timestamp && (self.bus_words == 3) {
+            return Err(NfpError::InvalidArgument(
+                "Timestamp must be read when sampling all 3 words \
+                    on the performance bus."
+                    .to_string(),
+            ));
+        }
+
+        // Create an RFPC from input parameters.
+        let rfpc = self.core.rfpc()?;
+
+        // Open the selected NFP.
+        let nfp = global.open()?;
+
+        let mut words_per_sample = self.bus_words;
+        if self.timestamp {
+            words_per_sample += 1;
+        }
+
+        // Configure Performance Analyzer to trigger on an uncompressed trace.
+        let mut pa = pa_trigger_on_uncomp_trace(
+            nfp,
+            &rfpc,
+            self.trace_pc,
+            self.trace_seq,
+            self.trace_bp,
+            self.trace_reg,
+            self.bus_words,
+            self.word_index,
+            self.timestamp,
+        )?;
+
+        // Read the specified number of samples from the Performance Analyzer.
+        let samples: Vec<u32> = read_trace(&mut pa, self.num_samples * words_per_sample)?;
+        // Format the samples, annotating program counters with their symbol.
+        let symbols = ElfSymbols::load(&global.elf(&self.elf)?)?;
+        let annotate = |pc: u32| symbols.symbolize_code(Some(rfpc.island.id()), pc as u64);
+        let formatted_lines = format_uncomp_trace(
+            samples,
+            self.bus_words,
+            self.word_index,
+            self.timestamp,
+            words_per_sample.try_into().unwrap(),
+            (!symbols.is_empty()).then_some(&annotate),
+        );
+        for line in formatted_lines {
+            println!("{}", line);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,189 @@
+#![allow(dead_code)]
+
+use clap::{ArgAction, Args, Subcommand};
+use clap_num::maybe_hex;
+
+use crate::cli::global::GlobalArgs;
+use crate::libs::common::hex_parser;
+use crate::libs::error::NfpError;
+use crate::libs::output_format::{words_to_bytes, write_region, OutputArgs};
+use crate::libs::rtsym::{rtsym_read, rtsym_write, RtsymTable, RtsymType};
+
+/// Commands of `nfp rtsym`.
+#[derive(Subcommand, Debug)]
+pub enum RtsymCommand {
+    /// List the run-time symbols.
+    List,
+
+    /// Read words of a symbol.
+    Read {
+        /// Symbol to read.
+        #[arg(value_name = "NAME")]
+        name: String,
+
+        /// Byte offset in the symbol.
+        #[arg(short = 'o', long = "offset", default_value_t = 0, value_parser = maybe_hex::<u64>)]
+        offset: u64,
+
+        /// Number of 32-bit words, by default up to the end of the symbol.
+        #[arg(short = 'l', long = "length", value_parser = maybe_hex::<u64>)]
+        length: Option<u64>,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+
+    /// Write words to a symbol, repeating the values up to `length` words.
+    Write {
+        /// Symbol to write.
+        #[arg(value_name = "NAME")]
+        name: String,
+
+        /// Byte offset in the symbol.
+        #[arg(short = 'o', long = "offset", default_value_t = 0, value_parser = maybe_hex::<u64>)]
+        offset: u64,
+
+        /// Number of 32-bit words, by default the number of values.
+        #[arg(short = 'l', long = "length", value_parser = maybe_hex::<u64>)]
+        length: Option<u64>,
+
+        #[arg(short = 'v', long = "value", required = true, action = ArgAction::Append, num_args = 1.., value_parser = hex_parser)]
+        values: Vec<u32>,
+    },
+}
+
+/// Arguments of `nfp-rtsym`, which lists the symbols unless a name is
+/// given.
+#[derive(Args, Debug)]
+pub struct RtsymArgs {
+    /// Symbol to read or write. Lists all symbols if not given.
+    #[arg(short = 'n', long = "name")]
+    name: Option<String>,
+
+    /// Byte offset in the symbol.
+    #[arg(short = 'o', long = "offset", default_value_t = 0, value_parser = maybe_hex::<u64>, requires = "name")]
+    offset: u64,
+
+    /// Number of 32-bit words, by default up to the end of the symbol.
+    #[arg(short = 'l', long = "length", value_parser = maybe_hex::<u64>, requires = "name")]
+    length: Option<u64>,
+
+    #[arg(short = 'v', long = "value", action = ArgAction::Append, num_args = 1.., value_parser = hex_parser, requires = "name")]
+    values: Vec<u32>,
+
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+impl RtsymArgs {
+    /// Run the listing, read or write on the NFP selected by `global`.
+    pub fn run(self, global: &GlobalArgs) -> Result<(), NfpError> {
+        let command = match self.name {
+            None => RtsymCommand::List,
+            Some(name) if self.values.is_empty() => RtsymCommand::Read {
+                name,
+                offset: self.offset,
+                length: self.length,
+                output: self.output,
+            },
+            Some(name) => RtsymCommand::Write {
+                name,
+                offset: self.offset,
+                length: self.length,
+                values: self.values,
+            },
+        };
+
+        command.run(global)
+    }
+}
+
+/// Print the symbol table.
+fn list_symbols(table: &RtsymTable) {
+    println!(
+        "{:<32} {:<8} {:<14} {:<18} SIZE",
+        "NAME", "TYPE", "LOCATION", "ADDRESS"
+    );
+    for symbol in table.symbols() {
+        println!(
+            "{:<32} {:<8} {:<14} 0x{:016x} 0x{:x}",
+            symbol.name,
+            symbol.sym_type,
+            symbol.location_name(),
+            symbol.address,
+            symbol.size
+        );
+    }
+}
+
+impl RtsymCommand {
+    /// Run the command on the NFP selected by `global`.
+    pub fn run(self, global: &GlobalArgs) -> Result<(), NfpError> {
+        // Open the selected NFP.
+        let nfp = global.open()?;
+
+        // Take an expansion BAR of the PCIe device.
+        let mut exp_bar = nfp.exp_bar()?;
+
+        let table = RtsymTable::read(&mut exp_bar)?;
+
+        let (name, offset, length, values) = match &self {
+            RtsymCommand::List => {
+                list_symbols(&table);
+                return Ok(());
+            }
+            RtsymCommand::Read {
+                name,
+                offset,
+                length,
+                ..
+            } => (name, *offset, *length, &[][..]),
+            RtsymCommand::Write {
+                name,
+                offset,
+                length,
+                values,
+            } => (name, *offset, *length, &values[..]),
+        };
+
+        let symbol = table.get(name).ok_or_else(|| {
+            NfpError::InvalidArgument(format!("Unknown run-time symbol {}", name))
+        })?;
+
+        // The value of an absolute symbol is its address.
+        if symbol.sym_type == RtsymType::Abs {
+            if !values.is_empty() {
+                return Err(NfpError::InvalidArgument(format!(
+                    "Cannot write absolute symbol {}",
+                    name
+                )));
+            }
+            println!("0x{:016x}", symbol.address);
+            return Ok(());
+        }
+
+        if let RtsymCommand::Read { output, .. } = &self {
+            let length = length.unwrap_or_else(|| symbol.size.saturating_sub(offset) / 4);
+            let read_words = rtsym_read(&mut exp_bar, symbol, offset, length)?;
+            write_region(
+                &mut std::io::stdout().lock(),
+                output.format,
+                output.width,
+                symbol.address + offset,
+                &words_to_bytes(&read_words),
+                None,
+            )?;
+        } else {
+            let length = length.unwrap_or(values.len() as u64);
+            let values_to_write: Vec<u32> = values
+                .iter()
+                .cycle()
+                .take(length as usize)
+                .copied()
+                .collect();
+            rtsym_write(&mut exp_bar, symbol, offset, values_to_write)?;
+        }
+
+        Ok(())
+    }
+}
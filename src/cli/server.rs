@@ -0,0 +1,70 @@
+#![allow(dead_code)]
+
+use clap::Args;
+use std::fs;
+use std::net::TcpListener;
+use std::path::PathBuf;
+
+use crate::cli::global::GlobalArgs;
+use crate::libs::error::NfpError;
+use crate::libs::remote::{CppServer, ServerEvent, DEFAULT_SERVER_ADDR, REMOTE_TOKEN_ENV};
+
+/// Arguments of `nfp server` and `nfp-cpp-server`.
+#[derive(Args, Debug)]
+pub struct ServerArgs {
+    /// Address and port to accept clients on.
+    #[arg(short = 'l', long = "listen", value_name = "ADDR:PORT", default_value = DEFAULT_SERVER_ADDR)]
+    listen: String,
+
+    /// File holding the token clients must present.
+    #[arg(long = "token-file", value_name = "PATH")]
+    token_file: Option<PathBuf>,
+}
+
+impl ServerArgs {
+    /// Read the token from `--token-file`, or from the environment.
+    fn read_token(&self) -> Result<String, NfpError> {
+        let token = match &self.token_file {
+            Some(path) => fs::read_to_string(path)
+                .map_err(|e| NfpError::io(format!("Failed to read {}", path.display()), e))?
+                .trim()
+                .to_string(),
+            None => std::env::var(REMOTE_TOKEN_ENV).unwrap_or_default(),
+        };
+
+        if token.is_empty() {
+            return Err(NfpError::InvalidArgument(format!(
+                "A token is required, give --token-file or set {}",
+                REMOTE_TOKEN_ENV
+            )));
+        }
+        Ok(token)
+    }
+
+    /// Serve the NFP selected by `global` to remote clients.
+    pub fn run(&self, global: &GlobalArgs) -> Result<(), NfpError> {
+        if global.remote.is_some() {
+            return Err(NfpError::InvalidArgument(
+                "A remote NFP cannot be served again".to_string(),
+            ));
+        }
+
+        let token = self.read_token()?;
+
+        // Open the selected NFP.
+        let nfp = global.open()?;
+
+        let listener = TcpListener::bind(&self.listen)
+            .map_err(|e| NfpError::io(format!("Failed to listen on {}", self.listen), e))?;
+        println!("Serving {} on {}", nfp.name(), self.listen);
+
+        CppServer::new(nfp, &token).serve(listener, |event| match event {
+            ServerEvent::Connected { peer } => println!("{}: connected", peer),
+            ServerEvent::Disconnected { peer, error: None } => println!("{}: disconnected", peer),
+            ServerEvent::Disconnected {
+                peer,
+                error: Some(e),
+            } => println!("{}: disconnected: {}", peer, e),
+        })
+    }
+}
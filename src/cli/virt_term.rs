@@ -0,0 +1,98 @@
+#![allow(dead_code)]
+
+use clap::Args;
+use clap_num::maybe_hex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::cli::global::GlobalArgs;
+use crate::libs::cpp_bus::CppIsland;
+use crate::libs::error::NfpError;
+use crate::libs::mem_access::MemoryType;
+use crate::libs::rfpc::Rfpc;
+use crate::libs::virtual_terminal::VirtualTerminal;
+
+/// Arguments of `nfp virt-term` and `nfp-virt-term`.
+#[derive(Args, Debug)]
+pub struct VirtTermArgs {
+    #[arg(short = 'i', long = "island", required = true)]
+    island: CppIsland,
+
+    /// Memory holding the virtual terminal. Defaults to the main memory of
+    /// the island, e.g. CTM for RFPC islands and EMEM for EMU islands.
+    #[arg(short = 'm', long = "mem-type")]
+    mem_type: Option<MemoryType>,
+
+    #[arg(short = 'a', long = "address", default_value = "0x3fef4", value_parser = maybe_hex::<u32>)]
+    address: u32,
+}
+
+impl VirtTermArgs {
+    /// Print the virtual terminal of the NFP selected by `global` until
+    /// Ctrl-C is pressed.
+    pub fn run(&self, global: &GlobalArgs) -> Result<(), NfpError> {
+        // Open the selected NFP.
+        let nfp = global.open()?;
+
+        let mem_type = self
+            .mem_type
+            .unwrap_or_else(|| MemoryType::default_for_island(self.island));
+        let device_name = nfp.name().to_string();
+        let mut virt_term = VirtualTerminal::new(nfp, self.island, mem_type, self.address);
+        let mut prev_holder: Option<Rfpc> = None;
+
+        // Use an atomic flag to handle ctrl+c termination
+        let running = Arc::new(AtomicBool::new(true));
+        let r = running.clone();
+
+        println!(
+            "Polling virtual terminal on device {} \
+            in island {} memory \
+            at address {}.\n \
+            Press ctrl+C to escape.",
+            device_name, self.island, self.address
+        );
+
+        // Handle ctrl+c to gracefully exit
+        ctrlc::set_handler(move || {
+            println!("\n\nKeyboard interrupt received (ctrl+C). Exiting.");
+            r.store(false, Ordering::SeqCst); // Set running to false
+        })
+        .expect("Error setting Ctrl-C handler");
+
+        // Main loop
+        while running.load(Ordering::SeqCst) {
+            let cur_holder: Option<Rfpc> = virt_term.holder()?;
+
+            if prev_holder != cur_holder {
+                if let Some(prev) = &prev_holder {
+                    // Print closing footer for previous holder
+                    println!("</{}>", prev);
+                }
+
+                if let Some(current) = &cur_holder {
+                    // Print opening header for current holder
+                    println!("<{}>", current);
+                }
+
+                prev_holder = cur_holder.clone();
+            }
+
+            if cur_holder.is_none() {
+                // Virtual terminal not held, nothing to do.
+                thread::sleep(Duration::from_millis(100)); // Polling interval
+                continue;
+            }
+
+            if virt_term.data_available()? != 0 {
+                print!("{}", virt_term.read_string()?);
+            }
+
+            thread::sleep(Duration::from_millis(100)); // Polling interval
+        }
+
+        Ok(())
+    }
+}
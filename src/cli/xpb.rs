@@ -0,0 +1,520 @@
+#![allow(dead_code)]
+
+use clap::{ArgAction, Args, Subcommand};
+use clap_num::maybe_hex;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::cli::global::GlobalArgs;
+use crate::libs::common::{duration_parser, hex_parser};
+use crate::libs::cpp_bus::CppIsland;
+use crate::libs::error::NfpError;
+use crate::libs::output_format::{
+    words_to_bytes, write_region, write_registers, GroupWidth, OutputArgs, OutputFormat,
+    RegisterValue,
+};
+use crate::libs::watch::{ctrlc_running_flag, watch_words};
+use crate::libs::xpb_bus::XpbBar;
+use crate::libs::xpb_regs::{reg_desc, FieldAssignment, XpbRegister};
+use crate::libs::xpb_snapshot::{snapshot_range, snapshot_registers, XpbSnapshot, XpbSnapshotDiff};
+
+/// Commands of `nfp xpb`.
+#[derive(Subcommand, Debug)]
+pub enum XpbCommand {
+    /// Read registers by address.
+    Read {
+        #[arg(short = 'i', long = "island", required = true)]
+        island: CppIsland,
+
+        #[arg(short = 'a', long = "address", required = true, value_parser = maybe_hex::<u32>)]
+        address: u32,
+
+        #[arg(short = 'l', long = "length", default_value_t = 1, value_parser = maybe_hex::<u64>)]
+        length: u64,
+
+        #[arg(short = 'x', long = "xpbm", action = ArgAction::SetTrue)]
+        xpbm: bool,
+
+        #[command(flatten)]
+        output: OutputArgs,
+
+        /// Read `length` registers every INTERVAL, e.g. `500ms` or `2s`, and
+        /// print the ones that change until Ctrl-C is pressed.
+        #[arg(long = "watch", value_name = "INTERVAL", value_parser = duration_parser)]
+        watch: Option<Duration>,
+    },
+
+    /// Write registers by address.
+    Write {
+        #[arg(short = 'i', long = "island", required = true)]
+        island: CppIsland,
+
+        #[arg(short = 'a', long = "address", required = true, value_parser = maybe_hex::<u32>)]
+        address: u32,
+
+        #[arg(short = 'v', long = "value", required = true, action = ArgAction::Append, num_args = 1.., value_parser = hex_parser)]
+        values: Vec<u32>,
+
+        #[arg(short = 'x', long = "xpbm", action = ArgAction::SetTrue)]
+        xpbm: bool,
+    },
+
+    /// Read or write registers by name.
+    Reg {
+        /// Register to access, e.g. `rfpc0.cl0.grp0.PAControl`, or a block
+        /// instance such as `rfpc0.cl0.grp0` to read all its registers.
+        #[arg(value_name = "NAME")]
+        name: String,
+
+        /// Set a field of the register with a read-modify-write, e.g.
+        /// `trace_en=1`. May be given more than once.
+        #[arg(long = "field", value_name = "NAME=VALUE", action = ArgAction::Append, conflicts_with = "values")]
+        fields: Vec<FieldAssignment>,
+
+        /// Value to write to the register.
+        #[arg(short = 'v', long = "value", action = ArgAction::Append, value_parser = hex_parser)]
+        values: Vec<u32>,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+
+    /// Save the registers of blocks or XPB address ranges to a file.
+    Snapshot(SnapshotArgs),
+
+    /// Compare two snapshots, or a snapshot with the device.
+    Diff(DiffArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct SnapshotArgs {
+    /// Island to read. May be given more than once.
+    #[arg(short = 'i', long = "island", required = true, action = ArgAction::Append)]
+    islands: Vec<CppIsland>,
+
+    /// Block or block instance of the register description to read, e.g.
+    /// `pa` or `cl0.grp0`. May be given more than once.
+    #[arg(short = 'b', long = "block", action = ArgAction::Append, required_unless_present = "ranges")]
+    blocks: Vec<String>,
+
+    /// XPB address range START:END to read. May be given more than once.
+    #[arg(short = 'r', long = "range", value_name = "START:END", action = ArgAction::Append, value_parser = parse_xpb_range)]
+    ranges: Vec<(u32, u32)>,
+
+    /// Read the address ranges through the XPB master.
+    #[arg(short = 'x', long = "xpbm", action = ArgAction::SetTrue)]
+    xpbm: bool,
+
+    /// File to save the snapshot to.
+    #[arg(short = 'o', long = "output", required = true)]
+    output: PathBuf,
+}
+
+#[derive(Args, Debug)]
+pub struct DiffArgs {
+    /// Snapshot to compare.
+    before: PathBuf,
+
+    /// Snapshot to compare with. Reads the registers of `before` from the
+    /// device if not given.
+    after: Option<PathBuf>,
+}
+
+/// Arguments of `nfp-xpb`, which accesses registers by address or by name
+/// unless a subcommand is given.
+#[derive(Args, Debug)]
+pub struct XpbArgs {
+    /// Access the registers with explicit commands through an explicit BAR
+    /// instead of through an expansion BAR.
+    #[arg(long = "explicit", global = true, action = ArgAction::SetTrue)]
+    explicit: bool,
+
+    #[command(subcommand)]
+    command: Option<XpbCommand>,
+
+    #[arg(short = 'i', long = "island", required_unless_present = "reg")]
+    island: Option<CppIsland>,
+
+    #[arg(short = 'a', long = "address", required_unless_present = "reg", value_parser = maybe_hex::<u32>)]
+    address: Option<u32>,
+
+    /// Register to access by name, e.g. `rfpc0.cl0.grp0.PAControl`, or a
+    /// block instance such as `rfpc0.cl0.grp0` to read all its registers.
+    #[arg(long = "reg", value_name = "NAME", conflicts_with_all = ["island", "address", "xpbm", "watch"])]
+    reg: Option<String>,
+
+    /// Set a field of the register with a read-modify-write, e.g.
+    /// `trace_en=1`. May be given more than once.
+    #[arg(long = "field", value_name = "NAME=VALUE", action = ArgAction::Append, requires = "reg", conflicts_with = "values")]
+    fields: Vec<FieldAssignment>,
+
+    #[arg(short = 'l', long = "length", default_value_t = 1, value_parser = maybe_hex::<u64>)]
+    length: u64,
+
+    #[arg(short = 'v', long = "value", action = ArgAction::Append, num_args = 1.., value_parser = hex_parser)]
+    values: Vec<u32>,
+
+    #[arg(short = 'x', long = "xpbm", action = ArgAction::SetTrue)]
+    xpbm: bool,
+
+    #[command(flatten)]
+    output: OutputArgs,
+
+    /// Read `length` registers every INTERVAL, e.g. `500ms` or `2s`, and
+    /// print the ones that change until Ctrl-C is pressed.
+    #[arg(long = "watch", value_name = "INTERVAL", value_parser = duration_parser, conflicts_with = "values")]
+    watch: Option<Duration>,
+}
+
+impl XpbArgs {
+    /// Run the subcommand, or the access selected by the flags, on the NFP
+    /// selected by `global`.
+    pub fn run(self, global: &GlobalArgs) -> Result<(), NfpError> {
+        let command = if let Some(command) = self.command {
+            command
+        } else if let Some(name) = self.reg {
+            XpbCommand::Reg {
+                name,
+                fields: self.fields,
+                values: self.values,
+                output: self.output,
+            }
+        } else {
+            let (Some(island), Some(address)) = (self.island, self.address) else {
+                return Err(NfpError::InvalidArgument(
+                    "--island and --address are required without --reg".to_string(),
+                ));
+            };
+
+            if self.values.is_empty() {
+                XpbCommand::Read {
+                    island,
+                    address,
+                    length: self.length,
+                    xpbm: self.xpbm,
+                    output: self.output,
+                    watch: self.watch,
+                }
+            } else {
+                XpbCommand::Write {
+                    island,
+                    address,
+                    values: self.values,
+                    xpbm: self.xpbm,
+                }
+            }
+        };
+
+        command.run(global, self.explicit)
+    }
+}
+
+/// Parse an XPB address range given as `START:END`.
+fn parse_xpb_range(s: &str) -> Result<(u32, u32), String> {
+    let (start, end) = s
+        .split_once(':')
+        .ok_or_else(|| format!("Invalid range {}, expected START:END", s))?;
+    let start = maybe_hex::<u32>(start.trim())?;
+    let end = maybe_hex::<u32>(end.trim())?;
+    Ok((start, end))
+}
+
+impl XpbCommand {
+    /// Run the command on the NFP selected by `global`, with explicit
+    /// commands if `explicit` is set.
+    pub fn run(self, global: &GlobalArgs, explicit: bool) -> Result<(), NfpError> {
+        match self {
+            XpbCommand::Snapshot(args) => run_snapshot(global, explicit, &args),
+            XpbCommand::Diff(args) => run_diff(global, explicit, &args),
+            XpbCommand::Reg {
+                name,
+                fields,
+                values,
+                output,
+            } => {
+                let mut xpb_bar = XpbBar::new(&global.open()?, explicit)?;
+                run_reg(&mut xpb_bar, &name, &fields, &values, output.format)
+            }
+            XpbCommand::Read {
+                island,
+                address,
+                length,
+                xpbm,
+                output,
+                watch,
+            } => {
+                // Take an explicit or expansion BAR of the PCIe device.
+                let mut xpb_bar = XpbBar::new(&global.open()?, explicit)?;
+
+                if let Some(interval) = watch {
+                    // Poll the registers over Xpb bus.
+                    let mut read_registers = || xpb_bar.read(&island, address, length, xpbm);
+                    let running = ctrlc_running_flag()?;
+                    return watch_words(
+                        &mut std::io::stdout().lock(),
+                        interval,
+                        address as u64,
+                        &mut read_registers,
+                        None,
+                        &running,
+                    );
+                }
+
+                // Read over Xpb bus.
+                let read_words = xpb_bar.read(&island, address, length, xpbm)?;
+                if output.format == OutputFormat::Text {
+                    for value in read_words {
+                        println!("0x{:08x}", value);
+                    }
+                    Ok(())
+                } else {
+                    write_region(
+                        &mut std::io::stdout().lock(),
+                        output.format,
+                        output.width,
+                        address as u64,
+                        &words_to_bytes(&read_words),
+                        None,
+                    )
+                }
+            }
+            XpbCommand::Write {
+                island,
+                address,
+                values,
+                xpbm,
+            } => {
+                // Write over Xpb bus.
+                let mut xpb_bar = XpbBar::new(&global.open()?, explicit)?;
+                xpb_bar.write(&island, address, values, xpbm)
+            }
+        }
+    }
+}
+
+/// Save registers to a snapshot file.
+fn run_snapshot(global: &GlobalArgs, explicit: bool, args: &SnapshotArgs) -> Result<(), NfpError> {
+    let nfp = global.open()?;
+    let mut xpb_bar = XpbBar::new(&nfp, explicit)?;
+
+    let mut entries = Vec::new();
+    for island in &args.islands {
+        for block in &args.blocks {
+            let registers = reg_desc().lookup(&format!("{}.{}", island, block))?;
+            entries.extend(snapshot_registers(&mut xpb_bar, &registers)?);
+        }
+        for (start, end) in &args.ranges {
+            entries.extend(snapshot_range(
+                &mut xpb_bar,
+                *island,
+                *start,
+                *end,
+                args.xpbm,
+            )?);
+        }
+    }
+
+    let snapshot = XpbSnapshot::new(nfp.name(), entries);
+    snapshot.save(&args.output)?;
+    println!(
+        "Saved {} registers to {}",
+        snapshot.entries.len(),
+        args.output.display()
+    );
+
+    Ok(())
+}
+
+/// Compare snapshots, or a snapshot with the device.
+fn run_diff(global: &GlobalArgs, explicit: bool, args: &DiffArgs) -> Result<(), NfpError> {
+    let before = XpbSnapshot::load(&args.before)?;
+    let (after, after_name) = match &args.after {
+        Some(path) => (XpbSnapshot::load(path)?, path.display().to_string()),
+        None => {
+            let nfp = global.open()?;
+            let mut xpb_bar = XpbBar::new(&nfp, explicit)?;
+            (
+                before.reread(&mut xpb_bar, nfp.name())?,
+                nfp.name().to_string(),
+            )
+        }
+    };
+
+    let diffs = before.diff(&after);
+    for diff in &diffs {
+        match diff {
+            XpbSnapshotDiff::Changed { entry, value } => {
+                println!(
+                    "{}: 0x{:08x} -> 0x{:08x}",
+                    entry.label(),
+                    entry.value,
+                    value
+                );
+                if let Some(desc) = entry.desc() {
+                    for ((field, old), (_, new)) in desc
+                        .decode(entry.value)
+                        .into_iter()
+                        .zip(desc.decode(*value))
+                    {
+                        if old != new {
+                            println!("    {} {:#x} -> {:#x}", field.name, old, new);
+                        }
+                    }
+                }
+            }
+            XpbSnapshotDiff::Removed(entry) => {
+                println!("{}: only in {}", entry.label(), args.before.display())
+            }
+            XpbSnapshotDiff::Added(entry) => {
+                println!("{}: only in {}", entry.label(), after_name)
+            }
+        }
+    }
+
+    if diffs.is_empty() {
+        println!("No differences in {} registers", before.entries.len());
+    } else {
+        println!(
+            "{} of {} registers differ",
+            diffs.len(),
+            before.entries.len()
+        );
+    }
+
+    Ok(())
+}
+
+/// Return the single register a write by name accesses.
+fn single_register<'a>(
+    name: &str,
+    registers: &'a [XpbRegister<'a>],
+) -> Result<&'a XpbRegister<'a>, NfpError> {
+    match registers {
+        [register] => Ok(register),
+        _ => Err(NfpError::InvalidArgument(format!(
+            "{} names {} registers, writes need a single register",
+            name,
+            registers.len()
+        ))),
+    }
+}
+
+/// Print a register and its decoded fields.
+fn print_register(
+    format: OutputFormat,
+    register: &XpbRegister,
+    value: Option<u32>,
+) -> Result<(), NfpError> {
+    let Some(value) = value else {
+        if format == OutputFormat::Text {
+            println!(
+                "{} (0x{:08x}) is write-only",
+                register.name, register.address
+            );
+        }
+        return Ok(());
+    };
+
+    if format != OutputFormat::Text {
+        let mut values = vec![RegisterValue {
+            name: register.name.clone(),
+            value: value as u64,
+            width: GroupWidth::W32,
+            symbol: None,
+        }];
+        values.extend(
+            register
+                .desc
+                .decode(value)
+                .into_iter()
+                .map(|(field, field_value)| RegisterValue {
+                    name: format!("{}.{}", register.name, field.name),
+                    value: field_value as u64,
+                    width: GroupWidth::W32,
+                    symbol: None,
+                }),
+        );
+        return write_registers(&mut std::io::stdout().lock(), format, &values);
+    }
+
+    println!(
+        "{} (0x{:08x}) = 0x{:08x}",
+        register.name, register.address, value
+    );
+    let name_width = register
+        .desc
+        .fields
+        .iter()
+        .map(|field| field.name.len())
+        .max()
+        .unwrap_or(0);
+    for (field, field_value) in register.desc.decode(value) {
+        println!(
+            "    {:<name_width$} {:<7} {:<3} = {:#x}",
+            field.name,
+            field.bits(),
+            register.desc.field_access(field),
+            field_value,
+            name_width = name_width
+        );
+    }
+
+    Ok(())
+}
+
+/// Read or write registers by name.
+fn run_reg(
+    xpb_bar: &mut XpbBar,
+    name: &str,
+    fields: &[FieldAssignment],
+    values: &[u32],
+    format: OutputFormat,
+) -> Result<(), NfpError> {
+    let registers = reg_desc().lookup(name)?;
+
+    if !values.is_empty() || !fields.is_empty() {
+        let register = single_register(name, &registers)?;
+        if !register.desc.access.writable() {
+            return Err(NfpError::InvalidArgument(format!(
+                "Register {} is read-only",
+                register.name
+            )));
+        }
+
+        let value = if !fields.is_empty() {
+            // Read-modify-write, starting from the reset value if the
+            // register cannot be read.
+            let current = if register.desc.access.readable() {
+                xpb_bar.read(&register.island, register.address, 1, register.xpbm)?[0]
+            } else {
+                register.desc.reset
+            };
+            register.desc.apply_fields(current, fields)?
+        } else if let [value] = values[..] {
+            value
+        } else {
+            return Err(NfpError::InvalidArgument(format!(
+                "Register {} takes a single value",
+                register.name
+            )));
+        };
+
+        return xpb_bar.write(
+            &register.island,
+            register.address,
+            vec![value],
+            register.xpbm,
+        );
+    }
+
+    for register in &registers {
+        let value = if register.desc.access.readable() {
+            Some(xpb_bar.read(&register.island, register.address, 1, register.xpbm)?[0])
+        } else {
+            None
+        };
+        print_register(format, register, value)?;
+    }
+
+    Ok(())
+}
@@ -1,14 +1,50 @@
+pub mod cli {
+    pub mod config;
+    pub mod cpp;
+    pub mod exit;
+    pub mod gdb;
+    pub mod global;
+    pub mod list;
+    pub mod mem;
+    pub mod memtest;
+    pub mod rfpc;
+    pub mod rtsym;
+    pub mod server;
+    pub mod virt_term;
+    pub mod xpb;
+}
+
 pub mod libs {
+    pub mod bus_trace;
+    pub mod capi;
+    pub mod chip_desc;
     pub mod common;
+    pub mod cpp_batch;
     pub mod cpp_bus;
+    pub mod device_backend;
+    pub mod device_enum;
+    pub mod elf_symbols;
+    pub mod error;
     pub mod expansion_bar;
     pub mod explicit_bar;
     pub mod gdb_server_stub;
     pub mod mem_access;
+    pub mod mem_test;
+    pub mod nfp;
+    pub mod output_format;
     pub mod performance_analyzer;
+    #[cfg(feature = "python")]
+    pub mod python;
+    pub mod remote;
     pub mod rfpc;
     pub mod rfpc_debugger;
     pub mod rfpc_trace;
+    pub mod rtsym;
+    pub mod sim_debug_module;
+    pub mod sim_device;
     pub mod virtual_terminal;
+    pub mod watch;
     pub mod xpb_bus;
+    pub mod xpb_regs;
+    pub mod xpb_snapshot;
 }
@@ -0,0 +1,1104 @@
+#![allow(dead_code)]
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, LineWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::libs::common::hex_bytes;
+use crate::libs::cpp_bus::CppTarget;
+use crate::libs::device_backend::{BarWindow, DeviceBackend};
+use crate::libs::error::NfpError;
+use crate::libs::expansion_bar::MapType;
+use crate::libs::explicit_bar::NUM_EXPL_BARS;
+use crate::libs::sim_device::{
+    decode_exp_bar_cfg, decode_expl_bar_cfg, exp_bar_csr_addr, expl_bar_csr_addr, is_write_command,
+    window_address, CppCommand,
+};
+
+// Version of the trace file format.
+const BUS_TRACE_VERSION: u32 = 1;
+// Size of the PCIe configuration space mirrored by the recorder.
+const CONFIG_SPACE_SIZE: usize = 4096;
+
+/// Expansion BAR `exp_bar` of physical BAR `phys_bar`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BarId {
+    pub phys_bar: u8,
+    pub exp_bar: u8,
+}
+
+/// A single device access in a bus trace.
+///
+/// BAR window accesses carry the CPP command they make, decoded from the BAR
+/// CSRs written before them. An access through an explicit BAR is the read
+/// of the explicit trigger window; its `data` is what the read returned, the
+/// data of the command itself passes through the PCIe SRAM. Accesses that
+/// failed carry their `error`; a failed read records zeros for the bytes it
+/// was to read.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BusAccess {
+    /// Read of the PCIe configuration space.
+    ConfigRead {
+        offset: u64,
+        #[serde(with = "hex_bytes")]
+        data: Vec<u8>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// Write of the PCIe configuration space outside the BAR CSRs.
+    ConfigWrite {
+        offset: u64,
+        #[serde(with = "hex_bytes")]
+        data: Vec<u8>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// Write of the CSRs of an expansion BAR, with the mapping they set up.
+    ExpBarConfig {
+        offset: u64,
+        bar: BarId,
+        map_type: MapType,
+        command: CppCommand,
+        #[serde(with = "hex_bytes")]
+        data: Vec<u8>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// Write of the CSRs of an explicit BAR, with the command they set up.
+    ExplBarConfig {
+        offset: u64,
+        expl_bar: u32,
+        command: CppCommand,
+        length_words: u64,
+        #[serde(with = "hex_bytes")]
+        data: Vec<u8>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    LockExpBar {
+        bar: BarId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+        /// The BAR was locked by someone else.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        contended: bool,
+    },
+    UnlockExpBar {
+        bar: BarId,
+    },
+    LockExplBar {
+        expl_bar: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+        /// The BAR was locked by someone else.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        contended: bool,
+    },
+    UnlockExplBar {
+        expl_bar: u32,
+    },
+    MapExpBar {
+        bar: BarId,
+        size: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// Read of an expansion BAR window. The command is `None` if the BAR is
+    /// set up with a reserved map type.
+    BarRead {
+        bar: BarId,
+        offset: u64,
+        explicit: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        command: Option<CppCommand>,
+        #[serde(with = "hex_bytes")]
+        data: Vec<u8>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// Write of an expansion BAR window. The command is `None` if the BAR is
+    /// set up with a reserved map type.
+    BarWrite {
+        bar: BarId,
+        offset: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        command: Option<CppCommand>,
+        #[serde(with = "hex_bytes")]
+        data: Vec<u8>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// BAR window access reading XPB registers.
+    XpbRead {
+        bar: BarId,
+        offset: u64,
+        explicit: bool,
+        island: u8,
+        xpbm: bool,
+        address: u32,
+        #[serde(with = "hex_bytes")]
+        data: Vec<u8>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// BAR window access writing XPB registers.
+    XpbWrite {
+        bar: BarId,
+        offset: u64,
+        explicit: bool,
+        island: u8,
+        xpbm: bool,
+        address: u32,
+        #[serde(with = "hex_bytes")]
+        data: Vec<u8>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+/// Device operation recorded by a trace entry, without its result.
+#[derive(Debug, PartialEq, Eq)]
+enum BusOp<'a> {
+    ConfigRead {
+        offset: u64,
+        length: usize,
+    },
+    ConfigWrite {
+        offset: u64,
+        data: &'a [u8],
+    },
+    LockExpBar(BarId),
+    UnlockExpBar(BarId),
+    LockExplBar(u32),
+    UnlockExplBar(u32),
+    MapExpBar(BarId),
+    BarRead {
+        bar: BarId,
+        offset: u64,
+        length: usize,
+    },
+    BarWrite {
+        bar: BarId,
+        offset: u64,
+        data: &'a [u8],
+    },
+}
+
+impl BusAccess {
+    fn op(&self) -> BusOp<'_> {
+        match self {
+            BusAccess::ConfigRead { offset, data, .. } => BusOp::ConfigRead {
+                offset: *offset,
+                length: data.len(),
+            },
+            BusAccess::ConfigWrite { offset, data, .. }
+            | BusAccess::ExpBarConfig { offset, data, .. }
+            | BusAccess::ExplBarConfig { offset, data, .. } => BusOp::ConfigWrite {
+                offset: *offset,
+                data,
+            },
+            BusAccess::LockExpBar { bar, .. } => BusOp::LockExpBar(*bar),
+            BusAccess::UnlockExpBar { bar } => BusOp::UnlockExpBar(*bar),
+            BusAccess::LockExplBar { expl_bar, .. } => BusOp::LockExplBar(*expl_bar),
+            BusAccess::UnlockExplBar { expl_bar } => BusOp::UnlockExplBar(*expl_bar),
+            BusAccess::MapExpBar { bar, .. } => BusOp::MapExpBar(*bar),
+            BusAccess::BarRead {
+                bar, offset, data, ..
+            }
+            | BusAccess::XpbRead {
+                bar, offset, data, ..
+            } => BusOp::BarRead {
+                bar: *bar,
+                offset: *offset,
+                length: data.len(),
+            },
+            // Explicit XPB writes are made by reading the trigger window.
+            BusAccess::XpbWrite {
+                bar,
+                offset,
+                explicit: true,
+                data,
+                ..
+            } => BusOp::BarRead {
+                bar: *bar,
+                offset: *offset,
+                length: data.len(),
+            },
+            BusAccess::BarWrite {
+                bar, offset, data, ..
+            }
+            | BusAccess::XpbWrite {
+                bar, offset, data, ..
+            } => BusOp::BarWrite {
+                bar: *bar,
+                offset: *offset,
+                data,
+            },
+        }
+    }
+
+    /// Error the access failed with, if any.
+    fn error(&self) -> Option<&str> {
+        match self {
+            BusAccess::ConfigRead { error, .. }
+            | BusAccess::ConfigWrite { error, .. }
+            | BusAccess::ExpBarConfig { error, .. }
+            | BusAccess::ExplBarConfig { error, .. }
+            | BusAccess::LockExpBar { error, .. }
+            | BusAccess::LockExplBar { error, .. }
+            | BusAccess::MapExpBar { error, .. }
+            | BusAccess::BarRead { error, .. }
+            | BusAccess::BarWrite { error, .. }
+            | BusAccess::XpbRead { error, .. }
+            | BusAccess::XpbWrite { error, .. } => error.as_deref(),
+            BusAccess::UnlockExpBar { .. } | BusAccess::UnlockExplBar { .. } => None,
+        }
+    }
+
+    /// Kind of the error the access failed with.
+    fn error_kind(&self) -> io::ErrorKind {
+        match self {
+            BusAccess::LockExpBar {
+                contended: true, ..
+            }
+            | BusAccess::LockExplBar {
+                contended: true, ..
+            } => io::ErrorKind::WouldBlock,
+            _ => io::ErrorKind::Other,
+        }
+    }
+
+    /// Data read or written by the access.
+    fn data(&self) -> &[u8] {
+        match self {
+            BusAccess::ConfigRead { data, .. }
+            | BusAccess::ConfigWrite { data, .. }
+            | BusAccess::ExpBarConfig { data, .. }
+            | BusAccess::ExplBarConfig { data, .. }
+            | BusAccess::BarRead { data, .. }
+            | BusAccess::BarWrite { data, .. }
+            | BusAccess::XpbRead { data, .. }
+            | BusAccess::XpbWrite { data, .. } => data,
+            _ => &[],
+        }
+    }
+}
+
+/// A device access and the time it was made at.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BusTraceEntry {
+    /// Time of the access, in nanoseconds since the recording started.
+    pub time_ns: u64,
+    #[serde(flatten)]
+    pub access: BusAccess,
+}
+
+/// First line of a trace file.
+#[derive(Serialize, Deserialize)]
+struct BusTraceHeader {
+    version: u32,
+    device: String,
+    time: u64,
+}
+
+/// Device accesses recorded by a `RecordingBackend`.
+///
+/// Trace files hold one JSON object per line: a header naming the device,
+/// followed by the accesses in the order they were made.
+#[derive(Clone, Debug)]
+pub struct BusTrace {
+    /// Device the trace was recorded from.
+    pub device: String,
+    /// Time the recording started at, in seconds since the Unix epoch.
+    pub time: u64,
+    pub entries: Vec<BusTraceEntry>,
+}
+
+impl BusTrace {
+    /// Load a trace recorded by a `RecordingBackend`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::Io` if the file cannot be read, or
+    /// `NfpError::InvalidArgument` if it is not a trace of a supported
+    /// version.
+    pub fn load(path: &Path) -> Result<Self, NfpError> {
+        let io_err = |e| NfpError::io(format!("Failed to read {}", path.display()), e);
+        let file = File::open(path).map_err(io_err)?;
+        let mut lines = BufReader::new(file).lines();
+        let invalid = |line: usize, e: serde_json::Error| {
+            NfpError::InvalidArgument(format!(
+                "Invalid bus trace {} line {}: {}",
+                path.display(),
+                line,
+                e
+            ))
+        };
+
+        let header_line = lines
+            .next()
+            .transpose()
+            .map_err(io_err)?
+            .unwrap_or_default();
+        let header: BusTraceHeader =
+            serde_json::from_str(&header_line).map_err(|e| invalid(1, e))?;
+        if header.version != BUS_TRACE_VERSION {
+            return Err(NfpError::InvalidArgument(format!(
+                "Bus trace {} has unsupported version {}",
+                path.display(),
+                header.version
+            )));
+        }
+
+        let mut entries = Vec::new();
+        for (index, line) in lines.enumerate() {
+            let line = line.map_err(io_err)?;
+            if !line.trim().is_empty() {
+                entries.push(serde_json::from_str(&line).map_err(|e| invalid(index + 2, e))?);
+            }
+        }
+
+        Ok(BusTrace {
+            device: header.device,
+            time: header.time,
+            entries,
+        })
+    }
+}
+
+/// Trace file shared by a `RecordingBackend` and its BAR windows.
+struct Recorder {
+    state: Mutex<RecorderState>,
+    start: Instant,
+}
+
+struct RecorderState {
+    writer: LineWriter<File>,
+    // Mirror of the PCIe configuration space, to decode the BAR CSRs.
+    config: Vec<u8>,
+    // Set once writing the trace failed, so the failure is reported once.
+    failed: bool,
+}
+
+impl RecorderState {
+    fn record(&mut self, start: Instant, access: BusAccess) {
+        let entry = BusTraceEntry {
+            time_ns: start.elapsed().as_nanos() as u64,
+            access,
+        };
+        let result = serde_json::to_writer(&mut self.writer, &entry)
+            .map_err(io::Error::other)
+            .and_then(|_| writeln!(self.writer));
+        if let Err(e) = result {
+            if !self.failed {
+                eprintln!("Warning: failed to write bus trace: {}", e);
+                self.failed = true;
+            }
+        }
+    }
+
+    fn update_config(&mut self, offset: u64, data: &[u8]) {
+        let start = (offset as usize).min(CONFIG_SPACE_SIZE);
+        let end = (offset as usize + data.len()).min(CONFIG_SPACE_SIZE);
+        self.config[start..end].copy_from_slice(&data[..end - start]);
+    }
+
+    fn config_word(&self, offset: u32) -> u32 {
+        let offset = offset as usize;
+        u32::from_le_bytes(self.config[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn expl_bar_cfg(&self, expl_bar_index: u32) -> [u32; 4] {
+        let csr_addr = expl_bar_csr_addr(expl_bar_index);
+        [0, 4, 8, 12].map(|word| self.config_word(csr_addr + word))
+    }
+
+    /// Describe a write of `data` to the configuration space, decoding the
+    /// BAR CSRs it sets up.
+    fn config_write_access(&self, offset: u64, data: &[u8], error: Option<String>) -> BusAccess {
+        let data = data.to_vec();
+        let exp_bar_csrs = exp_bar_csr_addr(0, 0) as u64..expl_bar_csr_addr(0) as u64;
+        let expl_bar_csrs = expl_bar_csr_addr(0) as u64..expl_bar_csr_addr(NUM_EXPL_BARS) as u64;
+
+        if exp_bar_csrs.contains(&offset) {
+            let csr_offset = offset - exp_bar_csrs.start;
+            let csr_size = (exp_bar_csr_addr(0, 1) - exp_bar_csr_addr(0, 0)) as u64;
+            let phys_bar_size = (exp_bar_csr_addr(1, 0) - exp_bar_csr_addr(0, 0)) as u64;
+            let bar = BarId {
+                phys_bar: (csr_offset / phys_bar_size) as u8,
+                exp_bar: (csr_offset % phys_bar_size / csr_size) as u8,
+            };
+            let csr_addr = exp_bar_csr_addr(bar.phys_bar, bar.exp_bar);
+            match decode_exp_bar_cfg(self.config_word(csr_addr), self.config_word(csr_addr + 4)) {
+                Some((map_type, command)) => BusAccess::ExpBarConfig {
+                    offset,
+                    bar,
+                    map_type,
+                    command,
+                    data,
+                    error,
+                },
+                // Reserved map types set up no mapping to decode.
+                None => BusAccess::ConfigWrite {
+                    offset,
+                    data,
+                    error,
+                },
+            }
+        } else if expl_bar_csrs.contains(&offset) {
+            let csr_size = (expl_bar_csr_addr(1) - expl_bar_csr_addr(0)) as u64;
+            let expl_bar = ((offset - expl_bar_csrs.start) / csr_size) as u32;
+            let (command, length_words) = decode_expl_bar_cfg(self.expl_bar_cfg(expl_bar));
+            BusAccess::ExplBarConfig {
+                offset,
+                expl_bar,
+                command,
+                length_words,
+                data,
+                error,
+            }
+        } else {
+            BusAccess::ConfigWrite {
+                offset,
+                data,
+                error,
+            }
+        }
+    }
+
+    /// Describe an access to byte `offset` of the window of `bar`, decoding
+    /// the CPP command it makes.
+    fn bar_access(
+        &self,
+        bar: BarId,
+        window_size: u64,
+        offset: u64,
+        write: bool,
+        data: Vec<u8>,
+        error: Option<String>,
+    ) -> BusAccess {
+        let csr_addr = exp_bar_csr_addr(bar.phys_bar, bar.exp_bar);
+        let (explicit, command) =
+            match decode_exp_bar_cfg(self.config_word(csr_addr), self.config_word(csr_addr + 4)) {
+                None if write => {
+                    return BusAccess::BarWrite {
+                        bar,
+                        offset,
+                        command: None,
+                        data,
+                        error,
+                    }
+                }
+                None => {
+                    return BusAccess::BarRead {
+                        bar,
+                        offset,
+                        explicit: false,
+                        command: None,
+                        data,
+                        error,
+                    }
+                }
+                Some((MapType::Explicit, _)) => {
+                    let expl_bar_size = window_size / NUM_EXPL_BARS as u64;
+                    let expl_bar_index = (offset / expl_bar_size) as u32;
+                    let (mut command, _) = decode_expl_bar_cfg(self.expl_bar_cfg(expl_bar_index));
+                    command.address =
+                        window_address(command.address, expl_bar_size, offset % expl_bar_size);
+                    (true, command)
+                }
+                Some((_, mut command)) => {
+                    command.address = window_address(command.address, window_size, offset);
+                    (false, command)
+                }
+            };
+
+        let write = if explicit {
+            is_write_command(command.target, command.action)
+        } else {
+            write
+        };
+        if command.target == CppTarget::ct().id() {
+            let island = ((command.address >> 24) & 0x7F) as u8;
+            let xpbm = command.address & (1 << 31) != 0;
+            let address = command.address as u32 & 0x00FFFFFF;
+            return if write {
+                BusAccess::XpbWrite {
+                    bar,
+                    offset,
+                    explicit,
+                    island,
+                    xpbm,
+                    address,
+                    data,
+                    error,
+                }
+            } else {
+                BusAccess::XpbRead {
+                    bar,
+                    offset,
+                    explicit,
+                    island,
+                    xpbm,
+                    address,
+                    data,
+                    error,
+                }
+            };
+        }
+
+        if write && !explicit {
+            BusAccess::BarWrite {
+                bar,
+                offset,
+                command: Some(command),
+                data,
+                error,
+            }
+        } else {
+            BusAccess::BarRead {
+                bar,
+                offset,
+                explicit,
+                command: Some(command),
+                data,
+                error,
+            }
+        }
+    }
+}
+
+/// Device backend recording every access made through it to a trace file.
+///
+/// Wraps another backend and logs its configuration space accesses, BAR
+/// locking and mapping, and BAR window reads and writes, decoding the
+/// expansion and explicit BAR configurations they use. Accesses are
+/// serialized while recording, so the trace holds them in the order the
+/// device saw them. A `ReplayBackend` plays the trace back.
+pub struct RecordingBackend {
+    backend: Arc<dyn DeviceBackend>,
+    recorder: Arc<Recorder>,
+}
+
+impl RecordingBackend {
+    /// Record the accesses made through `backend` to a new trace file at
+    /// `path`.
+    pub fn create(backend: Arc<dyn DeviceBackend>, path: &Path) -> Result<Self, NfpError> {
+        let io_err = |e| NfpError::io(format!("Failed to write {}", path.display()), e);
+        let mut writer = LineWriter::new(File::create(path).map_err(io_err)?);
+        let header = BusTraceHeader {
+            version: BUS_TRACE_VERSION,
+            device: backend.name().to_string(),
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs()),
+        };
+        serde_json::to_writer(&mut writer, &header).map_err(|e| io_err(io::Error::other(e)))?;
+        writeln!(writer).map_err(io_err)?;
+
+        Ok(RecordingBackend {
+            backend,
+            recorder: Arc::new(Recorder {
+                state: Mutex::new(RecorderState {
+                    writer,
+                    config: vec![0; CONFIG_SPACE_SIZE],
+                    failed: false,
+                }),
+                start: Instant::now(),
+            }),
+        })
+    }
+}
+
+impl DeviceBackend for RecordingBackend {
+    fn name(&self) -> &str {
+        self.backend.name()
+    }
+
+    fn config_read(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let mut state = self.recorder.state.lock().unwrap();
+        let result = self.backend.config_read(offset, buf);
+        if result.is_ok() {
+            state.update_config(offset, buf);
+        }
+        let access = BusAccess::ConfigRead {
+            offset,
+            data: buf.to_vec(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        };
+        state.record(self.recorder.start, access);
+        result
+    }
+
+    fn config_write(&self, offset: u64, data: &[u8]) -> io::Result<()> {
+        let mut state = self.recorder.state.lock().unwrap();
+        let result = self.backend.config_write(offset, data);
+        if result.is_ok() {
+            state.update_config(offset, data);
+        }
+        let access =
+            state.config_write_access(offset, data, result.as_ref().err().map(|e| e.to_string()));
+        state.record(self.recorder.start, access);
+        result
+    }
+
+    fn lock_exp_bar(&self, phys_bar: u8, exp_bar: u8) -> io::Result<()> {
+        let mut state = self.recorder.state.lock().unwrap();
+        let result = self.backend.lock_exp_bar(phys_bar, exp_bar);
+        let access = BusAccess::LockExpBar {
+            bar: BarId { phys_bar, exp_bar },
+            error: result.as_ref().err().map(|e| e.to_string()),
+            contended: result
+                .as_ref()
+                .is_err_and(|e| e.kind() == io::ErrorKind::WouldBlock),
+        };
+        state.record(self.recorder.start, access);
+        result
+    }
+
+    fn unlock_exp_bar(&self, phys_bar: u8, exp_bar: u8) {
+        let mut state = self.recorder.state.lock().unwrap();
+        self.backend.unlock_exp_bar(phys_bar, exp_bar);
+        let access = BusAccess::UnlockExpBar {
+            bar: BarId { phys_bar, exp_bar },
+        };
+        state.record(self.recorder.start, access);
+    }
+
+    fn lock_expl_bar(&self, expl_bar_index: u32) -> io::Result<()> {
+        let mut state = self.recorder.state.lock().unwrap();
+        let result = self.backend.lock_expl_bar(expl_bar_index);
+        let access = BusAccess::LockExplBar {
+            expl_bar: expl_bar_index,
+            error: result.as_ref().err().map(|e| e.to_string()),
+            contended: result
+                .as_ref()
+                .is_err_and(|e| e.kind() == io::ErrorKind::WouldBlock),
+        };
+        state.record(self.recorder.start, access);
+        result
+    }
+
+    fn unlock_expl_bar(&self, expl_bar_index: u32) {
+        let mut state = self.recorder.state.lock().unwrap();
+        self.backend.unlock_expl_bar(expl_bar_index);
+        let access = BusAccess::UnlockExplBar {
+            expl_bar: expl_bar_index,
+        };
+        state.record(self.recorder.start, access);
+    }
+
+    fn map_exp_bar(&self, phys_bar: u8, exp_bar: u8) -> io::Result<Box<dyn BarWindow>> {
+        let mut state = self.recorder.state.lock().unwrap();
+        let bar = BarId { phys_bar, exp_bar };
+        let result = self.backend.map_exp_bar(phys_bar, exp_bar);
+        let access = BusAccess::MapExpBar {
+            bar,
+            size: result.as_ref().map_or(0, |window| window.size()),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        };
+        state.record(self.recorder.start, access);
+
+        let window = result?;
+        Ok(Box::new(RecordingWindow {
+            window,
+            recorder: Arc::clone(&self.recorder),
+            bar,
+        }))
+    }
+}
+
+/// Expansion BAR window of a `RecordingBackend`.
+struct RecordingWindow {
+    window: Box<dyn BarWindow>,
+    recorder: Arc<Recorder>,
+    bar: BarId,
+}
+
+impl BarWindow for RecordingWindow {
+    fn size(&self) -> u64 {
+        self.window.size()
+    }
+
+    fn read(&self, offset: u64, length: u64) -> io::Result<Vec<u8>> {
+        let mut state = self.recorder.state.lock().unwrap();
+        let result = self.window.read(offset, length);
+        let access = state.bar_access(
+            self.bar,
+            self.window.size(),
+            offset,
+            false,
+            match &result {
+                Ok(read_bytes) => read_bytes.clone(),
+                Err(_) => vec![0; length as usize],
+            },
+            result.as_ref().err().map(|e| e.to_string()),
+        );
+        state.record(self.recorder.start, access);
+        result
+    }
+
+    fn write(&mut self, write_bytes: &[u8], offset: u64) -> io::Result<()> {
+        let mut state = self.recorder.state.lock().unwrap();
+        let result = self.window.write(write_bytes, offset);
+        let access = state.bar_access(
+            self.bar,
+            self.window.size(),
+            offset,
+            true,
+            write_bytes.to_vec(),
+            result.as_ref().err().map(|e| e.to_string()),
+        );
+        state.record(self.recorder.start, access);
+        result
+    }
+}
+
+/// Position of a `ReplayBackend` in its trace.
+struct ReplayState {
+    entries: VecDeque<BusTraceEntry>,
+    // Index of the next entry in the trace.
+    next: usize,
+}
+
+impl ReplayState {
+    /// Consume the next entry of the trace, which must record `op`.
+    fn replay(&mut self, op: BusOp) -> io::Result<BusAccess> {
+        let traced = match self.entries.front() {
+            Some(entry) if entry.access.op() == op => None,
+            Some(entry) => Some(format!("{:?}", entry.access.op())),
+            None => Some("end of trace".to_string()),
+        };
+        if let Some(traced) = traced {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Replay diverged from the bus trace at entry {}: made {:?}, traced {}",
+                    self.next, op, traced
+                ),
+            ));
+        }
+
+        self.next += 1;
+        let access = self.entries.pop_front().unwrap().access;
+        match access.error() {
+            Some(error) => Err(io::Error::new(access.error_kind(), error.to_string())),
+            None => Ok(access),
+        }
+    }
+}
+
+/// Fake device playing back a trace recorded by a `RecordingBackend`.
+///
+/// Every access must be the next one in the trace; reads return the data
+/// recorded for them. This reproduces a recorded session deterministically,
+/// e.g. in a unit test running `CppBus`, `xpb_bus` or `rfpc_debugger` code
+/// against a trace taken on a customer card. Configuration space accesses
+/// and BAR window accesses that diverge from the trace fail with an
+/// `io::ErrorKind::InvalidData` error, and accesses that failed while
+/// recording fail again with the recorded error.
+///
+/// Accesses from several threads are only reproduced if the threads make
+/// them in the recorded order.
+pub struct ReplayBackend {
+    device: String,
+    state: Arc<Mutex<ReplayState>>,
+}
+
+impl ReplayBackend {
+    pub fn new(trace: BusTrace) -> Self {
+        ReplayBackend {
+            device: trace.device,
+            state: Arc::new(Mutex::new(ReplayState {
+                entries: trace.entries.into(),
+                next: 0,
+            })),
+        }
+    }
+
+    /// Load the trace file at `path` for playback.
+    pub fn open(path: &Path) -> Result<Self, NfpError> {
+        Ok(ReplayBackend::new(BusTrace::load(path)?))
+    }
+
+    /// Number of traced accesses not played back yet. Zero once the code
+    /// under test has made every access of the trace.
+    pub fn remaining(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+}
+
+impl DeviceBackend for ReplayBackend {
+    fn name(&self) -> &str {
+        &self.device
+    }
+
+    fn config_read(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let op = BusOp::ConfigRead {
+            offset,
+            length: buf.len(),
+        };
+        let access = self.state.lock().unwrap().replay(op)?;
+        buf.copy_from_slice(access.data());
+        Ok(())
+    }
+
+    fn config_write(&self, offset: u64, data: &[u8]) -> io::Result<()> {
+        let op = BusOp::ConfigWrite { offset, data };
+        self.state.lock().unwrap().replay(op).map(|_| ())
+    }
+
+    fn lock_exp_bar(&self, phys_bar: u8, exp_bar: u8) -> io::Result<()> {
+        let op = BusOp::LockExpBar(BarId { phys_bar, exp_bar });
+        self.state.lock().unwrap().replay(op).map(|_| ())
+    }
+
+    fn unlock_exp_bar(&self, phys_bar: u8, exp_bar: u8) {
+        // Unlocking cannot fail, a diverging unlock is left in the trace
+        // for `remaining` to report.
+        let op = BusOp::UnlockExpBar(BarId { phys_bar, exp_bar });
+        let _ = self.state.lock().unwrap().replay(op);
+    }
+
+    fn lock_expl_bar(&self, expl_bar_index: u32) -> io::Result<()> {
+        let op = BusOp::LockExplBar(expl_bar_index);
+        self.state.lock().unwrap().replay(op).map(|_| ())
+    }
+
+    fn unlock_expl_bar(&self, expl_bar_index: u32) {
+        let op = BusOp::UnlockExplBar(expl_bar_index);
+        let _ = self.state.lock().unwrap().replay(op);
+    }
+
+    fn map_exp_bar(&self, phys_bar: u8, exp_bar: u8) -> io::Result<Box<dyn BarWindow>> {
+        let bar = BarId { phys_bar, exp_bar };
+        let access = self.state.lock().unwrap().replay(BusOp::MapExpBar(bar))?;
+        let BusAccess::MapExpBar { size, .. } = access else {
+            unreachable!();
+        };
+        Ok(Box::new(ReplayWindow {
+            state: Arc::clone(&self.state),
+            bar,
+            size,
+        }))
+    }
+}
+
+/// Expansion BAR window of a `ReplayBackend`.
+struct ReplayWindow {
+    state: Arc<Mutex<ReplayState>>,
+    bar: BarId,
+    size: u64,
+}
+
+impl BarWindow for ReplayWindow {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read(&self, offset: u64, length: u64) -> io::Result<Vec<u8>> {
+        let op = BusOp::BarRead {
+            bar: self.bar,
+            offset,
+            length: length as usize,
+        };
+        let access = self.state.lock().unwrap().replay(op)?;
+        Ok(access.data().to_vec())
+    }
+
+    fn write(&mut self, write_bytes: &[u8], offset: u64) -> io::Result<()> {
+        let op = BusOp::BarWrite {
+            bar: self.bar,
+            offset,
+            data: write_bytes,
+        };
+        self.state.lock().unwrap().replay(op).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::chip_desc::IslandKind;
+    use crate::libs::cpp_bus::{CppBus, CppIsland, CppLength};
+    use crate::libs::nfp::Nfp;
+    use crate::libs::sim_device::SimDevice;
+    use crate::libs::xpb_bus::{xpb_read, xpb_write};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// Write and read back memory over the CPP bus and an XPB register.
+    fn session(nfp: &Nfp, values: [u32; 2]) -> Result<(Vec<u32>, Vec<u32>), NfpError> {
+        let island = CppIsland::first_of_kind(IslandKind::Emu);
+        let mut exp_bar = nfp.exp_bar()?;
+
+        let mut cpp_bus = CppBus::new(&mut exp_bar);
+        cpp_bus.write(
+            island,
+            CppTarget::mem(),
+            1,
+            0,
+            CppLength::Len64,
+            0x40,
+            values.to_vec(),
+        )?;
+        let words = cpp_bus.read(island, CppTarget::mem(), 0, 0, CppLength::Len64, 0x40, 2)?;
+
+        xpb_write(&mut exp_bar, &island, 0x100, vec![values[0]], false)?;
+        let registers = xpb_read(&mut exp_bar, &island, 0x100, 1, false)?;
+        Ok((words, registers))
+    }
+
+    #[test]
+    fn recorded_session_replays() {
+        let path = std::env::temp_dir().join(format!("nfp-bus-trace-{}", std::process::id()));
+
+        let recorder = RecordingBackend::create(Arc::new(SimDevice::new()), &path).unwrap();
+        let nfp = Nfp::new(Arc::new(recorder)).unwrap();
+        let recorded = session(&nfp, [0x1234, 0x5678]).unwrap();
+        drop(nfp);
+        assert_eq!(recorded, (vec![0x1234, 0x5678], vec![0x1234]));
+
+        let trace = BusTrace::load(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(trace.device, SimDevice::new().name());
+
+        // The same session gets the recorded data back.
+        let replay = Arc::new(ReplayBackend::new(trace.clone()));
+        let nfp = Nfp::new(replay.clone()).unwrap();
+        assert_eq!(session(&nfp, [0x1234, 0x5678]).unwrap(), recorded);
+        drop(nfp);
+        assert_eq!(replay.remaining(), 0);
+
+        // A session writing other values diverges from the trace.
+        let nfp = Nfp::new(Arc::new(ReplayBackend::new(trace))).unwrap();
+        assert!(matches!(
+            session(&nfp, [0x1234, 0]),
+            Err(NfpError::Io { .. })
+        ));
+    }
+
+    /// Backend whose BAR windows fail every access while `fail` is set, like
+    /// a remote device whose connection dropped.
+    struct FailingBackend {
+        sim: SimDevice,
+        fail: Arc<AtomicBool>,
+    }
+
+    struct FailingWindow {
+        window: Box<dyn BarWindow>,
+        fail: Arc<AtomicBool>,
+    }
+
+    impl FailingWindow {
+        fn check(&self) -> io::Result<()> {
+            if self.fail.load(Ordering::SeqCst) {
+                return Err(io::Error::other("connection lost"));
+            }
+            Ok(())
+        }
+    }
+
+    impl BarWindow for FailingWindow {
+        fn size(&self) -> u64 {
+            self.window.size()
+        }
+
+        fn read(&self, offset: u64, length: u64) -> io::Result<Vec<u8>> {
+            self.check()?;
+            self.window.read(offset, length)
+        }
+
+        fn write(&mut self, write_bytes: &[u8], offset: u64) -> io::Result<()> {
+            self.check()?;
+            self.window.write(write_bytes, offset)
+        }
+    }
+
+    impl DeviceBackend for FailingBackend {
+        fn name(&self) -> &str {
+            self.sim.name()
+        }
+
+        fn config_read(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+            self.sim.config_read(offset, buf)
+        }
+
+        fn config_write(&self, offset: u64, data: &[u8]) -> io::Result<()> {
+            self.sim.config_write(offset, data)
+        }
+
+        fn lock_exp_bar(&self, phys_bar: u8, exp_bar: u8) -> io::Result<()> {
+            self.sim.lock_exp_bar(phys_bar, exp_bar)
+        }
+
+        fn unlock_exp_bar(&self, phys_bar: u8, exp_bar: u8) {
+            self.sim.unlock_exp_bar(phys_bar, exp_bar)
+        }
+
+        fn lock_expl_bar(&self, expl_bar_index: u32) -> io::Result<()> {
+            self.sim.lock_expl_bar(expl_bar_index)
+        }
+
+        fn unlock_expl_bar(&self, expl_bar_index: u32) {
+            self.sim.unlock_expl_bar(expl_bar_index)
+        }
+
+        fn map_exp_bar(&self, phys_bar: u8, exp_bar: u8) -> io::Result<Box<dyn BarWindow>> {
+            Ok(Box::new(FailingWindow {
+                window: self.sim.map_exp_bar(phys_bar, exp_bar)?,
+                fail: Arc::clone(&self.fail),
+            }))
+        }
+    }
+
+    #[test]
+    fn failed_bar_accesses_replay() {
+        let path = std::env::temp_dir().join(format!("nfp-bus-trace-fail-{}", std::process::id()));
+        let fail = Arc::new(AtomicBool::new(false));
+        let backend = FailingBackend {
+            sim: SimDevice::new(),
+            fail: Arc::clone(&fail),
+        };
+
+        let recorder = RecordingBackend::create(Arc::new(backend), &path).unwrap();
+        let nfp = Nfp::new(Arc::new(recorder)).unwrap();
+        let island = CppIsland::first_of_kind(IslandKind::Emu);
+        let session = |nfp: &Nfp, fail: &AtomicBool| {
+            let mut exp_bar = nfp.exp_bar().unwrap();
+            xpb_write(&mut exp_bar, &island, 0x100, vec![1], false).unwrap();
+            fail.store(true, Ordering::SeqCst);
+            let read = xpb_read(&mut exp_bar, &island, 0x100, 1, false);
+            let write = xpb_write(&mut exp_bar, &island, 0x100, vec![2], false);
+            fail.store(false, Ordering::SeqCst);
+            (read, write)
+        };
+        let (read, write) = session(&nfp, &fail);
+        assert!(matches!(read, Err(NfpError::Io { .. })));
+        assert!(matches!(write, Err(NfpError::Io { .. })));
+        drop(nfp);
+
+        let trace = BusTrace::load(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let errors: Vec<_> = trace
+            .entries
+            .iter()
+            .filter_map(|entry| entry.access.error())
+            .collect();
+        assert_eq!(errors, ["connection lost", "connection lost"]);
+
+        // The replayed session fails at the same accesses and runs to the
+        // end of the trace.
+        let replay = Arc::new(ReplayBackend::new(trace));
+        let nfp = Nfp::new(replay.clone()).unwrap();
+        let (read, write) = session(&nfp, &AtomicBool::new(false));
+        assert!(matches!(read, Err(NfpError::Io { .. })));
+        assert!(matches!(write, Err(NfpError::Io { .. })));
+        drop(nfp);
+        assert_eq!(replay.remaining(), 0);
+    }
+}
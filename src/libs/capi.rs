@@ -0,0 +1,945 @@
+#![allow(dead_code)]
+
+// C API of the access libraries, exported by the cdylib of the crate. The
+// header include/nfp_tools.h is generated from this file by build.rs.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+use std::str::FromStr;
+
+use crate::libs::cpp_bus::{CppBus, CppIsland, CppLength, CppTarget};
+use crate::libs::device_enum::DeviceEnumerator;
+use crate::libs::error::NfpError;
+use crate::libs::expansion_bar::ExpansionBar;
+use crate::libs::mem_access::{mem_read, mem_write, MemoryType, MuMemoryEngine};
+use crate::libs::nfp::{Nfp, PooledBar};
+use crate::libs::rfpc::Rfpc;
+use crate::libs::rfpc_debugger::{
+    rfpc_dbg_halt, rfpc_dbg_read_reg, rfpc_dbg_resume, rfpc_dbg_write_reg,
+};
+use crate::libs::xpb_bus::{xpb_read, xpb_write};
+
+// CPP length field values of `nfp_cpp_read` and `nfp_cpp_write`.
+pub const NFP_CPP_LEN32: u8 = 0;
+pub const NFP_CPP_LEN64: u8 = 1;
+pub const NFP_CPP_NOLEN: u8 = 3;
+
+// Memory types of `nfp_mem_read` and `nfp_mem_write`.
+pub const NFP_MEM_EMEM: u8 = 0;
+pub const NFP_MEM_IMEM: u8 = 1;
+pub const NFP_MEM_CTM: u8 = 2;
+pub const NFP_MEM_CLS: u8 = 3;
+pub const NFP_MEM_PCIE_SRAM: u8 = 4;
+pub const NFP_MEM_ARM: u8 = 5;
+
+// MU memory engines of `nfp_mem_read` and `nfp_mem_write`.
+pub const NFP_MEM_ENGINE_ATOMIC32: u8 = 0;
+pub const NFP_MEM_ENGINE_BULK32: u8 = 1;
+pub const NFP_MEM_ENGINE_BULK64: u8 = 2;
+
+/// Result of a C API call. Each error code matches a variant of the Rust
+/// `NfpError`. The values are part of the ABI and never change.
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum NfpStatus {
+    Ok = 0,
+    /// A device resource, such as an expansion BAR, is held by someone else.
+    LockContention = -1,
+    /// An access to the device backend failed.
+    Io = -2,
+    /// An address or length does not fit where it is used.
+    AddressOutOfRange = -3,
+    /// An argument is not valid, including a NULL pointer.
+    InvalidArgument = -4,
+    /// No NFP matches the requested device.
+    DeviceNotFound = -5,
+    /// A RISC-V debug module abstract command failed.
+    DmCmdErr = -6,
+    /// The device did not reach the expected state in time.
+    Timeout = -7,
+    /// Data read back from the device does not match what was expected.
+    VerifyFailed = -8,
+    /// The operation needs firmware, but none is loaded on the NFP.
+    FirmwareNotLoaded = -9,
+    /// The library hit an internal error. The device should be closed.
+    Panic = -100,
+}
+
+impl From<&NfpError> for NfpStatus {
+    fn from(error: &NfpError) -> Self {
+        match error {
+            NfpError::LockContention(_) => NfpStatus::LockContention,
+            NfpError::Io { .. } => NfpStatus::Io,
+            NfpError::AddressOutOfRange(_) => NfpStatus::AddressOutOfRange,
+            NfpError::InvalidArgument(_) => NfpStatus::InvalidArgument,
+            NfpError::DeviceNotFound(_) => NfpStatus::DeviceNotFound,
+            NfpError::DmCmdErr(_) => NfpStatus::DmCmdErr,
+            NfpError::Timeout(_) => NfpStatus::Timeout,
+            NfpError::VerifyFailed(_) => NfpStatus::VerifyFailed,
+            NfpError::FirmwareNotLoaded(_) => NfpStatus::FirmwareNotLoaded,
+        }
+    }
+}
+
+/// Handle of an opened NFP. Handles may be shared between threads.
+pub struct NfpDevice {
+    nfp: Nfp,
+}
+
+/// Expansion BAR taken from an `NfpDevice` for exclusive use. A BAR must
+/// only be used by one thread at a time.
+pub struct NfpExpBar {
+    bar: PooledBar<ExpansionBar>,
+}
+
+/// RFPC core, by island ID and cluster, group and core number.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct NfpRfpc {
+    pub island: u8,
+    pub cluster: u8,
+    pub group: u8,
+    pub core: u8,
+}
+
+thread_local! {
+    // Message of the last error returned to the calling thread.
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = message);
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// Run the body of a C API function, turning its error or panic into a
+/// status and the last error message.
+fn ffi_call(body: impl FnOnce() -> Result<(), NfpError>) -> NfpStatus {
+    match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Ok(())) => NfpStatus::Ok,
+        Ok(Err(e)) => {
+            set_last_error(e.to_string());
+            NfpStatus::from(&e)
+        }
+        Err(payload) => {
+            set_last_error(format!("internal error: {}", panic_message(&*payload)));
+            NfpStatus::Panic
+        }
+    }
+}
+
+fn non_null<'a, T>(ptr: *const T, name: &str) -> Result<&'a T, NfpError> {
+    // SAFETY: the caller passes a pointer to a live object, or NULL.
+    unsafe { ptr.as_ref() }.ok_or_else(|| NfpError::InvalidArgument(format!("{} is NULL", name)))
+}
+
+fn non_null_mut<'a, T>(ptr: *mut T, name: &str) -> Result<&'a mut T, NfpError> {
+    // SAFETY: the caller passes a pointer to a live object, or NULL.
+    unsafe { ptr.as_mut() }.ok_or_else(|| NfpError::InvalidArgument(format!("{} is NULL", name)))
+}
+
+fn words_in<'a>(words: *const u32, length_words: usize) -> Result<&'a [u32], NfpError> {
+    if length_words == 0 {
+        return Ok(&[]);
+    }
+    non_null(words, "words")?;
+    // SAFETY: the caller passes an array of `length_words` words.
+    Ok(unsafe { slice::from_raw_parts(words, length_words) })
+}
+
+fn words_out<'a>(words: *mut u32, length_words: usize) -> Result<&'a mut [u32], NfpError> {
+    if length_words == 0 {
+        return Ok(&mut []);
+    }
+    non_null_mut(words, "words")?;
+    // SAFETY: the caller passes an array of `length_words` words.
+    Ok(unsafe { slice::from_raw_parts_mut(words, length_words) })
+}
+
+fn c_str<'a>(s: *const c_char, name: &str) -> Result<&'a str, NfpError> {
+    non_null(s, name)?;
+    // SAFETY: the caller passes a NUL-terminated string.
+    unsafe { CStr::from_ptr(s) }
+        .to_str()
+        .map_err(|_| NfpError::InvalidArgument(format!("{} is not valid UTF-8", name)))
+}
+
+fn cpp_length_from_id(id: u8) -> Result<CppLength, NfpError> {
+    match id {
+        NFP_CPP_LEN32 => Ok(CppLength::Len32),
+        NFP_CPP_LEN64 => Ok(CppLength::Len64),
+        NFP_CPP_NOLEN => Ok(CppLength::NoLen),
+        _ => Err(NfpError::InvalidArgument(format!(
+            "Invalid CPP length {}",
+            id
+        ))),
+    }
+}
+
+fn memory_type_from_id(id: u8) -> Result<MemoryType, NfpError> {
+    match id {
+        NFP_MEM_EMEM => Ok(MemoryType::Emem),
+        NFP_MEM_IMEM => Ok(MemoryType::Imem),
+        NFP_MEM_CTM => Ok(MemoryType::Ctm),
+        NFP_MEM_CLS => Ok(MemoryType::Cls),
+        NFP_MEM_PCIE_SRAM => Ok(MemoryType::PcieSram),
+        NFP_MEM_ARM => Ok(MemoryType::Arm),
+        _ => Err(NfpError::InvalidArgument(format!(
+            "Invalid memory type {}",
+            id
+        ))),
+    }
+}
+
+fn engine_from_id(id: u8) -> Result<MuMemoryEngine, NfpError> {
+    match id {
+        NFP_MEM_ENGINE_ATOMIC32 => Ok(MuMemoryEngine::Atomic32),
+        NFP_MEM_ENGINE_BULK32 => Ok(MuMemoryEngine::Bulk32),
+        NFP_MEM_ENGINE_BULK64 => Ok(MuMemoryEngine::Bulk64),
+        _ => Err(NfpError::InvalidArgument(format!(
+            "Invalid MU memory engine {}",
+            id
+        ))),
+    }
+}
+
+impl NfpRfpc {
+    fn rfpc(&self) -> Result<Rfpc, NfpError> {
+        Rfpc::new(
+            CppIsland::from_id(self.island)?,
+            self.cluster,
+            self.group,
+            self.core,
+        )
+    }
+}
+
+fn open_device(
+    pci_bdf: Option<&str>,
+    remote: Option<&str>,
+    sim: bool,
+    device: *mut *mut NfpDevice,
+) -> NfpStatus {
+    ffi_call(|| {
+        let device = non_null_mut(device, "device")?;
+        *device = ptr::null_mut();
+        let pci_bdf = pci_bdf
+            .map(|pci_bdf| DeviceEnumerator::new().select(pci_bdf))
+            .transpose()?;
+        let nfp = Nfp::open(pci_bdf.as_deref(), remote, sim)?;
+        *device = Box::into_raw(Box::new(NfpDevice { nfp }));
+        Ok(())
+    })
+}
+
+/// Return the message of the last error returned to the calling thread.
+///
+/// The string stays valid until the next failing call on the thread.
+#[no_mangle]
+pub extern "C" fn nfp_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| last_error.borrow().as_ptr())
+}
+
+/// Open the NFP at PCIe BDF `pci_bdf`, e.g. "0000:65:00.0", and initialize
+/// its PCIe BARs.
+///
+/// # Safety
+///
+/// `pci_bdf` must be a NUL-terminated string and `device` must point to
+/// writable storage for the handle, which is set to NULL on failure.
+#[no_mangle]
+pub unsafe extern "C" fn nfp_device_open(
+    pci_bdf: *const c_char,
+    device: *mut *mut NfpDevice,
+) -> NfpStatus {
+    match c_str(pci_bdf, "pci_bdf") {
+        Ok(pci_bdf) => open_device(Some(pci_bdf), None, false, device),
+        Err(e) => ffi_call(|| Err(e)),
+    }
+}
+
+/// Open an in-process simulated NFP.
+///
+/// # Safety
+///
+/// See `nfp_device_open`.
+#[no_mangle]
+pub unsafe extern "C" fn nfp_device_open_sim(device: *mut *mut NfpDevice) -> NfpStatus {
+    open_device(None, None, true, device)
+}
+
+/// Open the NFP served by the `nfp-cpp-server` at `addr` ("HOST:PORT"),
+/// authenticating with the token in `NFP_REMOTE_TOKEN`.
+///
+/// # Safety
+///
+/// See `nfp_device_open`.
+#[no_mangle]
+pub unsafe extern "C" fn nfp_device_open_remote(
+    addr: *const c_char,
+    device: *mut *mut NfpDevice,
+) -> NfpStatus {
+    match c_str(addr, "addr") {
+        Ok(addr) => open_device(None, Some(addr), false, device),
+        Err(e) => ffi_call(|| Err(e)),
+    }
+}
+
+/// Close a device handle. BARs taken from it stay valid until freed.
+///
+/// # Safety
+///
+/// `device` must be NULL or a handle returned by an `nfp_device_open`
+/// function that is not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn nfp_device_close(device: *mut NfpDevice) {
+    if !device.is_null() {
+        // SAFETY: the handle was created by `open_device`.
+        let device = unsafe { Box::from_raw(device) };
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(device)));
+    }
+}
+
+/// Take an expansion BAR of `device` for exclusive use. Waits for another
+/// thread to free a BAR if all of them are in use.
+///
+/// # Safety
+///
+/// `device` must be a handle returned by an `nfp_device_open` function and
+/// `bar` must point to writable storage for the BAR, which is set to NULL
+/// on failure.
+#[no_mangle]
+pub unsafe extern "C" fn nfp_exp_bar_alloc(
+    device: *const NfpDevice,
+    bar: *mut *mut NfpExpBar,
+) -> NfpStatus {
+    ffi_call(|| {
+        let bar = non_null_mut(bar, "bar")?;
+        *bar = ptr::null_mut();
+        let device = non_null(device, "device")?;
+        let exp_bar = device.nfp.exp_bar()?;
+        *bar = Box::into_raw(Box::new(NfpExpBar { bar: exp_bar }));
+        Ok(())
+    })
+}
+
+/// Return an expansion BAR to its device.
+///
+/// # Safety
+///
+/// `bar` must be NULL or a BAR returned by `nfp_exp_bar_alloc` that is not
+/// used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn nfp_exp_bar_free(bar: *mut NfpExpBar) {
+    if !bar.is_null() {
+        // SAFETY: the BAR was created by `nfp_exp_bar_alloc`.
+        let bar = unsafe { Box::from_raw(bar) };
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(bar)));
+    }
+}
+
+/// Look up the ID of the island called `name` in the chip description, or
+/// parse an island ID.
+///
+/// # Safety
+///
+/// `name` must be a NUL-terminated string and `id` must point to writable
+/// storage.
+#[no_mangle]
+pub unsafe extern "C" fn nfp_island_id(name: *const c_char, id: *mut u8) -> NfpStatus {
+    ffi_call(|| {
+        let island =
+            CppIsland::from_str(c_str(name, "name")?).map_err(NfpError::InvalidArgument)?;
+        *non_null_mut(id, "id")? = island.id();
+        Ok(())
+    })
+}
+
+/// Look up the ID of the CPP target called `name` in the chip description,
+/// or parse a target ID.
+///
+/// # Safety
+///
+/// See `nfp_island_id`.
+#[no_mangle]
+pub unsafe extern "C" fn nfp_target_id(name: *const c_char, id: *mut u8) -> NfpStatus {
+    ffi_call(|| {
+        let target =
+            CppTarget::from_str(c_str(name, "name")?).map_err(NfpError::InvalidArgument)?;
+        *non_null_mut(id, "id")? = target.id();
+        Ok(())
+    })
+}
+
+/// Read `length_words` 32-bit words from the CPP bus into `words`.
+///
+/// # Parameters
+///
+/// * `cpp_len` - One of the `NFP_CPP_*` length values.
+///
+/// # Safety
+///
+/// `bar` must be a BAR returned by `nfp_exp_bar_alloc` and `words` must
+/// point to `length_words` writable words.
+#[no_mangle]
+pub unsafe extern "C" fn nfp_cpp_read(
+    bar: *mut NfpExpBar,
+    island: u8,
+    target: u8,
+    action: u8,
+    token: u8,
+    cpp_len: u8,
+    address: u64,
+    words: *mut u32,
+    length_words: usize,
+) -> NfpStatus {
+    ffi_call(|| {
+        let bar = non_null_mut(bar, "bar")?;
+        let words = words_out(words, length_words)?;
+        let values = CppBus::new(&mut bar.bar).read(
+            CppIsland::from_id(island)?,
+            CppTarget::from_id(target)?,
+            action,
+            token,
+            cpp_length_from_id(cpp_len)?,
+            address,
+            length_words as u64,
+        )?;
+        words.copy_from_slice(&values);
+        Ok(())
+    })
+}
+
+/// Write `length_words` 32-bit words from `words` to the CPP bus.
+///
+/// # Safety
+///
+/// `bar` must be a BAR returned by `nfp_exp_bar_alloc` and `words` must
+/// point to `length_words` words.
+#[no_mangle]
+pub unsafe extern "C" fn nfp_cpp_write(
+    bar: *mut NfpExpBar,
+    island: u8,
+    target: u8,
+    action: u8,
+    token: u8,
+    cpp_len: u8,
+    address: u64,
+    words: *const u32,
+    length_words: usize,
+) -> NfpStatus {
+    ffi_call(|| {
+        let bar = non_null_mut(bar, "bar")?;
+        let words = words_in(words, length_words)?;
+        CppBus::new(&mut bar.bar).write(
+            CppIsland::from_id(island)?,
+            CppTarget::from_id(target)?,
+            action,
+            token,
+            cpp_length_from_id(cpp_len)?,
+            address,
+            words.to_vec(),
+        )
+    })
+}
+
+/// Read `length_words` XPB registers starting at `address` of `island`, or
+/// of the XPB master of the island if `xpbm` is set.
+///
+/// # Safety
+///
+/// See `nfp_cpp_read`.
+#[no_mangle]
+pub unsafe extern "C" fn nfp_xpb_read(
+    bar: *mut NfpExpBar,
+    island: u8,
+    address: u32,
+    xpbm: bool,
+    words: *mut u32,
+    length_words: usize,
+) -> NfpStatus {
+    ffi_call(|| {
+        let bar = non_null_mut(bar, "bar")?;
+        let words = words_out(words, length_words)?;
+        let island = CppIsland::from_id(island)?;
+        let values = xpb_read(&mut bar.bar, &island, address, length_words as u64, xpbm)?;
+        words.copy_from_slice(&values);
+        Ok(())
+    })
+}
+
+/// Write `length_words` XPB registers starting at `address` of `island`.
+///
+/// # Safety
+///
+/// See `nfp_cpp_write`.
+#[no_mangle]
+pub unsafe extern "C" fn nfp_xpb_write(
+    bar: *mut NfpExpBar,
+    island: u8,
+    address: u32,
+    xpbm: bool,
+    words: *const u32,
+    length_words: usize,
+) -> NfpStatus {
+    ffi_call(|| {
+        let bar = non_null_mut(bar, "bar")?;
+        let words = words_in(words, length_words)?;
+        let island = CppIsland::from_id(island)?;
+        xpb_write(&mut bar.bar, &island, address, words.to_vec(), xpbm)
+    })
+}
+
+/// Read `length_words` 32-bit words at byte `address` of a memory of
+/// `island`.
+///
+/// # Parameters
+///
+/// * `mem_type` - One of the `NFP_MEM_*` memory types.
+/// * `engine` - One of the `NFP_MEM_ENGINE_*` engines, used for MU memories.
+///
+/// # Safety
+///
+/// See `nfp_cpp_read`.
+#[no_mangle]
+pub unsafe extern "C" fn nfp_mem_read(
+    bar: *mut NfpExpBar,
+    island: u8,
+    mem_type: u8,
+    engine: u8,
+    address: u64,
+    words: *mut u32,
+    length_words: usize,
+) -> NfpStatus {
+    ffi_call(|| {
+        let bar = non_null_mut(bar, "bar")?;
+        let words = words_out(words, length_words)?;
+        let values = mem_read(
+            &mut bar.bar,
+            CppIsland::from_id(island)?,
+            memory_type_from_id(mem_type)?,
+            engine_from_id(engine)?,
+            address,
+            length_words as u64,
+        )?;
+        words.copy_from_slice(&values);
+        Ok(())
+    })
+}
+
+/// Write `length_words` 32-bit words at byte `address` of a memory of
+/// `island`.
+///
+/// # Safety
+///
+/// See `nfp_cpp_write`.
+#[no_mangle]
+pub unsafe extern "C" fn nfp_mem_write(
+    bar: *mut NfpExpBar,
+    island: u8,
+    mem_type: u8,
+    engine: u8,
+    address: u64,
+    words: *const u32,
+    length_words: usize,
+) -> NfpStatus {
+    ffi_call(|| {
+        let bar = non_null_mut(bar, "bar")?;
+        let words = words_in(words, length_words)?;
+        mem_write(
+            &mut bar.bar,
+            CppIsland::from_id(island)?,
+            memory_type_from_id(mem_type)?,
+            engine_from_id(engine)?,
+            address,
+            words.to_vec(),
+        )
+    })
+}
+
+/// Halt an RFPC core through its debug module.
+///
+/// # Safety
+///
+/// `device` must be a handle returned by an `nfp_device_open` function and
+/// `rfpc` must point to an `NfpRfpc`.
+#[no_mangle]
+pub unsafe extern "C" fn nfp_rfpc_halt(
+    device: *const NfpDevice,
+    rfpc: *const NfpRfpc,
+) -> NfpStatus {
+    ffi_call(|| {
+        let device = non_null(device, "device")?;
+        let rfpc = non_null(rfpc, "rfpc")?.rfpc()?;
+        let mut expl_bar = device.nfp.expl_bar()?;
+        rfpc_dbg_halt(&mut expl_bar, &rfpc)
+    })
+}
+
+/// Resume a halted RFPC core.
+///
+/// # Safety
+///
+/// See `nfp_rfpc_halt`.
+#[no_mangle]
+pub unsafe extern "C" fn nfp_rfpc_resume(
+    device: *const NfpDevice,
+    rfpc: *const NfpRfpc,
+) -> NfpStatus {
+    ffi_call(|| {
+        let device = non_null(device, "device")?;
+        let rfpc = non_null(rfpc, "rfpc")?.rfpc()?;
+        let mut expl_bar = device.nfp.expl_bar()?;
+        rfpc_dbg_resume(&mut expl_bar, &rfpc)
+    })
+}
+
+/// Read a register of a halted RFPC core.
+///
+/// # Parameters
+///
+/// * `reg_addr` - Debug module register number: 0x1000 + n for GPR xn, or
+///   the CSR number.
+///
+/// # Safety
+///
+/// See `nfp_rfpc_halt`. `value` must point to writable storage.
+#[no_mangle]
+pub unsafe extern "C" fn nfp_rfpc_reg_read(
+    device: *const NfpDevice,
+    rfpc: *const NfpRfpc,
+    reg_addr: u16,
+    value: *mut u64,
+) -> NfpStatus {
+    ffi_call(|| {
+        let device = non_null(device, "device")?;
+        let rfpc = non_null(rfpc, "rfpc")?.rfpc()?;
+        let value = non_null_mut(value, "value")?;
+        let mut expl_bar = device.nfp.expl_bar()?;
+        *value = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr as u64)?;
+        Ok(())
+    })
+}
+
+/// Write a register of a halted RFPC core.
+///
+/// # Safety
+///
+/// See `nfp_rfpc_halt`.
+#[no_mangle]
+pub unsafe extern "C" fn nfp_rfpc_reg_write(
+    device: *const NfpDevice,
+    rfpc: *const NfpRfpc,
+    reg_addr: u16,
+    value: u64,
+) -> NfpStatus {
+    ffi_call(|| {
+        let device = non_null(device, "device")?;
+        let rfpc = non_null(rfpc, "rfpc")?.rfpc()?;
+        let mut expl_bar = device.nfp.expl_bar()?;
+        rfpc_dbg_write_reg(&mut expl_bar, &rfpc, reg_addr as u64, value)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::chip_desc::IslandKind;
+    use crate::libs::error::DmCmdErr;
+
+    fn last_error() -> String {
+        // SAFETY: `nfp_last_error` returns a NUL-terminated string.
+        unsafe { CStr::from_ptr(nfp_last_error()) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn open_sim() -> (*mut NfpDevice, *mut NfpExpBar) {
+        let mut device = ptr::null_mut();
+        let mut bar = ptr::null_mut();
+        unsafe {
+            assert_eq!(nfp_device_open_sim(&mut device), NfpStatus::Ok);
+            assert_eq!(nfp_exp_bar_alloc(device, &mut bar), NfpStatus::Ok);
+        }
+        assert!(!device.is_null() && !bar.is_null());
+        (device, bar)
+    }
+
+    fn close_sim((device, bar): (*mut NfpDevice, *mut NfpExpBar)) {
+        unsafe {
+            nfp_exp_bar_free(bar);
+            nfp_device_close(device);
+        }
+    }
+
+    #[test]
+    fn status_codes_follow_errors() {
+        let cases = [
+            (
+                NfpError::LockContention(String::new()),
+                NfpStatus::LockContention,
+            ),
+            (NfpError::io("", std::io::Error::other("")), NfpStatus::Io),
+            (
+                NfpError::AddressOutOfRange(String::new()),
+                NfpStatus::AddressOutOfRange,
+            ),
+            (
+                NfpError::InvalidArgument(String::new()),
+                NfpStatus::InvalidArgument,
+            ),
+            (
+                NfpError::DeviceNotFound(String::new()),
+                NfpStatus::DeviceNotFound,
+            ),
+            (NfpError::DmCmdErr(DmCmdErr::Busy), NfpStatus::DmCmdErr),
+            (NfpError::Timeout(String::new()), NfpStatus::Timeout),
+            (
+                NfpError::VerifyFailed(String::new()),
+                NfpStatus::VerifyFailed,
+            ),
+            (
+                NfpError::FirmwareNotLoaded(String::new()),
+                NfpStatus::FirmwareNotLoaded,
+            ),
+        ];
+        for (error, status) in cases {
+            assert_eq!(NfpStatus::from(&error), status);
+        }
+
+        assert_eq!(ffi_call(|| panic!("broken")), NfpStatus::Panic);
+        assert_eq!(last_error(), "internal error: broken");
+    }
+
+    #[test]
+    fn cpp_xpb_and_memory_round_trips() {
+        let sim = open_sim();
+        let (_, bar) = sim;
+        let island = CppIsland::first_of_kind(IslandKind::Emu).id();
+        let mem = CppTarget::mem().id();
+        let mut words = [0u32; 4];
+
+        unsafe {
+            let data = [1, 2, 3, 4];
+            assert_eq!(
+                nfp_cpp_write(
+                    bar,
+                    island,
+                    mem,
+                    31,
+                    0,
+                    NFP_CPP_LEN32,
+                    0x100,
+                    data.as_ptr(),
+                    4
+                ),
+                NfpStatus::Ok
+            );
+            assert_eq!(
+                nfp_cpp_read(
+                    bar,
+                    island,
+                    mem,
+                    28,
+                    0,
+                    NFP_CPP_LEN32,
+                    0x100,
+                    words.as_mut_ptr(),
+                    4
+                ),
+                NfpStatus::Ok
+            );
+            assert_eq!(words, data);
+
+            let data = [0x11, 0x22];
+            assert_eq!(
+                nfp_xpb_write(bar, island, 0x1000, false, data.as_ptr(), 2),
+                NfpStatus::Ok
+            );
+            assert_eq!(
+                nfp_xpb_read(bar, island, 0x1000, false, words.as_mut_ptr(), 2),
+                NfpStatus::Ok
+            );
+            assert_eq!(words[..2], data);
+
+            let data = [5, 6, 7, 8];
+            for engine in [NFP_MEM_ENGINE_BULK32, NFP_MEM_ENGINE_BULK64] {
+                assert_eq!(
+                    nfp_mem_write(bar, island, NFP_MEM_EMEM, engine, 0x200, data.as_ptr(), 4),
+                    NfpStatus::Ok
+                );
+                words = [0; 4];
+                assert_eq!(
+                    nfp_mem_read(
+                        bar,
+                        island,
+                        NFP_MEM_EMEM,
+                        engine,
+                        0x200,
+                        words.as_mut_ptr(),
+                        4
+                    ),
+                    NfpStatus::Ok
+                );
+                assert_eq!(words, data);
+            }
+            // The CPP and memory accesses reach the same memory.
+            assert_eq!(
+                nfp_cpp_read(
+                    bar,
+                    island,
+                    mem,
+                    28,
+                    0,
+                    NFP_CPP_LEN32,
+                    0x200,
+                    words.as_mut_ptr(),
+                    4
+                ),
+                NfpStatus::Ok
+            );
+            assert_eq!(words, data);
+
+            // Zero-length accesses need no buffer.
+            assert_eq!(
+                nfp_mem_read(bar, island, NFP_MEM_EMEM, 1, 0, ptr::null_mut(), 0),
+                NfpStatus::Ok
+            );
+        }
+        close_sim(sim);
+    }
+
+    #[test]
+    fn null_pointers_are_invalid_arguments() {
+        let sim = open_sim();
+        let (device, bar) = sim;
+        let mut words = [0u32; 1];
+
+        unsafe {
+            assert_eq!(
+                nfp_device_open_sim(ptr::null_mut()),
+                NfpStatus::InvalidArgument
+            );
+            assert_eq!(last_error(), "invalid argument: device is NULL");
+
+            let mut null_bar = bar;
+            assert_eq!(
+                nfp_exp_bar_alloc(ptr::null(), &mut null_bar),
+                NfpStatus::InvalidArgument
+            );
+            assert!(null_bar.is_null());
+            assert_eq!(
+                nfp_exp_bar_alloc(device, ptr::null_mut()),
+                NfpStatus::InvalidArgument
+            );
+
+            assert_eq!(
+                nfp_cpp_read(ptr::null_mut(), 0, 7, 28, 0, 0, 0, words.as_mut_ptr(), 1),
+                NfpStatus::InvalidArgument
+            );
+            assert!(last_error().contains("bar is NULL"));
+            assert_eq!(
+                nfp_cpp_write(bar, 0, 7, 31, 0, 0, 0, ptr::null(), 1),
+                NfpStatus::InvalidArgument
+            );
+            assert!(last_error().contains("words is NULL"));
+            assert_eq!(
+                nfp_xpb_read(bar, 0, 0, false, ptr::null_mut(), 1),
+                NfpStatus::InvalidArgument
+            );
+            assert_eq!(
+                nfp_mem_write(bar, 0, NFP_MEM_EMEM, 1, 0, ptr::null(), 1),
+                NfpStatus::InvalidArgument
+            );
+            assert_eq!(
+                nfp_island_id(ptr::null(), ptr::null_mut()),
+                NfpStatus::InvalidArgument
+            );
+            assert_eq!(
+                nfp_rfpc_halt(device, ptr::null()),
+                NfpStatus::InvalidArgument
+            );
+            assert!(last_error().contains("rfpc is NULL"));
+
+            // Freeing and closing NULL handles is allowed.
+            nfp_exp_bar_free(ptr::null_mut());
+            nfp_device_close(ptr::null_mut());
+        }
+        close_sim(sim);
+    }
+
+    #[test]
+    fn errors_set_the_last_error() {
+        let sim = open_sim();
+        let (_, bar) = sim;
+        let island = CppIsland::first_of_kind(IslandKind::Emu).id();
+        let mut words = [0u32; 2];
+        let mut id = 0;
+
+        unsafe {
+            let name = CString::new("chip_exec").unwrap();
+            assert_eq!(nfp_island_id(name.as_ptr(), &mut id), NfpStatus::Ok);
+            assert_eq!(id, CppIsland::chip_exec().id());
+            let name = CString::new("mem").unwrap();
+            assert_eq!(nfp_target_id(name.as_ptr(), &mut id), NfpStatus::Ok);
+            assert_eq!(id, CppTarget::mem().id());
+            let name = CString::new("nowhere").unwrap();
+            assert_eq!(
+                nfp_island_id(name.as_ptr(), &mut id),
+                NfpStatus::InvalidArgument
+            );
+            assert!(last_error().contains("nowhere"));
+
+            assert_eq!(
+                nfp_mem_read(bar, island, 9, 1, 0, words.as_mut_ptr(), 2),
+                NfpStatus::InvalidArgument
+            );
+            assert!(last_error().contains("Invalid memory type 9"));
+            assert_eq!(
+                nfp_cpp_read(bar, island, 7, 28, 0, 2, 0, words.as_mut_ptr(), 2),
+                NfpStatus::InvalidArgument
+            );
+            assert!(last_error().contains("Invalid CPP length 2"));
+            assert_eq!(
+                nfp_xpb_read(bar, island, 0xfffffc, false, words.as_mut_ptr(), 2),
+                NfpStatus::AddressOutOfRange
+            );
+            assert!(!last_error().is_empty());
+            assert_eq!(
+                nfp_mem_read(
+                    bar,
+                    island,
+                    NFP_MEM_EMEM,
+                    1,
+                    u64::MAX - 3,
+                    words.as_mut_ptr(),
+                    2
+                ),
+                NfpStatus::AddressOutOfRange
+            );
+
+            // A successful call leaves the message of the last error.
+            let message = last_error();
+            assert_eq!(
+                nfp_xpb_read(bar, island, 0, false, words.as_mut_ptr(), 2),
+                NfpStatus::Ok
+            );
+            assert_eq!(last_error(), message);
+        }
+        close_sim(sim);
+    }
+}
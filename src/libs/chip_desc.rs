@@ -0,0 +1,356 @@
+#![allow(dead_code)]
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::libs::error::NfpError;
+
+/// Environment variable naming a chip description file to use instead of
+/// the bundled one.
+pub const CHIP_DESC_ENV: &str = "NFP_CHIP_DESC";
+
+// Chip description bundled with the tools.
+const BUNDLED_CHIP_DESC: &str = include_str!("../../chips/merlin.toml");
+
+// CPP targets the access libraries address by name.
+const REQUIRED_TARGETS: [&str; 3] = ["mem", "ct", "cls"];
+// Island kinds the tools look up with `CppIsland::first_of_kind`.
+const REQUIRED_ISLAND_KINDS: [IslandKind; 3] =
+    [IslandKind::ChipExec, IslandKind::Emu, IslandKind::Rfpc];
+
+static CHIP_DESC: OnceLock<ChipDesc> = OnceLock::new();
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IslandKind {
+    Local,
+    ChipExec,
+    Pcie,
+    Nbi,
+    Emu,
+    Rfpc,
+    #[serde(other)]
+    Other,
+}
+
+impl fmt::Display for IslandKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IslandKind::Local => write!(f, "local"),
+            IslandKind::ChipExec => write!(f, "chip_exec"),
+            IslandKind::Pcie => write!(f, "pcie"),
+            IslandKind::Nbi => write!(f, "nbi"),
+            IslandKind::Emu => write!(f, "emu"),
+            IslandKind::Rfpc => write!(f, "rfpc"),
+            IslandKind::Other => write!(f, "other"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct IslandDesc {
+    pub name: String,
+    pub id: u8,
+    pub kind: IslandKind,
+    /// XPB base addresses of the functional blocks in the island, by block name.
+    #[serde(default)]
+    pub xpb_blocks: BTreeMap<String, u32>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TargetDesc {
+    pub name: String,
+    pub id: u8,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct MemoryDesc {
+    pub name: String,
+    /// Kind of island holding an instance of the memory.
+    pub island_kind: IslandKind,
+    /// Name of the CPP target used to access the memory, if it can be
+    /// accessed over CPP at all.
+    #[serde(default)]
+    pub target: Option<String>,
+    /// Address of the memory in the address space of its CPP target.
+    #[serde(default)]
+    pub base: u64,
+    /// Size of one instance of the memory in bytes.
+    pub size: u64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RfpcClusterDesc {
+    pub dm_xpb_base: u32,
+    pub group_ctl_xpb_base: u32,
+    /// IMB port of each group in the cluster.
+    pub imb_ports: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RfpcDesc {
+    pub groups_per_cluster: u8,
+    pub cores_per_group: u8,
+    pub group_ctl_stride: u32,
+    pub clusters: Vec<RfpcClusterDesc>,
+}
+
+/// Description of an NFP chip: its islands, CPP targets, memories and the
+/// XPB layout of its functional blocks.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ChipDesc {
+    pub name: String,
+    pub targets: Vec<TargetDesc>,
+    pub islands: Vec<IslandDesc>,
+    #[serde(default)]
+    pub memories: Vec<MemoryDesc>,
+    pub rfpc: RfpcDesc,
+}
+
+/// Compare island and target names ignoring case, '-' and '_', so that
+/// `chipExec`, `chip-exec` and `chip_exec` are the same island.
+pub(crate) fn name_matches(name: &str, wanted: &str) -> bool {
+    let normalize = |s: &str| -> String {
+        s.chars()
+            .filter(|c| *c != '-' && *c != '_')
+            .map(|c| c.to_ascii_lowercase())
+            .collect()
+    };
+    normalize(name) == normalize(wanted)
+}
+
+impl ChipDesc {
+    /// The chip description bundled with the tools.
+    pub fn bundled() -> ChipDesc {
+        ChipDesc::from_toml(BUNDLED_CHIP_DESC).expect("Invalid bundled chip description")
+    }
+
+    /// Parse and validate a chip description in TOML format.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::InvalidArgument` if the description cannot be parsed,
+    /// has duplicate island or target IDs, lacks a chip exec island or one of
+    /// the CPP targets the tools rely on, or if an RFPC cluster does not give
+    /// an IMB port for every group.
+    pub fn from_toml(contents: &str) -> Result<ChipDesc, NfpError> {
+        let chip: ChipDesc = toml::from_str(contents)
+            .map_err(|e| NfpError::InvalidArgument(format!("Invalid chip description: {}", e)))?;
+        chip.validate()?;
+        Ok(chip)
+    }
+
+    /// Load a chip description from a TOML file.
+    pub fn load(path: &Path) -> Result<ChipDesc, NfpError> {
+        let contents = fs::read_to_string(path).map_err(|e| {
+            NfpError::io(
+                format!("Failed to read chip description {}", path.display()),
+                e,
+            )
+        })?;
+        ChipDesc::from_toml(&contents)
+    }
+
+    fn validate(&self) -> Result<(), NfpError> {
+        let invalid = |msg: String| {
+            Err(NfpError::InvalidArgument(format!(
+                "Invalid chip description {}: {}",
+                self.name, msg
+            )))
+        };
+
+        for (index, island) in self.islands.iter().enumerate() {
+            if island.id > 0x7F {
+                return invalid(format!("island {} has ID {} > 127", island.name, island.id));
+            }
+            if self.islands[..index].iter().any(|i| i.id == island.id) {
+                return invalid(format!("duplicate island ID {}", island.id));
+            }
+        }
+
+        for (index, target) in self.targets.iter().enumerate() {
+            if target.id > 0xF {
+                return invalid(format!("target {} has ID {} > 15", target.name, target.id));
+            }
+            if self.targets[..index].iter().any(|t| t.id == target.id) {
+                return invalid(format!("duplicate target ID {}", target.id));
+            }
+        }
+
+        for kind in REQUIRED_ISLAND_KINDS {
+            if self.first_island(kind).is_none() {
+                return invalid(format!("no {} island", kind));
+            }
+        }
+
+        for name in REQUIRED_TARGETS {
+            if self.target_by_name(name).is_none() {
+                return invalid(format!("no \"{}\" target", name));
+            }
+        }
+
+        for memory in &self.memories {
+            if let Some(target) = &memory.target {
+                if self.target_by_name(target).is_none() {
+                    return invalid(format!(
+                        "memory {} uses unknown target {}",
+                        memory.name, target
+                    ));
+                }
+            }
+        }
+
+        if self.rfpc.groups_per_cluster == 0 || self.rfpc.cores_per_group == 0 {
+            return invalid(format!(
+                "RFPC clusters of {} groups of {} cores",
+                self.rfpc.groups_per_cluster, self.rfpc.cores_per_group
+            ));
+        }
+
+        for (index, cluster) in self.rfpc.clusters.iter().enumerate() {
+            if cluster.imb_ports.len() != self.rfpc.groups_per_cluster as usize {
+                return invalid(format!(
+                    "RFPC cluster {} has {} IMB ports for {} groups",
+                    index,
+                    cluster.imb_ports.len(),
+                    self.rfpc.groups_per_cluster
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn island(&self, id: u8) -> Option<&IslandDesc> {
+        self.islands.iter().find(|island| island.id == id)
+    }
+
+    pub fn island_by_name(&self, name: &str) -> Option<&IslandDesc> {
+        self.islands
+            .iter()
+            .find(|island| name_matches(&island.name, name))
+    }
+
+    /// Return the island with the lowest ID of the given kind.
+    pub fn first_island(&self, kind: IslandKind) -> Option<&IslandDesc> {
+        self.islands
+            .iter()
+            .filter(|island| island.kind == kind)
+            .min_by_key(|island| island.id)
+    }
+
+    pub fn target(&self, id: u8) -> Option<&TargetDesc> {
+        self.targets.iter().find(|target| target.id == id)
+    }
+
+    pub fn target_by_name(&self, name: &str) -> Option<&TargetDesc> {
+        self.targets
+            .iter()
+            .find(|target| name_matches(&target.name, name))
+    }
+
+    /// Return the XPB base address of `block` in island `island_id`, if the
+    /// island has such a block.
+    pub fn xpb_block_base(&self, island_id: u8, block: &str) -> Option<u32> {
+        self.island(island_id)?.xpb_blocks.get(block).copied()
+    }
+
+    /// Return the description of memory `name` in island `island_id`, if
+    /// the island holds such a memory.
+    pub fn memory(&self, name: &str, island_id: u8) -> Option<&MemoryDesc> {
+        let kind = self.island(island_id)?.kind;
+        self.memories
+            .iter()
+            .find(|memory| name_matches(&memory.name, name) && memory.island_kind == kind)
+    }
+
+    pub fn rfpc_cluster(&self, cluster: u8) -> Option<&RfpcClusterDesc> {
+        self.rfpc.clusters.get(cluster as usize)
+    }
+}
+
+/// Return the chip description used by the tools.
+///
+/// This is the description installed with `install_chip_desc` or
+/// `install_env_chip_desc`, or the bundled description if none was installed
+/// before the first call.
+pub fn chip_desc() -> &'static ChipDesc {
+    CHIP_DESC.get_or_init(ChipDesc::bundled)
+}
+
+/// Use `chip` as the chip description for the rest of the process.
+///
+/// # Errors
+///
+/// Returns `NfpError::InvalidArgument` if a chip description is already in
+/// use, i.e. `chip_desc` has been called before.
+pub fn install_chip_desc(chip: ChipDesc) -> Result<(), NfpError> {
+    CHIP_DESC
+        .set(chip)
+        .map_err(|_| NfpError::InvalidArgument("A chip description is already in use".to_string()))
+}
+
+/// Load the chip description named by the `NFP_CHIP_DESC` environment
+/// variable, if it is set, and use it for the rest of the process.
+///
+/// # Errors
+///
+/// Returns `NfpError::Io` if the file cannot be read, and
+/// `NfpError::InvalidArgument` if it is not a valid chip description or a
+/// chip description is already in use.
+pub fn install_env_chip_desc() -> Result<(), NfpError> {
+    let Some(path) = std::env::var_os(CHIP_DESC_ENV) else {
+        return Ok(());
+    };
+    let chip = ChipDesc::load(Path::new(&path)).map_err(|e| match e {
+        NfpError::InvalidArgument(msg) => {
+            NfpError::InvalidArgument(format!("{} ({}={:?})", msg, CHIP_DESC_ENV, path))
+        }
+        e => e,
+    })?;
+    install_chip_desc(chip)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_rejects_empty_rfpc_groups() {
+        let no_groups = BUNDLED_CHIP_DESC
+            .replace("groups_per_cluster = 4", "groups_per_cluster = 0")
+            .replace("imb_ports = [4, 4, 7, 7]", "imb_ports = []")
+            .replace("imb_ports = [8, 8, 11, 11]", "imb_ports = []");
+        assert!(matches!(
+            ChipDesc::from_toml(&no_groups),
+            Err(NfpError::InvalidArgument(_))
+        ));
+
+        let no_cores = BUNDLED_CHIP_DESC.replace("cores_per_group = 8", "cores_per_group = 0");
+        assert!(matches!(
+            ChipDesc::from_toml(&no_cores),
+            Err(NfpError::InvalidArgument(_))
+        ));
+
+        assert!(ChipDesc::from_toml(BUNDLED_CHIP_DESC).is_ok());
+    }
+
+    #[test]
+    fn validate_requires_island_kinds() {
+        let no_emu = BUNDLED_CHIP_DESC.replace(r#"kind = "emu" }"#, r#"kind = "other" }"#);
+        assert!(matches!(
+            ChipDesc::from_toml(&no_emu),
+            Err(NfpError::InvalidArgument(_))
+        ));
+
+        let no_rfpc = BUNDLED_CHIP_DESC.replace(r#"kind = "rfpc""#, r#"kind = "other""#);
+        assert!(matches!(
+            ChipDesc::from_toml(&no_rfpc),
+            Err(NfpError::InvalidArgument(_))
+        ));
+    }
+}
@@ -1,74 +1,38 @@
 #![allow(dead_code)]
 
-use std::fs;
 use std::num::ParseIntError;
-use std::path::Path;
-use std::path::PathBuf;
+use std::time::Duration;
 
-/// Validates a PCIe Bus/Device/Function (BDF) identifier for a Merlin NFP device.
+use crate::libs::device_enum::DeviceEnumerator;
+
+/// Validates a PCIe device selector for a Merlin NFP device.
 ///
-/// This function checks if the provided BDF is formatted correctly and corresponds
-/// to a valid PCIe device in the system. If the BDF is missing the domain part,
-/// it automatically adds "0000:" as a prefix. The function also reads the vendor
-/// and device IDs to confirm that the device is a Merlin NFP.
+/// This function resolves the `-Z` argument of the tools to the PCIe
+/// Bus/Device/Function (BDF) identifier of a Merlin NFP in the system. The
+/// selector may be a BDF, an index into the list of NFPs shown by `nfp-list`,
+/// or a card serial number. If a BDF is missing the domain part, "0000:" is
+/// added as a prefix.
 ///
 /// # Parameters
 ///
-/// * `pci_bdf`: A string slice representing the PCIe BDF identifier to validate.
+/// * `pci_bdf`: A string slice holding the BDF, index or serial number to validate.
 ///
 /// # Returns
 ///
 /// Returns `Ok(String)` containing the formatted BDF if it is valid,
-/// or `Err(String)` with an error message if the BDF is invalid or does not correspond
+/// or `Err(String)` with an error message if the selector does not correspond
 /// to a Merlin NFP device.
 ///
 /// # Errors
 ///
 /// The function can return errors for the following reasons:
 /// - The specified PCIe device does not exist.
-/// - Failed to read the vendor or device ID.
-/// - The vendor or device ID does not match that of a Merlin NFP
+/// - The vendor or device ID does not match that of a Merlin NFP.
+/// - No NFP has the given index or serial number.
 pub fn validate_nfp_bdf(pci_bdf: &str) -> Result<String, String> {
-    // If the BDF is missing the domain part, add "0000:" as a prefix
-    let pci_bdf = if pci_bdf.split(':').count() < 3 {
-        format!("0000:{}", pci_bdf)
-    } else {
-        pci_bdf.to_string()
-    };
-
-    // Construct the path to the PCIe device in the sysfs
-    let str_path = format!("/sys/bus/pci/devices/{}", pci_bdf);
-    let base_path = Path::new(&str_path);
-    if !base_path.exists() {
-        return Err(format!("No such PCIe device: {}", pci_bdf));
-    }
-
-    // Read the vendor ID
-    let mut vendor_path = PathBuf::from(base_path);
-    vendor_path.push("vendor");
-    let vendor_id = match fs::read_to_string(&vendor_path) {
-        Ok(contents) => contents,
-        Err(_) => return Err(format!("Failed to read vendor ID for device: {}", pci_bdf)),
-    };
-
-    // Read the device ID
-    let mut device_path = PathBuf::from(base_path);
-    device_path.push("device");
-    let device_id = match fs::read_to_string(&device_path) {
-        Ok(contents) => contents,
-        Err(_) => return Err(format!("Failed to read device ID for device: {}", pci_bdf)),
-    };
-
-    // Validate the vendor and device IDs for a Merlin NFP device
-    if vendor_id.trim() != "0x1da8" || device_id.trim() != "0x7000" {
-        return Err(format!(
-            "PCIe BDF {} does not belong to a Merlin NFP.",
-            pci_bdf
-        ));
-    }
-
-    // If everything is valid, return the formatted PCI BDF
-    Ok(pci_bdf)
+    DeviceEnumerator::new()
+        .select(pci_bdf)
+        .map_err(|e| e.to_string())
 }
 
 /// Splits a 48-bit address into a base address and an offset.
@@ -119,6 +83,40 @@ pub fn hex_parser(s: &str) -> Result<u32, ParseIntError> {
     }
 }
 
+/// Parses a time interval given on the command line.
+///
+/// The interval is a decimal number followed by an optional unit: `ms` for
+/// milliseconds, or `s` for seconds, which is the default. For example
+/// `250ms`, `2s` or `0.5`.
+///
+/// # Parameters
+///
+/// * `s`: A string slice containing the interval to be parsed.
+///
+/// # Returns
+///
+/// Returns `Ok(Duration)` if the parsing is successful, or an error message if
+/// the string is not a valid, non-zero interval.
+pub fn duration_parser(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (number, scale) = if let Some(ms) = s.strip_suffix("ms") {
+        (ms, 0.001)
+    } else {
+        (s.strip_suffix('s').unwrap_or(s), 1.0)
+    };
+
+    let seconds = number
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| format!("Invalid interval {}", s))?
+        * scale;
+    if !seconds.is_finite() || seconds <= 0.0 {
+        return Err(format!("Invalid interval {}", s));
+    }
+
+    Ok(Duration::from_secs_f64(seconds))
+}
+
 /// Aligns a memory transaction to the nearest 64-bit boundary.
 ///
 /// This function takes an address and a length (in words) and aligns the address
@@ -156,3 +154,25 @@ pub fn align_transaction64(address: u64, length_in_words: u64) -> (u64, u64) {
 
     (aligned_address, aligned_length_in_words)
 }
+
+/// Serialize byte strings as hex, e.g. "efbeadde".
+pub(crate) mod hex_bytes {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        serializer.serialize_str(&hex)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        if !hex.is_ascii() || hex.len() % 2 != 0 {
+            return Err(D::Error::custom(format!("Invalid hex bytes {:?}", hex)));
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).map_err(D::Error::custom))
+            .collect()
+    }
+}
@@ -0,0 +1,671 @@
+#![allow(dead_code)]
+
+use bytemuck::cast_slice;
+use std::collections::HashMap;
+
+use crate::libs::cpp_bus::{CppBus, CppIsland, CppLength, CppTarget};
+use crate::libs::error::NfpError;
+use crate::libs::expansion_bar::{ExpansionBar, MapType};
+use crate::libs::mem_access::{mem_cpp_address, word_bytes, MemoryType, MuMemoryEngine};
+use crate::libs::xpb_bus::xpb_cpp_address;
+
+/// Data moved by a `CppTransaction`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CppTransfer {
+    /// Read this many 32-bit words.
+    Read(u64),
+    /// Write these 32-bit words.
+    Write(Vec<u32>),
+}
+
+impl CppTransfer {
+    fn length_words(&self) -> u64 {
+        match self {
+            CppTransfer::Read(length_words) => *length_words,
+            CppTransfer::Write(words) => words.len() as u64,
+        }
+    }
+}
+
+/// A single CPP bus read or write of a `CppBatch`.
+#[derive(Clone, Debug)]
+pub struct CppTransaction {
+    /// Mapping of the expansion BAR the transaction is made through.
+    pub map_type: MapType,
+    pub island: CppIsland,
+    pub target: CppTarget,
+    pub action: u8,
+    pub token: u8,
+    pub cpp_len: CppLength,
+    pub address: u64,
+    pub transfer: CppTransfer,
+}
+
+impl CppTransaction {
+    fn is_write(&self) -> bool {
+        matches!(self.transfer, CppTransfer::Write(_))
+    }
+
+    /// Return the address after the last byte of the transaction. `push`
+    /// only accepts transactions within the CPP address space, but a
+    /// region past it saturates rather than wraps.
+    fn end(&self) -> u64 {
+        self.address
+            .saturating_add(self.transfer.length_words().saturating_mul(4))
+    }
+
+    /// Return whether this transaction must be made after `earlier`.
+    ///
+    /// Reads may be reordered among themselves, and so may writes to
+    /// different addresses of the same memory. A write is kept in order with
+    /// any access to another island or target, whose completion it may
+    /// signal or depend on, and with any access to the same addresses. XPB
+    /// register writes may have side effects on any register, so they are
+    /// kept in order with all accesses.
+    fn must_follow(&self, earlier: &CppTransaction) -> bool {
+        if !self.is_write() && !earlier.is_write() {
+            return false;
+        }
+        if self.island != earlier.island
+            || self.target != earlier.target
+            || self.target == CppTarget::ct()
+        {
+            return true;
+        }
+
+        self.address < earlier.end() && earlier.address < self.end()
+    }
+}
+
+/// Part of a transaction that lies within a single expansion BAR window.
+struct CppChunk {
+    transaction: usize,
+    // Index of the first word of the chunk in the transaction.
+    word: usize,
+    words: usize,
+    // Offset of the chunk into the expansion BAR window.
+    offset: u64,
+}
+
+/// Chunks made through the same expansion BAR configuration.
+struct CppChunkGroup {
+    map_type: MapType,
+    base_addr: u64,
+    cfg: [u32; 2],
+    chunks: Vec<CppChunk>,
+}
+
+/// Queue of CPP bus transactions executed together over a set of expansion
+/// BARs.
+///
+/// Executing a batch splits the transactions into expansion BAR windows and
+/// makes all accesses through the same BAR configuration back to back, so
+/// each configuration is written at most once. Configurations that a BAR
+/// already holds are not written at all. Reads may be reordered among
+/// themselves, but a write is kept in order with every access to another
+/// target or to the same addresses, e.g. a memory write and a following XPB
+/// doorbell read; use `barrier` to order any other transactions.
+#[derive(Clone, Debug, Default)]
+pub struct CppBatch {
+    transactions: Vec<CppTransaction>,
+    // Indices of the transactions that start a new ordered segment.
+    barriers: Vec<usize>,
+}
+
+impl CppBatch {
+    pub fn new() -> Self {
+        CppBatch::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    /// Add a transaction to the batch.
+    ///
+    /// # Returns
+    ///
+    /// The index of the result of the transaction in the results of
+    /// `execute`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::AddressOutOfRange` if the transaction lies outside
+    /// the 48-bit CPP address space. The batch is left unchanged.
+    pub fn push(&mut self, transaction: CppTransaction) -> Result<usize, NfpError> {
+        CppBus::check_cpp_region(transaction.address, transaction.transfer.length_words())?;
+
+        let segment_start = self.barriers.last().copied().unwrap_or(0);
+        if self.transactions[segment_start..]
+            .iter()
+            .any(|earlier| transaction.must_follow(earlier))
+        {
+            self.barrier();
+        }
+
+        self.transactions.push(transaction);
+        Ok(self.transactions.len() - 1)
+    }
+
+    /// Complete all transactions added so far before any added after.
+    pub fn barrier(&mut self) {
+        let len = self.transactions.len();
+        if len != 0 && self.barriers.last() != Some(&len) {
+            self.barriers.push(len);
+        }
+    }
+
+    /// Add a read of `length_words` 32-bit words, as `CppBus::read` through
+    /// a fixed mapping.
+    ///
+    /// # Errors
+    ///
+    /// As `push`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn read(
+        &mut self,
+        island: CppIsland,
+        target: CppTarget,
+        action: u8,
+        token: u8,
+        cpp_len: CppLength,
+        address: u64,
+        length_words: u64,
+    ) -> Result<usize, NfpError> {
+        self.push(CppTransaction {
+            map_type: MapType::Fixed,
+            island,
+            target,
+            action,
+            token,
+            cpp_len,
+            address,
+            transfer: CppTransfer::Read(length_words),
+        })
+    }
+
+    /// Add a write of `write_words`, as `CppBus::write` through a fixed
+    /// mapping.
+    ///
+    /// # Errors
+    ///
+    /// As `push`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn write(
+        &mut self,
+        island: CppIsland,
+        target: CppTarget,
+        action: u8,
+        token: u8,
+        cpp_len: CppLength,
+        address: u64,
+        write_words: Vec<u32>,
+    ) -> Result<usize, NfpError> {
+        self.push(CppTransaction {
+            map_type: MapType::Fixed,
+            island,
+            target,
+            action,
+            token,
+            cpp_len,
+            address,
+            transfer: CppTransfer::Write(write_words),
+        })
+    }
+
+    /// Add a read of `length` consecutive XPB registers, as `xpb_read`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::AddressOutOfRange` if the registers lie beyond 24
+    /// bits of address.
+    pub fn xpb_read(
+        &mut self,
+        island: &CppIsland,
+        address: u32,
+        length: u64,
+        xpbm: bool,
+    ) -> Result<usize, NfpError> {
+        let (tgt_island, xpb_addr) = xpb_cpp_address(island, address, length, xpbm)?;
+        self.push(CppTransaction {
+            map_type: MapType::Bulk,
+            island: tgt_island,
+            target: CppTarget::ct(),
+            action: 0,
+            token: 0,
+            cpp_len: CppLength::Len32,
+            address: xpb_addr,
+            transfer: CppTransfer::Read(length),
+        })
+    }
+
+    /// Add a write of `write_words` to consecutive XPB registers, as
+    /// `xpb_write`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::AddressOutOfRange` if the registers lie beyond 24
+    /// bits of address.
+    pub fn xpb_write(
+        &mut self,
+        island: &CppIsland,
+        address: u32,
+        write_words: Vec<u32>,
+        xpbm: bool,
+    ) -> Result<usize, NfpError> {
+        let (tgt_island, xpb_addr) =
+            xpb_cpp_address(island, address, write_words.len() as u64, xpbm)?;
+        self.push(CppTransaction {
+            map_type: MapType::Bulk,
+            island: tgt_island,
+            target: CppTarget::ct(),
+            action: 0,
+            token: 0,
+            cpp_len: CppLength::Len32,
+            address: xpb_addr,
+            transfer: CppTransfer::Write(write_words),
+        })
+    }
+
+    /// Add a read of `length` 32-bit words of memory, as `mem_read`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::InvalidArgument` if the island holds no such
+    /// memory, or `NfpError::AddressOutOfRange` if the words are outside it.
+    pub fn mem_read(
+        &mut self,
+        cpp_island: CppIsland,
+        mem_type: MemoryType,
+        engine: MuMemoryEngine,
+        address: u64,
+        length: u64,
+    ) -> Result<usize, NfpError> {
+        let (target, cpp_address) =
+            mem_cpp_address(cpp_island, mem_type, address, word_bytes(length)?)?;
+        let ((action, token), _, cpp_len) = mem_type.cpp_commands(engine);
+        self.push(CppTransaction {
+            map_type: mem_type.map_type(),
+            island: cpp_island,
+            target,
+            action,
+            token,
+            cpp_len,
+            address: cpp_address,
+            transfer: CppTransfer::Read(length),
+        })
+    }
+
+    /// Add a write of `values` to memory, as `mem_write`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::InvalidArgument` if the island holds no such
+    /// memory, or `NfpError::AddressOutOfRange` if the words are outside it.
+    pub fn mem_write(
+        &mut self,
+        cpp_island: CppIsland,
+        mem_type: MemoryType,
+        engine: MuMemoryEngine,
+        address: u64,
+        values: Vec<u32>,
+    ) -> Result<usize, NfpError> {
+        let (target, cpp_address) = mem_cpp_address(
+            cpp_island,
+            mem_type,
+            address,
+            word_bytes(values.len() as u64)?,
+        )?;
+        let (_, (action, token), cpp_len) = mem_type.cpp_commands(engine);
+        self.push(CppTransaction {
+            map_type: mem_type.map_type(),
+            island: cpp_island,
+            target,
+            action,
+            token,
+            cpp_len,
+            address: cpp_address,
+            transfer: CppTransfer::Write(values),
+        })
+    }
+
+    /// Split the transactions `first..end` into expansion BAR windows of
+    /// `window_size` bytes and group them by BAR configuration, in the order
+    /// the configurations are first needed.
+    fn group_chunks(
+        &self,
+        first: usize,
+        end: usize,
+        window_size: u64,
+    ) -> Result<Vec<CppChunkGroup>, NfpError> {
+        let mut groups: Vec<CppChunkGroup> = Vec::new();
+        let mut group_index: HashMap<[u32; 2], usize> = HashMap::new();
+
+        for (index, transaction) in self.transactions[first..end].iter().enumerate() {
+            let length_words = transaction.transfer.length_words();
+
+            let mut word = 0;
+            while word < length_words {
+                let address = transaction.address + word * 4;
+                let base_addr = address & !(window_size - 1);
+                let offset = address - base_addr;
+                let words = (length_words - word).min((window_size - offset) / 4);
+                if words == 0 {
+                    return Err(NfpError::AddressOutOfRange(format!(
+                        "Unaligned CPP access at {:#x} straddles an expansion BAR window boundary",
+                        address
+                    )));
+                }
+
+                let cfg = ExpansionBar::cfg_words(
+                    transaction.map_type,
+                    transaction.island.id(),
+                    transaction.target.id(),
+                    transaction.action,
+                    transaction.token,
+                    base_addr,
+                    transaction.cpp_len.id(),
+                )?;
+                let group = *group_index.entry(cfg).or_insert_with(|| {
+                    groups.push(CppChunkGroup {
+                        map_type: transaction.map_type,
+                        base_addr,
+                        cfg,
+                        chunks: Vec::new(),
+                    });
+                    groups.len() - 1
+                });
+                groups[group].chunks.push(CppChunk {
+                    transaction: first + index,
+                    word: word as usize,
+                    words: words as usize,
+                    offset,
+                });
+
+                word += words;
+            }
+        }
+
+        Ok(groups)
+    }
+
+    /// Choose the expansion BAR each group of chunks is made through.
+    ///
+    /// Groups whose configuration a BAR already holds use that BAR. The
+    /// other groups are spread over the remaining BARs, so that as many
+    /// configurations as possible stay set for later batches.
+    fn assign_bars(groups: &[CppChunkGroup], exp_bars: &[&mut ExpansionBar]) -> Vec<usize> {
+        let mut assigned: Vec<Option<usize>> = vec![None; groups.len()];
+        let mut reserved = vec![false; exp_bars.len()];
+
+        for (group, assigned) in groups.iter().zip(assigned.iter_mut()) {
+            if let Some(bar) = (0..exp_bars.len())
+                .find(|&bar| !reserved[bar] && exp_bars[bar].cached_cfg() == group.cfg)
+            {
+                *assigned = Some(bar);
+                reserved[bar] = true;
+            }
+        }
+
+        let free: Vec<usize> = (0..exp_bars.len()).filter(|&bar| !reserved[bar]).collect();
+        let mut next = 0;
+        assigned
+            .into_iter()
+            .map(|assigned| {
+                assigned.unwrap_or_else(|| {
+                    next += 1;
+                    if free.is_empty() {
+                        (next - 1) % exp_bars.len()
+                    } else {
+                        free[(next - 1) % free.len()]
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// Execute the batch over `exp_bars`.
+    ///
+    /// # Returns
+    ///
+    /// The result of each transaction, in the order the transactions were
+    /// added: the words read for reads, `None` for writes.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::InvalidArgument` if no expansion BAR is given, or
+    /// the error of the first failed access. Transactions before the failed
+    /// access may have been executed.
+    pub fn execute(
+        &self,
+        exp_bars: &mut [&mut ExpansionBar],
+    ) -> Result<Vec<Option<Vec<u32>>>, NfpError> {
+        let Some(bar_size) = exp_bars.iter().map(|exp_bar| exp_bar.exp_bar_size).min() else {
+            return Err(NfpError::InvalidArgument(
+                "A CPP batch needs at least one expansion BAR".to_string(),
+            ));
+        };
+        // Windows are aligned to the largest power of two in the BAR.
+        let window_size = 1u64 << (63 - bar_size.leading_zeros());
+
+        let mut results: Vec<Option<Vec<u32>>> = vec![None; self.transactions.len()];
+
+        let mut segment_starts = vec![0];
+        segment_starts.extend(&self.barriers);
+        let segment_ends = segment_starts[1..]
+            .iter()
+            .copied()
+            .chain([self.transactions.len()]);
+
+        for (first, end) in segment_starts.iter().copied().zip(segment_ends) {
+            let groups = self.group_chunks(first, end, window_size)?;
+            let bars = Self::assign_bars(&groups, exp_bars);
+
+            // Only allocate the words of a read once its segment is reached.
+            for (transaction, result) in self.transactions[first..end]
+                .iter()
+                .zip(&mut results[first..end])
+            {
+                if let CppTransfer::Read(length_words) = transaction.transfer {
+                    *result = Some(vec![0; length_words as usize]);
+                }
+            }
+
+            // Groups a BAR already holds the configuration of go first, so
+            // that the BAR is only reconfigured once they are done.
+            let mut order: Vec<usize> = (0..groups.len()).collect();
+            order.sort_by_key(|&group| {
+                let bar = bars[group];
+                (bar, exp_bars[bar].cached_cfg() != groups[group].cfg)
+            });
+
+            for group in order {
+                let group_desc = &groups[group];
+                let exp_bar = &mut exp_bars[bars[group]];
+                exp_bar.exp_bar_map = group_desc.map_type;
+                exp_bar.exp_bar_base_addr = group_desc.base_addr;
+                exp_bar.write_cfg(group_desc.cfg)?;
+
+                for chunk in &group_desc.chunks {
+                    match &self.transactions[chunk.transaction].transfer {
+                        CppTransfer::Read(_) => {
+                            let read_bytes = exp_bar.read(chunk.offset, chunk.words as u64 * 4)?;
+                            let read_words: &[u32] = cast_slice(&read_bytes);
+                            results[chunk.transaction].as_mut().unwrap()
+                                [chunk.word..chunk.word + chunk.words]
+                                .copy_from_slice(read_words);
+                        }
+                        CppTransfer::Write(words) => {
+                            exp_bar.write(
+                                cast_slice(&words[chunk.word..chunk.word + chunk.words]),
+                                chunk.offset,
+                            )?;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::chip_desc::IslandKind;
+    use crate::libs::nfp::Nfp;
+    use crate::libs::sim_device::SimDevice;
+    use std::sync::Arc;
+
+    fn emu() -> CppIsland {
+        CppIsland::first_of_kind(IslandKind::Emu)
+    }
+
+    #[test]
+    fn chunks_are_grouped_by_window() {
+        let mut batch = CppBatch::new();
+        let mem = CppTarget::mem();
+        batch
+            .read(emu(), mem, 0, 0, CppLength::Len32, 0x0, 1)
+            .unwrap();
+        batch
+            .read(emu(), mem, 0, 0, CppLength::Len32, 0x10000, 1)
+            .unwrap();
+        batch
+            .read(emu(), mem, 0, 0, CppLength::Len32, 0x10, 1)
+            .unwrap();
+        // Straddles the first and second window.
+        batch
+            .read(emu(), mem, 0, 0, CppLength::Len32, 0xfff8, 4)
+            .unwrap();
+
+        let groups = batch.group_chunks(0, batch.len(), 0x10000).unwrap();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].base_addr, 0x0);
+        assert_eq!(groups[1].base_addr, 0x10000);
+
+        let chunks = |group: &CppChunkGroup| -> Vec<(usize, usize, usize, u64)> {
+            group
+                .chunks
+                .iter()
+                .map(|chunk| (chunk.transaction, chunk.word, chunk.words, chunk.offset))
+                .collect()
+        };
+        assert_eq!(
+            chunks(&groups[0]),
+            [(0, 0, 1, 0x0), (2, 0, 1, 0x10), (3, 0, 2, 0xfff8)]
+        );
+        assert_eq!(chunks(&groups[1]), [(1, 0, 1, 0x0), (3, 2, 2, 0x0)]);
+    }
+
+    #[test]
+    fn groups_keep_configured_bars() {
+        let nfp = Nfp::new(Arc::new(SimDevice::new())).unwrap();
+        let mut bar0 = nfp.exp_bar().unwrap();
+        let mut bar1 = nfp.exp_bar().unwrap();
+
+        let mut batch = CppBatch::new();
+        let mem = CppTarget::mem();
+        batch
+            .read(emu(), mem, 0, 0, CppLength::Len32, 0x0, 1)
+            .unwrap();
+        batch
+            .read(emu(), mem, 0, 0, CppLength::Len32, 0x10000, 1)
+            .unwrap();
+        let groups = batch.group_chunks(0, batch.len(), 0x10000).unwrap();
+
+        // The second BAR holds no configuration of the batch, the first
+        // holds that of the second group.
+        bar0.write_cfg(groups[1].cfg).unwrap();
+        let bars = CppBatch::assign_bars(&groups, &[&mut bar0, &mut bar1]);
+        assert_eq!(bars, [1, 0]);
+    }
+
+    #[test]
+    fn writes_are_ordered_with_other_targets() {
+        let mut batch = CppBatch::new();
+        let emem = MemoryType::Emem;
+        let engine = MuMemoryEngine::Bulk32;
+
+        // Reads and writes to different memory words stay in one segment.
+        batch.mem_read(emu(), emem, engine, 0x0, 1).unwrap();
+        batch.mem_write(emu(), emem, engine, 0x10, vec![1]).unwrap();
+        batch.mem_read(emu(), emem, engine, 0x20, 1).unwrap();
+        assert!(batch.barriers.is_empty());
+
+        // Rereading the written word waits for the write.
+        batch.mem_read(emu(), emem, engine, 0x10, 1).unwrap();
+        assert_eq!(batch.barriers, [3]);
+
+        // So does a doorbell read after a memory write.
+        batch.mem_write(emu(), emem, engine, 0x30, vec![2]).unwrap();
+        batch.xpb_read(&emu(), 0x100, 1, false).unwrap();
+        assert_eq!(batch.barriers, [3, 5]);
+
+        // An XPB write is kept in order with all accesses around it.
+        batch.xpb_write(&emu(), 0x104, vec![3], false).unwrap();
+        batch.xpb_read(&emu(), 0x108, 1, false).unwrap();
+        assert_eq!(batch.barriers, [3, 5, 6, 7]);
+
+        // XPB reads alone may be reordered.
+        let mut batch = CppBatch::new();
+        batch.xpb_read(&emu(), 0x100, 1, false).unwrap();
+        batch.xpb_read(&emu(), 0x10000, 1, false).unwrap();
+        assert!(batch.barriers.is_empty());
+    }
+
+    #[test]
+    fn batch_executes_on_sim() {
+        let nfp = Nfp::new(Arc::new(SimDevice::new())).unwrap();
+        let mut bar0 = nfp.exp_bar().unwrap();
+        let mut bar1 = nfp.exp_bar().unwrap();
+        let emem = MemoryType::Emem;
+        let engine = MuMemoryEngine::Bulk32;
+
+        let mut batch = CppBatch::new();
+        batch
+            .mem_write(emu(), emem, engine, 0x200, vec![1, 2, 3])
+            .unwrap();
+        batch.xpb_write(&emu(), 0x100, vec![0xabcd], false).unwrap();
+        let xpb = batch.xpb_read(&emu(), 0x100, 1, false).unwrap();
+        let mem = batch.mem_read(emu(), emem, engine, 0x204, 2).unwrap();
+        let results = batch.execute(&mut [&mut bar0, &mut bar1]).unwrap();
+
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0], None);
+        assert_eq!(results[xpb], Some(vec![0xabcd]));
+        assert_eq!(results[mem], Some(vec![2, 3]));
+    }
+
+    #[test]
+    fn oversized_transactions_are_rejected() {
+        let mut batch = CppBatch::new();
+        let mem = CppTarget::mem();
+        let len = CppLength::Len32;
+
+        batch.read(emu(), mem, 28, 0, len, 0x100, 4).unwrap();
+        for (address, length) in [(0, 0x4000000000000001), (0, u64::MAX), (0xfffffffffff0, 8)] {
+            assert!(matches!(
+                batch.read(emu(), mem, 28, 0, len, address, length),
+                Err(NfpError::AddressOutOfRange(_))
+            ));
+        }
+        assert!(matches!(
+            batch.write(emu(), mem, 31, 0, len, u64::MAX - 3, vec![0; 2]),
+            Err(NfpError::AddressOutOfRange(_))
+        ));
+        assert!(matches!(
+            batch.mem_read(emu(), MemoryType::Emem, MuMemoryEngine::Bulk32, 0, u64::MAX),
+            Err(NfpError::AddressOutOfRange(_))
+        ));
+        // A rejected write does not order the batch.
+        batch.write(emu(), mem, 31, 0, len, 0x200, vec![1]).unwrap();
+        assert_eq!(batch.len(), 2);
+        assert!(batch.barriers.is_empty());
+    }
+}
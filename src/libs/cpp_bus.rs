@@ -2,135 +2,210 @@
 
 use bytemuck::cast_slice;
 use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::str::FromStr;
 
-use crate::libs::expansion_bar::ExpansionBar;
-
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
-pub enum CppIsland {
-    Local,
-    ChipExec,
-    Pcie0,
-    Pcie1,
-    Nbi0,
-    Nbi1,
-    Nbi2,
-    Nbi3,
-    Emu0,
-    Rfpc0,
-    Rfpc1,
-    Rfpc2,
-    Rfpc3,
-    Rfpc4,
-    Rfpc5,
-    Rfpc6,
-}
+use crate::libs::chip_desc::{chip_desc, IslandKind};
+use crate::libs::error::NfpError;
+use crate::libs::expansion_bar::{ExpansionBar, MapType};
+
+// Size of the 48-bit CPP address space.
+const CPP_ADDRESS_SPACE: u64 = 1 << 48;
+
+/// CPP island, identified by its island ID. Island names and kinds come
+/// from the chip description.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct CppIsland(u8);
 
 impl CppIsland {
-    pub fn from_id(id: u8) -> CppIsland {
-        match id {
-            0 => CppIsland::Local,
-            1 => CppIsland::ChipExec,
-            2 => CppIsland::Pcie0,
-            3 => CppIsland::Pcie1,
-            4 => CppIsland::Nbi0,
-            5 => CppIsland::Nbi1,
-            6 => CppIsland::Nbi2,
-            7 => CppIsland::Nbi3,
-            8 => CppIsland::Emu0,
-            9 => CppIsland::Rfpc0,
-            10 => CppIsland::Rfpc1,
-            11 => CppIsland::Rfpc2,
-            12 => CppIsland::Rfpc3,
-            13 => CppIsland::Rfpc4,
-            14 => CppIsland::Rfpc5,
-            15 => CppIsland::Rfpc6,
-            _ => panic!("Invalid island ID: {}", id),
+    /// The island issuing the CPP command.
+    pub const LOCAL: CppIsland = CppIsland(0);
+
+    /// Return the island with the given ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::InvalidArgument` if the chip has no such island.
+    pub fn from_id(id: u8) -> Result<CppIsland, NfpError> {
+        match chip_desc().island(id) {
+            Some(_) => Ok(CppIsland(id)),
+            None => Err(NfpError::InvalidArgument(format!(
+                "No island {} in chip {}",
+                id,
+                chip_desc().name
+            ))),
         }
     }
 
     pub fn id(&self) -> u8 {
-        match self {
-            CppIsland::Local => 0,
-            CppIsland::ChipExec => 1,
-            CppIsland::Pcie0 => 2,
-            CppIsland::Pcie1 => 3,
-            CppIsland::Nbi0 => 4,
-            CppIsland::Nbi1 => 5,
-            CppIsland::Nbi2 => 6,
-            CppIsland::Nbi3 => 7,
-            CppIsland::Emu0 => 8,
-            CppIsland::Rfpc0 => 9,
-            CppIsland::Rfpc1 => 10,
-            CppIsland::Rfpc2 => 11,
-            CppIsland::Rfpc3 => 12,
-            CppIsland::Rfpc4 => 13,
-            CppIsland::Rfpc5 => 14,
-            CppIsland::Rfpc6 => 15,
+        self.0
+    }
+
+    /// Return the island with the lowest ID of the given kind.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the chip has no island of that kind. The
+    /// chip exec, EMU and RFPC islands used by the access libraries are
+    /// checked when the description is loaded.
+    pub fn first_of_kind(kind: IslandKind) -> CppIsland {
+        match chip_desc().first_island(kind) {
+            Some(island) => CppIsland(island.id),
+            None => panic!("Chip {} has no {} island", chip_desc().name, kind),
+        }
+    }
+
+    /// The chip exec island, which holds the XPB master.
+    pub fn chip_exec() -> CppIsland {
+        CppIsland::first_of_kind(IslandKind::ChipExec)
+    }
+
+    pub fn kind(&self) -> IslandKind {
+        chip_desc()
+            .island(self.0)
+            .map_or(IslandKind::Other, |island| island.kind)
+    }
+}
+
+impl FromStr for CppIsland {
+    type Err = String;
+
+    /// Parse an island name from the chip description, or an island ID.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let island = match clap_num::maybe_hex::<u8>(s) {
+            Ok(id) => chip_desc().island(id),
+            Err(_) => chip_desc().island_by_name(s),
+        };
+
+        match island {
+            Some(island) => Ok(CppIsland(island.id)),
+            None => Err(format!(
+                "no island {} in chip {} [possible values: {}]",
+                s,
+                chip_desc().name,
+                chip_desc()
+                    .islands
+                    .iter()
+                    .map(|island| island.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
         }
     }
 }
 
 impl fmt::Display for CppIsland {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            CppIsland::Local => write!(f, "local"),
-            CppIsland::ChipExec => write!(f, "chipExec"),
-            CppIsland::Pcie0 => write!(f, "pcie0"),
-            CppIsland::Pcie1 => write!(f, "pcie1"),
-            CppIsland::Nbi0 => write!(f, "nbi0"),
-            CppIsland::Nbi1 => write!(f, "nbi1"),
-            CppIsland::Nbi2 => write!(f, "nbi2"),
-            CppIsland::Nbi3 => write!(f, "nbi3"),
-            CppIsland::Emu0 => write!(f, "emu0"),
-            CppIsland::Rfpc0 => write!(f, "rfpc0"),
-            CppIsland::Rfpc1 => write!(f, "rfpc1"),
-            CppIsland::Rfpc2 => write!(f, "rfpc2"),
-            CppIsland::Rfpc3 => write!(f, "rfpc3"),
-            CppIsland::Rfpc4 => write!(f, "rfpc4"),
-            CppIsland::Rfpc5 => write!(f, "rfpc5"),
-            CppIsland::Rfpc6 => write!(f, "rfpc6"),
+        match chip_desc().island(self.0) {
+            Some(island) => write!(f, "{}", island.name),
+            None => write!(f, "island{}", self.0),
         }
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
-pub enum CppTarget {
-    Nbi,
-    Mem,
-    Pcie,
-    Arm,
-    Ct,
-    Cls,
-}
+/// CPP target, identified by its target ID. Target names come from the chip
+/// description.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct CppTarget(u8);
 
 impl CppTarget {
+    /// Return the target with the given ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::InvalidArgument` if the chip has no such target.
+    pub fn from_id(id: u8) -> Result<CppTarget, NfpError> {
+        match chip_desc().target(id) {
+            Some(_) => Ok(CppTarget(id)),
+            None => Err(NfpError::InvalidArgument(format!(
+                "No target {} in chip {}",
+                id,
+                chip_desc().name
+            ))),
+        }
+    }
+
     pub fn id(&self) -> u8 {
-        match self {
-            CppTarget::Nbi => 1,
-            CppTarget::Mem => 7,
-            CppTarget::Pcie => 9,
-            CppTarget::Arm => 10,
-            CppTarget::Ct => 14,
-            CppTarget::Cls => 15,
+        self.0
+    }
+
+    /// Return the target called `name` in the chip description.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::InvalidArgument` if the chip has no such target.
+    pub fn named(name: &str) -> Result<CppTarget, NfpError> {
+        match chip_desc().target_by_name(name) {
+            Some(target) => Ok(CppTarget(target.id)),
+            None => Err(NfpError::InvalidArgument(format!(
+                "No target {} in chip {}",
+                name,
+                chip_desc().name
+            ))),
+        }
+    }
+
+    /// Return a target the access libraries use, which the chip description
+    /// is checked for when loaded.
+    fn required(name: &str) -> CppTarget {
+        CppTarget::named(name).expect("Required targets are checked when the chip is loaded")
+    }
+
+    /// The memory unit target.
+    pub fn mem() -> CppTarget {
+        CppTarget::required("mem")
+    }
+
+    /// The cluster target, which carries XPB accesses.
+    pub fn ct() -> CppTarget {
+        CppTarget::required("ct")
+    }
+
+    /// The cluster local scratch target.
+    pub fn cls() -> CppTarget {
+        CppTarget::required("cls")
+    }
+}
+
+impl FromStr for CppTarget {
+    type Err = String;
+
+    /// Parse a target name from the chip description, or a target ID.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let target = match clap_num::maybe_hex::<u8>(s) {
+            Ok(id) => chip_desc().target(id),
+            Err(_) => chip_desc().target_by_name(s),
+        };
+
+        match target {
+            Some(target) => Ok(CppTarget(target.id)),
+            None => Err(format!(
+                "no target {} in chip {} [possible values: {}]",
+                s,
+                chip_desc().name,
+                chip_desc()
+                    .targets
+                    .iter()
+                    .map(|target| target.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
         }
     }
 }
 
 impl fmt::Display for CppTarget {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            CppTarget::Nbi => write!(f, "nbi"),
-            CppTarget::Mem => write!(f, "mem"),
-            CppTarget::Pcie => write!(f, "pcie"),
-            CppTarget::Arm => write!(f, "arm"),
-            CppTarget::Ct => write!(f, "ct"),
-            CppTarget::Cls => write!(f, "cls"),
+        match chip_desc().target(self.0) {
+            Some(target) => write!(f, "{}", target.name),
+            None => write!(f, "target{}", self.0),
         }
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum CppLength {
     Len32,
     Len64,
@@ -167,11 +242,21 @@ impl fmt::Display for CppLength {
 
 pub struct CppBus<'a> {
     pub exp_bar: &'a mut ExpansionBar,
+    // Mapping the expansion BAR is configured with for each access.
+    map_type: MapType,
 }
 
 impl<'a> CppBus<'a> {
+    /// Make CPP accesses through `exp_bar` with a Fixed mapping, which
+    /// carries the action and token of each access.
     pub fn new(exp_bar: &'a mut ExpansionBar) -> Self {
-        CppBus { exp_bar }
+        CppBus::with_map_type(exp_bar, MapType::Fixed)
+    }
+
+    /// Make CPP accesses through `exp_bar` with mapping `map_type`, e.g.
+    /// Bulk for XPB accesses.
+    pub fn with_map_type(exp_bar: &'a mut ExpansionBar, map_type: MapType) -> Self {
+        CppBus { exp_bar, map_type }
     }
 
     fn configure_exp_bar(
@@ -182,9 +267,11 @@ impl<'a> CppBus<'a> {
         token: u8,
         cpp_len: CppLength,
         address: u64,
-    ) -> u64 {
+    ) -> Result<u64, NfpError> {
         let log2_bar_size = (self.exp_bar.exp_bar_size as f64).log2().floor() as u64;
         let mask = (1u64 << 48) - (1u64 << log2_bar_size);
+        // A pooled BAR may still hold the mapping of its previous user.
+        self.exp_bar.exp_bar_map = self.map_type;
         self.exp_bar.exp_bar_base_addr = address & mask;
         self.exp_bar.expansion_bar_cfg(
             island.id(),
@@ -193,10 +280,48 @@ impl<'a> CppBus<'a> {
             token,
             self.exp_bar.exp_bar_base_addr,
             cpp_len.id(),
-        );
-        address - self.exp_bar.exp_bar_base_addr
+        )?;
+        Ok(address - self.exp_bar.exp_bar_base_addr)
+    }
+
+    /// Check that a transfer of `length_words` 32-bit words starting at
+    /// `address` lies within the 48-bit CPP address space.
+    pub(crate) fn check_cpp_region(address: u64, length_words: u64) -> Result<(), NfpError> {
+        let end = length_words
+            .checked_mul(4)
+            .and_then(|length| address.checked_add(length));
+        if end.is_none_or(|end| end > CPP_ADDRESS_SPACE) {
+            return Err(NfpError::AddressOutOfRange(format!(
+                "CPP region of {:#x} words at {:#x} exceeds the 48-bit CPP address space",
+                length_words, address
+            )));
+        }
+        Ok(())
     }
 
+    /// Return the number of words, up to `remaining_words`, that can be
+    /// transferred from `offset` before the end of the expansion BAR window.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::AddressOutOfRange` if a single word would straddle
+    /// the window boundary, which can only happen for unaligned addresses.
+    fn window_chunk_words(&self, offset: u64, remaining_words: u64) -> Result<u64, NfpError> {
+        let chunk_words = remaining_words.min((self.exp_bar.exp_bar_size - offset) / 4);
+        if chunk_words == 0 {
+            return Err(NfpError::AddressOutOfRange(format!(
+                "Unaligned CPP access at {:#x} straddles an expansion BAR window boundary",
+                self.exp_bar.exp_bar_base_addr + offset
+            )));
+        }
+        Ok(chunk_words)
+    }
+
+    /// Read `length_words` 32-bit words from the CPP bus.
+    ///
+    /// Transfers that do not fit in the expansion BAR window are split into
+    /// window-sized chunks, reconfiguring the BAR between chunks.
+    #[allow(clippy::too_many_arguments)]
     pub fn read(
         &mut self,
         island: CppIsland,
@@ -206,14 +331,31 @@ impl<'a> CppBus<'a> {
         cpp_len: CppLength,
         address: u64,
         length_words: u64,
-    ) -> Vec<u32> {
-        let offset = self.configure_exp_bar(island, target, action, token, cpp_len, address);
-        let length_bytes: u64 = length_words * 4;
-        let read_bytes = self.exp_bar.read(offset, length_bytes);
-        let read_words_slice: &[u32] = cast_slice(&read_bytes);
-        read_words_slice.to_vec()
+    ) -> Result<Vec<u32>, NfpError> {
+        Self::check_cpp_region(address, length_words)?;
+
+        let mut read_words: Vec<u32> = Vec::with_capacity(length_words as usize);
+        let mut chunk_address = address;
+        let mut remaining_words = length_words;
+        while remaining_words > 0 {
+            let offset =
+                self.configure_exp_bar(island, target, action, token, cpp_len, chunk_address)?;
+            let chunk_words = self.window_chunk_words(offset, remaining_words)?;
+            let read_bytes = self.exp_bar.read(offset, chunk_words * 4)?;
+            let read_words_slice: &[u32] = cast_slice(&read_bytes);
+            read_words.extend_from_slice(read_words_slice);
+
+            chunk_address += chunk_words * 4;
+            remaining_words -= chunk_words;
+        }
+        Ok(read_words)
     }
 
+    /// Write `write_words` to the CPP bus.
+    ///
+    /// Transfers that do not fit in the expansion BAR window are split into
+    /// window-sized chunks, reconfiguring the BAR between chunks.
+    #[allow(clippy::too_many_arguments)]
     pub fn write(
         &mut self,
         island: CppIsland,
@@ -223,9 +365,170 @@ impl<'a> CppBus<'a> {
         cpp_len: CppLength,
         address: u64,
         write_words: Vec<u32>,
-    ) {
-        let offset = self.configure_exp_bar(island, target, action, token, cpp_len, address);
-        let write_bytes: Vec<u8> = cast_slice(&write_words).to_vec();
-        self.exp_bar.write(&write_bytes, offset);
+    ) -> Result<(), NfpError> {
+        Self::check_cpp_region(address, write_words.len() as u64)?;
+
+        let mut chunk_address = address;
+        let mut remaining_words: &[u32] = &write_words;
+        while !remaining_words.is_empty() {
+            let offset =
+                self.configure_exp_bar(island, target, action, token, cpp_len, chunk_address)?;
+            let chunk_words = self.window_chunk_words(offset, remaining_words.len() as u64)?;
+            let (chunk, rest) = remaining_words.split_at(chunk_words as usize);
+            self.exp_bar.write(cast_slice(chunk), offset)?;
+
+            chunk_address += chunk_words * 4;
+            remaining_words = rest;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::cpp_batch::CppBatch;
+    use crate::libs::nfp::Nfp;
+    use crate::libs::sim_device::SimDevice;
+    use crate::libs::xpb_bus::xpb_read;
+    use std::sync::Arc;
+
+    #[test]
+    fn reused_bar_is_mapped_fixed() {
+        let nfp = Nfp::new(Arc::new(SimDevice::new())).unwrap();
+        let island = CppIsland::first_of_kind(IslandKind::Emu);
+
+        // Leave the pooled BAR with a Bulk mapping.
+        let mut exp_bar = nfp.exp_bar().unwrap();
+        exp_bar.exp_bar_map = MapType::Bulk;
+        drop(exp_bar);
+
+        let mut exp_bar = nfp.exp_bar().unwrap();
+        let mut cpp_bus = CppBus::new(&mut exp_bar);
+        cpp_bus
+            .write(
+                island,
+                CppTarget::mem(),
+                1,
+                0,
+                CppLength::Len64,
+                0x40,
+                vec![1, 2],
+            )
+            .unwrap();
+        assert_eq!(exp_bar.exp_bar_map, MapType::Fixed);
+        let words = CppBus::new(&mut exp_bar)
+            .read(island, CppTarget::mem(), 0, 0, CppLength::Len64, 0x40, 2)
+            .unwrap();
+        assert_eq!(words, [1, 2]);
+    }
+
+    #[test]
+    fn xpb_accesses_map_bulk() {
+        let nfp = Nfp::new(Arc::new(SimDevice::new())).unwrap();
+        let island = CppIsland::first_of_kind(IslandKind::Emu);
+        let mut exp_bar = nfp.exp_bar().unwrap();
+
+        // A plain XPB access and a batched one configure the BAR alike.
+        xpb_read(&mut exp_bar, &island, 0x100, 1, false).unwrap();
+        assert_eq!(exp_bar.exp_bar_map, MapType::Bulk);
+        let cfg = exp_bar.cached_cfg();
+
+        let mut batch = CppBatch::new();
+        batch.xpb_read(&island, 0x100, 1, false).unwrap();
+        let mut other_bar = nfp.exp_bar().unwrap();
+        batch.execute(&mut [&mut other_bar]).unwrap();
+        assert_eq!(other_bar.exp_bar_map, MapType::Bulk);
+        assert_eq!(other_bar.cached_cfg(), cfg);
+    }
+
+    fn mem_read(exp_bar: &mut ExpansionBar, address: u64, length_words: u64) -> Vec<u32> {
+        let island = CppIsland::first_of_kind(IslandKind::Emu);
+        CppBus::new(exp_bar)
+            .read(
+                island,
+                CppTarget::mem(),
+                28,
+                0,
+                CppLength::Len32,
+                address,
+                length_words,
+            )
+            .unwrap()
+    }
+
+    fn mem_write(exp_bar: &mut ExpansionBar, address: u64, words: Vec<u32>) {
+        let island = CppIsland::first_of_kind(IslandKind::Emu);
+        CppBus::new(exp_bar)
+            .write(
+                island,
+                CppTarget::mem(),
+                31,
+                0,
+                CppLength::Len32,
+                address,
+                words,
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn transfers_cross_bar_windows() {
+        let sim = Arc::new(SimDevice::new());
+        let nfp = Nfp::new(sim.clone()).unwrap();
+        let island = CppIsland::first_of_kind(IslandKind::Emu);
+        let mut exp_bar = nfp.exp_bar().unwrap();
+        let window = exp_bar.exp_bar_size;
+        assert_eq!(window, 16 << 20);
+
+        // Two words on either side of a window boundary.
+        mem_write(&mut exp_bar, window - 8, vec![1, 2, 3, 4]);
+        assert_eq!(mem_read(&mut exp_bar, window - 8, 4), [1, 2, 3, 4]);
+        let bytes = sim.read_memory(island.id(), CppTarget::mem(), window, 8);
+        assert_eq!(bytes, [3, 0, 0, 0, 4, 0, 0, 0]);
+
+        // A transfer spanning a whole window and part of two others.
+        let words: Vec<u32> = (0..(window / 4 + 4) as u32).collect();
+        mem_write(&mut exp_bar, window - 8, words.clone());
+        assert_eq!(
+            mem_read(&mut exp_bar, window - 8, words.len() as u64),
+            words
+        );
+        let bytes = sim.read_memory(island.id(), CppTarget::mem(), 2 * window - 4, 8);
+        let expected: Vec<u8> = [words.len() as u32 - 3, words.len() as u32 - 2]
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect();
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn unaligned_word_straddling_a_window_is_rejected() {
+        let nfp = Nfp::new(Arc::new(SimDevice::new())).unwrap();
+        let island = CppIsland::first_of_kind(IslandKind::Emu);
+        let mut exp_bar = nfp.exp_bar().unwrap();
+        let window = exp_bar.exp_bar_size;
+
+        let result = CppBus::new(&mut exp_bar).read(
+            island,
+            CppTarget::mem(),
+            28,
+            0,
+            CppLength::Len32,
+            window - 2,
+            1,
+        );
+        assert!(matches!(result, Err(NfpError::AddressOutOfRange(_))));
+    }
+
+    #[test]
+    fn oversized_regions_are_rejected() {
+        assert!(CppBus::check_cpp_region(0, CPP_ADDRESS_SPACE / 4).is_ok());
+        for length_words in [CPP_ADDRESS_SPACE / 4 + 1, 0x4000000000000000, u64::MAX] {
+            assert!(matches!(
+                CppBus::check_cpp_region(0, length_words),
+                Err(NfpError::AddressOutOfRange(_))
+            ));
+        }
     }
 }
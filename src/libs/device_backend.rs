@@ -0,0 +1,275 @@
+#![allow(dead_code)]
+
+use fs2::FileExt;
+use memmap2::{MmapMut, MmapOptions};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::libs::bus_trace::{RecordingBackend, ReplayBackend};
+use crate::libs::error::NfpError;
+use crate::libs::remote::{RemoteBackend, REMOTE_TOKEN_ENV};
+use crate::libs::sim_device::SimDevice;
+
+// Directory holding the per-device expansion and explicit BAR lock files.
+pub const LOCK_FILE_ROOT: &str = "/var/run/nfp_tools";
+// Number of expansion BARs per physical BAR.
+pub const EXPANSION_BARS_PER_PHYS_BAR: u64 = 8;
+// Environment variable naming a file to record all device accesses to.
+pub const BUS_TRACE_ENV: &str = "NFP_BUS_TRACE";
+// Environment variable naming a recorded trace to play back as the device.
+pub const BUS_REPLAY_ENV: &str = "NFP_BUS_REPLAY";
+
+/// Transport used to reach the PCIe BARs and configuration space of an NFP.
+///
+/// `ExpansionBar` and `ExplicitBar` perform every device access through this
+/// trait, so everything built on top of them (`CppBus`, `xpb_bus`,
+/// `mem_access`, `rfpc_debugger`, ...) works unchanged against any transport
+/// that implements it. `SysfsBackend` talks to a real NFP through sysfs.
+pub trait DeviceBackend: Send + Sync {
+    /// Name of the device, e.g. its PCIe BDF.
+    fn name(&self) -> &str;
+
+    /// Read `buf.len()` bytes from the PCIe configuration space at `offset`.
+    fn config_read(&self, offset: u64, buf: &mut [u8]) -> io::Result<()>;
+
+    /// Write `data` to the PCIe configuration space at `offset`.
+    fn config_write(&self, offset: u64, data: &[u8]) -> io::Result<()>;
+
+    /// Take exclusive ownership of expansion BAR `exp_bar` of physical
+    /// BAR `phys_bar`. Fails if the expansion BAR is already in use.
+    fn lock_exp_bar(&self, phys_bar: u8, exp_bar: u8) -> io::Result<()>;
+
+    /// Release an expansion BAR acquired with `lock_exp_bar`.
+    fn unlock_exp_bar(&self, phys_bar: u8, exp_bar: u8);
+
+    /// Take exclusive ownership of explicit BAR `expl_bar_index`, i.e. of
+    /// its CSRs and its share of the explicit command SRAM. Fails if the
+    /// explicit BAR is already in use.
+    fn lock_expl_bar(&self, expl_bar_index: u32) -> io::Result<()>;
+
+    /// Release an explicit BAR acquired with `lock_expl_bar`.
+    fn unlock_expl_bar(&self, expl_bar_index: u32);
+
+    /// Map the host-side window of expansion BAR `exp_bar` of physical BAR
+    /// `phys_bar`.
+    fn map_exp_bar(&self, phys_bar: u8, exp_bar: u8) -> io::Result<Box<dyn BarWindow>>;
+}
+
+/// Host-side window of a single expansion BAR.
+pub trait BarWindow: Send + Sync {
+    /// Size of the window in bytes.
+    fn size(&self) -> u64;
+
+    /// Read `length` bytes at `offset` into the window.
+    fn read(&self, offset: u64, length: u64) -> io::Result<Vec<u8>>;
+
+    /// Write `write_bytes` at `offset` into the window.
+    fn write(&mut self, write_bytes: &[u8], offset: u64) -> io::Result<()>;
+}
+
+/// Open the device backend selected on the command line.
+///
+/// If `NFP_BUS_REPLAY` names a bus trace, the trace is played back instead
+/// of opening a device, and no device may be selected. If `NFP_BUS_TRACE`
+/// names a file, all accesses to the device are recorded to it.
+///
+/// # Parameters
+///
+/// * `pci_bdf` - PCIe BDF of an NFP attached to this host.
+/// * `remote` - Address of an `nfp-cpp-server` giving access to an NFP in
+///   another machine. The token of the server is taken from
+///   `NFP_REMOTE_TOKEN`.
+/// * `sim` - Use an in-process simulated NFP instead of real hardware.
+///
+/// # Returns
+///
+/// A shared handle to the backend.
+///
+/// # Errors
+///
+/// Returns `NfpError::Io` if the bus trace cannot be read or created, or
+/// the server cannot be reached, and `NfpError::InvalidArgument` if no token
+/// is given for the server, no device is selected, or a device is selected
+/// while replaying a bus trace.
+pub fn open_backend(
+    pci_bdf: Option<&str>,
+    remote: Option<&str>,
+    sim: bool,
+) -> Result<Arc<dyn DeviceBackend>, NfpError> {
+    let backend: Arc<dyn DeviceBackend> = if let Some(path) = std::env::var_os(BUS_REPLAY_ENV) {
+        if pci_bdf.is_some() || remote.is_some() || sim {
+            return Err(NfpError::InvalidArgument(format!(
+                "{} replays a bus trace in place of a device, do not also select one",
+                BUS_REPLAY_ENV
+            )));
+        }
+        Arc::new(ReplayBackend::open(Path::new(&path))?)
+    } else if let Some(addr) = remote {
+        let token = std::env::var(REMOTE_TOKEN_ENV).map_err(|_| {
+            NfpError::InvalidArgument(format!(
+                "Set {} to the token of the server at {}",
+                REMOTE_TOKEN_ENV, addr
+            ))
+        })?;
+        Arc::new(RemoteBackend::connect(addr, &token)?)
+    } else if sim {
+        Arc::new(SimDevice::new())
+    } else {
+        match pci_bdf {
+            Some(pci_bdf) => Arc::new(SysfsBackend::new(pci_bdf)),
+            None => {
+                return Err(NfpError::InvalidArgument(
+                    "A PCIe BDF is required when not using the simulator".to_string(),
+                ))
+            }
+        }
+    };
+
+    match std::env::var_os(BUS_TRACE_ENV) {
+        Some(path) => Ok(Arc::new(RecordingBackend::create(
+            backend,
+            Path::new(&path),
+        )?)),
+        None => Ok(backend),
+    }
+}
+
+/// Device backend for an NFP attached to this host, accessed through the
+/// sysfs PCIe `config` and `resourceN` files.
+pub struct SysfsBackend {
+    pci_bdf: String,
+    // Locked BAR lock files, by lock name.
+    lock_files: Mutex<HashMap<String, File>>,
+}
+
+impl SysfsBackend {
+    pub fn new(pci_bdf: &str) -> Self {
+        SysfsBackend {
+            pci_bdf: pci_bdf.to_string(),
+            lock_files: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn device_path(&self, file_name: &str) -> String {
+        format!("/sys/bus/pci/devices/{}/{}", self.pci_bdf, file_name)
+    }
+
+    /// Take the lock file `name` of the device, e.g. `exp_bar0-1`.
+    fn lock(&self, name: String) -> io::Result<()> {
+        let lock_file_dir = format!("{}/{}", LOCK_FILE_ROOT, self.pci_bdf);
+        fs::create_dir_all(&lock_file_dir)?;
+        let lock_path = format!("{}/{}_lock", lock_file_dir, name);
+
+        let mut lock_files = self.lock_files.lock().unwrap();
+        if lock_files.contains_key(&name) {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                format!("{} is already locked", name),
+            ));
+        }
+
+        // Create the lock file and take an exclusive lock on it.
+        let lock_file = File::create(&lock_path)?;
+        lock_file.try_lock_exclusive()?;
+        lock_files.insert(name, lock_file);
+
+        Ok(())
+    }
+
+    fn unlock(&self, name: &str) {
+        if let Some(lock_file) = self.lock_files.lock().unwrap().remove(name) {
+            let _ = FileExt::unlock(&lock_file);
+        }
+    }
+
+    fn open_config(&self) -> io::Result<File> {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(self.device_path("config"))
+    }
+}
+
+impl DeviceBackend for SysfsBackend {
+    fn name(&self) -> &str {
+        &self.pci_bdf
+    }
+
+    fn config_read(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let mut pcie_cfg_file = self.open_config()?;
+        pcie_cfg_file.seek(SeekFrom::Start(offset))?;
+        pcie_cfg_file.read_exact(buf)
+    }
+
+    fn config_write(&self, offset: u64, data: &[u8]) -> io::Result<()> {
+        let mut pcie_cfg_file = self.open_config()?;
+        pcie_cfg_file.seek(SeekFrom::Start(offset))?;
+        pcie_cfg_file.write_all(data)
+    }
+
+    fn lock_exp_bar(&self, phys_bar: u8, exp_bar: u8) -> io::Result<()> {
+        self.lock(format!("exp_bar{}-{}", phys_bar, exp_bar))
+    }
+
+    fn unlock_exp_bar(&self, phys_bar: u8, exp_bar: u8) {
+        self.unlock(&format!("exp_bar{}-{}", phys_bar, exp_bar))
+    }
+
+    fn lock_expl_bar(&self, expl_bar_index: u32) -> io::Result<()> {
+        self.lock(format!("expl_bar{}", expl_bar_index))
+    }
+
+    fn unlock_expl_bar(&self, expl_bar_index: u32) {
+        self.unlock(&format!("expl_bar{}", expl_bar_index))
+    }
+
+    fn map_exp_bar(&self, phys_bar: u8, exp_bar: u8) -> io::Result<Box<dyn BarWindow>> {
+        let phys_bar_path = self.device_path(&format!("resource{}", 2 * phys_bar));
+
+        let phys_bar_size = fs::metadata(&phys_bar_path)?.len();
+        let exp_bar_size = phys_bar_size / EXPANSION_BARS_PER_PHYS_BAR;
+        let exp_bar_offset = (exp_bar as u64) * exp_bar_size;
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true) // Open the file in read-write mode
+            .open(&phys_bar_path)?;
+
+        let mmap = unsafe {
+            MmapOptions::new()
+                .offset(exp_bar_offset)
+                .len(exp_bar_size as usize)
+                .map_mut(&file)?
+        };
+
+        Ok(Box::new(MmapWindow {
+            _mmap_file: file,
+            mmap_region: mmap,
+        }))
+    }
+}
+
+/// Expansion BAR window backed by an mmap of a sysfs `resourceN` file.
+struct MmapWindow {
+    _mmap_file: File,
+    mmap_region: MmapMut,
+}
+
+impl BarWindow for MmapWindow {
+    fn size(&self) -> u64 {
+        self.mmap_region.len() as u64
+    }
+
+    fn read(&self, offset: u64, length: u64) -> io::Result<Vec<u8>> {
+        Ok(self.mmap_region[offset as usize..(offset + length) as usize].to_vec())
+    }
+
+    fn write(&mut self, write_bytes: &[u8], offset: u64) -> io::Result<()> {
+        self.mmap_region[offset as usize..(offset as usize + write_bytes.len())]
+            .copy_from_slice(write_bytes);
+        Ok(())
+    }
+}
@@ -0,0 +1,557 @@
+#![allow(dead_code)]
+
+use fs2::FileExt;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::libs::device_backend::LOCK_FILE_ROOT;
+use crate::libs::error::NfpError;
+
+// PCIe vendor and device ID of a Merlin NFP.
+pub const NFP_PCI_VENDOR_ID: u16 = 0x1da8;
+pub const NFP_PCI_DEVICE_ID: u16 = 0x7000;
+
+// Offset of the first PCIe extended capability in config space.
+const PCI_EXT_CAP_BASE: u64 = 0x100;
+// PCIe extended capability ID of the Device Serial Number capability.
+const PCI_EXT_CAP_ID_DSN: u32 = 0x0003;
+// Upper bound on the number of extended capabilities to walk.
+const PCI_EXT_CAP_MAX: usize = 64;
+
+/// Description of a single NFP found in sysfs.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct NfpDeviceInfo {
+    /// PCIe BDF, including the domain.
+    pub pci_bdf: String,
+    /// Card serial number, from the PCIe Device Serial Number capability.
+    /// `None` if the extended config space is not readable.
+    pub serial: Option<String>,
+    /// NUMA node the device is attached to, if known.
+    pub numa_node: Option<i32>,
+    /// Name of the kernel driver bound to the device, if any.
+    pub driver: Option<String>,
+    /// Sizes of the implemented PCIe BARs as (BAR index, size in bytes).
+    pub bar_sizes: Vec<(u8, u64)>,
+    /// Expansion BARs whose lock files are currently held, as
+    /// (physical BAR, expansion BAR).
+    pub locked_exp_bars: Vec<(u8, u8)>,
+}
+
+impl fmt::Display for NfpDeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.pci_bdf)?;
+        if let Some(serial) = &self.serial {
+            write!(f, " ({})", serial)?;
+        }
+        Ok(())
+    }
+}
+
+/// Discovers NFP devices by walking `<sysfs_root>/bus/pci/devices`.
+///
+/// The sysfs and lock file roots are configurable so that discovery can be
+/// exercised against a fake directory tree.
+pub struct DeviceEnumerator {
+    sysfs_root: PathBuf,
+    lock_root: PathBuf,
+}
+
+impl Default for DeviceEnumerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeviceEnumerator {
+    /// Enumerator for the devices attached to this host.
+    pub fn new() -> Self {
+        Self::with_roots("/sys", LOCK_FILE_ROOT)
+    }
+
+    /// Enumerator rooted at an alternative sysfs mount and lock file directory.
+    ///
+    /// # Parameters
+    ///
+    /// * `sysfs_root`: Directory laid out like `/sys`, containing `bus/pci/devices`.
+    /// * `lock_root`: Directory holding the per-device expansion BAR lock files.
+    pub fn with_roots(sysfs_root: impl Into<PathBuf>, lock_root: impl Into<PathBuf>) -> Self {
+        DeviceEnumerator {
+            sysfs_root: sysfs_root.into(),
+            lock_root: lock_root.into(),
+        }
+    }
+
+    fn devices_path(&self) -> PathBuf {
+        self.sysfs_root.join("bus/pci/devices")
+    }
+
+    /// List every NFP found in sysfs, sorted by PCIe BDF.
+    ///
+    /// # Returns
+    ///
+    /// Returns the devices in a stable order, so that a device's position in
+    /// the list can be used as its index.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::Io` if the PCIe device directory cannot be read.
+    pub fn list(&self) -> Result<Vec<NfpDeviceInfo>, NfpError> {
+        let devices_path = self.devices_path();
+        let entries = fs::read_dir(&devices_path)
+            .map_err(|e| NfpError::io(format!("Failed to list {}", devices_path.display()), e))?;
+
+        let mut pci_bdfs: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|pci_bdf| self.is_nfp(pci_bdf))
+            .collect();
+        pci_bdfs.sort();
+
+        Ok(pci_bdfs
+            .into_iter()
+            .map(|pci_bdf| self.device_info(&pci_bdf))
+            .collect())
+    }
+
+    /// Resolve a device selector to the PCIe BDF of an NFP.
+    ///
+    /// # Parameters
+    ///
+    /// * `selector`: One of
+    ///   - a PCIe BDF, with or without the `0000:` domain prefix,
+    ///   - an index into the list returned by `list`, e.g. `1`,
+    ///   - a card serial number, e.g. `00-15-4d-13-51-0c`. Separators and
+    ///     case are ignored.
+    ///
+    /// # Returns
+    ///
+    /// Returns the full PCIe BDF of the selected NFP.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::InvalidArgument` if the selector is empty, and
+    /// `NfpError::DeviceNotFound` if no NFP matches the selector.
+    pub fn select(&self, selector: &str) -> Result<String, NfpError> {
+        if selector.trim().is_empty() {
+            return Err(NfpError::InvalidArgument(
+                "Empty NFP selector, give a PCIe BDF, index or serial number".to_string(),
+            ));
+        }
+
+        // Only a BDF carries a ".<function>" suffix.
+        if selector.contains('.') {
+            return self.select_by_bdf(selector);
+        }
+
+        let devices = self.list()?;
+
+        // Short decimal numbers are indices, anything else is a serial.
+        let index = (selector.len() <= 3 && selector.chars().all(|c| c.is_ascii_digit()))
+            .then(|| selector.parse::<usize>().ok())
+            .flatten();
+        if let Some(index) = index {
+            return devices
+                .get(index)
+                .map(|device| device.pci_bdf.clone())
+                .ok_or_else(|| {
+                    NfpError::DeviceNotFound(format!(
+                        "No NFP with index {} ({} found)",
+                        index,
+                        devices.len()
+                    ))
+                });
+        }
+
+        let wanted = normalize_serial(selector);
+        devices
+            .into_iter()
+            .find(|device| {
+                device
+                    .serial
+                    .as_deref()
+                    .is_some_and(|serial| normalize_serial(serial) == wanted)
+            })
+            .map(|device| device.pci_bdf)
+            .ok_or_else(|| NfpError::DeviceNotFound(format!("No NFP with serial {}", selector)))
+    }
+
+    fn select_by_bdf(&self, pci_bdf: &str) -> Result<String, NfpError> {
+        // If the BDF is missing the domain part, add "0000:" as a prefix
+        let pci_bdf = if pci_bdf.split(':').count() < 3 {
+            format!("0000:{}", pci_bdf)
+        } else {
+            pci_bdf.to_string()
+        };
+
+        if !self.devices_path().join(&pci_bdf).exists() {
+            return Err(NfpError::DeviceNotFound(format!(
+                "No such PCIe device: {}",
+                pci_bdf
+            )));
+        }
+
+        if !self.is_nfp(&pci_bdf) {
+            return Err(NfpError::DeviceNotFound(format!(
+                "PCIe BDF {} does not belong to a Merlin NFP.",
+                pci_bdf
+            )));
+        }
+
+        Ok(pci_bdf)
+    }
+
+    fn device_file(&self, pci_bdf: &str, file_name: &str) -> PathBuf {
+        self.devices_path().join(pci_bdf).join(file_name)
+    }
+
+    fn read_id(&self, pci_bdf: &str, file_name: &str) -> Option<u16> {
+        let contents = fs::read_to_string(self.device_file(pci_bdf, file_name)).ok()?;
+        let contents = contents.trim();
+        u16::from_str_radix(contents.strip_prefix("0x").unwrap_or(contents), 16).ok()
+    }
+
+    fn is_nfp(&self, pci_bdf: &str) -> bool {
+        self.read_id(pci_bdf, "vendor") == Some(NFP_PCI_VENDOR_ID)
+            && self.read_id(pci_bdf, "device") == Some(NFP_PCI_DEVICE_ID)
+    }
+
+    fn device_info(&self, pci_bdf: &str) -> NfpDeviceInfo {
+        NfpDeviceInfo {
+            pci_bdf: pci_bdf.to_string(),
+            serial: self.read_serial(pci_bdf),
+            numa_node: self.read_numa_node(pci_bdf),
+            driver: self.read_driver(pci_bdf),
+            bar_sizes: self.read_bar_sizes(pci_bdf),
+            locked_exp_bars: self.read_locked_exp_bars(pci_bdf),
+        }
+    }
+
+    fn read_numa_node(&self, pci_bdf: &str) -> Option<i32> {
+        let contents = fs::read_to_string(self.device_file(pci_bdf, "numa_node")).ok()?;
+        // The kernel reports -1 when the NUMA node is unknown.
+        contents
+            .trim()
+            .parse::<i32>()
+            .ok()
+            .filter(|node| *node >= 0)
+    }
+
+    fn read_driver(&self, pci_bdf: &str) -> Option<String> {
+        let driver_link = fs::read_link(self.device_file(pci_bdf, "driver")).ok()?;
+        driver_link
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+    }
+
+    fn read_bar_sizes(&self, pci_bdf: &str) -> Vec<(u8, u64)> {
+        // Each line of `resource` holds "start end flags" for one resource.
+        // Lines 0-5 are the BARs; unimplemented BARs are all zero.
+        let contents = match fs::read_to_string(self.device_file(pci_bdf, "resource")) {
+            Ok(contents) => contents,
+            Err(_) => return Vec::new(),
+        };
+
+        contents
+            .lines()
+            .take(6)
+            .enumerate()
+            .filter_map(|(bar, line)| {
+                let mut fields = line
+                    .split_whitespace()
+                    .map(|field| u64::from_str_radix(field.trim_start_matches("0x"), 16).ok());
+                let start = fields.next()??;
+                let end = fields.next()??;
+                (end != 0).then(|| (bar as u8, end - start + 1))
+            })
+            .collect()
+    }
+
+    fn read_serial(&self, pci_bdf: &str) -> Option<String> {
+        let mut config = File::open(self.device_file(pci_bdf, "config")).ok()?;
+        let mut read_dword = |offset: u64| -> Option<u32> {
+            let mut buf = [0u8; 4];
+            config.seek(SeekFrom::Start(offset)).ok()?;
+            config.read_exact(&mut buf).ok()?;
+            Some(u32::from_le_bytes(buf))
+        };
+
+        // Walk the extended capability list looking for the Device Serial
+        // Number capability.
+        let mut cap_offset = PCI_EXT_CAP_BASE;
+        for _ in 0..PCI_EXT_CAP_MAX {
+            let header = read_dword(cap_offset)?;
+            if header == 0 || header == 0xFFFFFFFF {
+                return None;
+            }
+
+            if header & 0xFFFF == PCI_EXT_CAP_ID_DSN {
+                let dsn_lo = read_dword(cap_offset + 4)? as u64;
+                let dsn_hi = read_dword(cap_offset + 8)? as u64;
+                let dsn = (dsn_hi << 32) | dsn_lo;
+                // The upper 48 bits of the DSN hold the card serial, the
+                // lower 16 bits identify the PCIe interface.
+                let serial_bytes = &(dsn >> 16).to_be_bytes()[2..];
+                return Some(
+                    serial_bytes
+                        .iter()
+                        .map(|byte| format!("{:02x}", byte))
+                        .collect::<Vec<_>>()
+                        .join("-"),
+                );
+            }
+
+            cap_offset = ((header >> 20) & 0xFFC) as u64;
+            if cap_offset < PCI_EXT_CAP_BASE {
+                return None;
+            }
+        }
+
+        None
+    }
+
+    fn read_locked_exp_bars(&self, pci_bdf: &str) -> Vec<(u8, u8)> {
+        let entries = match fs::read_dir(self.lock_root.join(pci_bdf)) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+
+        let mut locked_exp_bars: Vec<(u8, u8)> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let bar_mapping = parse_lock_file_name(&entry.file_name().to_string_lossy())?;
+                is_lock_held(&entry.path()).then_some(bar_mapping)
+            })
+            .collect();
+        locked_exp_bars.sort();
+        locked_exp_bars
+    }
+}
+
+/// Parse an expansion BAR lock file name of the form `exp_bar<phys>-<exp>_lock`.
+fn parse_lock_file_name(file_name: &str) -> Option<(u8, u8)> {
+    let bar_mapping = file_name.strip_prefix("exp_bar")?.strip_suffix("_lock")?;
+    let (phys_bar, exp_bar) = bar_mapping.split_once('-')?;
+    Some((phys_bar.parse().ok()?, exp_bar.parse().ok()?))
+}
+
+/// Check whether another process holds the lock on `lock_path`.
+fn is_lock_held(lock_path: &Path) -> bool {
+    let lock_file = match File::open(lock_path) {
+        Ok(lock_file) => lock_file,
+        Err(_) => return false,
+    };
+
+    if lock_file.try_lock_exclusive().is_ok() {
+        let _ = FileExt::unlock(&lock_file);
+        return false;
+    }
+
+    true
+}
+
+/// Strip separators and case from a serial number so that
+/// `00-15-4D-13-51-0C`, `00:15:4d:13:51:0c` and `00154d13510c` compare equal.
+fn normalize_serial(serial: &str) -> String {
+    serial
+        .chars()
+        .filter(|c| c.is_ascii_hexdigit())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    /// Fake sysfs and lock file tree in a temporary directory, removed on
+    /// drop.
+    struct FakeSysfs {
+        root: PathBuf,
+    }
+
+    impl FakeSysfs {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir().join(format!(
+                "nfp-device-enum-{}-{}",
+                std::process::id(),
+                name
+            ));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(root.join("sys/bus/pci/devices")).unwrap();
+            fs::create_dir_all(root.join("lock")).unwrap();
+            FakeSysfs { root }
+        }
+
+        fn enumerator(&self) -> DeviceEnumerator {
+            DeviceEnumerator::with_roots(self.root.join("sys"), self.root.join("lock"))
+        }
+
+        /// Add a PCIe device with the given IDs and config space.
+        fn add_device(&self, pci_bdf: &str, vendor: u16, device: u16, config: &[u8]) -> PathBuf {
+            let dir = self.root.join("sys/bus/pci/devices").join(pci_bdf);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("vendor"), format!("0x{:04x}\n", vendor)).unwrap();
+            fs::write(dir.join("device"), format!("0x{:04x}\n", device)).unwrap();
+            fs::write(dir.join("config"), config).unwrap();
+            dir
+        }
+
+        /// Add an NFP with a Device Serial Number capability holding `dsn`,
+        /// preceded by `other_caps` other extended capabilities.
+        fn add_nfp(&self, pci_bdf: &str, dsn: u64, other_caps: usize) -> PathBuf {
+            let mut config = vec![0u8; 0x100];
+            for cap in 0..other_caps {
+                // An AER capability pointing at the next capability.
+                let next = 0x100 + 0x40 * (cap as u32 + 1);
+                config.extend((0x0001 | (1 << 16) | (next << 20)).to_le_bytes());
+                config.resize(next as usize, 0);
+            }
+            config.extend((PCI_EXT_CAP_ID_DSN | (1 << 16)).to_le_bytes());
+            config.extend((dsn as u32).to_le_bytes());
+            config.extend(((dsn >> 32) as u32).to_le_bytes());
+            self.add_device(pci_bdf, NFP_PCI_VENDOR_ID, NFP_PCI_DEVICE_ID, &config)
+        }
+    }
+
+    impl Drop for FakeSysfs {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    /// Tree with three NFPs and one other device.
+    fn fake_host(name: &str) -> FakeSysfs {
+        let sysfs = FakeSysfs::new(name);
+        let nfp0 = sysfs.add_nfp("0000:65:00.0", 0x0015_4d13_510c_0001, 0);
+        fs::write(nfp0.join("numa_node"), "1\n").unwrap();
+        fs::write(
+            nfp0.join("resource"),
+            "0x00000000d0000000 0x00000000d7ffffff 0x0000000000140204\n\
+             0x0000000000000000 0x0000000000000000 0x0000000000000000\n\
+             0x00000000d8000000 0x00000000d80fffff 0x0000000000140204\n",
+        )
+        .unwrap();
+        symlink("../../../bus/pci/drivers/nfp", nfp0.join("driver")).unwrap();
+
+        let nfp1 = sysfs.add_nfp("0000:17:00.0", 0x0015_4d00_0001_0000, 2);
+        fs::write(nfp1.join("numa_node"), "-1\n").unwrap();
+
+        // Only the standard config space is readable without privileges.
+        sysfs.add_device(
+            "0000:b3:00.0",
+            NFP_PCI_VENDOR_ID,
+            NFP_PCI_DEVICE_ID,
+            &[0u8; 0x100],
+        );
+
+        sysfs.add_device("0000:00:1f.0", 0x8086, 0xa1c1, &[0u8; 0x100]);
+        sysfs
+    }
+
+    #[test]
+    fn list_finds_nfps_sorted_by_bdf() {
+        let sysfs = fake_host("list");
+        let devices = sysfs.enumerator().list().unwrap();
+
+        let pci_bdfs: Vec<&str> = devices.iter().map(|d| d.pci_bdf.as_str()).collect();
+        assert_eq!(pci_bdfs, ["0000:17:00.0", "0000:65:00.0", "0000:b3:00.0"]);
+
+        assert_eq!(devices[1].serial.as_deref(), Some("00-15-4d-13-51-0c"));
+        assert_eq!(devices[1].numa_node, Some(1));
+        assert_eq!(devices[1].driver.as_deref(), Some("nfp"));
+        assert_eq!(devices[1].bar_sizes, [(0, 0x800_0000), (2, 0x10_0000)]);
+
+        // The DSN is found after other extended capabilities.
+        assert_eq!(devices[0].serial.as_deref(), Some("00-15-4d-00-00-01"));
+        assert_eq!(devices[0].numa_node, None);
+        assert_eq!(devices[0].driver, None);
+
+        assert_eq!(devices[2].serial, None);
+        assert!(devices[2].bar_sizes.is_empty());
+    }
+
+    #[test]
+    fn select_by_bdf() {
+        let sysfs = fake_host("bdf");
+        let enumerator = sysfs.enumerator();
+
+        assert_eq!(enumerator.select("0000:65:00.0").unwrap(), "0000:65:00.0");
+        assert_eq!(enumerator.select("17:00.0").unwrap(), "0000:17:00.0");
+        assert!(matches!(
+            enumerator.select("0000:00:1f.0"),
+            Err(NfpError::DeviceNotFound(_))
+        ));
+        assert!(matches!(
+            enumerator.select("99:00.0"),
+            Err(NfpError::DeviceNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn select_by_index() {
+        let sysfs = fake_host("index");
+        let enumerator = sysfs.enumerator();
+
+        assert_eq!(enumerator.select("0").unwrap(), "0000:17:00.0");
+        assert_eq!(enumerator.select("2").unwrap(), "0000:b3:00.0");
+        assert!(matches!(
+            enumerator.select("3"),
+            Err(NfpError::DeviceNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn select_by_serial() {
+        let sysfs = fake_host("serial");
+        let enumerator = sysfs.enumerator();
+
+        assert_eq!(
+            enumerator.select("00-15-4d-13-51-0c").unwrap(),
+            "0000:65:00.0"
+        );
+        assert_eq!(
+            enumerator.select("00:15:4D:00:00:01").unwrap(),
+            "0000:17:00.0"
+        );
+        assert_eq!(enumerator.select("00154d13510c").unwrap(), "0000:65:00.0");
+        assert!(matches!(
+            enumerator.select("00-15-4d-ff-ff-ff"),
+            Err(NfpError::DeviceNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn empty_selector_is_rejected() {
+        let sysfs = fake_host("empty");
+        let enumerator = sysfs.enumerator();
+
+        for selector in ["", " "] {
+            assert!(matches!(
+                enumerator.select(selector),
+                Err(NfpError::InvalidArgument(_))
+            ));
+        }
+        assert!(matches!(
+            enumerator.select("+1"),
+            Err(NfpError::DeviceNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn locked_exp_bars() {
+        let sysfs = fake_host("locks");
+        let lock_dir = sysfs.root.join("lock/0000:65:00.0");
+        fs::create_dir_all(&lock_dir).unwrap();
+        let held = File::create(lock_dir.join("exp_bar0-3_lock")).unwrap();
+        held.lock_exclusive().unwrap();
+        File::create(lock_dir.join("exp_bar0-1_lock")).unwrap();
+        File::create(lock_dir.join("unrelated")).unwrap();
+
+        let devices = sysfs.enumerator().list().unwrap();
+        assert_eq!(devices[1].locked_exp_bars, [(0, 3)]);
+        assert!(devices[0].locked_exp_bars.is_empty());
+    }
+}
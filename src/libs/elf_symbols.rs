@@ -0,0 +1,470 @@
+#![allow(dead_code)]
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use object::{Object, ObjectSection, ObjectSymbol, SectionKind, SymbolKind};
+
+use crate::libs::cpp_bus::CppIsland;
+use crate::libs::error::NfpError;
+
+// RFPC firmware is linked at global addresses: bit 48 is set, bits 42:36 hold
+// the ID of the island whose memory holds the code or data, and bits 31:0
+// hold the offset in that memory.
+const GLOBAL_ADDRESS_FLAG: u64 = 1 << 48;
+const GLOBAL_ADDRESS_ISLAND_SHIFT: u64 = 36;
+const GLOBAL_ADDRESS_ISLAND_MASK: u64 = 0x7F;
+const GLOBAL_ADDRESS_OFFSET_MASK: u64 = 0xFFFF_FFFF;
+
+/// Split an RFPC global address into the island ID and the offset in the
+/// island's memory. Returns `None` for addresses that are not global.
+pub fn split_global_address(address: u64) -> Option<(u8, u64)> {
+    if address & GLOBAL_ADDRESS_FLAG == 0 {
+        return None;
+    }
+    let island = ((address >> GLOBAL_ADDRESS_ISLAND_SHIFT) & GLOBAL_ADDRESS_ISLAND_MASK) as u8;
+    Some((island, address & GLOBAL_ADDRESS_OFFSET_MASK))
+}
+
+/// Parse a decimal or `0x` prefixed hexadecimal number.
+fn parse_number(s: &str) -> Option<u64> {
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16).ok()
+    } else {
+        s.parse::<u64>().ok()
+    }
+}
+
+/// Address given on the command line: a number, or a symbol with an optional
+/// offset, e.g. `0x1000`, `counters` or `counters+0x10`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum AddressExpr {
+    Number(u64),
+    Symbol { name: String, offset: i64 },
+}
+
+impl FromStr for AddressExpr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(number) = parse_number(s) {
+            return Ok(AddressExpr::Number(number));
+        }
+
+        let (name, offset) = match s.find(['+', '-']) {
+            Some(index) if index > 0 => {
+                let magnitude = parse_number(s[index + 1..].trim())
+                    .and_then(|offset| i64::try_from(offset).ok())
+                    .ok_or_else(|| format!("Invalid offset in address {}", s))?;
+                let offset = if s[index..].starts_with('-') {
+                    -magnitude
+                } else {
+                    magnitude
+                };
+                (s[..index].trim(), offset)
+            }
+            _ => (s, 0),
+        };
+
+        // A leading sign is an offset without a symbol.
+        let not_a_name = |c: char| c.is_ascii_digit() || c == '+' || c == '-';
+        if name.is_empty() || name.starts_with(not_a_name) {
+            return Err(format!("Invalid address {}", s));
+        }
+
+        Ok(AddressExpr::Symbol {
+            name: name.to_string(),
+            offset,
+        })
+    }
+}
+
+impl fmt::Display for AddressExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressExpr::Number(number) => write!(f, "{:#x}", number),
+            AddressExpr::Symbol { name, offset } if *offset < 0 => {
+                write!(f, "{}-{:#x}", name, offset.unsigned_abs())
+            }
+            AddressExpr::Symbol { name, offset } if *offset > 0 => {
+                write!(f, "{}+{:#x}", name, offset)
+            }
+            AddressExpr::Symbol { name, .. } => write!(f, "{}", name),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ElfSymbolKind {
+    Code,
+    Data,
+    Other,
+}
+
+/// A symbol from a firmware ELF.
+#[derive(Clone, Debug)]
+pub struct ElfSymbol {
+    pub name: String,
+    /// Address the symbol is linked at.
+    pub address: u64,
+    pub size: u64,
+    pub kind: ElfSymbolKind,
+    /// Island holding the symbol, if it is linked at a global address.
+    pub island: Option<u8>,
+    /// Offset of the symbol in the memory holding it.
+    pub offset: u64,
+    // End of the range of offsets the symbol covers: the end of the symbol
+    // if it has a size, else the end of its section.
+    limit: u64,
+}
+
+impl ElfSymbol {
+    /// Describe a symbol linked at `address` in a section ending at
+    /// `section_end`. The sizes come from the ELF, so the range is clamped
+    /// rather than trusted to fit.
+    fn new(name: &str, address: u64, size: u64, kind: ElfSymbolKind, section_end: u64) -> Self {
+        let (island, offset) = match split_global_address(address) {
+            Some((island, offset)) => (Some(island), offset),
+            None => (None, address),
+        };
+        let limit = if size != 0 {
+            offset.saturating_add(size)
+        } else {
+            offset.saturating_add(section_end.saturating_sub(address))
+        };
+
+        ElfSymbol {
+            name: name.to_string(),
+            address,
+            size,
+            kind,
+            island,
+            offset,
+            limit,
+        }
+    }
+
+    fn contains(&self, island: Option<u8>, offset: u64) -> bool {
+        (self.island.is_none() || self.island == island)
+            && self.offset <= offset
+            && offset < self.limit
+    }
+}
+
+/// Symbols loaded from one or more firmware ELFs.
+#[derive(Clone, Debug, Default)]
+pub struct ElfSymbols {
+    symbols: Vec<ElfSymbol>,
+}
+
+impl ElfSymbols {
+    pub fn new() -> Self {
+        ElfSymbols::default()
+    }
+
+    /// Load the symbols of every ELF in `paths`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::Io` if a file cannot be read, or
+    /// `NfpError::InvalidArgument` if it is not an ELF.
+    pub fn load(paths: &[PathBuf]) -> Result<Self, NfpError> {
+        let mut symbols = ElfSymbols::new();
+        for path in paths {
+            symbols.add_elf(path)?;
+        }
+        Ok(symbols)
+    }
+
+    /// Add the symbols of the ELF at `path`.
+    ///
+    /// Section symbols, file symbols and compiler local labels (`.L*`) are
+    /// skipped.
+    pub fn add_elf(&mut self, path: &Path) -> Result<(), NfpError> {
+        let data = fs::read(path)
+            .map_err(|e| NfpError::io(format!("Failed to read {}", path.display()), e))?;
+        let file = object::File::parse(&*data).map_err(|e| {
+            NfpError::InvalidArgument(format!("Invalid ELF {}: {}", path.display(), e))
+        })?;
+
+        for symbol in file.symbols() {
+            let name = match symbol.name() {
+                Ok(name) if !name.is_empty() && !name.starts_with(".L") => name,
+                _ => continue,
+            };
+            let Some(section) = symbol
+                .section_index()
+                .and_then(|index| file.section_by_index(index).ok())
+            else {
+                continue;
+            };
+            // Assembly labels have no type, so also go by the section.
+            let kind = match symbol.kind() {
+                SymbolKind::Section | SymbolKind::File => continue,
+                SymbolKind::Text => ElfSymbolKind::Code,
+                _ if section.kind() == SectionKind::Text => ElfSymbolKind::Code,
+                SymbolKind::Data | SymbolKind::Tls => ElfSymbolKind::Data,
+                _ => ElfSymbolKind::Other,
+            };
+
+            self.symbols.push(ElfSymbol::new(
+                name,
+                symbol.address(),
+                symbol.size(),
+                kind,
+                section.address().saturating_add(section.size()),
+            ));
+        }
+
+        self.symbols
+            .sort_by_key(|symbol| (symbol.offset, symbol.island));
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn symbols(&self) -> &[ElfSymbol] {
+        &self.symbols
+    }
+
+    /// Return the symbol called `name`, preferring one held in `island` when
+    /// several ELFs define it.
+    pub fn symbol(&self, name: &str, island: Option<u8>) -> Option<&ElfSymbol> {
+        let mut matches = self.symbols.iter().filter(|symbol| symbol.name == name);
+        let first = matches.clone().next();
+        matches
+            .find(|symbol| island.is_some() && symbol.island == island)
+            .or(first)
+    }
+
+    /// Resolve an address given on the command line to an offset in the
+    /// memory of `island`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::InvalidArgument` if the symbol is unknown or is
+    /// held in another island, or `NfpError::AddressOutOfRange` if the
+    /// offset moves the address below zero.
+    pub fn resolve(&self, expr: &AddressExpr, island: CppIsland) -> Result<u64, NfpError> {
+        let (name, offset) = match expr {
+            AddressExpr::Number(number) => return Ok(*number),
+            AddressExpr::Symbol { name, offset } => (name, *offset),
+        };
+
+        let symbol = self
+            .symbol(name, Some(island.id()))
+            .ok_or_else(|| NfpError::InvalidArgument(format!("Unknown symbol {}", name)))?;
+        if let Some(symbol_island) = symbol.island {
+            if symbol_island != island.id() {
+                return Err(NfpError::InvalidArgument(format!(
+                    "Symbol {} is in island {}, not {}",
+                    name,
+                    CppIsland::from_id(symbol_island)
+                        .map_or_else(|_| format!("island{}", symbol_island), |i| i.to_string()),
+                    island
+                )));
+            }
+        }
+
+        symbol
+            .offset
+            .checked_add_signed(offset)
+            .ok_or_else(|| NfpError::AddressOutOfRange(format!("Address {} is out of range", expr)))
+    }
+
+    fn symbolize_matching(
+        &self,
+        island: Option<u8>,
+        offset: u64,
+        code_only: bool,
+    ) -> Option<String> {
+        // Symbols are sorted by offset, so the match with the highest offset
+        // is the innermost.
+        let symbol = self
+            .symbols
+            .iter()
+            .take_while(|symbol| symbol.offset <= offset)
+            .filter(|symbol| !code_only || symbol.kind == ElfSymbolKind::Code)
+            .filter(|symbol| symbol.contains(island, offset))
+            .max_by_key(|symbol| {
+                (
+                    symbol.offset,
+                    symbol.size != 0,
+                    symbol.kind != ElfSymbolKind::Other,
+                )
+            })?;
+
+        Some(match offset - symbol.offset {
+            0 => symbol.name.clone(),
+            delta => format!("{}+{:#x}", symbol.name, delta),
+        })
+    }
+
+    /// Return `symbol+offset` for the symbol covering `offset` in the memory
+    /// of island `island`, or `None` if no symbol covers it.
+    pub fn symbolize_offset(&self, island: Option<u8>, offset: u64) -> Option<String> {
+        self.symbolize_matching(island, offset, false)
+    }
+
+    /// Like `symbolize_offset`, but only considers code symbols. Use this
+    /// for values that are known to be program counters.
+    pub fn symbolize_code(&self, island: Option<u8>, offset: u64) -> Option<String> {
+        self.symbolize_matching(island, offset, true)
+    }
+
+    /// Return `symbol+offset` for an address as seen by an RFPC, e.g. a
+    /// program counter.
+    pub fn symbolize(&self, address: u64) -> Option<String> {
+        match split_global_address(address) {
+            Some((island, offset)) => self.symbolize_offset(Some(island), offset),
+            None => self.symbolize_offset(None, address),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::chip_desc::IslandKind;
+
+    fn global_address(island: u8, offset: u64) -> u64 {
+        GLOBAL_ADDRESS_FLAG | (island as u64) << GLOBAL_ADDRESS_ISLAND_SHIFT | offset
+    }
+
+    fn symbol(name: &str, address: u64, size: u64, kind: ElfSymbolKind) -> ElfSymbol {
+        ElfSymbol::new(name, address, size, kind, 0x10000)
+    }
+
+    fn symbols(mut symbols: Vec<ElfSymbol>) -> ElfSymbols {
+        symbols.sort_by_key(|symbol| (symbol.offset, symbol.island));
+        ElfSymbols { symbols }
+    }
+
+    fn symbol_expr(name: &str, offset: i64) -> AddressExpr {
+        AddressExpr::Symbol {
+            name: name.to_string(),
+            offset,
+        }
+    }
+
+    #[test]
+    fn address_expressions_parse() {
+        let cases = [
+            ("0x1000", AddressExpr::Number(0x1000)),
+            ("0X1f", AddressExpr::Number(0x1f)),
+            (" 4096 ", AddressExpr::Number(4096)),
+            ("counters", symbol_expr("counters", 0)),
+            ("counters+0x10", symbol_expr("counters", 0x10)),
+            ("counters + 16", symbol_expr("counters", 16)),
+            ("counters-4", symbol_expr("counters", -4)),
+            ("_start", symbol_expr("_start", 0)),
+        ];
+        for (s, expr) in cases {
+            assert_eq!(s.parse::<AddressExpr>(), Ok(expr.clone()), "{}", s);
+            // Display gives an expression that parses back to the same address.
+            assert_eq!(expr.to_string().parse::<AddressExpr>(), Ok(expr));
+        }
+
+        for s in [
+            "",
+            "+counters",
+            "-4",
+            "0x",
+            "12ab",
+            "counters+",
+            "counters+x",
+            "counters+0x8000000000000000",
+        ] {
+            assert!(s.parse::<AddressExpr>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn symbols_resolve_in_their_island() {
+        let emu = CppIsland::first_of_kind(IslandKind::Emu);
+        let local = CppIsland::from_id(0).unwrap();
+        let symbols = symbols(vec![
+            symbol("local", 0x100, 4, ElfSymbolKind::Data),
+            symbol(
+                "table",
+                global_address(emu.id(), 0x2000),
+                0x40,
+                ElfSymbolKind::Data,
+            ),
+        ]);
+
+        let resolve = |s: &str, island| symbols.resolve(&s.parse().unwrap(), island);
+        assert_eq!(resolve("0x1234", emu).unwrap(), 0x1234);
+        assert_eq!(resolve("table", emu).unwrap(), 0x2000);
+        assert_eq!(resolve("table+0x10", emu).unwrap(), 0x2010);
+        assert_eq!(resolve("table-4", emu).unwrap(), 0x1ffc);
+        assert_eq!(resolve("local", emu).unwrap(), 0x100);
+        assert_eq!(resolve("local", local).unwrap(), 0x100);
+
+        assert!(matches!(
+            resolve("table", local),
+            Err(NfpError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            resolve("missing", emu),
+            Err(NfpError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            resolve("local-0x200", emu),
+            Err(NfpError::AddressOutOfRange(_))
+        ));
+    }
+
+    #[test]
+    fn symbolize_finds_the_innermost_symbol() {
+        let symbols = symbols(vec![
+            symbol("main", 0x1000, 0x100, ElfSymbolKind::Code),
+            symbol("loop", 0x1040, 0, ElfSymbolKind::Other),
+            symbol("buffer", 0x2000, 0x20, ElfSymbolKind::Data),
+            symbol("table", global_address(4, 0x1000), 8, ElfSymbolKind::Data),
+        ]);
+
+        assert_eq!(symbols.symbolize(0x1000).as_deref(), Some("main"));
+        assert_eq!(symbols.symbolize(0x1010).as_deref(), Some("main+0x10"));
+        // A label without a size covers the rest of its section.
+        assert_eq!(symbols.symbolize(0x1048).as_deref(), Some("loop+0x8"));
+        assert_eq!(symbols.symbolize(0x1ff0).as_deref(), Some("loop+0xfb0"));
+        assert_eq!(symbols.symbolize(0x2000).as_deref(), Some("buffer"));
+        assert_eq!(
+            symbols.symbolize_code(None, 0x1048).as_deref(),
+            Some("main+0x48")
+        );
+        assert_eq!(symbols.symbolize_code(None, 0x1100), None);
+        assert_eq!(symbols.symbolize_code(None, 0x2000), None);
+        assert_eq!(
+            symbols.symbolize_offset(None, 0x2008).as_deref(),
+            Some("buffer+0x8")
+        );
+
+        // Global symbols only match their own island.
+        assert_eq!(
+            symbols.symbolize(global_address(4, 0x1004)).as_deref(),
+            Some("table+0x4")
+        );
+        assert_eq!(
+            symbols.symbolize(global_address(5, 0x1004)).as_deref(),
+            Some("main+0x4")
+        );
+        assert_eq!(symbols.symbolize(0xfff), None);
+    }
+
+    #[test]
+    fn symbol_ranges_saturate() {
+        let symbol = symbol("huge", 0x1000, u64::MAX, ElfSymbolKind::Data);
+        assert_eq!(symbol.limit, u64::MAX);
+        assert!(symbol.contains(None, u64::MAX - 1));
+
+        let label = ElfSymbol::new("label", 0x100, 0, ElfSymbolKind::Other, 0x80);
+        assert_eq!(label.limit, 0x100);
+    }
+}
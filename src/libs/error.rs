@@ -0,0 +1,121 @@
+#![allow(dead_code)]
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Abstract command error reported by a RISC-V debug module in
+/// abstractcs.cmderr (see section 3.12.6 of "RISC-V External Debug Support"
+/// version 0.13.2).
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum DmCmdErr {
+    Busy,
+    NotSupported,
+    Exception,
+    HaltResume,
+    Bus,
+    Other,
+}
+
+impl DmCmdErr {
+    /// Decode a non-zero cmderr field value.
+    pub fn from_id(id: u8) -> DmCmdErr {
+        match id {
+            1 => DmCmdErr::Busy,
+            2 => DmCmdErr::NotSupported,
+            3 => DmCmdErr::Exception,
+            4 => DmCmdErr::HaltResume,
+            5 => DmCmdErr::Bus,
+            _ => DmCmdErr::Other,
+        }
+    }
+
+    pub fn id(&self) -> u8 {
+        match self {
+            DmCmdErr::Busy => 1,
+            DmCmdErr::NotSupported => 2,
+            DmCmdErr::Exception => 3,
+            DmCmdErr::HaltResume => 4,
+            DmCmdErr::Bus => 5,
+            DmCmdErr::Other => 7,
+        }
+    }
+}
+
+impl fmt::Display for DmCmdErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DmCmdErr::Busy => write!(f, "busy"),
+            DmCmdErr::NotSupported => write!(f, "not supported"),
+            DmCmdErr::Exception => write!(f, "exception"),
+            DmCmdErr::HaltResume => write!(f, "hart not in the required halt/resume state"),
+            DmCmdErr::Bus => write!(f, "bus error"),
+            DmCmdErr::Other => write!(f, "other error"),
+        }
+    }
+}
+
+/// Errors returned by the NFP access stack.
+#[derive(Debug)]
+pub enum NfpError {
+    /// A device resource, such as an expansion BAR, is held by someone else.
+    LockContention(String),
+    /// An access to the device backend (sysfs config space, BAR mapping or
+    /// lock file) failed.
+    Io { context: String, source: io::Error },
+    /// An address or length does not fit where it is used.
+    AddressOutOfRange(String),
+    /// An argument is not valid for the requested operation.
+    InvalidArgument(String),
+    /// No NFP matches the requested device.
+    DeviceNotFound(String),
+    /// A RISC-V debug module abstract command failed.
+    DmCmdErr(DmCmdErr),
+    /// The device did not reach the expected state in time.
+    Timeout(String),
+    /// Data read back from the device does not match what was expected.
+    VerifyFailed(String),
+    /// The operation needs firmware, but none is loaded on the NFP.
+    FirmwareNotLoaded(String),
+}
+
+impl NfpError {
+    /// Build an `NfpError::Io` describing what was being done when `source`
+    /// occurred.
+    pub fn io(context: impl Into<String>, source: io::Error) -> Self {
+        NfpError::Io {
+            context: context.into(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for NfpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NfpError::LockContention(msg) => write!(f, "lock contention: {}", msg),
+            NfpError::Io { context, source } => write!(f, "{}: {}", context, source),
+            NfpError::AddressOutOfRange(msg) => write!(f, "address out of range: {}", msg),
+            NfpError::InvalidArgument(msg) => write!(f, "invalid argument: {}", msg),
+            NfpError::DeviceNotFound(msg) => write!(f, "device not found: {}", msg),
+            NfpError::DmCmdErr(cmderr) => write!(
+                f,
+                "RFPC abstract command returned error {} ({})",
+                cmderr.id(),
+                cmderr
+            ),
+            NfpError::Timeout(msg) => write!(f, "timeout: {}", msg),
+            NfpError::VerifyFailed(msg) => write!(f, "verify failed: {}", msg),
+            NfpError::FirmwareNotLoaded(msg) => write!(f, "firmware not loaded: {}", msg),
+        }
+    }
+}
+
+impl Error for NfpError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            NfpError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
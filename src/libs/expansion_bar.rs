@@ -1,20 +1,22 @@
 #![allow(dead_code)]
 
-use fs2::FileExt;
-use memmap2::{MmapMut, MmapOptions};
+use serde::{Deserialize, Serialize};
 use std::fmt;
-use std::fs::{self, File, OpenOptions};
-use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::io;
+use std::sync::Arc;
+
+use crate::libs::device_backend::{BarWindow, DeviceBackend};
+use crate::libs::error::NfpError;
 
 // Base address of PCIe2CPP BAR CSRs.
 const BAR_CONFIG_BASE_PCIE_INTERNAL: u32 = 0x30000; // When accessed by PCIe internal target.
 pub const BAR_CONFIG_BASE_CONFIG_SNOOP: u32 = 0xA00; // When accessed by config snoop i/f
                                                      // Offset from BAR config base address to expansion BAR CSRs.
-const EXPANSION_BAR_BASE_OFFSET: u32 = 0x0;
+pub const EXPANSION_BAR_BASE_OFFSET: u32 = 0x0;
 // Offset from expansion BAR CSR base address per physical BAR.
-const EXPANSION_BAR_PHYS_OFFSET: u32 = 0x40;
+pub const EXPANSION_BAR_PHYS_OFFSET: u32 = 0x40;
 // Offset from physical BAR expansion BAR CSR base address per expansion BAR.
-const EXPANSION_BAR_CSR_OFFSET: u32 = 0x8;
+pub const EXPANSION_BAR_CSR_OFFSET: u32 = 0x8;
 // Offset from BAR config base address to explicit command BAR CSRs.
 const EXPLICIT_BAR_BASE_OFFSET: u32 = 0x180;
 // Offset from explicit BAR CSR base address per explicit command BAR.
@@ -27,7 +29,8 @@ const CPP_EXPANSION_BAR_PHYSICAL_BAR: u32 = 2;
 // Maximum number of expansion BARs.
 const CPP_MAX_NUM_EXPANSION_BARS: u32 = 8;
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum MapType {
     Fixed,
     Bulk,
@@ -36,200 +39,162 @@ pub enum MapType {
     Explicit,
 }
 
-pub fn init_device_bars(pci_bdf: &str) {
-    let pcie_cfg_path = format!("/sys/bus/pci/devices/{}/config", pci_bdf);
-    let mut pcie_cfg_file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .open(&pcie_cfg_path)
-        .expect(&format!("Failed to open file {}", &pcie_cfg_path));
-    pcie_cfg_file
-        .seek(SeekFrom::Start(4))
-        .expect(&format!("File {} seek failed", pcie_cfg_path));
+impl MapType {
+    /// Return the map type encoded as `id` in an expansion BAR config.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::InvalidArgument` for the reserved encodings 5-7.
+    pub fn from_id(id: u8) -> Result<MapType, NfpError> {
+        match id {
+            0 => Ok(MapType::Fixed),
+            1 => Ok(MapType::Bulk),
+            2 => Ok(MapType::Target),
+            3 => Ok(MapType::General),
+            4 => Ok(MapType::Explicit),
+            _ => Err(NfpError::InvalidArgument(format!(
+                "Invalid map type ID: {}",
+                id
+            ))),
+        }
+    }
+}
+
+/// Enable memory space accesses and bus mastering in the PCIe command
+/// register of the device.
+pub fn init_device_bars(backend: &Arc<dyn DeviceBackend>) -> Result<(), NfpError> {
     let mut buf = [0u8; 1];
-    pcie_cfg_file
-        .read(&mut buf)
-        .expect(&format!("File {} read failed", &pcie_cfg_path));
+    backend
+        .config_read(4, &mut buf)
+        .map_err(|e| NfpError::io(format!("{} config read failed", backend.name()), e))?;
     let cfg_val = buf[0] | 0x06;
-    pcie_cfg_file
-        .seek(SeekFrom::Start(4))
-        .expect(&format!("File {} seek failed", &pcie_cfg_path));
-    pcie_cfg_file
-        .write(&[cfg_val])
-        .expect(&format!("File {} write failed", &pcie_cfg_path));
+    backend
+        .config_write(4, &[cfg_val])
+        .map_err(|e| NfpError::io(format!("{} config write failed", backend.name()), e))
 }
 
 pub struct ExpansionBar {
-    pci_bdf: String,
+    backend: Arc<dyn DeviceBackend>,
     phys_bar: u8,
-    phys_bar_path: String,
     exp_bar: u8,
     pub exp_bar_map: MapType,
     exp_bar_cached_cfg: [u32; 2],
     pub exp_bar_base_addr: u64,
     pub exp_bar_size: u64,
-    lock_file: File,
-    mmap_file: Option<File>,
-    mmap_region: Option<MmapMut>,
+    window: Box<dyn BarWindow>,
 }
 
 impl ExpansionBar {
-    pub fn new(pci_bdf_str: &str, bar_mapping: Option<(u8, u8)>) -> Self {
-        let (phys_bar, exp_bar, lock_file) = if let Some(bar_map) = bar_mapping {
-            let lock_file_dir = format!("/var/run/nfp_tools/{}", pci_bdf_str);
-            let lock_file_name = format!("exp_bar{}-{}_lock", bar_map.0, bar_map.1);
-            let full_path = format!("{}/{}", lock_file_dir, lock_file_name);
-
-            match Self::acquire_lock_file(&full_path) {
-                Ok(file) => {
-                    let (phys_bar, exp_bar) = bar_map;
-                    (phys_bar, exp_bar, file)
-                }
-                Err(_) => {
-                    panic!("exp_bar{}-{} should not be locked!", bar_map.0, bar_map.1);
-                }
-            }
+    pub fn new(
+        backend: &Arc<dyn DeviceBackend>,
+        bar_mapping: Option<(u8, u8)>,
+    ) -> Result<Self, NfpError> {
+        let (phys_bar, exp_bar) = if let Some(bar_map) = bar_mapping {
+            backend
+                .lock_exp_bar(bar_map.0, bar_map.1)
+                .map_err(|e| Self::lock_error(bar_map, e))?;
+            bar_map
         } else {
-            Self::allocate_exp_bar(pci_bdf_str)
+            Self::allocate_exp_bar(backend)?
         };
 
-        let phys_bar_path = format!(
-            "/sys/bus/pci/devices/{}/{}",
-            pci_bdf_str,
-            format!("resource{}", 2 * phys_bar)
-        );
-
-        let metadata = fs::metadata(&phys_bar_path).expect("Error getting file metadata!");
-        let phys_bar_size = metadata.len() as u64;
-        let exp_bar_size = phys_bar_size / 8;
-        let exp_bar_offset = (exp_bar as u64) * exp_bar_size;
-        let exp_bar_base_addr = 0;
-
-        let file = OpenOptions::new()
-            .read(true)
-            .write(true) // Open the file in read-write mode
-            .open(&phys_bar_path)
-            .expect("Failed to open mmap file in read-write mode");
-
-        let mmap = unsafe {
-            MmapOptions::new()
-                .offset(exp_bar_offset)
-                .len(exp_bar_size as usize)
-                .map_mut(&file)
-                .expect("Failed to map expansion BAR region")
+        let window = match backend.map_exp_bar(phys_bar, exp_bar) {
+            Ok(window) => window,
+            Err(e) => {
+                backend.unlock_exp_bar(phys_bar, exp_bar);
+                return Err(NfpError::io("Failed to map expansion BAR region", e));
+            }
         };
+        let exp_bar_size = window.size();
 
-        ExpansionBar {
-            pci_bdf: pci_bdf_str.to_string(),
+        Ok(ExpansionBar {
+            backend: Arc::clone(backend),
             phys_bar,
-            phys_bar_path,
             exp_bar,
             exp_bar_map: MapType::Fixed,
             exp_bar_cached_cfg: [0; 2],
-            exp_bar_base_addr,
+            exp_bar_base_addr: 0,
             exp_bar_size,
-            lock_file,
-            mmap_file: Some(file),
-            mmap_region: Some(mmap),
-        }
+            window,
+        })
     }
 
-    fn acquire_lock_file(lock_path: &str) -> io::Result<File> {
-        // Attempt to create and open the file
-        let lock_file = File::create(lock_path)?;
-
-        // Attempt to lock the file (using an exclusive lock)
-        lock_file.try_lock_exclusive()?;
-
-        Ok(lock_file)
-    }
-
-    fn allocate_exp_bar(pci_bdf: &str) -> (u8, u8, File) {
-        let lock_file_dir = format!("/var/run/nfp_tools/{}", pci_bdf);
-        fs::create_dir_all(&lock_file_dir)
-            .expect(&format!("Failed to create dir {}", &lock_file_dir));
-
-        let mut bar_locks: Vec<(u8, u8, String)> = Vec::new();
-        for exp_bar in 0..CPP_MAX_NUM_EXPANSION_BARS {
-            let lock_file = format!("exp_bar{}-{}_lock", CPP_EXPANSION_BAR_PHYSICAL_BAR, exp_bar);
-            let full_path = format!("{}/{}", lock_file_dir, lock_file);
-            // Attempt to create the file only if it does not already exist
-            bar_locks.push((
-                CPP_EXPANSION_BAR_PHYSICAL_BAR as u8,
-                exp_bar as u8,
-                full_path,
-            ));
+    /// Map a failure to lock expansion BAR `bar` onto an `NfpError`: a
+    /// BAR held by someone else is `LockContention`, anything else, such as
+    /// a lock directory that cannot be written, is `Io`.
+    fn lock_error(bar: (u8, u8), e: io::Error) -> NfpError {
+        if e.kind() == io::ErrorKind::WouldBlock {
+            NfpError::LockContention(format!("exp_bar{}-{} is already locked", bar.0, bar.1))
+        } else {
+            NfpError::io(format!("Failed to lock exp_bar{}-{}", bar.0, bar.1), e)
         }
+    }
 
-        for (phys_bar, exp_bar, lock_path) in bar_locks {
-            match Self::acquire_lock_file(&lock_path) {
-                Ok(file) => return (phys_bar, exp_bar, file),
-                Err(_) => {
-                    // Continue to next lock if this one fails
-                }
+    fn allocate_exp_bar(backend: &Arc<dyn DeviceBackend>) -> Result<(u8, u8), NfpError> {
+        let phys_bar = CPP_EXPANSION_BAR_PHYSICAL_BAR as u8;
+        for exp_bar in 0..CPP_MAX_NUM_EXPANSION_BARS as u8 {
+            match backend.lock_exp_bar(phys_bar, exp_bar) {
+                Ok(()) => return Ok((phys_bar, exp_bar)),
+                // Continue to next expansion BAR if this one is taken.
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(Self::lock_error((phys_bar, exp_bar), e)),
             }
         }
 
-        panic!("No expansion BARs available!");
+        Err(NfpError::LockContention(
+            "No expansion BARs available!".to_string(),
+        ))
     }
 
-    fn exp_bar_config_write(&self, cfg_reg0: u32, cfg_reg1: u32) {
-        let pcie_cfg_path = format!("/sys/bus/pci/devices/{}/config", &self.pci_bdf);
-
+    fn exp_bar_config_write(&self, cfg_reg0: u32, cfg_reg1: u32) -> Result<(), NfpError> {
         let exp_bar_csr_addr = BAR_CONFIG_BASE_CONFIG_SNOOP
             + EXPANSION_BAR_BASE_OFFSET
             + (self.phys_bar as u32) * EXPANSION_BAR_PHYS_OFFSET
             + (self.exp_bar as u32) * EXPANSION_BAR_CSR_OFFSET;
 
-        let mut pcie_cfg_file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open(&pcie_cfg_path)
-            .expect(&format!("Failed to open file {}", &pcie_cfg_path));
-
-        pcie_cfg_file
-            .seek(SeekFrom::Start(exp_bar_csr_addr as u64))
-            .expect(&format!("File {} seek failed", pcie_cfg_path));
-
         // Write using little-endian format
-        pcie_cfg_file
-            .write_all(&cfg_reg0.to_le_bytes())
-            .expect(&format!("File {} write failed", &pcie_cfg_path));
-        pcie_cfg_file
-            .write_all(&cfg_reg1.to_le_bytes())
-            .expect(&format!("File {} write failed", &pcie_cfg_path));
+        let mut cfg_bytes = cfg_reg0.to_le_bytes().to_vec();
+        cfg_bytes.extend_from_slice(&cfg_reg1.to_le_bytes());
+        self.backend
+            .config_write(exp_bar_csr_addr as u64, &cfg_bytes)
+            .map_err(|e| NfpError::io(format!("{} config write failed", self.backend.name()), e))
     }
 
-    pub fn expansion_bar_cfg(
-        &mut self,
+    /// Compute the two expansion BAR CSR words for a mapping of type
+    /// `map_type`, without writing them.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::AddressOutOfRange` if `base_addr` is not a valid
+    /// base address for the map type.
+    pub fn cfg_words(
+        map_type: MapType,
         tgt_island_id: u8,
         target: u8,
         action: u8,
         token: u8,
         base_addr: u64,
         cpp_len: u8,
-    ) {
+    ) -> Result<[u32; 2], NfpError> {
         let (mut cfg0, mut cfg1): (u32, u32) = (0, 0);
 
         cfg0 |= 1 << 31; // Enable bit.
-        cfg0 |= ((self.exp_bar_map as u32) & 0x7) << 20; // Map type.
+        cfg0 |= ((map_type as u32) & 0x7) << 20; // Map type.
 
         // Early return for explicit mapping.
-        if self.exp_bar_map == MapType::Explicit {
-            if cfg0 != self.exp_bar_cached_cfg[0] || cfg1 != self.exp_bar_cached_cfg[1] {
-                self.exp_bar_config_write(cfg0, cfg1);
-                self.exp_bar_cached_cfg[0] = cfg0;
-                self.exp_bar_cached_cfg[1] = cfg1;
-            }
-            return;
+        if map_type == MapType::Explicit {
+            return Ok([cfg0, cfg1]);
         }
 
         // Check if the base address is valid.
         if (64 - base_addr.leading_zeros()) > 48 {
-            panic!("Provided base_addr is too long for a CPP address!")
+            return Err(NfpError::AddressOutOfRange(format!(
+                "Base address {:#x} is too long for a CPP address",
+                base_addr
+            )));
         }
 
-        let base_addr_width = match self.exp_bar_map {
+        let base_addr_width = match map_type {
             MapType::Fixed => 32,
             MapType::Bulk => 38,
             MapType::Target => 40,
@@ -240,14 +205,14 @@ impl ExpansionBar {
         let lowest_bit = base_addr & base_addr.wrapping_neg(); // Two's complement negation.
         let bit_length = 64 - lowest_bit.leading_zeros();
 
-        if (0..(48 - base_addr_width)).contains(&(bit_length - 1)) {
-            panic!(
+        if (0..(48 - base_addr_width)).contains(&bit_length.wrapping_sub(1)) {
+            return Err(NfpError::AddressOutOfRange(format!(
                 "Expansion BAR uses a {}-bit base address. \
                  The lower {} bits of address {:#010x} would be truncated.",
                 base_addr_width,
                 48 - base_addr_width,
                 base_addr
-            );
+            )));
         }
 
         let mut addr_idx = 48; // Track position in base address.
@@ -259,7 +224,7 @@ impl ExpansionBar {
         cfg0 |= (tgt_island_id as u32 & 0x7F) << 24;
 
         // CPP target / base address [43:40] field.
-        match self.exp_bar_map {
+        match map_type {
             MapType::General => {
                 cfg0 |= (((base_addr >> (addr_idx - 4)) & 0xF) << 12) as u32;
                 addr_idx -= 4;
@@ -270,7 +235,7 @@ impl ExpansionBar {
         };
 
         // CPP token / base address [39:38] field.
-        match self.exp_bar_map {
+        match map_type {
             MapType::Fixed | MapType::Bulk => {
                 cfg0 |= (token as u32 & 0x3) << 8;
             }
@@ -281,7 +246,7 @@ impl ExpansionBar {
         };
 
         // CPP action / base address [37:32] field.
-        match self.exp_bar_map {
+        match map_type {
             MapType::Fixed => {
                 cfg0 |= action as u32 & 0x3F;
             }
@@ -294,48 +259,78 @@ impl ExpansionBar {
         // Base address [31:0] field.
         cfg1 = (base_addr >> (addr_idx - 32)) as u32;
 
-        // Write configuration if it has changed.
-        if cfg0 != self.exp_bar_cached_cfg[0] || cfg1 != self.exp_bar_cached_cfg[1] {
-            self.exp_bar_config_write(cfg0, cfg1);
-            self.exp_bar_cached_cfg[0] = cfg0;
-            self.exp_bar_cached_cfg[1] = cfg1;
+        Ok([cfg0, cfg1])
+    }
+
+    /// CSR words the expansion BAR is currently configured with.
+    pub fn cached_cfg(&self) -> [u32; 2] {
+        self.exp_bar_cached_cfg
+    }
+
+    /// Configure the expansion BAR with CSR words computed by `cfg_words`.
+    /// The configuration is only written if it has changed.
+    pub fn write_cfg(&mut self, cfg: [u32; 2]) -> Result<(), NfpError> {
+        if cfg != self.exp_bar_cached_cfg {
+            self.exp_bar_config_write(cfg[0], cfg[1])?;
+            self.exp_bar_cached_cfg = cfg;
         }
+        Ok(())
     }
 
-    pub fn read(&self, offset: u64, length: u64) -> Vec<u8> {
-        if let Some(ref mmap) = self.mmap_region {
-            // Ensure offset and length are valid
-            if offset + length > mmap.len() as u64 {
-                panic!("Requested region exceeds mapped region!");
-            }
-            // Return a copied vector from the mmap
-            mmap[offset as usize..(offset + length) as usize].to_vec()
-        } else {
-            panic!("Memory region not mapped!");
+    pub fn expansion_bar_cfg(
+        &mut self,
+        tgt_island_id: u8,
+        target: u8,
+        action: u8,
+        token: u8,
+        base_addr: u64,
+        cpp_len: u8,
+    ) -> Result<(), NfpError> {
+        let cfg = Self::cfg_words(
+            self.exp_bar_map,
+            tgt_island_id,
+            target,
+            action,
+            token,
+            base_addr,
+            cpp_len,
+        )?;
+        self.write_cfg(cfg)
+    }
+
+    pub fn read(&self, offset: u64, length: u64) -> Result<Vec<u8>, NfpError> {
+        // Ensure offset and length are valid
+        if offset + length > self.exp_bar_size {
+            return Err(NfpError::AddressOutOfRange(format!(
+                "Requested region {:#x}+{:#x} exceeds mapped region of {:#x} bytes",
+                offset, length, self.exp_bar_size
+            )));
         }
+        self.window
+            .read(offset, length)
+            .map_err(|e| NfpError::io(format!("exp_bar{} read failed", self), e))
     }
 
-    pub fn write(&mut self, write_bytes: &[u8], offset: u64) {
-        if let Some(ref mut mmap) = self.mmap_region {
-            // Ensure offset and length are valid
-            if offset + write_bytes.len() as u64 > mmap.len() as u64 {
-                panic!("Requested region exceeds mapped region!");
-            }
-            // Directly copy the bytes into the mmap region
-            mmap[offset as usize..(offset as usize + write_bytes.len())]
-                .copy_from_slice(write_bytes);
-        } else {
-            panic!("Memory region not mapped!");
+    pub fn write(&mut self, write_bytes: &[u8], offset: u64) -> Result<(), NfpError> {
+        // Ensure offset and length are valid
+        if offset + write_bytes.len() as u64 > self.exp_bar_size {
+            return Err(NfpError::AddressOutOfRange(format!(
+                "Requested region {:#x}+{:#x} exceeds mapped region of {:#x} bytes",
+                offset,
+                write_bytes.len(),
+                self.exp_bar_size
+            )));
         }
+        self.window
+            .write(write_bytes, offset)
+            .map_err(|e| NfpError::io(format!("exp_bar{} write failed", self), e))
     }
 }
 
 impl Drop for ExpansionBar {
     fn drop(&mut self) {
-        // Unlock the file (using a blocking lock to ensure proper unlocking).
-        let _ = self.lock_file.unlock();
-        self.mmap_file = None;
-        self.mmap_region = None;
+        // Hand the expansion BAR back to the backend.
+        self.backend.unlock_exp_bar(self.phys_bar, self.exp_bar);
     }
 }
 
@@ -344,3 +339,76 @@ impl fmt::Display for ExpansionBar {
         write!(f, "{}.{}", self.phys_bar, self.exp_bar)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::sim_device::SimDevice;
+
+    /// Simulated NFP whose BAR locks cannot be taken, as with a lock
+    /// directory the user cannot write.
+    struct DeniedLocks(SimDevice);
+
+    impl DeviceBackend for DeniedLocks {
+        fn name(&self) -> &str {
+            self.0.name()
+        }
+
+        fn config_read(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+            self.0.config_read(offset, buf)
+        }
+
+        fn config_write(&self, offset: u64, data: &[u8]) -> io::Result<()> {
+            self.0.config_write(offset, data)
+        }
+
+        fn lock_exp_bar(&self, _phys_bar: u8, _exp_bar: u8) -> io::Result<()> {
+            Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Permission denied",
+            ))
+        }
+
+        fn unlock_exp_bar(&self, phys_bar: u8, exp_bar: u8) {
+            self.0.unlock_exp_bar(phys_bar, exp_bar)
+        }
+
+        fn lock_expl_bar(&self, expl_bar_index: u32) -> io::Result<()> {
+            self.0.lock_expl_bar(expl_bar_index)
+        }
+
+        fn unlock_expl_bar(&self, expl_bar_index: u32) {
+            self.0.unlock_expl_bar(expl_bar_index)
+        }
+
+        fn map_exp_bar(&self, phys_bar: u8, exp_bar: u8) -> io::Result<Box<dyn BarWindow>> {
+            self.0.map_exp_bar(phys_bar, exp_bar)
+        }
+    }
+
+    #[test]
+    fn lock_failures_are_not_contention() {
+        let sim: Arc<dyn DeviceBackend> = Arc::new(SimDevice::new());
+        let bars: Vec<_> = (0..CPP_MAX_NUM_EXPANSION_BARS)
+            .map(|_| ExpansionBar::new(&sim, None).unwrap())
+            .collect();
+        assert!(matches!(
+            ExpansionBar::new(&sim, None),
+            Err(NfpError::LockContention(_))
+        ));
+        assert!(matches!(
+            ExpansionBar::new(&sim, Some((bars[0].phys_bar, bars[0].exp_bar))),
+            Err(NfpError::LockContention(_))
+        ));
+
+        let denied: Arc<dyn DeviceBackend> = Arc::new(DeniedLocks(SimDevice::new()));
+        assert!(matches!(
+            ExpansionBar::new(&denied, None),
+            Err(NfpError::Io { .. })
+        ));
+        assert!(matches!(
+            ExpansionBar::new(&denied, Some((2, 0))),
+            Err(NfpError::Io { .. })
+        ));
+    }
+}
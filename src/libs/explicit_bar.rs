@@ -1,28 +1,64 @@
 #![allow(dead_code)]
 
-use crate::libs::cpp_bus::{CppIsland, CppLength};
+use crate::libs::common::split_addr48;
+use crate::libs::cpp_bus::{CppIsland, CppLength, CppTarget};
+use crate::libs::device_backend::DeviceBackend;
+use crate::libs::error::NfpError;
 use crate::libs::expansion_bar::{ExpansionBar, MapType, BAR_CONFIG_BASE_CONFIG_SNOOP};
 use bytemuck::cast_slice;
-use std::fs::OpenOptions;
-use std::io::{Seek, SeekFrom, Write};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::sync::Arc;
 
 // Number of explicit command BARs per PF.
-const NUM_EXPL_BARS: u32 = 4;
+pub const NUM_EXPL_BARS: u32 = 4;
 
 // Offset of explicit command BAR CSRs from PCIe BAR config base address.
-const CSR_EXPL_BASE_OFFSET: u32 = 0x180;
+pub const CSR_EXPL_BASE_OFFSET: u32 = 0x180;
 // Offset of explicit command BAR CSRs per explicit BAR.
-const CSR_EXPL_BAR_OFFSET: u32 = 0x10;
+pub const CSR_EXPL_BAR_OFFSET: u32 = 0x10;
 
 // Base address of PCIe SRAM in PCIe internal target.
-const PCIE_INT_SRAM_BASE: u32 = 0x40000;
+pub const PCIE_INT_SRAM_BASE: u32 = 0x40000;
 // Offset of explicit command data transfer memory in PCIe SRAM.
-const SRAM_DATA_BASE_OFFSET: u32 = 0xE000;
+pub const SRAM_DATA_BASE_OFFSET: u32 = 0xE000;
 // Offset of explicit command data per explicit command BAR.
-const SRAM_DATA_EXPL_BAR_OFFSET: u32 = 128;
+pub const SRAM_DATA_EXPL_BAR_OFFSET: u32 = 128;
+// Most data an explicit command can pull or push through SRAM, in 32-bit words.
+pub const EXPL_MAX_DATA_WORDS: u64 = (SRAM_DATA_EXPL_BAR_OFFSET / 4) as u64;
+
+/// Signal, byte mask and master fields of an explicit command, as passed to
+/// `ExplicitBar::explicit_bar_cfg`.
+///
+/// The default signals the command with signal type 1 and enables all bytes,
+/// as used for plain reads and writes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExplicitCmdFields {
+    pub sig_type: Option<u8>,
+    pub byte_mask: u8,
+    pub master_island: Option<u8>,
+    pub data_master: Option<u8>,
+    pub data_ref: Option<u16>,
+    pub signal_master: Option<u8>,
+    pub signal_ref: Option<u8>,
+}
+
+impl Default for ExplicitCmdFields {
+    fn default() -> Self {
+        ExplicitCmdFields {
+            sig_type: Some(1),
+            byte_mask: 0xFF,
+            master_island: None,
+            data_master: None,
+            data_ref: None,
+            signal_master: None,
+            signal_ref: None,
+        }
+    }
+}
 
 pub struct ExplicitBar {
-    pci_bdf: String,
+    backend: Arc<dyn DeviceBackend>,
     expl_bar_index: u32,
     trigger_exp_bar: ExpansionBar,
     data_exp_bar: ExpansionBar,
@@ -30,30 +66,91 @@ pub struct ExplicitBar {
 }
 
 impl ExplicitBar {
-    pub fn new(pci_bdf_str: &str, expl_bar_index: u32) -> Self {
-        let mut trigger_exp_bar = ExpansionBar::new(pci_bdf_str, None);
+    /// Lock explicit BAR `expl_bar_index`, or the first free explicit BAR if
+    /// `None`, and map it through two expansion BARs of the device.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::LockContention` if the explicit BAR, or every
+    /// explicit BAR, or the expansion BARs it needs, are held by someone
+    /// else.
+    pub fn new(
+        backend: &Arc<dyn DeviceBackend>,
+        expl_bar_index: Option<u32>,
+    ) -> Result<Self, NfpError> {
+        let expl_bar_index = if let Some(expl_bar_index) = expl_bar_index {
+            backend
+                .lock_expl_bar(expl_bar_index)
+                .map_err(|e| Self::lock_error(expl_bar_index, e))?;
+            expl_bar_index
+        } else {
+            Self::allocate_expl_bar(backend)?
+        };
+
+        let (trigger_exp_bar, data_exp_bar) = match Self::map_expl_bar(backend) {
+            Ok(exp_bars) => exp_bars,
+            Err(e) => {
+                backend.unlock_expl_bar(expl_bar_index);
+                return Err(e);
+            }
+        };
+
+        Ok(ExplicitBar {
+            backend: Arc::clone(backend),
+            expl_bar_index,
+            trigger_exp_bar,
+            data_exp_bar,
+            expl_bar_cached_cfg: [0; 4],
+        })
+    }
+
+    /// Map a failure to lock explicit BAR `expl_bar_index` onto an
+    /// `NfpError`, as `ExpansionBar` does for expansion BARs.
+    fn lock_error(expl_bar_index: u32, e: io::Error) -> NfpError {
+        if e.kind() == io::ErrorKind::WouldBlock {
+            NfpError::LockContention(format!("expl_bar{} is already locked", expl_bar_index))
+        } else {
+            NfpError::io(format!("Failed to lock expl_bar{}", expl_bar_index), e)
+        }
+    }
+
+    fn allocate_expl_bar(backend: &Arc<dyn DeviceBackend>) -> Result<u32, NfpError> {
+        for expl_bar_index in 0..NUM_EXPL_BARS {
+            match backend.lock_expl_bar(expl_bar_index) {
+                Ok(()) => return Ok(expl_bar_index),
+                // Continue to next explicit BAR if this one is taken.
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(Self::lock_error(expl_bar_index, e)),
+            }
+        }
+
+        Err(NfpError::LockContention(
+            "No explicit BARs available!".to_string(),
+        ))
+    }
+
+    /// Allocate and configure the expansion BARs triggering explicit
+    /// commands and reaching their data in PCIe SRAM.
+    fn map_expl_bar(
+        backend: &Arc<dyn DeviceBackend>,
+    ) -> Result<(ExpansionBar, ExpansionBar), NfpError> {
+        let mut trigger_exp_bar = ExpansionBar::new(backend, None)?;
         trigger_exp_bar.exp_bar_map = MapType::Explicit;
         // All fields are ignored when configuring the Explicit Bar.
         // The only relevant field is the MapType.
-        trigger_exp_bar.expansion_bar_cfg(0, 0, 0, 0, 0, 0);
-        let mut data_exp_bar = ExpansionBar::new(pci_bdf_str, None);
+        trigger_exp_bar.expansion_bar_cfg(0, 0, 0, 0, 0, 0)?;
+        let mut data_exp_bar = ExpansionBar::new(backend, None)?;
         data_exp_bar.exp_bar_map = MapType::General;
         data_exp_bar.expansion_bar_cfg(
-            CppIsland::Local.id(),
+            CppIsland::LOCAL.id(),
             0, // Unused for General mapping
             0, // Unused for General mapping
             0, // Unused for General mapping
             (PCIE_INT_SRAM_BASE + SRAM_DATA_BASE_OFFSET) as u64,
             CppLength::Len32.id(),
-        );
+        )?;
 
-        ExplicitBar {
-            pci_bdf: pci_bdf_str.to_string(),
-            expl_bar_index,
-            trigger_exp_bar,
-            data_exp_bar,
-            expl_bar_cached_cfg: [0; 4],
-        }
+        Ok((trigger_exp_bar, data_exp_bar))
     }
 
     pub fn expa_bar_offset(&self) -> u64 {
@@ -74,36 +171,26 @@ impl ExplicitBar {
             + (self.expl_bar_index * SRAM_DATA_EXPL_BAR_OFFSET)) as u64
     }
 
-    fn expl_bar_config_write(&self, cfg_reg0: u32, cfg_reg1: u32, cfg_reg2: u32, cfg_reg3: u32) {
-        let pcie_cfg_path = format!("/sys/bus/pci/devices/{}/config", &self.pci_bdf);
-
+    fn expl_bar_config_write(
+        &self,
+        cfg_reg0: u32,
+        cfg_reg1: u32,
+        cfg_reg2: u32,
+        cfg_reg3: u32,
+    ) -> Result<(), NfpError> {
         let expl_bar_csr_addr = BAR_CONFIG_BASE_CONFIG_SNOOP as u64 + self.csr_offset();
 
-        let mut pcie_cfg_file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open(&pcie_cfg_path)
-            .expect(&format!("Failed to open file {}", &pcie_cfg_path));
-
-        pcie_cfg_file
-            .seek(SeekFrom::Start(expl_bar_csr_addr as u64))
-            .expect(&format!("File {} seek failed", pcie_cfg_path));
-
         // Write using little-endian format
-        pcie_cfg_file
-            .write_all(&cfg_reg0.to_le_bytes())
-            .expect(&format!("File {} write failed", &pcie_cfg_path));
-        pcie_cfg_file
-            .write_all(&cfg_reg1.to_le_bytes())
-            .expect(&format!("File {} write failed", &pcie_cfg_path));
-        pcie_cfg_file
-            .write_all(&cfg_reg2.to_le_bytes())
-            .expect(&format!("File {} write failed", &pcie_cfg_path));
-        pcie_cfg_file
-            .write_all(&cfg_reg3.to_le_bytes())
-            .expect(&format!("File {} write failed", &pcie_cfg_path));
+        let cfg_bytes: Vec<u8> = [cfg_reg0, cfg_reg1, cfg_reg2, cfg_reg3]
+            .iter()
+            .flat_map(|cfg_reg| cfg_reg.to_le_bytes())
+            .collect();
+        self.backend
+            .config_write(expl_bar_csr_addr, &cfg_bytes)
+            .map_err(|e| NfpError::io(format!("{} config write failed", self.backend.name()), e))
     }
 
+    #[allow(clippy::identity_op, clippy::too_many_arguments)]
     pub fn explicit_bar_cfg(
         &self,
         tgt_island_id: u8,
@@ -116,10 +203,10 @@ impl ExplicitBar {
         byte_mask: u8,
         master_island: Option<u8>,
         data_master: Option<u8>,
-        data_ref: Option<u8>,
+        data_ref: Option<u16>,
         signal_master: Option<u8>,
         signal_ref: Option<u8>,
-    ) {
+    ) -> Result<(), NfpError> {
         // Check if the optional input parameters are valid.
         if sig_type.is_some()
             && (master_island.is_some()
@@ -128,20 +215,38 @@ impl ExplicitBar {
                 || signal_master.is_some()
                 || signal_ref.is_some())
         {
-            panic!(
+            return Err(NfpError::InvalidArgument(
                 "sig_type must not be Some() if any of the master or \
-                     reference parameters are Some()"
-            );
+                 reference parameters are Some()"
+                    .to_string(),
+            ));
+        }
+
+        // The fields are narrower than their types; reject values that
+        // would be truncated.
+        let fields = [
+            ("sig_type", sig_type.map(u16::from), 0x3),
+            ("master_island", master_island.map(u16::from), 0x7F),
+            ("data_master", data_master.map(u16::from), 0x1F),
+            ("signal_master", signal_master.map(u16::from), 0x1F),
+            ("signal_ref", signal_ref.map(u16::from), 0x7F),
+        ];
+        for (name, value, max) in fields {
+            if let Some(value) = value.filter(|value| *value > max) {
+                return Err(NfpError::InvalidArgument(format!(
+                    "Explicit command {} {:#x} exceeds the field maximum {:#x}",
+                    name, value, max
+                )));
+            }
         }
 
-        if (0..16).contains(
-            &((base_addr & base_addr.wrapping_neg()).leading_zeros() as u64).saturating_sub(1),
-        ) {
-            panic!(
-                "Explicit command BARs use a 32-bit base address. \
-                 The lower 16 bits of address {:#010x} would be truncated.",
+        // The base address field holds bits 47:16 of the address.
+        if base_addr & 0xFFFF != 0 || base_addr >> 48 != 0 {
+            return Err(NfpError::AddressOutOfRange(format!(
+                "Explicit command BAR base address {:#x} is not a 64 KiB aligned \
+                 48-bit address",
                 base_addr
-            );
+            )));
         }
 
         let (mut cfg0, mut cfg1, mut cfg2, mut cfg3): (u32, u32, u32, u32) = (0, 0, 0, 0);
@@ -164,38 +269,44 @@ impl ExplicitBar {
 
         cfg3 |= (base_addr >> 16) as u32 & 0xFFFFFFFF; // Base address field.
 
-        self.expl_bar_config_write(cfg0, cfg1, cfg2, cfg3);
+        self.expl_bar_config_write(cfg0, cfg1, cfg2, cfg3)
     }
 
-    fn trigger(&self, offset: u64, length_words: u64) -> Vec<u32> {
+    fn trigger(&self, offset: u64, length_words: u64) -> Result<Vec<u32>, NfpError> {
         let length_bytes = length_words * 4;
         let read_bytes: Vec<u8> = self
             .trigger_exp_bar
-            .read(self.expa_bar_offset() as u64 + offset, length_bytes);
+            .read(self.expa_bar_offset() + offset, length_bytes)?;
         let read_words_slice: &[u32] = cast_slice(&read_bytes);
-        read_words_slice.to_vec()
+        Ok(read_words_slice.to_vec())
     }
 
-    fn write_data(&mut self, data: Vec<u32>) {
+    fn write_data(&mut self, data: Vec<u32>) -> Result<(), NfpError> {
         if data.len() > ((SRAM_DATA_EXPL_BAR_OFFSET / 4) as usize) {
-            panic!("Length of data exceeds the SRAM size!");
+            return Err(NfpError::AddressOutOfRange(format!(
+                "Length of data ({} words) exceeds the SRAM size!",
+                data.len()
+            )));
         }
 
         let sram_addr = self.sram_data_offset();
         let write_bytes: Vec<u8> = cast_slice(&data).to_vec();
-        self.data_exp_bar.write(&write_bytes, sram_addr);
+        self.data_exp_bar.write(&write_bytes, sram_addr)
     }
 
-    fn read_data(&self, length_words: u64) -> Vec<u32> {
+    fn read_data(&self, length_words: u64) -> Result<Vec<u32>, NfpError> {
         if length_words > (SRAM_DATA_EXPL_BAR_OFFSET / 4).into() {
-            panic!("Length of data exceeds the SRAM size!");
+            return Err(NfpError::AddressOutOfRange(format!(
+                "Length of data ({} words) exceeds the SRAM size!",
+                length_words
+            )));
         }
 
         let sram_addr = self.sram_data_offset();
         let length_bytes: u64 = length_words * 4;
-        let read_bytes = self.data_exp_bar.read(sram_addr, length_bytes);
+        let read_bytes = self.data_exp_bar.read(sram_addr, length_bytes)?;
         let read_words_slice: &[u32] = cast_slice(&read_bytes);
-        read_words_slice.to_vec()
+        Ok(read_words_slice.to_vec())
     }
 
     pub fn run_explicit_cmd(
@@ -204,10 +315,10 @@ impl ExplicitBar {
         pull_data: Option<Vec<u32>>,
         push_data_len: Option<u64>,
         require_push_data_from_sram: bool,
-    ) -> Option<Vec<u32>> {
+    ) -> Result<Option<Vec<u32>>, NfpError> {
         // Write pull data if provided.
         if let Some(data) = pull_data {
-            self.write_data(data);
+            self.write_data(data)?;
         }
 
         // Constants for acceptable direct push data lengths.
@@ -215,23 +326,243 @@ impl ExplicitBar {
 
         // Determine if SRAM is required for push data.
         let use_sram = require_push_data_from_sram
-            || push_data_len.map_or(true, |len| !VALID_DIRECT_SIZES.contains(&len));
+            || push_data_len.is_none_or(|len| !VALID_DIRECT_SIZES.contains(&len));
 
         if use_sram {
             // Trigger explicit command by reading from expansion BAR.
-            self.trigger(offset, 1);
+            self.trigger(offset, 1)?;
 
             // If push_data_len is provided, read from SRAM.
             if let Some(len) = push_data_len {
-                return Some(self.read_data(len));
+                return Ok(Some(self.read_data(len)?));
             }
         } else {
             // Read directly from trigger expansion BAR.
             if let Some(len) = push_data_len {
-                return Some(self.trigger(offset, len));
+                return Ok(Some(self.trigger(offset, len)?));
             }
         }
 
-        None
+        Ok(None)
+    }
+
+    /// Configure and run a single explicit CPP command.
+    ///
+    /// # Parameters
+    ///
+    /// * `island`: Target island of the command.
+    /// * `target`: CPP target of the command.
+    /// * `action`: CPP action of the command.
+    /// * `token`: CPP token of the command.
+    /// * `address`: 48-bit CPP address of the command.
+    /// * `length`: Raw CPP length field of the command.
+    /// * `fields`: Signal, byte mask and master fields of the command.
+    /// * `pull_data`: Data the command pulls, for writes.
+    /// * `push_data_len`: Number of 32-bit words the command pushes, for reads
+    ///   and test atomics.
+    ///
+    /// # Returns
+    ///
+    /// The pushed data if `push_data_len` is given.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::InvalidArgument` if `fields` are inconsistent or do
+    /// not fit their command fields, or `NfpError::AddressOutOfRange` if
+    /// `address` is wider than 48 bits or the data does not fit the explicit
+    /// command SRAM.
+    #[allow(clippy::too_many_arguments)]
+    pub fn explicit_cmd(
+        &mut self,
+        island: CppIsland,
+        target: CppTarget,
+        action: u8,
+        token: u8,
+        address: u64,
+        length: u8,
+        fields: &ExplicitCmdFields,
+        pull_data: Option<Vec<u32>>,
+        push_data_len: Option<u64>,
+    ) -> Result<Option<Vec<u32>>, NfpError> {
+        if address >> 48 != 0 {
+            return Err(NfpError::AddressOutOfRange(format!(
+                "Explicit command address {:#x} is wider than 48 bits",
+                address
+            )));
+        }
+        let (base_addr, offset) = split_addr48(address, self.size());
+
+        self.explicit_bar_cfg(
+            island.id(),
+            target.id(),
+            action,
+            token,
+            base_addr,
+            fields.sig_type,
+            length,
+            fields.byte_mask,
+            fields.master_island,
+            fields.data_master,
+            fields.data_ref,
+            fields.signal_master,
+            fields.signal_ref,
+        )?;
+
+        // Commands that both pull and push, such as test atomics, return
+        // their push data through SRAM.
+        let push_data_from_sram = pull_data.is_some();
+        self.run_explicit_cmd(offset, pull_data, push_data_len, push_data_from_sram)
+    }
+}
+
+impl Drop for ExplicitBar {
+    fn drop(&mut self) {
+        // Hand the explicit BAR back to the backend.
+        self.backend.unlock_expl_bar(self.expl_bar_index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::sim_device::SimDevice;
+
+    fn expl_bar() -> ExplicitBar {
+        let backend: Arc<dyn DeviceBackend> = Arc::new(SimDevice::new());
+        ExplicitBar::new(&backend, Some(1)).unwrap()
+    }
+
+    fn cfg_words(expl_bar: &ExplicitBar) -> [u32; 4] {
+        let mut cfg_bytes = [0u8; 16];
+        expl_bar
+            .backend
+            .config_read(
+                BAR_CONFIG_BASE_CONFIG_SNOOP as u64 + expl_bar.csr_offset(),
+                &mut cfg_bytes,
+            )
+            .unwrap();
+        let cfg_words: &[u32] = cast_slice(&cfg_bytes);
+        cfg_words.try_into().unwrap()
+    }
+
+    #[test]
+    fn base_addresses_must_be_aligned_48_bit() {
+        let expl_bar = expl_bar();
+        let cfg = |base_addr| {
+            expl_bar.explicit_bar_cfg(
+                1,
+                7,
+                0,
+                0,
+                base_addr,
+                Some(1),
+                0,
+                0xFF,
+                None,
+                None,
+                None,
+                None,
+                None,
+            )
+        };
+
+        for base_addr in [0, 0x10000, 0x800000000000, 0xFFFFFFFF0000] {
+            cfg(base_addr).unwrap();
+            assert_eq!(cfg_words(&expl_bar)[3], (base_addr >> 16) as u32);
+        }
+        for base_addr in [0x1, 0x8000, 0x10008000, 1 << 48, u64::MAX] {
+            assert!(matches!(
+                cfg(base_addr),
+                Err(NfpError::AddressOutOfRange(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn fields_are_range_checked() {
+        let expl_bar = expl_bar();
+        let cfg = |fields: ExplicitCmdFields| {
+            expl_bar.explicit_bar_cfg(
+                1,
+                7,
+                0,
+                0,
+                0,
+                fields.sig_type,
+                0,
+                fields.byte_mask,
+                fields.master_island,
+                fields.data_master,
+                fields.data_ref,
+                fields.signal_master,
+                fields.signal_ref,
+            )
+        };
+        let masters = ExplicitCmdFields {
+            sig_type: None,
+            master_island: Some(0x7F),
+            data_master: Some(0x1F),
+            data_ref: Some(0x1FF),
+            signal_master: Some(0x1F),
+            signal_ref: Some(0x7F),
+            ..ExplicitCmdFields::default()
+        };
+
+        cfg(masters).unwrap();
+        let [_, cfg1, cfg2, _] = cfg_words(&expl_bar);
+        assert_eq!(cfg1 & 0xFFFF, 0x1FF);
+        assert_eq!((cfg1 >> 21) & 0x7F, 0x7F);
+        assert_eq!((cfg1 >> 16) & 0x1F, 0x1F);
+        assert_eq!(cfg2 & 0x7F1F, 0x7F1F);
+
+        for fields in [
+            ExplicitCmdFields {
+                master_island: Some(0x80),
+                ..masters
+            },
+            ExplicitCmdFields {
+                data_master: Some(0x20),
+                ..masters
+            },
+            ExplicitCmdFields {
+                signal_master: Some(0x20),
+                ..masters
+            },
+            ExplicitCmdFields {
+                signal_ref: Some(0x80),
+                ..masters
+            },
+            ExplicitCmdFields {
+                sig_type: Some(4),
+                ..ExplicitCmdFields::default()
+            },
+        ] {
+            assert!(matches!(cfg(fields), Err(NfpError::InvalidArgument(_))));
+        }
+    }
+
+    #[test]
+    fn explicit_bars_are_locked() {
+        let backend: Arc<dyn DeviceBackend> = Arc::new(SimDevice::new());
+        let expl_bar = ExplicitBar::new(&backend, Some(0)).unwrap();
+        assert!(matches!(
+            ExplicitBar::new(&backend, Some(0)),
+            Err(NfpError::LockContention(_))
+        ));
+
+        // Other users of the device get the remaining explicit BARs.
+        let others: Vec<ExplicitBar> = (1..NUM_EXPL_BARS)
+            .map(|_| ExplicitBar::new(&backend, None).unwrap())
+            .collect();
+        let mut indices: Vec<u32> = others.iter().map(|bar| bar.expl_bar_index).collect();
+        indices.sort();
+        assert_eq!(indices, (1..NUM_EXPL_BARS).collect::<Vec<_>>());
+        assert!(matches!(
+            ExplicitBar::new(&backend, None),
+            Err(NfpError::LockContention(_))
+        ));
+
+        drop(expl_bar);
+        assert_eq!(ExplicitBar::new(&backend, None).unwrap().expl_bar_index, 0);
     }
 }
@@ -1,11 +1,12 @@
 #![allow(dead_code)]
 
+use crate::libs::chip_desc::IslandKind;
 use crate::libs::cpp_bus::CppIsland;
-use crate::libs::expansion_bar::ExpansionBar;
-use crate::libs::explicit_bar::ExplicitBar;
+use crate::libs::error::NfpError;
 use crate::libs::mem_access::{mem_write, MemoryType, MuMemoryEngine};
+use crate::libs::nfp::Nfp;
 use crate::libs::rfpc::{Rfpc, RfpcCsr, RfpcGpr, RfpcReg};
-use crate::libs::rfpc_debugger::{rfpc_dbg_read_reg, rfpc_dbg_resume, rfpc_dbg_write_reg};
+use crate::libs::rfpc_debugger::{rfpc_dbg_read_reg, rfpc_dbg_write_reg};
 use bytemuck::cast_slice;
 use std::collections::HashMap;
 use std::io::{Read, Write};
@@ -20,16 +21,15 @@ const PORT: u16 = 12727;
 
 // Define the function type enum.
 #[derive(Clone)]
-enum FuncType<'a> {
+enum FuncType {
     Ascii(String),
-    NoArg(fn(&mut RspServer<'a>) -> String),
-    WithArg(fn(&mut RspServer<'a>, Vec<u8>) -> String),
+    NoArg(fn(&mut RspServer) -> Result<String, NfpError>),
+    WithArg(fn(&mut RspServer, Vec<u8>) -> Result<String, NfpError>),
 }
 
-pub struct RspServer<'a> {
-    exp_bar: &'a mut ExpansionBar,
-    expl_bar: &'a mut ExplicitBar,
-    cmd_resp_map: HashMap<String, Option<FuncType<'a>>>,
+pub struct RspServer {
+    nfp: Nfp,
+    cmd_resp_map: HashMap<String, Option<FuncType>>,
     server_kv_support: HashMap<String, String>,
     server_v_support: Vec<String>,
     client_kv_support: HashMap<String, String>,
@@ -37,18 +37,19 @@ pub struct RspServer<'a> {
     disable_ack: bool,
 }
 
-impl<'a> RspServer<'a> {
+impl RspServer {
     /// Creates a new instance of the `RspServer`.
     ///
     /// # Parameters
     ///
-    /// * `exp_bar: &'a mut ExpansionBar` - A mutable reference to an
-    ///   `ExpansionBar`.
+    /// * `nfp: Nfp` - Handle to the NFP being debugged. The server takes
+    ///   BARs from it for each command.
     ///
     /// # Returns
     ///
     /// `RspServer` instance.
-    pub fn new(exp_bar: &'a mut ExpansionBar, expl_bar: &'a mut ExplicitBar) -> Self {
+    #[allow(clippy::vec_init_then_push)]
+    pub fn new(nfp: Nfp) -> Self {
         let mut cmd_resp_map: HashMap<String, Option<FuncType>> = HashMap::new();
         cmd_resp_map.insert(
             "!".to_string(),
@@ -116,8 +117,7 @@ impl<'a> RspServer<'a> {
 
         // Return the server struct.
         RspServer {
-            exp_bar,
-            expl_bar,
+            nfp,
             cmd_resp_map,
             server_kv_support,
             server_v_support,
@@ -133,8 +133,8 @@ impl<'a> RspServer<'a> {
     /// # Returns
     ///
     /// `String` - Empty string.
-    fn cmd_not_supported(&mut self) -> String {
-        "".to_string()
+    fn cmd_not_supported(&mut self) -> Result<String, NfpError> {
+        Ok("".to_string())
     }
 
     /// Returns a concatenated string of the all the GPR register values
@@ -143,156 +143,157 @@ impl<'a> RspServer<'a> {
     /// # Returns
     ///
     /// `String` - Concatenated list of GPR values.
-    fn read_gprs(&mut self) -> String {
+    fn read_gprs(&mut self) -> Result<String, NfpError> {
         let mut gprs = String::new();
         let rfpc = Rfpc {
-            island: CppIsland::Rfpc0,
+            island: CppIsland::first_of_kind(IslandKind::Rfpc),
             cluster: 0,
             group: 0,
             core: 0,
         };
+        let mut expl_bar = self.nfp.expl_bar()?;
 
         // Read all the GPRs and send them to the debug client.
         let mut reg_addr = RfpcGpr::X0.reg_addr();
-        let mut reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        let mut reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X1.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X2.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X3.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X4.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X5.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X6.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X7.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X8.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X9.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X10.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X11.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X12.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X13.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X14.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X15.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X16.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X17.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X18.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X19.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X20.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X21.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X22.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X23.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X24.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X25.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X26.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X27.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X28.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X29.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X30.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         reg_addr = RfpcGpr::X31.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
         // Read the program counter and send to the debug client.
         reg_addr = RfpcCsr::Dpc.reg_addr();
-        reg_val = rfpc_dbg_read_reg(self.expl_bar, &rfpc, reg_addr);
+        reg_val = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, reg_addr)?;
         gprs.push_str(&format!("{:016x}", reg_val.swap_bytes()));
 
-        gprs
+        Ok(gprs)
     }
 
-    fn write_csr(&mut self, packet: Vec<u8>) -> String {
+    fn write_csr(&mut self, packet: Vec<u8>) -> Result<String, NfpError> {
         // Create RFPC instance.
         let rfpc = Rfpc {
-            island: CppIsland::Rfpc0,
+            island: CppIsland::first_of_kind(IslandKind::Rfpc),
             cluster: 0,
             group: 0,
             core: 0,
@@ -309,10 +310,10 @@ impl<'a> RspServer<'a> {
 
         if address == 32 {
             let dpc = RfpcCsr::Dpc.reg_addr();
-            rfpc_dbg_write_reg(self.expl_bar, &rfpc, dpc, value.swap_bytes());
+            rfpc_dbg_write_reg(&mut *self.nfp.expl_bar()?, &rfpc, dpc, value.swap_bytes())?;
         }
 
-        "OK".to_string()
+        Ok("OK".to_string())
     }
 
     /// Load a program segment into the appropriate memory that is
@@ -326,7 +327,8 @@ impl<'a> RspServer<'a> {
     /// # Returns
     ///
     /// * `String` - Returns OK on successful memory write.
-    fn load_segment(&mut self, packet: Vec<u8>) -> String {
+    #[allow(clippy::manual_split_once, clippy::needless_borrow)]
+    fn load_segment(&mut self, packet: Vec<u8>) -> Result<String, NfpError> {
         // Find the position of the colon.
         let colon_index = packet.iter().position(|&b| b == b':').unwrap();
 
@@ -347,7 +349,7 @@ impl<'a> RspServer<'a> {
 
         // The first loaded segment will always be a length of zero and should return OK.
         if length == 0 {
-            return "OK".to_string();
+            return Ok("OK".to_string());
         }
 
         println!("address: {:x}", &address);
@@ -363,7 +365,9 @@ impl<'a> RspServer<'a> {
         let remainder = length as usize % 4;
         if remainder != 0 {
             packet_data.extend(vec![0; 4 - remainder]);
-            panic!("Program segment not a multiple of u32");
+            return Err(NfpError::InvalidArgument(
+                "Program segment not a multiple of u32".to_string(),
+            ));
         }
 
         // Cast the byte slice to u32 vec safely.
@@ -371,22 +375,22 @@ impl<'a> RspServer<'a> {
 
         // Write program segment to memory.
         mem_write(
-            self.exp_bar,
-            CppIsland::Rfpc0,
+            &mut *self.nfp.exp_bar()?,
+            CppIsland::first_of_kind(IslandKind::Rfpc),
             MemoryType::Ctm,
             MuMemoryEngine::Bulk32,
             address,
             program_data,
-        );
+        )?;
 
-        "OK".to_string()
+        Ok("OK".to_string())
     }
 
     /// Code is not being relocated because the ELF file is assumed to be
     /// statically linked. Therefore the offsets in the address are the offsets
     /// we use on the chip.
-    fn load_offsets(&mut self) -> String {
-        "Text=000;Data=000;Bss=000".to_string()
+    fn load_offsets(&mut self) -> Result<String, NfpError> {
+        Ok("Text=000;Data=000;Bss=000".to_string())
     }
 
     /// This method parses the input packet to extract the features that
@@ -402,7 +406,7 @@ impl<'a> RspServer<'a> {
     ///
     /// A semicolon-separated string of supported values and key-value
     /// pairs.
-    fn supported_features(&mut self, packet: Vec<u8>) -> String {
+    fn supported_features(&mut self, packet: Vec<u8>) -> Result<String, NfpError> {
         let colon_index = packet.iter().position(|&b| b == b':').unwrap();
         let args = String::from_utf8_lossy(&packet[colon_index + 1..]).to_string();
 
@@ -431,7 +435,7 @@ impl<'a> RspServer<'a> {
         );
 
         // Return the joined response
-        response.join(";")
+        Ok(response.join(";"))
     }
 
     /// Disable packet +/- ACK NACK.
@@ -442,11 +446,11 @@ impl<'a> RspServer<'a> {
     /// # Returns
     ///
     /// A String confirming operation completed successfully.
-    pub fn toggle_ack(&mut self) -> String {
+    pub fn toggle_ack(&mut self) -> Result<String, NfpError> {
         if !self.disable_ack {
             self.disable_ack = true;
         }
-        "OK".to_string()
+        Ok("OK".to_string())
     }
 
     /// Handles an incoming RSP packet and determines what type of
@@ -460,6 +464,7 @@ impl<'a> RspServer<'a> {
     ///
     /// `Option<String>` - A String Option with return value sent back
     /// to the GDB client.
+    #[allow(clippy::unnecessary_lazy_evaluations)]
     fn handle_packet(&mut self, packet: Vec<u8>) -> Option<String> {
         // Extract the command
         let colon_index = packet
@@ -472,8 +477,10 @@ impl<'a> RspServer<'a> {
         if let Some(response) = self.cmd_resp_map.get(&rsp_command) {
             match response {
                 Some(FuncType::Ascii(resp)) => return Some(resp.to_string()),
-                Some(FuncType::NoArg(func)) => return Some(func(self)),
-                Some(FuncType::WithArg(func)) => return Some(func(self, packet)),
+                Some(FuncType::NoArg(func)) => return Some(Self::handler_reply(func(self))),
+                Some(FuncType::WithArg(func)) => {
+                    return Some(Self::handler_reply(func(self, packet)))
+                }
                 None => return None,
             }
         }
@@ -485,15 +492,27 @@ impl<'a> RspServer<'a> {
         if let Some(response) = self.cmd_resp_map.get(first_char_str) {
             match response {
                 Some(FuncType::Ascii(resp)) => return Some(resp.to_string()),
-                Some(FuncType::NoArg(func)) => return Some(func(self)),
-                Some(FuncType::WithArg(func)) => return Some(func(self, packet)),
+                Some(FuncType::NoArg(func)) => return Some(Self::handler_reply(func(self))),
+                Some(FuncType::WithArg(func)) => {
+                    return Some(Self::handler_reply(func(self, packet)))
+                }
                 None => return None,
             }
         }
 
         // If neither the command nor the first character is found
         println!("Unknown RSP command {}", rsp_command);
-        Some(self.cmd_not_supported())
+        Some(String::new())
+    }
+
+    /// Turns the result of an RSP command handler into the reply sent to
+    /// the GDB client. Errors are reported to the client as an `E01` reply
+    /// instead of bringing down the server.
+    fn handler_reply(result: Result<String, NfpError>) -> String {
+        result.unwrap_or_else(|e| {
+            println!("RSP command failed: {}", e);
+            "E01".to_string()
+        })
     }
 
     /// Calculates the checksum for an RSP packet.
@@ -509,6 +528,7 @@ impl<'a> RspServer<'a> {
     /// # Returns
     ///
     /// `u8` - value representing the computed checksum.
+    #[allow(clippy::ptr_arg)]
     fn calculate_rsp_checksum(&self, data: &Vec<u8>) -> u8 {
         data.iter().fold(0, |acc, &b| acc.wrapping_add(b))
     }
@@ -638,6 +658,7 @@ impl<'a> RspServer<'a> {
     ///   indicating whether the server should continue running. When
     ///   this flag is set to `false`, the server will gracefully shut
     ///   down.
+    #[allow(clippy::single_match)]
     pub fn run(&mut self, running: Arc<AtomicBool>) {
         // Bind to an address and port.
         let listener =
@@ -693,3 +714,52 @@ impl<'a> RspServer<'a> {
         println!("Server shutting down gracefully.");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::rfpc_debugger::rfpc_dbg_halt;
+    use crate::libs::sim_device::SimDevice;
+
+    fn first_rfpc() -> Rfpc {
+        Rfpc {
+            island: CppIsland::first_of_kind(IslandKind::Rfpc),
+            cluster: 0,
+            group: 0,
+            core: 0,
+        }
+    }
+
+    #[test]
+    fn read_and_write_registers() {
+        let sim = SimDevice::new();
+        let nfp = Nfp::new(Arc::new(sim.clone())).unwrap();
+        let rfpc = first_rfpc();
+        let dm = sim.debug_module(&rfpc).unwrap();
+        dm.write_reg(&rfpc, &RfpcGpr::X1, 0x1122_3344_5566_7788);
+        dm.write_reg(&rfpc, &RfpcCsr::Dpc, 0x8000_0000);
+
+        let mut server = RspServer::new(nfp.clone());
+
+        // Register accesses fail while the hart is running.
+        assert_eq!(server.handle_packet(b"g".to_vec()), Some("E01".to_string()));
+
+        rfpc_dbg_halt(&mut nfp.expl_bar().unwrap(), &rfpc).unwrap();
+        let gprs = server.handle_packet(b"g".to_vec()).unwrap();
+        // x0-x31 and the PC, each as 16 hex digits in target byte order.
+        assert_eq!(gprs.len(), 33 * 16);
+        assert_eq!(&gprs[16..32], "8877665544332211");
+        assert_eq!(&gprs[32 * 16..], "0000008000000000");
+
+        let reply = server.handle_packet(b"P20=0000001000000000".to_vec());
+        assert_eq!(reply, Some("OK".to_string()));
+        assert_eq!(dm.read_reg(&rfpc, &RfpcCsr::Dpc), 0x1000_0000);
+    }
+
+    #[test]
+    fn format_packet() {
+        let sim = SimDevice::new();
+        let server = RspServer::new(Nfp::new(Arc::new(sim)).unwrap());
+        assert_eq!(server.format_rsp_packet("OK"), "$OK#9a");
+    }
+}
@@ -1,10 +1,18 @@
 #![allow(dead_code)]
 use std::fmt;
+use std::io::{Read, Write};
 
+use bytemuck::cast_slice;
 use clap::ValueEnum;
 
+use crate::libs::chip_desc::{chip_desc, IslandKind};
+use crate::libs::common::split_addr48;
 use crate::libs::cpp_bus::{CppBus, CppIsland, CppLength, CppTarget};
+use crate::libs::error::NfpError;
 use crate::libs::expansion_bar::{ExpansionBar, MapType};
+use crate::libs::explicit_bar::ExplicitBar;
+use crate::libs::rfpc::Rfpc;
+use crate::libs::rfpc_debugger::{rfpc_dbg_read_memory, rfpc_dbg_write_memory};
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
 pub enum MuMemoryEngine {
@@ -52,11 +60,174 @@ impl fmt::Display for MuMemoryEngine {
     }
 }
 
+// MU atomic command tokens, from the MU command table of the databook. The
+// pull forms take their operands as pull data; the immediate forms pull
+// nothing. The test forms also push back the previous value of the word.
+const MU_ATOMIC_TOKEN_PULL: u8 = 0;
+const MU_ATOMIC_TOKEN_TEST_PULL: u8 = 1;
+const MU_ATOMIC_TOKEN_IMM: u8 = 2;
+const MU_ATOMIC_TOKEN_TEST_IMM: u8 = 3;
+// Bits set by set_imm: those of the byte mask of a 32-bit command to a
+// 64-bit aligned word.
+const MU_SET_IMM_BITS: u32 = 0xF;
+
+/// MU atomic operation on a 32-bit word.
+///
+/// Every operation has a plain variant, which only applies the operation,
+/// and a test variant, which also pushes back the value the word held before
+/// the operation.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+pub enum MuAtomicOp {
+    /// Set the bits given by the operand.
+    Set,
+    /// Clear the bits given by the operand.
+    Clr,
+    /// Add the operand.
+    Add,
+    /// Subtract the operand.
+    Sub,
+    /// Add one. Immediate, takes no operand.
+    Incr,
+    /// Subtract one. Immediate, takes no operand.
+    Decr,
+    /// Set the low 4 bits of a 64-bit aligned word. Immediate, takes no
+    /// operand. The test variant takes firmware locks.
+    SetImm,
+    /// Replace the word with the operand. The plain variant is an atomic write.
+    Swap,
+    /// Replace the word with the second operand if it equals the first.
+    /// Only available as a test variant.
+    CompareWrite,
+}
+
+impl MuAtomicOp {
+    /// Return the CPP (action, token) combination for the operation.
+    /// `test` selects the variant that returns the previous value.
+    pub fn command(&self, test: bool) -> (u8, u8) {
+        let token = match (self.is_immediate(), test) {
+            (false, false) => MU_ATOMIC_TOKEN_PULL,
+            (false, true) => MU_ATOMIC_TOKEN_TEST_PULL,
+            (true, false) => MU_ATOMIC_TOKEN_IMM,
+            (true, true) => MU_ATOMIC_TOKEN_TEST_IMM,
+        };
+        match self {
+            MuAtomicOp::Set | MuAtomicOp::SetImm => (5, token),
+            MuAtomicOp::Clr => (6, token),
+            MuAtomicOp::Add | MuAtomicOp::Incr => (7, token),
+            MuAtomicOp::Sub | MuAtomicOp::Decr => (9, token),
+            MuAtomicOp::Swap => (4, token),
+            MuAtomicOp::CompareWrite => (3, MU_ATOMIC_TOKEN_TEST_PULL),
+        }
+    }
+
+    /// Decode a CPP (action, token) combination on the MU target into an
+    /// atomic operation and whether it is the test variant. Atomic writes
+    /// decode as a plain `Swap`.
+    pub fn from_command(action: u8, token: u8) -> Option<(MuAtomicOp, bool)> {
+        let op = match (action, token) {
+            (3, MU_ATOMIC_TOKEN_TEST_PULL) => MuAtomicOp::CompareWrite,
+            (4, MU_ATOMIC_TOKEN_PULL | MU_ATOMIC_TOKEN_TEST_PULL) => MuAtomicOp::Swap,
+            (5, MU_ATOMIC_TOKEN_PULL | MU_ATOMIC_TOKEN_TEST_PULL) => MuAtomicOp::Set,
+            (6, MU_ATOMIC_TOKEN_PULL | MU_ATOMIC_TOKEN_TEST_PULL) => MuAtomicOp::Clr,
+            (7, MU_ATOMIC_TOKEN_PULL | MU_ATOMIC_TOKEN_TEST_PULL) => MuAtomicOp::Add,
+            (9, MU_ATOMIC_TOKEN_PULL | MU_ATOMIC_TOKEN_TEST_PULL) => MuAtomicOp::Sub,
+            (5, MU_ATOMIC_TOKEN_IMM | MU_ATOMIC_TOKEN_TEST_IMM) => MuAtomicOp::SetImm,
+            (7, MU_ATOMIC_TOKEN_IMM | MU_ATOMIC_TOKEN_TEST_IMM) => MuAtomicOp::Incr,
+            (9, MU_ATOMIC_TOKEN_IMM | MU_ATOMIC_TOKEN_TEST_IMM) => MuAtomicOp::Decr,
+            _ => return None,
+        };
+
+        let test = matches!(token, MU_ATOMIC_TOKEN_TEST_PULL | MU_ATOMIC_TOKEN_TEST_IMM);
+        Some((op, test))
+    }
+
+    /// Return whether the operation is an immediate one, which pulls no
+    /// operands.
+    pub fn is_immediate(&self) -> bool {
+        matches!(
+            self,
+            MuAtomicOp::Incr | MuAtomicOp::Decr | MuAtomicOp::SetImm
+        )
+    }
+
+    /// Number of operands the caller provides for the operation.
+    pub fn operand_count(&self) -> usize {
+        match self {
+            MuAtomicOp::Incr | MuAtomicOp::Decr | MuAtomicOp::SetImm => 0,
+            MuAtomicOp::CompareWrite => 2,
+            _ => 1,
+        }
+    }
+
+    /// Check the number of operands given for the operation.
+    fn check_operands(&self, operands: &[u32]) -> Result<(), NfpError> {
+        if operands.len() != self.operand_count() {
+            return Err(NfpError::InvalidArgument(format!(
+                "MU atomic {} takes {} operand(s), got {}",
+                self,
+                self.operand_count(),
+                operands.len()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Return the new value of a word holding `previous` after the operation,
+    /// given the data pulled by the CPP command. Immediate operations ignore
+    /// the pull data.
+    pub fn apply(&self, previous: u32, pull_data: &[u32]) -> u32 {
+        match self {
+            MuAtomicOp::Set => previous | pull_data[0],
+            MuAtomicOp::Clr => previous & !pull_data[0],
+            MuAtomicOp::Add => previous.wrapping_add(pull_data[0]),
+            MuAtomicOp::Sub => previous.wrapping_sub(pull_data[0]),
+            MuAtomicOp::Incr => previous.wrapping_add(1),
+            MuAtomicOp::Decr => previous.wrapping_sub(1),
+            MuAtomicOp::SetImm => previous | MU_SET_IMM_BITS,
+            MuAtomicOp::Swap => pull_data[0],
+            MuAtomicOp::CompareWrite => {
+                if previous == pull_data[0] {
+                    pull_data[1]
+                } else {
+                    previous
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for MuAtomicOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MuAtomicOp::Set => write!(f, "set"),
+            MuAtomicOp::Clr => write!(f, "clr"),
+            MuAtomicOp::Add => write!(f, "add"),
+            MuAtomicOp::Sub => write!(f, "sub"),
+            MuAtomicOp::Incr => write!(f, "incr"),
+            MuAtomicOp::Decr => write!(f, "decr"),
+            MuAtomicOp::SetImm => write!(f, "set_imm"),
+            MuAtomicOp::Swap => write!(f, "swap"),
+            MuAtomicOp::CompareWrite => write!(f, "compare_write"),
+        }
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
 pub enum MemoryType {
+    /// External memory of an EMU island.
     Emem,
+    /// Internal memory of an EMU island.
+    Imem,
+    /// Cluster target memory of an RFPC island.
     Ctm,
+    /// Cluster local scratch of an RFPC island.
     Cls,
+    /// SRAM of a PCIe island.
+    PcieSram,
+    /// Memory of the ARM (chip exec) island.
+    Arm,
+    /// Local memory of an RFPC core.
+    Lmem,
 }
 
 impl MemoryType {
@@ -64,8 +235,68 @@ impl MemoryType {
     pub fn locality(&self) -> &'static str {
         match self {
             MemoryType::Emem => "global",
+            MemoryType::Imem => "global",
             MemoryType::Ctm => "island",
             MemoryType::Cls => "island",
+            MemoryType::PcieSram => "island",
+            MemoryType::Arm => "island",
+            MemoryType::Lmem => "core",
+        }
+    }
+
+    /// Return the name of the memory in the chip description.
+    pub fn name(&self) -> &'static str {
+        match self {
+            MemoryType::Emem => "emem",
+            MemoryType::Imem => "imem",
+            MemoryType::Ctm => "ctm",
+            MemoryType::Cls => "cls",
+            MemoryType::PcieSram => "pcie_sram",
+            MemoryType::Arm => "arm",
+            MemoryType::Lmem => "lmem",
+        }
+    }
+
+    /// Return whether the memory is an MU memory, which supports the
+    /// `MuMemoryEngine` commands and MU atomic operations.
+    pub fn is_mu(&self) -> bool {
+        matches!(self, MemoryType::Emem | MemoryType::Imem | MemoryType::Ctm)
+    }
+
+    /// Return the CPP (action, token) combinations for CPP read and write,
+    /// and the CPP length, to access the memory with `engine`. The engine
+    /// only applies to MU memories.
+    pub(crate) fn cpp_commands(&self, engine: MuMemoryEngine) -> ((u8, u8), (u8, u8), CppLength) {
+        if self.is_mu() {
+            (
+                engine.read_command(),
+                engine.write_command(),
+                engine.cpp_length(),
+            )
+        } else {
+            ((0, 0), (1, 0), CppLength::Len32)
+        }
+    }
+
+    /// Return the expansion BAR mapping used to access the memory. The PCIe
+    /// SRAM sits in the PCIe internal target, which is only reached through
+    /// a General mapping, as the explicit BAR data window does.
+    pub(crate) fn map_type(&self) -> MapType {
+        match self {
+            MemoryType::PcieSram => MapType::General,
+            _ => MapType::Fixed,
+        }
+    }
+
+    /// Return the memory type the tools use by default for data in
+    /// `island`: EMEM in EMU islands, ARM memory in the chip exec island,
+    /// PCIe SRAM in PCIe islands and CTM elsewhere.
+    pub fn default_for_island(island: CppIsland) -> MemoryType {
+        match island.kind() {
+            IslandKind::Emu => MemoryType::Emem,
+            IslandKind::ChipExec => MemoryType::Arm,
+            IslandKind::Pcie | IslandKind::Local => MemoryType::PcieSram,
+            _ => MemoryType::Ctm,
         }
     }
 }
@@ -74,12 +305,80 @@ impl fmt::Display for MemoryType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             MemoryType::Emem => write!(f, "Emem"),
+            MemoryType::Imem => write!(f, "Imem"),
             MemoryType::Ctm => write!(f, "Ctm"),
             MemoryType::Cls => write!(f, "Cls"),
+            MemoryType::PcieSram => write!(f, "PcieSram"),
+            MemoryType::Arm => write!(f, "Arm"),
+            MemoryType::Lmem => write!(f, "Lmem"),
         }
     }
 }
 
+/// Return the size in bytes of `mem_type` in `cpp_island`.
+///
+/// # Errors
+///
+/// Returns `NfpError::InvalidArgument` if the island holds no such memory.
+pub fn mem_size(cpp_island: CppIsland, mem_type: MemoryType) -> Result<u64, NfpError> {
+    chip_desc()
+        .memory(mem_type.name(), cpp_island.id())
+        .map(|memory| memory.size)
+        .ok_or_else(|| {
+            NfpError::InvalidArgument(format!(
+                "Island {} has no {} memory",
+                cpp_island,
+                mem_type.name()
+            ))
+        })
+}
+
+/// Return the CPP target and address of `length` bytes at `address` in
+/// `mem_type`, checking that the memory exists in `cpp_island` and that the
+/// bytes lie within it.
+pub(crate) fn mem_cpp_address(
+    cpp_island: CppIsland,
+    mem_type: MemoryType,
+    address: u64,
+    length: u64,
+) -> Result<(CppTarget, u64), NfpError> {
+    let size = mem_size(cpp_island, mem_type)?;
+    let memory = chip_desc()
+        .memory(mem_type.name(), cpp_island.id())
+        .unwrap();
+
+    let Some(target) = memory.target.as_deref().map(CppTarget::named).transpose()? else {
+        return Err(NfpError::InvalidArgument(format!(
+            "{} memory is local to an RFPC core, use lmem_read/lmem_write",
+            mem_type
+        )));
+    };
+
+    if address.checked_add(length).is_none_or(|end| end > size) {
+        return Err(NfpError::AddressOutOfRange(format!(
+            "Access of {:#x} bytes at {:#x} exceeds {} memory of size {:#x} in island {}",
+            length, address, mem_type, size, cpp_island
+        )));
+    }
+
+    Ok((target, memory.base + address))
+}
+
+/// Return the number of bytes in `length` 32-bit words.
+///
+/// # Errors
+///
+/// Returns `NfpError::AddressOutOfRange` if the byte count does not fit in
+/// 64 bits.
+pub(crate) fn word_bytes(length: u64) -> Result<u64, NfpError> {
+    length.checked_mul(4).ok_or_else(|| {
+        NfpError::AddressOutOfRange(format!(
+            "Access of {:#x} words does not fit in 64 bits of address",
+            length
+        ))
+    })
+}
+
 pub fn mem_read(
     exp_bar: &mut ExpansionBar,
     cpp_island: CppIsland,
@@ -87,38 +386,23 @@ pub fn mem_read(
     engine: MuMemoryEngine,
     address: u64,
     length: u64,
-) -> Vec<u32> {
-    // Ensure expansion BAR gets configured with Fixed mapping.
-    if exp_bar.exp_bar_map != MapType::Fixed {
-        exp_bar.exp_bar_map = MapType::Fixed;
-    }
+) -> Result<Vec<u32>, NfpError> {
+    let (target, cpp_address) =
+        mem_cpp_address(cpp_island, mem_type, address, word_bytes(length)?)?;
+    let ((action, token), _, cpp_length) = mem_type.cpp_commands(engine);
 
     // Instantiate Cpp bus with allocated expansion BAR.
-    let mut cpp_bus = CppBus::new(exp_bar);
+    let mut cpp_bus = CppBus::with_map_type(exp_bar, mem_type.map_type());
 
-    match mem_type {
-        MemoryType::Emem | MemoryType::Ctm => {
-            let (action, token) = engine.read_command();
-            cpp_bus.read(
-                cpp_island,
-                CppTarget::Mem,
-                action,
-                token,
-                engine.cpp_length(),
-                address,
-                length,
-            )
-        }
-        MemoryType::Cls => cpp_bus.read(
-            cpp_island,
-            CppTarget::Cls,
-            0,
-            0,
-            CppLength::Len32,
-            address,
-            length,
-        ),
-    }
+    cpp_bus.read(
+        cpp_island,
+        target,
+        action,
+        token,
+        cpp_length,
+        cpp_address,
+        length,
+    )
 }
 
 pub fn mem_write(
@@ -128,36 +412,835 @@ pub fn mem_write(
     engine: MuMemoryEngine,
     address: u64,
     values: Vec<u32>,
-) {
-    // Ensure expansion BAR gets configured with Fixed mapping.
-    if exp_bar.exp_bar_map != MapType::Fixed {
-        exp_bar.exp_bar_map = MapType::Fixed;
+) -> Result<(), NfpError> {
+    let (target, cpp_address) = mem_cpp_address(
+        cpp_island,
+        mem_type,
+        address,
+        word_bytes(values.len() as u64)?,
+    )?;
+    let (_, (action, token), cpp_length) = mem_type.cpp_commands(engine);
+
+    // Instantiate Cpp bus with allocated expansion BAR.
+    let mut cpp_bus = CppBus::with_map_type(exp_bar, mem_type.map_type());
+
+    cpp_bus.write(
+        cpp_island,
+        target,
+        action,
+        token,
+        cpp_length,
+        cpp_address,
+        values,
+    )
+}
+
+/// Read `length` 32-bit words of the local memory of an RFPC core.
+///
+/// Local memory is not reachable over CPP, so the words are read by the
+/// core itself through its debug module. The core must be halted.
+///
+/// # Errors
+///
+/// Returns `NfpError::AddressOutOfRange` if the words are outside the local
+/// memory.
+pub fn lmem_read(
+    expl_bar: &mut ExplicitBar,
+    rfpc: &Rfpc,
+    address: u64,
+    length: u64,
+) -> Result<Vec<u32>, NfpError> {
+    check_lmem_region(rfpc, address, word_bytes(length)?)?;
+    rfpc_dbg_read_memory(expl_bar, rfpc, address, length)
+}
+
+/// Write 32-bit words to the local memory of an RFPC core.
+///
+/// See `lmem_read`.
+pub fn lmem_write(
+    expl_bar: &mut ExplicitBar,
+    rfpc: &Rfpc,
+    address: u64,
+    values: Vec<u32>,
+) -> Result<(), NfpError> {
+    check_lmem_region(rfpc, address, word_bytes(values.len() as u64)?)?;
+    rfpc_dbg_write_memory(expl_bar, rfpc, address, values)
+}
+
+fn check_lmem_region(rfpc: &Rfpc, address: u64, length: u64) -> Result<(), NfpError> {
+    let size = mem_size(rfpc.island, MemoryType::Lmem)?;
+    if address.checked_add(length).is_none_or(|end| end > size) {
+        return Err(NfpError::AddressOutOfRange(format!(
+            "Access of {:#x} bytes at {:#x} exceeds local memory of size {:#x} of RFPC {}",
+            length, address, size, rfpc
+        )));
+    }
+    Ok(())
+}
+
+/// Check that MU atomic `op` can be issued to `mem_type` at `address`.
+fn check_mu_atomic(op: MuAtomicOp, mem_type: MemoryType, address: u64) -> Result<(), NfpError> {
+    if !mem_type.is_mu() {
+        return Err(NfpError::InvalidArgument(format!(
+            "MU atomic operations are not supported on {}",
+            mem_type
+        )));
+    }
+
+    // set_imm sets the bits of the byte mask, which only selects the low
+    // bits of the word at 64-bit aligned addresses.
+    let alignment = if op == MuAtomicOp::SetImm { 8 } else { 4 };
+    if !address.is_multiple_of(alignment) {
+        return Err(NfpError::AddressOutOfRange(format!(
+            "MU atomic {} address {:#x} is not {}-bit aligned",
+            op,
+            address,
+            alignment * 8
+        )));
     }
 
+    Ok(())
+}
+
+/// Apply an MU atomic operation to `length` consecutive 32-bit words.
+///
+/// The plain variant of the operation is issued through the expansion BAR,
+/// so the previous values are not returned. Use `mem_atomic_test` to
+/// retrieve them. Immediate operations pull nothing, so they are issued as
+/// reads of their test variant, as `mem_atomic_imm_test` does, and the
+/// previous values are discarded.
+///
+/// # Parameters
+///
+/// * `exp_bar`: Expansion BAR used to issue the operation.
+/// * `cpp_island`: Island holding the memory.
+/// * `mem_type`: Memory to operate on. Must be an MU memory.
+/// * `op`: Atomic operation.
+/// * `address`: 32-bit aligned address of the first word.
+/// * `operands`: Operands of the operation, see `MuAtomicOp::operand_count`.
+/// * `length`: Number of words to apply the operation to.
+///
+/// # Errors
+///
+/// Returns `NfpError::InvalidArgument` for memories other than MU memories,
+/// for `CompareWrite`, which
+/// has no plain variant, or for the wrong number of operands.
+pub fn mem_atomic(
+    exp_bar: &mut ExpansionBar,
+    cpp_island: CppIsland,
+    mem_type: MemoryType,
+    op: MuAtomicOp,
+    address: u64,
+    operands: &[u32],
+    length: u64,
+) -> Result<(), NfpError> {
+    check_mu_atomic(op, mem_type, address)?;
+    if op == MuAtomicOp::CompareWrite {
+        return Err(NfpError::InvalidArgument(
+            "MU atomic compare_write always returns the previous value, \
+             use mem_atomic_test"
+                .to_string(),
+        ));
+    }
+
+    op.check_operands(operands)?;
+    if op.is_immediate() {
+        mem_atomic_imm_test(exp_bar, cpp_island, mem_type, op, address, length)?;
+        return Ok(());
+    }
+
+    let (target, cpp_address) =
+        mem_cpp_address(cpp_island, mem_type, address, word_bytes(length)?)?;
+
     // Instantiate Cpp bus with allocated expansion BAR.
     let mut cpp_bus = CppBus::new(exp_bar);
 
-    match mem_type {
-        MemoryType::Emem | MemoryType::Ctm => {
-            let (action, token) = engine.write_command();
-            cpp_bus.write(
+    let (action, token) = op.command(false);
+    cpp_bus.write(
+        cpp_island,
+        target,
+        action,
+        token,
+        CppLength::Len32,
+        cpp_address,
+        operands.repeat(length as usize),
+    )
+}
+
+/// Apply the test variant of an immediate MU atomic operation to `length`
+/// consecutive 32-bit words and return the values the words held before the
+/// operation.
+///
+/// Immediate operations pull nothing, so the test variant is an ordinary
+/// read through the expansion BAR, one word at a time.
+///
+/// # Parameters
+///
+/// * `exp_bar`: Expansion BAR used to issue the operation.
+/// * `cpp_island`: Island holding the memory.
+/// * `mem_type`: Memory to operate on. Must be an MU memory.
+/// * `op`: Immediate atomic operation, see `MuAtomicOp::is_immediate`.
+/// * `address`: 32-bit aligned address of the first word, 64-bit aligned
+///   for `SetImm`.
+/// * `length`: Number of words to apply the operation to, 1 for `SetImm`.
+///
+/// # Returns
+///
+/// The previous values of the words.
+///
+/// # Errors
+///
+/// Returns `NfpError::InvalidArgument` for memories other than MU memories,
+/// operations that are not immediate or `SetImm` on more than one word, and
+/// `NfpError::AddressOutOfRange` for misaligned addresses or words outside
+/// the memory. No word is changed if the arguments are rejected.
+pub fn mem_atomic_imm_test(
+    exp_bar: &mut ExpansionBar,
+    cpp_island: CppIsland,
+    mem_type: MemoryType,
+    op: MuAtomicOp,
+    address: u64,
+    length: u64,
+) -> Result<Vec<u32>, NfpError> {
+    if !op.is_immediate() {
+        return Err(NfpError::InvalidArgument(format!(
+            "MU atomic {} pulls operands, use mem_atomic_test",
+            op
+        )));
+    }
+    // Only every other word of a range is 64-bit aligned.
+    if op == MuAtomicOp::SetImm && length > 1 {
+        return Err(NfpError::InvalidArgument(format!(
+            "MU atomic {} applies to a single 64-bit aligned word",
+            op
+        )));
+    }
+    // Check the whole range before changing any word.
+    check_mu_atomic(op, mem_type, address)?;
+    mem_cpp_address(cpp_island, mem_type, address, word_bytes(length)?)?;
+
+    let (action, token) = op.command(true);
+    let mut previous = Vec::with_capacity(length as usize);
+    for index in 0..length {
+        let word_address = address + index * 4;
+        let (target, cpp_address) = mem_cpp_address(cpp_island, mem_type, word_address, 4)?;
+        previous.extend(CppBus::new(exp_bar).read(
+            cpp_island,
+            target,
+            action,
+            token,
+            CppLength::Len32,
+            cpp_address,
+            1,
+        )?);
+    }
+
+    Ok(previous)
+}
+
+/// Apply the test variant of an MU atomic operation to one 32-bit word and
+/// return the value the word held before the operation.
+///
+/// The operation is issued through an explicit BAR. Operations that pull
+/// operands return the previous value through the explicit command SRAM,
+/// immediate operations return it on the trigger read.
+///
+/// # Parameters
+///
+/// * `expl_bar`: Explicit BAR used to issue the operation.
+/// * `cpp_island`: Island holding the memory.
+/// * `mem_type`: Memory to operate on. Must be an MU memory.
+/// * `op`: Atomic operation.
+/// * `address`: 32-bit aligned address of the word, 64-bit aligned for
+///   `SetImm`.
+/// * `operands`: Operands of the operation, see `MuAtomicOp::operand_count`.
+///
+/// # Returns
+///
+/// The previous value of the word.
+///
+/// # Errors
+///
+/// Returns `NfpError::InvalidArgument` for memories other than MU memories
+/// or for the wrong number of operands.
+pub fn mem_atomic_test(
+    expl_bar: &mut ExplicitBar,
+    cpp_island: CppIsland,
+    mem_type: MemoryType,
+    op: MuAtomicOp,
+    address: u64,
+    operands: &[u32],
+) -> Result<u32, NfpError> {
+    check_mu_atomic(op, mem_type, address)?;
+    op.check_operands(operands)?;
+    let (target, cpp_address) = mem_cpp_address(cpp_island, mem_type, address, 4)?;
+
+    let (base_addr, offset) = split_addr48(cpp_address, expl_bar.size());
+    let (action, token) = op.command(true);
+
+    // The length field counts the pulled words, or the single pushed word
+    // of an immediate operation, minus one.
+    let length = operands.len().max(1) - 1;
+    expl_bar.explicit_bar_cfg(
+        cpp_island.id(),
+        target.id(),
+        action,
+        token,
+        base_addr,
+        Some(1),
+        length as u8,
+        0xFF,
+        None,
+        None,
+        None,
+        None,
+        None,
+    )?;
+
+    let pull_data = (!op.is_immediate()).then(|| operands.to_vec());
+    let from_sram = pull_data.is_some();
+    match expl_bar.run_explicit_cmd(offset, pull_data, Some(1), from_sram)? {
+        Some(push_data) => Ok(push_data[0]),
+        None => Err(NfpError::InvalidArgument(format!(
+            "No push data returned for MU atomic {} at {:#x}",
+            op, address
+        ))),
+    }
+}
+
+/// Apply the test variant of an MU atomic operation to `length` consecutive
+/// 32-bit words, one word at a time with `mem_atomic_test`, and return the
+/// values the words held before the operation.
+///
+/// # Errors
+///
+/// As `mem_atomic_test`, and `NfpError::InvalidArgument` for `SetImm` on
+/// more than one word, and `NfpError::AddressOutOfRange` for words outside
+/// the memory. No word is changed if the arguments are rejected.
+pub fn mem_atomic_test_range(
+    expl_bar: &mut ExplicitBar,
+    cpp_island: CppIsland,
+    mem_type: MemoryType,
+    op: MuAtomicOp,
+    address: u64,
+    operands: &[u32],
+    length: u64,
+) -> Result<Vec<u32>, NfpError> {
+    // Only every other word of a range is 64-bit aligned.
+    if op == MuAtomicOp::SetImm && length > 1 {
+        return Err(NfpError::InvalidArgument(format!(
+            "MU atomic {} applies to a single 64-bit aligned word",
+            op
+        )));
+    }
+    // Check the whole range before changing any word.
+    check_mu_atomic(op, mem_type, address)?;
+    op.check_operands(operands)?;
+    mem_cpp_address(cpp_island, mem_type, address, word_bytes(length)?)?;
+
+    (0..length)
+        .map(|index| {
+            mem_atomic_test(
+                expl_bar,
                 cpp_island,
-                CppTarget::Mem,
-                action,
-                token,
-                engine.cpp_length(),
-                address,
-                values,
+                mem_type,
+                op,
+                address + index * 4,
+                operands,
             )
+        })
+        .collect()
+}
+
+/// Check that a file transfer of `length` bytes at `address` is word aligned.
+fn check_mem_file_region(address: u64, length: u64, partial_word: bool) -> Result<(), NfpError> {
+    if !address.is_multiple_of(4) {
+        return Err(NfpError::AddressOutOfRange(format!(
+            "Memory address {:#x} is not 32-bit aligned",
+            address
+        )));
+    }
+
+    if !partial_word && !length.is_multiple_of(4) {
+        return Err(NfpError::InvalidArgument(format!(
+            "Length {:#x} is not a multiple of 32 bits",
+            length
+        )));
+    }
+
+    Ok(())
+}
+
+/// Return the number of bytes, up to `remaining`, from `address` to the end
+/// of the expansion BAR window holding it.
+fn window_chunk_len(exp_bar: &ExpansionBar, address: u64, remaining: u64) -> u64 {
+    let window_size = exp_bar.exp_bar_size;
+    remaining.min(window_size - (address % window_size))
+}
+
+/// Report the first word that differs between `expected` and `actual`.
+fn verify_words(address: u64, expected: &[u32], actual: &[u32]) -> Result<(), NfpError> {
+    match expected.iter().zip(actual).position(|(e, a)| e != a) {
+        Some(index) => Err(NfpError::VerifyFailed(format!(
+            "address {:#010x}: expected 0x{:08x}, read 0x{:08x}",
+            address + index as u64 * 4,
+            expected[index],
+            actual[index]
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Stream a region of device memory to `writer`.
+///
+/// The region is read one expansion BAR window at a time.
+///
+/// # Parameters
+///
+/// * `exp_bar`: Expansion BAR used for the transfer.
+/// * `cpp_island`: Island holding the memory.
+/// * `mem_type`: Memory to read.
+/// * `engine`: MU engine used for the reads.
+/// * `address`: 32-bit aligned start address of the region.
+/// * `length`: Length of the region in bytes, a multiple of 4.
+/// * `writer`: Destination of the memory contents.
+/// * `verify`: Read every chunk twice and fail if the two reads differ.
+/// * `progress`: Called with the number of bytes done and the total after
+///   every chunk.
+///
+/// # Errors
+///
+/// Returns `NfpError::VerifyFailed` if `verify` is set and a chunk reads back
+/// differently, or `NfpError::Io` if writing to `writer` fails.
+#[allow(clippy::too_many_arguments)]
+pub fn mem_dump(
+    exp_bar: &mut ExpansionBar,
+    cpp_island: CppIsland,
+    mem_type: MemoryType,
+    engine: MuMemoryEngine,
+    address: u64,
+    length: u64,
+    writer: &mut dyn Write,
+    verify: bool,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<(), NfpError> {
+    check_mem_file_region(address, length, false)?;
+
+    let mut done: u64 = 0;
+    while done < length {
+        let chunk_address = address + done;
+        let chunk_len = window_chunk_len(exp_bar, chunk_address, length - done);
+        let words = mem_read(
+            exp_bar,
+            cpp_island,
+            mem_type,
+            engine,
+            chunk_address,
+            chunk_len / 4,
+        )?;
+
+        if verify {
+            let words_again = mem_read(
+                exp_bar,
+                cpp_island,
+                mem_type,
+                engine,
+                chunk_address,
+                chunk_len / 4,
+            )?;
+            verify_words(chunk_address, &words, &words_again)?;
         }
-        MemoryType::Cls => cpp_bus.write(
+
+        writer
+            .write_all(cast_slice(&words))
+            .map_err(|e| NfpError::io("Failed to write memory dump", e))?;
+
+        done += chunk_len;
+        progress(done, length);
+    }
+
+    writer
+        .flush()
+        .map_err(|e| NfpError::io("Failed to write memory dump", e))
+}
+
+/// Write `length` bytes from `reader` into device memory.
+///
+/// The data is written one expansion BAR window at a time. If `length` is
+/// not a multiple of 4, the bytes of the last word beyond `length` keep their
+/// current value on the device.
+///
+/// # Parameters
+///
+/// * `exp_bar`: Expansion BAR used for the transfer.
+/// * `cpp_island`: Island holding the memory.
+/// * `mem_type`: Memory to write.
+/// * `engine`: MU engine used for the writes.
+/// * `address`: 32-bit aligned start address of the region.
+/// * `length`: Number of bytes to take from `reader`.
+/// * `reader`: Source of the memory contents.
+/// * `verify`: Read every chunk back after writing it and fail if it differs.
+/// * `progress`: Called with the number of bytes done and the total after
+///   every chunk.
+///
+/// # Errors
+///
+/// Returns `NfpError::VerifyFailed` if `verify` is set and a chunk reads back
+/// differently, or `NfpError::Io` if reading from `reader` fails.
+#[allow(clippy::too_many_arguments)]
+pub fn mem_load(
+    exp_bar: &mut ExpansionBar,
+    cpp_island: CppIsland,
+    mem_type: MemoryType,
+    engine: MuMemoryEngine,
+    address: u64,
+    length: u64,
+    reader: &mut dyn Read,
+    verify: bool,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<(), NfpError> {
+    check_mem_file_region(address, length, true)?;
+
+    let mut done: u64 = 0;
+    while done < length {
+        let chunk_address = address + done;
+        let chunk_len = window_chunk_len(exp_bar, chunk_address, length - done);
+
+        let mut chunk_bytes = vec![0u8; chunk_len as usize];
+        reader
+            .read_exact(&mut chunk_bytes)
+            .map_err(|e| NfpError::io("Failed to read memory image", e))?;
+
+        // Fill a trailing partial word with the current device contents.
+        let partial = chunk_bytes.len() % 4;
+        if partial != 0 {
+            let last_address = chunk_address + (chunk_bytes.len() - partial) as u64;
+            let last_word = mem_read(exp_bar, cpp_island, mem_type, engine, last_address, 1)?;
+            chunk_bytes.extend_from_slice(&last_word[0].to_le_bytes()[partial..]);
+        }
+
+        let words: Vec<u32> = chunk_bytes
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect();
+        mem_write(
+            exp_bar,
             cpp_island,
-            CppTarget::Cls,
-            1,
-            0,
-            CppLength::Len32,
-            address,
-            values,
-        ),
+            mem_type,
+            engine,
+            chunk_address,
+            words.clone(),
+        )?;
+
+        if verify {
+            let words_read = mem_read(
+                exp_bar,
+                cpp_island,
+                mem_type,
+                engine,
+                chunk_address,
+                words.len() as u64,
+            )?;
+            verify_words(chunk_address, &words, &words_read)?;
+        }
+
+        done += chunk_len;
+        progress(done, length);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::explicit_bar::ExplicitCmdFields;
+    use crate::libs::nfp::Nfp;
+    use crate::libs::sim_device::SimDevice;
+    use std::sync::Arc;
+
+    #[test]
+    fn mu_atomic_commands_decode() {
+        for op in MuAtomicOp::value_variants() {
+            for test in [false, true] {
+                if *op == MuAtomicOp::CompareWrite && !test {
+                    continue;
+                }
+                let (action, token) = op.command(test);
+                assert_eq!(MuAtomicOp::from_command(action, token), Some((*op, test)));
+            }
+        }
+
+        // The lock command of the NSP ABI and the kernel NFP mutex.
+        assert_eq!(MuAtomicOp::SetImm.command(true), (5, 3));
+        assert_eq!(
+            MuAtomicOp::from_command(5, 3),
+            Some((MuAtomicOp::SetImm, true))
+        );
+        // Atomic reads are not atomic operations.
+        assert_eq!(MuAtomicOp::from_command(3, 0), None);
+    }
+
+    #[test]
+    fn mu_atomics_on_sim() {
+        let nfp = Nfp::new(Arc::new(SimDevice::new())).unwrap();
+        let island = CppIsland::first_of_kind(IslandKind::Emu);
+        let mut exp_bar = nfp.exp_bar().unwrap();
+        let mut expl_bar = nfp.expl_bar().unwrap();
+        let emem = MemoryType::Emem;
+
+        mem_atomic(&mut exp_bar, island, emem, MuAtomicOp::Add, 0x100, &[5], 2).unwrap();
+        mem_atomic(&mut exp_bar, island, emem, MuAtomicOp::Incr, 0x100, &[], 1).unwrap();
+        let previous =
+            mem_atomic_test(&mut expl_bar, island, emem, MuAtomicOp::Decr, 0x104, &[]).unwrap();
+        assert_eq!(previous, 5);
+        let previous =
+            mem_atomic_test(&mut expl_bar, island, emem, MuAtomicOp::Sub, 0x100, &[2]).unwrap();
+        assert_eq!(previous, 6);
+        let words = mem_read(&mut exp_bar, island, emem, MuMemoryEngine::Bulk32, 0x100, 2);
+        assert_eq!(words.unwrap(), [4, 4]);
+
+        // Taking a lock returns zero only while it is free.
+        let op = MuAtomicOp::SetImm;
+        assert_eq!(
+            mem_atomic_imm_test(&mut exp_bar, island, emem, op, 0x200, 1).unwrap(),
+            [0]
+        );
+        assert_eq!(
+            mem_atomic_test(&mut expl_bar, island, emem, op, 0x200, &[]).unwrap(),
+            0xF
+        );
+        assert!(matches!(
+            mem_atomic_imm_test(&mut exp_bar, island, emem, op, 0x204, 1),
+            Err(NfpError::AddressOutOfRange(_))
+        ));
+
+        // A rejected range leaves every word untouched.
+        assert!(matches!(
+            mem_atomic(&mut exp_bar, island, emem, op, 0x300, &[], 2),
+            Err(NfpError::InvalidArgument(_))
+        ));
+        let words = mem_read(&mut exp_bar, island, emem, MuMemoryEngine::Bulk32, 0x300, 2);
+        assert_eq!(words.unwrap(), [0, 0]);
+    }
+
+    #[test]
+    fn oversized_accesses_are_rejected() {
+        let nfp = Nfp::new(Arc::new(SimDevice::new())).unwrap();
+        let island = CppIsland::first_of_kind(IslandKind::Emu);
+        let mut exp_bar = nfp.exp_bar().unwrap();
+        let mut expl_bar = nfp.expl_bar().unwrap();
+        let rfpc = Rfpc::new(CppIsland::first_of_kind(IslandKind::Rfpc), 0, 0, 0).unwrap();
+
+        for length in [0x4000000000000001, u64::MAX] {
+            for engine in [MuMemoryEngine::Bulk32, MuMemoryEngine::Bulk64] {
+                assert!(matches!(
+                    mem_read(&mut exp_bar, island, MemoryType::Emem, engine, 0, length),
+                    Err(NfpError::AddressOutOfRange(_))
+                ));
+            }
+            assert!(matches!(
+                lmem_read(&mut expl_bar, &rfpc, 0, length),
+                Err(NfpError::AddressOutOfRange(_))
+            ));
+        }
+
+        let size = mem_size(island, MemoryType::Emem).unwrap();
+        assert!(matches!(
+            mem_write(
+                &mut exp_bar,
+                island,
+                MemoryType::Emem,
+                MuMemoryEngine::Bulk32,
+                size - 4,
+                vec![0; 2]
+            ),
+            Err(NfpError::AddressOutOfRange(_))
+        ));
+        assert!(matches!(
+            lmem_write(&mut expl_bar, &rfpc, u64::MAX - 3, vec![0; 2]),
+            Err(NfpError::AddressOutOfRange(_))
+        ));
+
+        let add = MuAtomicOp::Add;
+        for length in [0x4000000000000001, u64::MAX] {
+            assert!(matches!(
+                mem_atomic(&mut exp_bar, island, MemoryType::Emem, add, 0, &[1], length),
+                Err(NfpError::AddressOutOfRange(_))
+            ));
+            assert!(matches!(
+                mem_atomic_test_range(
+                    &mut expl_bar,
+                    island,
+                    MemoryType::Emem,
+                    add,
+                    0,
+                    &[1],
+                    length
+                ),
+                Err(NfpError::AddressOutOfRange(_))
+            ));
+            assert!(matches!(
+                mem_atomic_imm_test(
+                    &mut exp_bar,
+                    island,
+                    MemoryType::Emem,
+                    MuAtomicOp::Incr,
+                    0,
+                    length
+                ),
+                Err(NfpError::AddressOutOfRange(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn mu_atomic_test_range_checks_before_changing_memory() {
+        let nfp = Nfp::new(Arc::new(SimDevice::new())).unwrap();
+        let island = CppIsland::first_of_kind(IslandKind::Emu);
+        let mut exp_bar = nfp.exp_bar().unwrap();
+        let mut expl_bar = nfp.expl_bar().unwrap();
+        let emem = MemoryType::Emem;
+        let engine = MuMemoryEngine::Bulk32;
+        let last = mem_size(island, emem).unwrap() - 8;
+
+        let previous =
+            mem_atomic_test_range(&mut expl_bar, island, emem, MuAtomicOp::Incr, last, &[], 2);
+        assert_eq!(previous.unwrap(), [0, 0]);
+
+        // A range running past the end of the memory changes no word.
+        assert!(matches!(
+            mem_atomic_test_range(&mut expl_bar, island, emem, MuAtomicOp::Incr, last, &[], 3),
+            Err(NfpError::AddressOutOfRange(_))
+        ));
+        let words = mem_read(&mut exp_bar, island, emem, engine, last, 2);
+        assert_eq!(words.unwrap(), [1, 1]);
+
+        assert!(matches!(
+            mem_atomic_test_range(
+                &mut expl_bar,
+                island,
+                emem,
+                MuAtomicOp::SetImm,
+                0x400,
+                &[],
+                2
+            ),
+            Err(NfpError::InvalidArgument(_))
+        ));
+        let words = mem_read(&mut exp_bar, island, emem, engine, 0x400, 2);
+        assert_eq!(words.unwrap(), [0, 0]);
+    }
+
+    #[test]
+    fn pcie_sram_holds_explicit_command_data() {
+        let nfp = Nfp::new(Arc::new(SimDevice::new())).unwrap();
+        let emu = CppIsland::first_of_kind(IslandKind::Emu);
+        let mut exp_bar = nfp.exp_bar().unwrap();
+        let mut expl_bar = nfp.expl_bar().unwrap();
+        let sram_base = chip_desc()
+            .memory(MemoryType::PcieSram.name(), CppIsland::LOCAL.id())
+            .unwrap()
+            .base;
+
+        // An explicit write stages its pull data in the PCIe SRAM.
+        expl_bar
+            .explicit_cmd(
+                emu,
+                CppTarget::mem(),
+                31,
+                0,
+                0x100,
+                2,
+                &ExplicitCmdFields::default(),
+                Some(vec![0x11, 0x22, 0x33]),
+                None,
+            )
+            .unwrap();
+        let sram_address = expl_bar.sram_data_offset() - sram_base;
+        let words = mem_read(
+            &mut exp_bar,
+            CppIsland::LOCAL,
+            MemoryType::PcieSram,
+            MuMemoryEngine::Bulk32,
+            sram_address,
+            3,
+        );
+        assert_eq!(words.unwrap(), [0x11, 0x22, 0x33]);
+    }
+
+    #[test]
+    fn memories_follow_the_chip_description() {
+        let sim = Arc::new(SimDevice::new());
+        let nfp = Nfp::new(sim.clone()).unwrap();
+        let emu = CppIsland::first_of_kind(IslandKind::Emu);
+        let arm = CppIsland::chip_exec();
+        let rfpc_island = CppIsland::first_of_kind(IslandKind::Rfpc);
+        let mut exp_bar = nfp.exp_bar().unwrap();
+        let mut expl_bar = nfp.expl_bar().unwrap();
+        let engine = MuMemoryEngine::Bulk32;
+
+        // IMEM is reached through the MU target above the EMEM.
+        let imem_size = mem_size(emu, MemoryType::Imem).unwrap();
+        mem_write(&mut exp_bar, emu, MemoryType::Imem, engine, 0x10, vec![7]).unwrap();
+        let bytes = sim.read_memory(emu.id(), CppTarget::mem(), 0x8000000010, 4);
+        assert_eq!(bytes, 7u32.to_le_bytes());
+        assert!(matches!(
+            mem_read(
+                &mut exp_bar,
+                emu,
+                MemoryType::Imem,
+                engine,
+                imem_size - 4,
+                2
+            ),
+            Err(NfpError::AddressOutOfRange(_))
+        ));
+
+        // ARM memory is only found in the chip exec island.
+        let arm_size = mem_size(arm, MemoryType::Arm).unwrap();
+        mem_write(
+            &mut exp_bar,
+            arm,
+            MemoryType::Arm,
+            engine,
+            arm_size - 4,
+            vec![9],
+        )
+        .unwrap();
+        let bytes = sim.read_memory(arm.id(), CppTarget::named("arm").unwrap(), arm_size - 4, 4);
+        assert_eq!(bytes, 9u32.to_le_bytes());
+        assert!(matches!(
+            mem_write(
+                &mut exp_bar,
+                arm,
+                MemoryType::Arm,
+                engine,
+                arm_size,
+                vec![0]
+            ),
+            Err(NfpError::AddressOutOfRange(_))
+        ));
+        assert!(matches!(
+            mem_size(emu, MemoryType::Arm),
+            Err(NfpError::InvalidArgument(_))
+        ));
+
+        // LMEM has no CPP target and is checked against its size before the
+        // core is accessed.
+        assert!(matches!(
+            mem_read(&mut exp_bar, rfpc_island, MemoryType::Lmem, engine, 0, 1),
+            Err(NfpError::InvalidArgument(_))
+        ));
+        let lmem_size = mem_size(rfpc_island, MemoryType::Lmem).unwrap();
+        let rfpc = Rfpc::new(rfpc_island, 0, 0, 0).unwrap();
+        assert!(matches!(
+            lmem_read(&mut expl_bar, &rfpc, lmem_size - 4, 2),
+            Err(NfpError::AddressOutOfRange(_))
+        ));
+        assert!(matches!(
+            lmem_write(&mut expl_bar, &rfpc, lmem_size, vec![0]),
+            Err(NfpError::AddressOutOfRange(_))
+        ));
     }
 }
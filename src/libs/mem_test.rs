@@ -0,0 +1,362 @@
+#![allow(dead_code)]
+
+use std::fmt;
+
+use clap::ValueEnum;
+
+use crate::libs::cpp_bus::CppIsland;
+use crate::libs::error::NfpError;
+use crate::libs::expansion_bar::ExpansionBar;
+use crate::libs::mem_access::{
+    mem_cpp_address, mem_read, mem_write, word_bytes, MemoryType, MuMemoryEngine,
+};
+
+// Number of words written or read by one memory access during a test.
+const MEM_TEST_CHUNK_WORDS: u64 = 0x4000;
+
+/// Data pattern written by a memory test.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum MemTestPattern {
+    /// A single set bit, moved one position per word and per pass, so every
+    /// word holds every one-hot value once. Runs 32 passes.
+    WalkingOnes,
+    /// The complement of `WalkingOnes`. Runs 32 passes.
+    WalkingZeros,
+    /// Every word holds its own address, then the complement of it. Finds
+    /// address lines that are stuck or shorted. Runs 2 passes.
+    AddressInAddress,
+    /// Pseudo-random words from a seed. Runs 1 pass.
+    Random,
+}
+
+impl MemTestPattern {
+    /// Return the number of write and verify passes of the pattern.
+    pub fn passes(&self) -> u32 {
+        match self {
+            MemTestPattern::WalkingOnes | MemTestPattern::WalkingZeros => 32,
+            MemTestPattern::AddressInAddress => 2,
+            MemTestPattern::Random => 1,
+        }
+    }
+}
+
+impl fmt::Display for MemTestPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemTestPattern::WalkingOnes => write!(f, "walking-ones"),
+            MemTestPattern::WalkingZeros => write!(f, "walking-zeros"),
+            MemTestPattern::AddressInAddress => write!(f, "address-in-address"),
+            MemTestPattern::Random => write!(f, "random"),
+        }
+    }
+}
+
+/// A word that did not read back as written.
+#[derive(Copy, Clone, Debug)]
+pub struct MemTestFailure {
+    pub address: u64,
+    pub expected: u32,
+    pub actual: u32,
+}
+
+impl MemTestFailure {
+    /// Return the bits that differ.
+    pub fn xor(&self) -> u32 {
+        self.expected ^ self.actual
+    }
+}
+
+impl fmt::Display for MemTestFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "address 0x{:08x}: expected 0x{:08x}, actual 0x{:08x}, xor 0x{:08x}",
+            self.address,
+            self.expected,
+            self.actual,
+            self.xor()
+        )
+    }
+}
+
+/// Result of one memory test.
+#[derive(Clone, Debug)]
+pub struct MemTestReport {
+    pub pattern: MemTestPattern,
+    pub engine: MuMemoryEngine,
+    /// Number of words verified, over all passes.
+    pub words_tested: u64,
+    /// Number of words that failed, over all passes.
+    pub failure_count: u64,
+    /// The first failures, up to the limit given to `mem_test`.
+    pub failures: Vec<MemTestFailure>,
+    /// OR of the XOR masks of all failures, i.e. every bit seen failing.
+    pub failing_bits: u32,
+}
+
+impl MemTestReport {
+    pub fn passed(&self) -> bool {
+        self.failure_count == 0
+    }
+}
+
+/// Generator of the word a pattern writes at each address.
+struct PatternGenerator {
+    pattern: MemTestPattern,
+    pass: u32,
+    random_state: u32,
+}
+
+impl PatternGenerator {
+    fn new(pattern: MemTestPattern, pass: u32, seed: u32) -> Self {
+        PatternGenerator {
+            pattern,
+            pass,
+            // Xorshift gets stuck at zero.
+            random_state: if seed == 0 { 0x2545_F491 } else { seed },
+        }
+    }
+
+    /// Return the word for `index`, the word index in the region, at
+    /// `address`. Must be called for consecutive words.
+    fn next(&mut self, index: u64, address: u64) -> u32 {
+        let walking_one = 1u32 << ((index + self.pass as u64) % 32);
+        match self.pattern {
+            MemTestPattern::WalkingOnes => walking_one,
+            MemTestPattern::WalkingZeros => !walking_one,
+            MemTestPattern::AddressInAddress if self.pass == 0 => address as u32,
+            MemTestPattern::AddressInAddress => !(address as u32),
+            MemTestPattern::Random => {
+                let mut x = self.random_state;
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                self.random_state = x;
+                x
+            }
+        }
+    }
+}
+
+/// Test a region of device memory with one pattern and engine.
+///
+/// Each pass first writes the whole region and then reads it all back, so
+/// that writes aliasing to other addresses are caught.
+///
+/// # Parameters
+///
+/// * `exp_bar`: Expansion BAR used for the accesses.
+/// * `cpp_island`: Island holding the memory.
+/// * `mem_type`: Memory to test.
+/// * `engine`: MU engine used for the accesses. Ignored for non-MU memories.
+/// * `pattern`: Data pattern to write.
+/// * `address`: Start address of the region.
+/// * `length`: Length of the region in 32-bit words.
+/// * `seed`: Seed of the random pattern.
+/// * `max_failures`: Number of failures to keep in the report. All failures
+///   are counted.
+/// * `progress`: Called with the number of bytes written and read so far and
+///   the total, over all passes, after every chunk.
+///
+/// # Errors
+///
+/// Returns `NfpError::InvalidArgument` if the region is not aligned for
+/// `engine`, `NfpError::AddressOutOfRange` if it is not within the memory,
+/// or any error of `mem_read`/`mem_write`. Data mismatches are not errors
+/// but are listed in the report.
+#[allow(clippy::too_many_arguments)]
+pub fn mem_test(
+    exp_bar: &mut ExpansionBar,
+    cpp_island: CppIsland,
+    mem_type: MemoryType,
+    engine: MuMemoryEngine,
+    pattern: MemTestPattern,
+    address: u64,
+    length: u64,
+    seed: u32,
+    max_failures: usize,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<MemTestReport, NfpError> {
+    let bytes = word_bytes(length)?;
+    mem_cpp_address(cpp_island, mem_type, address, bytes)?;
+
+    let alignment = if engine == MuMemoryEngine::Bulk64 && mem_type.is_mu() {
+        8
+    } else {
+        4
+    };
+    if !address.is_multiple_of(alignment) || !bytes.is_multiple_of(alignment) {
+        return Err(NfpError::InvalidArgument(format!(
+            "{} test region at {:#x} of {:#x} words is not {}-bit aligned",
+            engine,
+            address,
+            length,
+            alignment * 8
+        )));
+    }
+
+    let mut report = MemTestReport {
+        pattern,
+        engine,
+        words_tested: 0,
+        failure_count: 0,
+        failures: Vec::new(),
+        failing_bits: 0,
+    };
+
+    let total = bytes * 2 * pattern.passes() as u64;
+    let mut done: u64 = 0;
+
+    for pass in 0..pattern.passes() {
+        // Write the pattern over the whole region.
+        let mut generator = PatternGenerator::new(pattern, pass, seed);
+        for start in (0..length).step_by(MEM_TEST_CHUNK_WORDS as usize) {
+            let words = MEM_TEST_CHUNK_WORDS.min(length - start);
+            let chunk_address = address + start * 4;
+            let values: Vec<u32> = (start..start + words)
+                .map(|index| generator.next(index, address + index * 4))
+                .collect();
+            mem_write(exp_bar, cpp_island, mem_type, engine, chunk_address, values)?;
+            done += words * 4;
+            progress(done, total);
+        }
+
+        // Read it back, regenerating the expected words.
+        let mut generator = PatternGenerator::new(pattern, pass, seed);
+        for start in (0..length).step_by(MEM_TEST_CHUNK_WORDS as usize) {
+            let words = MEM_TEST_CHUNK_WORDS.min(length - start);
+            let chunk_address = address + start * 4;
+            let actual = mem_read(exp_bar, cpp_island, mem_type, engine, chunk_address, words)?;
+
+            for (index, actual) in (start..start + words).zip(actual) {
+                let word_address = address + index * 4;
+                let expected = generator.next(index, word_address);
+                if actual != expected {
+                    let failure = MemTestFailure {
+                        address: word_address,
+                        expected,
+                        actual,
+                    };
+                    report.failure_count += 1;
+                    report.failing_bits |= failure.xor();
+                    if report.failures.len() < max_failures {
+                        report.failures.push(failure);
+                    }
+                }
+            }
+
+            report.words_tested += words;
+            done += words * 4;
+            progress(done, total);
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::chip_desc::IslandKind;
+    use crate::libs::mem_access::mem_size;
+    use crate::libs::nfp::Nfp;
+    use crate::libs::sim_device::SimDevice;
+    use std::sync::Arc;
+
+    #[test]
+    fn every_pattern_passes_on_good_memory() {
+        let nfp = Nfp::new(Arc::new(SimDevice::new())).unwrap();
+        let island = CppIsland::first_of_kind(IslandKind::Emu);
+        let mut exp_bar = nfp.exp_bar().unwrap();
+
+        for engine in [MuMemoryEngine::Bulk32, MuMemoryEngine::Bulk64] {
+            for pattern in MemTestPattern::value_variants() {
+                let mut last_progress = (0, 0);
+                let report = mem_test(
+                    &mut exp_bar,
+                    island,
+                    MemoryType::Emem,
+                    engine,
+                    *pattern,
+                    0x1000,
+                    MEM_TEST_CHUNK_WORDS + 8,
+                    0x1234,
+                    4,
+                    &mut |done, total| last_progress = (done, total),
+                )
+                .unwrap();
+                assert!(report.passed(), "{} {}", pattern, engine);
+                assert_eq!(
+                    report.words_tested,
+                    (MEM_TEST_CHUNK_WORDS + 8) * pattern.passes() as u64
+                );
+                assert_eq!(last_progress.0, last_progress.1);
+            }
+        }
+    }
+
+    #[test]
+    fn patterns_are_reproducible() {
+        let words = |pattern, pass, seed| {
+            let mut generator = PatternGenerator::new(pattern, pass, seed);
+            (0..64)
+                .map(|index| generator.next(index, 0x100 + index * 4))
+                .collect::<Vec<u32>>()
+        };
+
+        let random = words(MemTestPattern::Random, 0, 42);
+        assert_eq!(random, words(MemTestPattern::Random, 0, 42));
+        assert_ne!(random, words(MemTestPattern::Random, 0, 43));
+        // A zero seed still generates words.
+        assert!(words(MemTestPattern::Random, 0, 0).iter().all(|w| *w != 0));
+
+        let ones = words(MemTestPattern::WalkingOnes, 3, 0);
+        assert_eq!(ones[0], 1 << 3);
+        assert_eq!(ones[29], 1 << 0);
+        let zeros = words(MemTestPattern::WalkingZeros, 3, 0);
+        assert!(ones.iter().zip(&zeros).all(|(one, zero)| *one == !*zero));
+
+        assert_eq!(words(MemTestPattern::AddressInAddress, 0, 0)[2], 0x108);
+        assert_eq!(words(MemTestPattern::AddressInAddress, 1, 0)[2], !0x108);
+    }
+
+    #[test]
+    fn invalid_regions_are_rejected() {
+        let nfp = Nfp::new(Arc::new(SimDevice::new())).unwrap();
+        let island = CppIsland::first_of_kind(IslandKind::Emu);
+        let mut exp_bar = nfp.exp_bar().unwrap();
+        let mut run = |engine, address, length| {
+            mem_test(
+                &mut exp_bar,
+                island,
+                MemoryType::Emem,
+                engine,
+                MemTestPattern::Random,
+                address,
+                length,
+                1,
+                4,
+                &mut |_, _| {},
+            )
+        };
+
+        // Bulk64 accesses need 64-bit aligned regions.
+        assert!(matches!(
+            run(MuMemoryEngine::Bulk64, 0x1004, 2),
+            Err(NfpError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            run(MuMemoryEngine::Bulk64, 0x1000, 3),
+            Err(NfpError::InvalidArgument(_))
+        ));
+        assert!(run(MuMemoryEngine::Bulk32, 0x1004, 3).unwrap().passed());
+
+        let size = mem_size(island, MemoryType::Emem).unwrap();
+        for (address, length) in [(size - 4, 2), (0, 0x4000000000000000), (0, u64::MAX)] {
+            assert!(matches!(
+                run(MuMemoryEngine::Bulk32, address, length),
+                Err(NfpError::AddressOutOfRange(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,325 @@
+#![allow(dead_code)]
+
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Condvar, Mutex};
+
+use crate::libs::device_backend::{open_backend, DeviceBackend, EXPANSION_BARS_PER_PHYS_BAR};
+use crate::libs::error::NfpError;
+use crate::libs::expansion_bar::{init_device_bars, ExpansionBar};
+use crate::libs::explicit_bar::{ExplicitBar, NUM_EXPL_BARS};
+
+/// BARs of one kind allocated from a device and shared by the handles of an
+/// `Nfp`.
+struct BarPool<T> {
+    state: Mutex<BarPoolState<T>>,
+    returned: Condvar,
+}
+
+struct BarPoolState<T> {
+    idle: Vec<T>,
+    // Number of BARs allocated from the device, idle or in use.
+    allocated: usize,
+    // Most BARs to allocate, lowered once the device runs out.
+    limit: usize,
+}
+
+impl<T> BarPool<T> {
+    fn new(limit: usize) -> Self {
+        BarPool {
+            state: Mutex::new(BarPoolState {
+                idle: Vec::new(),
+                allocated: 0,
+                limit,
+            }),
+            returned: Condvar::new(),
+        }
+    }
+
+    /// Take an idle BAR, allocate a new one with `allocate`, or wait for a
+    /// BAR in use to be returned.
+    ///
+    /// `allocate` runs without holding the pool, so other threads can
+    /// return BARs while it locks and configures the new one.
+    fn take(
+        self: &Arc<Self>,
+        allocate: impl Fn() -> Result<T, NfpError>,
+    ) -> Result<PooledBar<T>, NfpError> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(bar) = state.idle.pop() {
+                return Ok(self.lend(bar));
+            }
+
+            if state.allocated < state.limit {
+                // Count the BAR as allocated until `allocate` fails, so
+                // other threads do not allocate past the limit meanwhile.
+                state.allocated += 1;
+                drop(state);
+                let result = allocate();
+                state = self.state.lock().unwrap();
+                match result {
+                    Ok(bar) => return Ok(self.lend(bar)),
+                    Err(e) => {
+                        state.allocated -= 1;
+                        // Let a thread waiting on the reservation retry.
+                        self.returned.notify_one();
+                        match e {
+                            // Other processes hold the remaining BARs,
+                            // share ours.
+                            NfpError::LockContention(_) if state.allocated > 0 => {
+                                state.limit = state.allocated;
+                            }
+                            e => return Err(e),
+                        }
+                    }
+                }
+                // BARs may have been returned while allocating.
+                continue;
+            }
+
+            state = self.returned.wait(state).unwrap();
+        }
+    }
+
+    fn lend(self: &Arc<Self>, bar: T) -> PooledBar<T> {
+        PooledBar {
+            bar: Some(bar),
+            pool: Arc::clone(self),
+        }
+    }
+
+    fn put(&self, bar: T) {
+        self.state.lock().unwrap().idle.push(bar);
+        self.returned.notify_one();
+    }
+}
+
+/// BAR lent out by an `Nfp` for exclusive use, returned to the `Nfp` when
+/// dropped.
+///
+/// Dereferences to the `ExpansionBar` or `ExplicitBar`, so it can be passed
+/// to any function taking `&mut ExpansionBar` or `&mut ExplicitBar`.
+pub struct PooledBar<T> {
+    bar: Option<T>,
+    pool: Arc<BarPool<T>>,
+}
+
+impl<T> Deref for PooledBar<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.bar.as_ref().unwrap()
+    }
+}
+
+impl<T> DerefMut for PooledBar<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.bar.as_mut().unwrap()
+    }
+}
+
+impl<T> Drop for PooledBar<T> {
+    fn drop(&mut self) {
+        if let Some(bar) = self.bar.take() {
+            self.pool.put(bar);
+        }
+    }
+}
+
+/// Shared handle to an NFP device.
+///
+/// Owns the device backend and pools of expansion and explicit BARs. The
+/// handle is cheap to clone and can be sent to other threads; all clones
+/// share the same BARs. Each thread takes a BAR with `exp_bar` or
+/// `expl_bar` for as long as it needs exclusive use of it, typically a
+/// single bus access. A thread waits if every BAR is in use, so it must not
+/// hold a BAR while taking another of the same kind.
+#[derive(Clone)]
+pub struct Nfp {
+    backend: Arc<dyn DeviceBackend>,
+    exp_bars: Arc<BarPool<ExpansionBar>>,
+    expl_bars: Arc<BarPool<ExplicitBar>>,
+}
+
+// Handles and their BARs are shared between threads.
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Nfp>();
+    assert_send_sync::<PooledBar<ExpansionBar>>();
+    assert_send_sync::<PooledBar<ExplicitBar>>();
+};
+
+impl Nfp {
+    /// Open the device selected on the command line and initialize its
+    /// PCIe BARs.
+    ///
+    /// # Parameters
+    ///
+    /// * `pci_bdf` - PCIe BDF of an NFP attached to this host.
+    /// * `remote` - Address of an `nfp-cpp-server` giving access to an NFP
+    ///   in another machine.
+    /// * `sim` - Use an in-process simulated NFP instead of real hardware.
+    pub fn open(pci_bdf: Option<&str>, remote: Option<&str>, sim: bool) -> Result<Self, NfpError> {
+        Nfp::new(open_backend(pci_bdf, remote, sim)?)
+    }
+
+    /// Create a handle for the device reached through `backend` and
+    /// initialize its PCIe BARs.
+    pub fn new(backend: Arc<dyn DeviceBackend>) -> Result<Self, NfpError> {
+        // Initialize the PCIe BARs in the PCIe config. space.
+        init_device_bars(&backend)?;
+
+        Ok(Nfp {
+            backend,
+            exp_bars: Arc::new(BarPool::new(EXPANSION_BARS_PER_PHYS_BAR as usize)),
+            expl_bars: Arc::new(BarPool::new(NUM_EXPL_BARS as usize)),
+        })
+    }
+
+    /// Backend the device is reached through.
+    pub fn backend(&self) -> &Arc<dyn DeviceBackend> {
+        &self.backend
+    }
+
+    /// Name of the device, e.g. its PCIe BDF.
+    pub fn name(&self) -> &str {
+        self.backend.name()
+    }
+
+    /// Take an expansion BAR for exclusive use.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::LockContention` if no expansion BAR can be
+    /// allocated from the device.
+    pub fn exp_bar(&self) -> Result<PooledBar<ExpansionBar>, NfpError> {
+        self.exp_bars
+            .take(|| ExpansionBar::new(&self.backend, None))
+    }
+
+    /// Take an explicit BAR for exclusive use. Each explicit BAR holds two
+    /// expansion BARs of the device.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::LockContention` if no explicit BAR can be
+    /// allocated from the device.
+    pub fn expl_bar(&self) -> Result<PooledBar<ExplicitBar>, NfpError> {
+        self.expl_bars
+            .take(|| ExplicitBar::new(&self.backend, None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::chip_desc::IslandKind;
+    use crate::libs::cpp_bus::{CppBus, CppIsland, CppLength, CppTarget};
+    use crate::libs::sim_device::SimDevice;
+    use std::sync::{mpsc, Barrier};
+    use std::thread;
+    use std::time::Duration;
+
+    // Physical BAR holding the expansion BARs of the simulator.
+    const EXP_BAR_PHYS_BAR: u8 = 2;
+
+    /// Take an expansion BAR of `nfp` on another thread, sending it back
+    /// once taken.
+    fn take_on_thread(nfp: &Nfp) -> mpsc::Receiver<PooledBar<ExpansionBar>> {
+        let nfp = nfp.clone();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || sender.send(nfp.exp_bar().unwrap()).unwrap());
+        receiver
+    }
+
+    fn allocated(nfp: &Nfp) -> (usize, usize) {
+        let state = nfp.exp_bars.state.lock().unwrap();
+        (state.allocated, state.limit)
+    }
+
+    #[test]
+    fn returned_bar_wakes_waiter() {
+        let nfp = Nfp::new(Arc::new(SimDevice::new())).unwrap();
+        let bars: Vec<_> = (0..EXPANSION_BARS_PER_PHYS_BAR)
+            .map(|_| nfp.exp_bar().unwrap())
+            .collect();
+
+        // Every BAR is in use, so the thread waits for one.
+        let waiter = take_on_thread(&nfp);
+        assert!(waiter.recv_timeout(Duration::from_millis(100)).is_err());
+
+        drop(bars);
+        waiter.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(allocated(&nfp).0, EXPANSION_BARS_PER_PHYS_BAR as usize);
+    }
+
+    #[test]
+    fn contention_lowers_limit() {
+        let backend: Arc<dyn DeviceBackend> = Arc::new(SimDevice::new());
+        let nfp = Nfp::new(Arc::clone(&backend)).unwrap();
+
+        // Another process holds all but two expansion BARs.
+        for exp_bar in 2..EXPANSION_BARS_PER_PHYS_BAR as u8 {
+            backend.lock_exp_bar(EXP_BAR_PHYS_BAR, exp_bar).unwrap();
+        }
+        let first = nfp.exp_bar().unwrap();
+        let second = nfp.exp_bar().unwrap();
+
+        // The next BAR cannot be allocated, so the thread shares ours.
+        let waiter = take_on_thread(&nfp);
+        assert!(waiter.recv_timeout(Duration::from_millis(100)).is_err());
+        assert_eq!(allocated(&nfp), (2, 2));
+
+        drop(first);
+        waiter.recv_timeout(Duration::from_secs(5)).unwrap();
+        drop(second);
+        assert_eq!(allocated(&nfp), (2, 2));
+
+        // Without BARs of its own to share, a handle reports the contention.
+        let other = Nfp::new(backend).unwrap();
+        assert!(matches!(other.exp_bar(), Err(NfpError::LockContention(_))));
+    }
+
+    #[test]
+    fn threads_share_one_handle() {
+        let nfp = Nfp::new(Arc::new(SimDevice::new())).unwrap();
+        let island = CppIsland::first_of_kind(IslandKind::Emu);
+        let both_hold_bars = Barrier::new(2);
+
+        thread::scope(|scope| {
+            for thread_index in 0..2u32 {
+                let nfp = nfp.clone();
+                let both_hold_bars = &both_hold_bars;
+                scope.spawn(move || {
+                    let address = 0x1000 * (thread_index as u64 + 1);
+                    for round in 0..100 {
+                        let mut exp_bar = nfp.exp_bar().unwrap();
+                        if round == 0 {
+                            both_hold_bars.wait();
+                        }
+                        let mut cpp_bus = CppBus::new(&mut exp_bar);
+                        let words = vec![thread_index, round];
+                        cpp_bus
+                            .write(
+                                island,
+                                CppTarget::mem(),
+                                1,
+                                0,
+                                CppLength::Len64,
+                                address,
+                                words.clone(),
+                            )
+                            .unwrap();
+                        let read = cpp_bus
+                            .read(island, CppTarget::mem(), 0, 0, CppLength::Len64, address, 2)
+                            .unwrap();
+                        assert_eq!(read, words);
+                    }
+                });
+            }
+        });
+
+        // Both threads held a BAR at once, and no more were needed.
+        assert_eq!(allocated(&nfp).0, 2);
+    }
+}
@@ -1,7 +1,8 @@
 #![allow(dead_code)]
 
 use crate::libs::expansion_bar::ExpansionBar;
-use crate::libs::cpp_bus::CppIsland;
+use crate::libs::cpp_bus::{CppIsland, CppLength, CppTarget};
+use crate::libs::mem_access::MuAtomicOp;
 use bitfield::bitfield;
 use bitfield::fmt::Debug;
 use std::time::{Duration, Instant};
@@ -75,14 +76,15 @@ impl NspAbi {
         let timeout = Instant::now() + Duration::from_secs(10);
 
         loop {
-            // Use atomic bitwise-OR immediate command to set the lower byte of
-            // the lock word. If the returned value is zero, then the lock was
+            // Use the MU test_set_imm atomic to set the low bits of the lock
+            // word. If the returned value is zero, then the lock was
             // previously unlocked and now held (acquired successfully).
+            let (action, token) = MuAtomicOp::SetImm.command(true);
             let lock_val = self.cpp_bus.read(
-                CppIsland::ChipExec,
-                CppTarget::Mem,
-                5,
-                3,
+                CppIsland::chip_exec(),
+                CppTarget::mem(),
+                action,
+                token,
                 CppLength::Len32,
                 self.abi_offset + ABI_LOCK_OFFSET,
                 1,
@@ -105,12 +107,13 @@ impl NspAbi {
     }
 
     pub fn release_lock(&mut self) {
-        // Zero the lock word.
+        // Zero the lock word with an MU atomic write.
+        let (action, token) = MuAtomicOp::Swap.command(false);
         self.cpp_bus.write(
-            CppIsland::ChipExec,
-            CppTarget::Mem,
-            4,
-            0,
+            CppIsland::chip_exec(),
+            CppTarget::mem(),
+            action,
+            token,
             CppLength::Len32,
             self.abi_offset + ABI_LOCK_OFFSET,
             vec![0]
@@ -0,0 +1,594 @@
+#![allow(dead_code)]
+
+use std::fmt;
+use std::io::{IsTerminal, Write};
+
+use clap::{Args, ValueEnum};
+use serde::Serialize;
+
+use crate::libs::error::NfpError;
+
+// Number of bytes shown on each hexdump line.
+const HEXDUMP_LINE_BYTES: usize = 16;
+
+/// Format in which the tools print the data they read.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum OutputFormat {
+    /// One line of text per value.
+    Text,
+    /// `hexdump -C` style lines with an ASCII column.
+    Hexdump,
+    /// The bytes read, unformatted.
+    Raw,
+    /// A JSON array of objects.
+    Json,
+    /// Comma-separated values with a header line.
+    Csv,
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputFormat::Text => write!(f, "text"),
+            OutputFormat::Hexdump => write!(f, "hexdump"),
+            OutputFormat::Raw => write!(f, "raw"),
+            OutputFormat::Json => write!(f, "json"),
+            OutputFormat::Csv => write!(f, "csv"),
+        }
+    }
+}
+
+/// Width of the values data is grouped into.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum GroupWidth {
+    #[value(name = "8")]
+    W8,
+    #[value(name = "16")]
+    W16,
+    #[value(name = "32")]
+    W32,
+    #[value(name = "64")]
+    W64,
+}
+
+impl GroupWidth {
+    /// Return the number of bytes in a group.
+    pub fn bytes(&self) -> usize {
+        match self {
+            GroupWidth::W8 => 1,
+            GroupWidth::W16 => 2,
+            GroupWidth::W32 => 4,
+            GroupWidth::W64 => 8,
+        }
+    }
+}
+
+impl fmt::Display for GroupWidth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.bytes() * 8)
+    }
+}
+
+/// Output options shared by the tools that print memory or register data.
+#[derive(Args, Debug)]
+pub struct OutputArgs {
+    /// Format of the data printed.
+    #[arg(short = 'f', long = "format", default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+
+    /// Width in bits of the values the data is grouped into.
+    #[arg(short = 'w', long = "width", default_value_t = GroupWidth::W32)]
+    pub width: GroupWidth,
+}
+
+/// A value at an address, as serialized to JSON and CSV.
+#[derive(Serialize)]
+struct RegionEntry {
+    address: u64,
+    value: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    symbol: Option<String>,
+}
+
+/// A named register value, as serialized to JSON and CSV.
+#[derive(Serialize)]
+pub struct RegisterValue {
+    pub name: String,
+    pub value: u64,
+    /// Width of the register, used for text output and raw output.
+    #[serde(skip)]
+    pub width: GroupWidth,
+    /// Symbol the value points to, if it is an address.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symbol: Option<String>,
+}
+
+/// Format an optional symbol as a ` <symbol>` suffix for text output.
+fn symbol_suffix(symbol: Option<String>) -> String {
+    symbol.map_or(String::new(), |symbol| format!(" <{}>", symbol))
+}
+
+/// Convert a slice of 32-bit words read from the device to bytes.
+pub fn words_to_bytes(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+/// Split `data` into little-endian values of `width` bytes, paired with
+/// their address. A trailing partial group becomes a narrower value.
+fn group_values(address: u64, data: &[u8], width: GroupWidth) -> Vec<(u64, u64, usize)> {
+    data.chunks(width.bytes())
+        .enumerate()
+        .map(|(index, group)| {
+            let value = group
+                .iter()
+                .rev()
+                .fold(0u64, |value, byte| (value << 8) | *byte as u64);
+            (address + (index * width.bytes()) as u64, value, group.len())
+        })
+        .collect()
+}
+
+fn write_err(e: std::io::Error) -> NfpError {
+    NfpError::io("Failed to write output", e)
+}
+
+fn write_json<T: Serialize>(out: &mut dyn Write, entries: &T) -> Result<(), NfpError> {
+    serde_json::to_writer_pretty(&mut *out, entries)
+        .map_err(|e| write_err(std::io::Error::other(e)))?;
+    writeln!(out).map_err(write_err)
+}
+
+/// Write one `hexdump -C` style line for the bytes at `address`.
+fn write_hexdump_line(
+    out: &mut dyn Write,
+    address: u64,
+    line: &[u8],
+    width: GroupWidth,
+) -> std::io::Result<()> {
+    let mut hex = String::new();
+    for (index, (_, value, len)) in group_values(address, line, width).into_iter().enumerate() {
+        // Split the line in halves like hexdump -C does for bytes.
+        if width == GroupWidth::W8 && index == HEXDUMP_LINE_BYTES / 2 {
+            hex.push(' ');
+        }
+        hex.push_str(&format!(" {:0w$x}", value, w = len * 2));
+    }
+
+    // Pad short lines so the ASCII column stays aligned.
+    let groups = HEXDUMP_LINE_BYTES / width.bytes();
+    let full_width = groups * (width.bytes() * 2 + 1) + usize::from(width == GroupWidth::W8);
+    let ascii: String = line
+        .iter()
+        .map(|byte| {
+            if byte.is_ascii_graphic() || *byte == b' ' {
+                *byte as char
+            } else {
+                '.'
+            }
+        })
+        .collect();
+
+    writeln!(
+        out,
+        "{:08x} {:<full_width$}  |{}|",
+        address,
+        hex,
+        ascii,
+        full_width = full_width
+    )
+}
+
+/// Write a hexdump of `data`, squeezing repeated lines into a `*` line.
+fn write_hexdump(
+    out: &mut dyn Write,
+    address: u64,
+    data: &[u8],
+    width: GroupWidth,
+) -> std::io::Result<()> {
+    let mut previous: Option<&[u8]> = None;
+    let mut squeezing = false;
+
+    for (index, line) in data.chunks(HEXDUMP_LINE_BYTES).enumerate() {
+        if line.len() == HEXDUMP_LINE_BYTES && previous == Some(line) {
+            if !squeezing {
+                writeln!(out, "*")?;
+                squeezing = true;
+            }
+            continue;
+        }
+        squeezing = false;
+        previous = Some(line);
+        write_hexdump_line(
+            out,
+            address + (index * HEXDUMP_LINE_BYTES) as u64,
+            line,
+            width,
+        )?;
+    }
+
+    writeln!(out, "{:08x}", address + data.len() as u64)
+}
+
+/// Write a region of data read from the device at `address`.
+///
+/// # Parameters
+///
+/// * `out`: Destination of the formatted data, usually stdout.
+/// * `format`: Output format.
+/// * `width`: Width of the values the data is grouped into. Ignored by the
+///   raw format.
+/// * `address`: Address of the first byte of `data`.
+/// * `data`: Bytes read from the device.
+/// * `annotate`: Returns the symbol at an address, if any. The text, JSON and
+///   CSV formats show the symbol of each value's address.
+///
+/// # Errors
+///
+/// Returns `NfpError::Io` if writing to `out` fails.
+pub fn write_region(
+    out: &mut dyn Write,
+    format: OutputFormat,
+    width: GroupWidth,
+    address: u64,
+    data: &[u8],
+    annotate: Option<&dyn Fn(u64) -> Option<String>>,
+) -> Result<(), NfpError> {
+    let symbol_at = |address: u64| annotate.and_then(|annotate| annotate(address));
+
+    match format {
+        OutputFormat::Text => {
+            for (address, value, len) in group_values(address, data, width) {
+                writeln!(
+                    out,
+                    "address 0x{:08x}: 0x{:0w$x}{}",
+                    address,
+                    value,
+                    symbol_suffix(symbol_at(address)),
+                    w = len * 2
+                )
+                .map_err(write_err)?;
+            }
+        }
+        OutputFormat::Hexdump => write_hexdump(out, address, data, width).map_err(write_err)?,
+        OutputFormat::Raw => out.write_all(data).map_err(write_err)?,
+        OutputFormat::Json => {
+            let entries: Vec<RegionEntry> = group_values(address, data, width)
+                .into_iter()
+                .map(|(address, value, _)| RegionEntry {
+                    address,
+                    value,
+                    symbol: symbol_at(address),
+                })
+                .collect();
+            write_json(out, &entries)?;
+        }
+        OutputFormat::Csv => {
+            if annotate.is_some() {
+                writeln!(out, "address,value,symbol").map_err(write_err)?;
+            } else {
+                writeln!(out, "address,value").map_err(write_err)?;
+            }
+            for (address, value, len) in group_values(address, data, width) {
+                write!(out, "0x{:08x},0x{:0w$x}", address, value, w = len * 2)
+                    .map_err(write_err)?;
+                if annotate.is_some() {
+                    write!(out, ",{}", symbol_at(address).unwrap_or_default())
+                        .map_err(write_err)?;
+                }
+                writeln!(out).map_err(write_err)?;
+            }
+        }
+    }
+
+    out.flush().map_err(write_err)
+}
+
+/// Write a list of named register values.
+///
+/// The text and hexdump formats print one `name = value` line per register,
+/// and the raw format writes the little-endian bytes of each value.
+///
+/// # Errors
+///
+/// Returns `NfpError::Io` if writing to `out` fails.
+pub fn write_registers(
+    out: &mut dyn Write,
+    format: OutputFormat,
+    registers: &[RegisterValue],
+) -> Result<(), NfpError> {
+    match format {
+        OutputFormat::Text | OutputFormat::Hexdump => {
+            for register in registers {
+                writeln!(
+                    out,
+                    "{} = 0x{:0w$x}{}",
+                    register.name,
+                    register.value,
+                    symbol_suffix(register.symbol.clone()),
+                    w = register.width.bytes() * 2
+                )
+                .map_err(write_err)?;
+            }
+        }
+        OutputFormat::Raw => {
+            for register in registers {
+                out.write_all(&register.value.to_le_bytes()[..register.width.bytes()])
+                    .map_err(write_err)?;
+            }
+        }
+        OutputFormat::Json => write_json(out, &registers)?,
+        OutputFormat::Csv => {
+            writeln!(out, "name,value,symbol").map_err(write_err)?;
+            for register in registers {
+                writeln!(
+                    out,
+                    "{},0x{:0w$x},{}",
+                    register.name,
+                    register.value,
+                    register.symbol.as_deref().unwrap_or_default(),
+                    w = register.width.bytes() * 2
+                )
+                .map_err(write_err)?;
+            }
+        }
+    }
+
+    out.flush().map_err(write_err)
+}
+
+/// Build a progress callback printing the percentage done to stderr, if it
+/// is a terminal.
+pub fn progress_reporter(action: &'static str) -> impl FnMut(u64, u64) {
+    let show = std::io::stderr().is_terminal();
+    let mut last_percent = None;
+    move |done, total| {
+        if !show {
+            return;
+        }
+        let percent = done * 100 / total.max(1);
+        if last_percent != Some(percent) {
+            last_percent = Some(percent);
+            eprint!("\r{}: {:3}% ({}/{} bytes)", action, percent, done, total);
+            if done == total {
+                eprintln!();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 16 distinct bytes, three identical lines and a partial line.
+    fn sample() -> Vec<u8> {
+        let mut data: Vec<u8> = (0..16).collect();
+        data.extend([b'A'; 48]);
+        data.extend(b"Hi!\n\0");
+        data
+    }
+
+    fn region(
+        format: OutputFormat,
+        width: GroupWidth,
+        address: u64,
+        data: &[u8],
+        annotate: Option<&dyn Fn(u64) -> Option<String>>,
+    ) -> String {
+        let mut out = Vec::new();
+        write_region(&mut out, format, width, address, data, annotate).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn registers(format: OutputFormat) -> Vec<u8> {
+        let registers = [
+            RegisterValue {
+                name: "pc".to_string(),
+                value: 0x80000010,
+                width: GroupWidth::W64,
+                symbol: Some("main+0x10".to_string()),
+            },
+            RegisterValue {
+                name: "status".to_string(),
+                value: 0x1a,
+                width: GroupWidth::W16,
+                symbol: None,
+            },
+        ];
+        let mut out = Vec::new();
+        write_registers(&mut out, format, &registers).unwrap();
+        out
+    }
+
+    #[test]
+    fn hexdump_bytes() {
+        // The halves split by column, wherever the region starts.
+        assert_eq!(
+            region(
+                OutputFormat::Hexdump,
+                GroupWidth::W8,
+                0x1004,
+                &sample(),
+                None
+            ),
+            concat!(
+                "00001004  00 01 02 03 04 05 06 07  08 09 0a 0b 0c 0d 0e 0f  |................|\n",
+                "00001014  41 41 41 41 41 41 41 41  41 41 41 41 41 41 41 41  |AAAAAAAAAAAAAAAA|\n",
+                "*\n",
+                "00001044  48 69 21 0a 00                                    |Hi!..|\n",
+                "00001049\n",
+            )
+        );
+    }
+
+    #[test]
+    fn hexdump_wide_groups() {
+        let data = sample();
+        assert_eq!(
+            region(OutputFormat::Hexdump, GroupWidth::W16, 0x1000, &data, None),
+            concat!(
+                "00001000  0100 0302 0504 0706 0908 0b0a 0d0c 0f0e  |................|\n",
+                "00001010  4141 4141 4141 4141 4141 4141 4141 4141  |AAAAAAAAAAAAAAAA|\n",
+                "*\n",
+                "00001040  6948 0a21 00                             |Hi!..|\n",
+                "00001045\n",
+            )
+        );
+        assert_eq!(
+            region(OutputFormat::Hexdump, GroupWidth::W32, 0x1000, &data, None),
+            concat!(
+                "00001000  03020100 07060504 0b0a0908 0f0e0d0c  |................|\n",
+                "00001010  41414141 41414141 41414141 41414141  |AAAAAAAAAAAAAAAA|\n",
+                "*\n",
+                "00001040  0a216948 00                          |Hi!..|\n",
+                "00001045\n",
+            )
+        );
+
+        // A partial line is never squeezed into the line before it.
+        let mut data = data[..16].to_vec();
+        data.extend(b"Hi!\n\0");
+        assert_eq!(
+            region(OutputFormat::Hexdump, GroupWidth::W64, 0x1000, &data, None),
+            concat!(
+                "00001000  0706050403020100 0f0e0d0c0b0a0908  |................|\n",
+                "00001010  000a216948                         |Hi!..|\n",
+                "00001015\n",
+            )
+        );
+        assert_eq!(
+            region(OutputFormat::Hexdump, GroupWidth::W32, 0, &[], None),
+            "00000000\n"
+        );
+    }
+
+    #[test]
+    fn text_values_and_symbols() {
+        let data: Vec<u8> = (0..10).collect();
+        let annotate = |address: u64| (address == 0x1004).then(|| "table".to_string());
+
+        assert_eq!(
+            region(OutputFormat::Text, GroupWidth::W8, 0x1000, &data[..3], None),
+            concat!(
+                "address 0x00001000: 0x00\n",
+                "address 0x00001001: 0x01\n",
+                "address 0x00001002: 0x02\n",
+            )
+        );
+        assert_eq!(
+            region(
+                OutputFormat::Text,
+                GroupWidth::W16,
+                0x1000,
+                &data[..5],
+                None
+            ),
+            concat!(
+                "address 0x00001000: 0x0100\n",
+                "address 0x00001002: 0x0302\n",
+                "address 0x00001004: 0x04\n",
+            )
+        );
+        assert_eq!(
+            region(
+                OutputFormat::Text,
+                GroupWidth::W32,
+                0x1000,
+                &data,
+                Some(&annotate)
+            ),
+            concat!(
+                "address 0x00001000: 0x03020100\n",
+                "address 0x00001004: 0x07060504 <table>\n",
+                "address 0x00001008: 0x0908\n",
+            )
+        );
+        assert_eq!(
+            region(OutputFormat::Text, GroupWidth::W64, 0x1000, &data, None),
+            concat!(
+                "address 0x00001000: 0x0706050403020100\n",
+                "address 0x00001008: 0x0908\n",
+            )
+        );
+    }
+
+    #[test]
+    fn csv_json_and_raw_values() {
+        let data: Vec<u8> = (0..6).collect();
+        let annotate = |address: u64| (address == 0x1000).then(|| "table".to_string());
+
+        assert_eq!(
+            region(OutputFormat::Csv, GroupWidth::W32, 0x1000, &data, None),
+            "address,value\n0x00001000,0x03020100\n0x00001004,0x0504\n"
+        );
+        assert_eq!(
+            region(
+                OutputFormat::Csv,
+                GroupWidth::W16,
+                0x1000,
+                &data[..4],
+                Some(&annotate)
+            ),
+            "address,value,symbol\n0x00001000,0x0100,table\n0x00001002,0x0302,\n"
+        );
+        assert_eq!(
+            region(
+                OutputFormat::Json,
+                GroupWidth::W32,
+                0x1000,
+                &data,
+                Some(&annotate)
+            ),
+            concat!(
+                "[\n",
+                "  {\n",
+                "    \"address\": 4096,\n",
+                "    \"value\": 50462976,\n",
+                "    \"symbol\": \"table\"\n",
+                "  },\n",
+                "  {\n",
+                "    \"address\": 4100,\n",
+                "    \"value\": 1284\n",
+                "  }\n",
+                "]\n",
+            )
+        );
+        for width in [GroupWidth::W8, GroupWidth::W64] {
+            assert_eq!(
+                region(OutputFormat::Raw, width, 0x1000, &data, None).as_bytes(),
+                data
+            );
+        }
+    }
+
+    #[test]
+    fn register_formats() {
+        let text = "pc = 0x0000000080000010 <main+0x10>\nstatus = 0x001a\n";
+        assert_eq!(registers(OutputFormat::Text), text.as_bytes());
+        assert_eq!(registers(OutputFormat::Hexdump), text.as_bytes());
+        assert_eq!(
+            registers(OutputFormat::Raw),
+            [0x10, 0, 0, 0x80, 0, 0, 0, 0, 0x1a, 0]
+        );
+        assert_eq!(
+            registers(OutputFormat::Csv),
+            b"name,value,symbol\npc,0x0000000080000010,main+0x10\nstatus,0x001a,\n"
+        );
+        assert_eq!(
+            String::from_utf8(registers(OutputFormat::Json)).unwrap(),
+            concat!(
+                "[\n",
+                "  {\n",
+                "    \"name\": \"pc\",\n",
+                "    \"value\": 2147483664,\n",
+                "    \"symbol\": \"main+0x10\"\n",
+                "  },\n",
+                "  {\n",
+                "    \"name\": \"status\",\n",
+                "    \"value\": 26\n",
+                "  }\n",
+                "]\n",
+            )
+        );
+    }
+}
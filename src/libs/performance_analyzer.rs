@@ -1,10 +1,12 @@
 #![allow(dead_code)]
 
+use crate::libs::chip_desc::chip_desc;
 use crate::libs::cpp_bus::CppIsland;
+use crate::libs::error::NfpError;
+use crate::libs::nfp::Nfp;
 use bitfield::bitfield;
 use bitfield::fmt::Debug;
 
-use crate::libs::expansion_bar::ExpansionBar;
 use crate::libs::xpb_bus::{xpb_read, xpb_write};
 
 /// Performance Analyzer XPB register MAP offsets.
@@ -35,6 +37,8 @@ const PA_CAPTURE_TCAM: [u32; 8] = [
     0x00C0, 0x00C4, 0x00C8, 0x00CC, 0x00D0, 0x00D4, 0x00D8, 0x00DC,
 ];
 const PA_PERFORMANCE_COUNTER: [u32; 4] = [0x00E0, 0x00E4, 0x00E8, 0x00EC];
+// Number of 32-bit words the Performance Analyzer FIFO holds.
+const PA_FIFO_SIZE: u32 = 4096;
 
 // PAConfig bitfields (see High Speed Performance Analyzer Peripheral EAS v0.3,
 // section 2.3)
@@ -343,10 +347,10 @@ pub enum TcamCaptureSource {
 ///
 /// # Fields
 ///
-/// * `exp_bar`: A mutable reference to a PCIe expansion BAR.
+/// * `nfp`: Handle to the NFP the Performance Analyzer is on.
 /// * `cpp_island`: CppIsland in which the performance analyzer resides.
-pub struct PerformanceAnalyzer<'a> {
-    pub exp_bar: &'a mut ExpansionBar,
+pub struct PerformanceAnalyzer {
+    pub nfp: Nfp,
     pub cpp_island: CppIsland,
     pub pa_base_addr: u32,
     pa_configuration: PAConfig,
@@ -356,8 +360,14 @@ pub struct PerformanceAnalyzer<'a> {
     state_transitions: Vec<(PATriggerTransitionConfig0, PATriggerTransitionConfig1)>,
 }
 
-impl<'a> PerformanceAnalyzer<'a> {
-    pub fn new(exp_bar: &'a mut ExpansionBar, cpp_island: CppIsland) -> Self {
+impl PerformanceAnalyzer {
+    /// Create a Performance Analyzer for the island `cpp_island`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::InvalidArgument` if the chip description gives no
+    /// `pa` XPB block for the island.
+    pub fn new(nfp: Nfp, cpp_island: CppIsland) -> Result<Self, NfpError> {
         let pa_configuration = PAConfig(0);
         let mask_compare_units = vec![PAMaskCompare(0); 16];
         let mask_compare_detect_units = vec![PAMaskCompareDetect(0); 8];
@@ -371,13 +381,18 @@ impl<'a> PerformanceAnalyzer<'a> {
             state_transitions.push((config0, config1));
         }
 
-        let pa_base_addr: u32 = match cpp_island {
-            CppIsland::Rfpc0 => 0x000F0000,
-            _ => panic!("Island not supported yet"),
+        let pa_base_addr: u32 = match chip_desc().xpb_block_base(cpp_island.id(), "pa") {
+            Some(pa_base_addr) => pa_base_addr,
+            None => {
+                return Err(NfpError::InvalidArgument(format!(
+                    "Island {} has no Performance Analyzer",
+                    cpp_island
+                )))
+            }
         };
 
-        PerformanceAnalyzer {
-            exp_bar,
+        Ok(PerformanceAnalyzer {
+            nfp,
             cpp_island,
             pa_base_addr,
             pa_configuration,
@@ -385,14 +400,14 @@ impl<'a> PerformanceAnalyzer<'a> {
             mask_compare_detect_units,
             tcam_capture_units,
             state_transitions,
-        }
+        })
     }
 
     /// This method applies the Performance Analyzer configuration
     /// and starts it.
-    pub fn start_pa(mut self) -> Self {
-        self.apply_configuration();
-        self
+    pub fn start_pa(mut self) -> Result<Self, NfpError> {
+        self.apply_configuration()?;
+        Ok(self)
     }
 
     /// Configures the Performance Analyzer for a specific mode of operation.
@@ -435,6 +450,7 @@ impl<'a> PerformanceAnalyzer<'a> {
     /// # Returns
     ///
     /// Returns a mutable reference to `self`.
+    #[allow(clippy::too_many_arguments)]
     pub fn set_pa_global_config(
         mut self,
         capture_decomp: bool,
@@ -549,6 +565,7 @@ impl<'a> PerformanceAnalyzer<'a> {
     /// # Returns
     ///
     /// Returns a mutable reference to `self`.
+    #[allow(clippy::doc_lazy_continuation)]
     pub fn set_mask_compare(
         mut self,
         byte_num: u8,
@@ -716,6 +733,7 @@ impl<'a> PerformanceAnalyzer<'a> {
     /// # Returns
     ///
     /// Returns a mutable reference to `self`.
+    #[allow(clippy::too_many_arguments)]
     pub fn set_state_transition(
         mut self,
         transition_num: u8,
@@ -771,62 +789,64 @@ impl<'a> PerformanceAnalyzer<'a> {
     ///
     /// This method ensures that any local configuration changes are written to
     /// the corresponding registers in the Performance Analyzer Peripheral.
-    fn apply_configuration(&mut self) {
+    fn apply_configuration(&mut self) -> Result<(), NfpError> {
         xpb_write(
-            self.exp_bar,
+            &mut *self.nfp.exp_bar()?,
             &self.cpp_island,
             self.pa_base_addr + PA_CONFIG,
             vec![self.pa_configuration.0],
             false,
-        );
+        )?;
 
         for mc_val in &self.mask_compare_units {
             xpb_write(
-                self.exp_bar,
+                &mut *self.nfp.exp_bar()?,
                 &self.cpp_island,
                 self.pa_base_addr + PA_MASK_COMPARE,
                 vec![mc_val.0],
                 false,
-            );
+            )?;
         }
 
         for (index, mcd_val) in self.mask_compare_detect_units.iter().enumerate() {
             xpb_write(
-                self.exp_bar,
+                &mut *self.nfp.exp_bar()?,
                 &self.cpp_island,
                 self.pa_base_addr + PA_MASK_COMPARE_DETECT[index],
                 vec![mcd_val.0],
                 false,
-            );
+            )?;
         }
 
         for (index, (config0, config1)) in self.state_transitions.iter().enumerate() {
             xpb_write(
-                self.exp_bar,
+                &mut *self.nfp.exp_bar()?,
                 &self.cpp_island,
                 self.pa_base_addr + PA_TRIGGER_TRANSITION_CONFIG[index][0],
                 vec![config0.0],
                 false,
-            );
+            )?;
 
             xpb_write(
-                self.exp_bar,
+                &mut *self.nfp.exp_bar()?,
                 &self.cpp_island,
                 self.pa_base_addr + PA_TRIGGER_TRANSITION_CONFIG[index][1],
                 vec![config1.0],
                 false,
-            );
+            )?;
         }
 
         for (index, tcam_val) in self.tcam_capture_units.iter().enumerate() {
             xpb_write(
-                self.exp_bar,
+                &mut *self.nfp.exp_bar()?,
                 &self.cpp_island,
                 self.pa_base_addr + PA_CAPTURE_TCAM[index],
                 vec![tcam_val.0],
                 false,
-            );
+            )?;
         }
+
+        Ok(())
     }
 
     /// Reads the configuration status of the Performance Analyzer.
@@ -834,45 +854,45 @@ impl<'a> PerformanceAnalyzer<'a> {
     /// # Returns
     ///
     /// * `PAStatus` - The current status of the Performance Analyzer.
-    pub fn read_pa_status(&mut self) -> PAStatus {
+    pub fn read_pa_status(&mut self) -> Result<PAStatus, NfpError> {
         let raw_val = xpb_read(
-            self.exp_bar,
+            &mut *self.nfp.exp_bar()?,
             &self.cpp_island,
             self.pa_base_addr + PA_STATUS,
             1,
             false,
-        );
-        PAStatus(raw_val[0])
+        )?;
+        Ok(PAStatus(raw_val[0]))
     }
 
     /// Puts the trigger into an idle state from any other state.
-    pub fn trigger_idle(&mut self) {
+    pub fn trigger_idle(&mut self) -> Result<(), NfpError> {
         let mut trigger = PATriggerControl(0);
         trigger.set_active_states(0);
         trigger.set_timeout(0);
         trigger.set_trigger_command(3);
         xpb_write(
-            self.exp_bar,
+            &mut *self.nfp.exp_bar()?,
             &self.cpp_island,
             self.pa_base_addr + PA_TRIGGER_CONTROL,
             vec![trigger.0],
             false,
-        );
+        )
     }
 
     /// Halt trigger if running.
-    pub fn trigger_halt(&mut self) {
+    pub fn trigger_halt(&mut self) -> Result<(), NfpError> {
         let mut trigger = PATriggerControl(0);
         trigger.set_active_states(0);
         trigger.set_timeout(0);
         trigger.set_trigger_command(2);
         xpb_write(
-            self.exp_bar,
+            &mut *self.nfp.exp_bar()?,
             &self.cpp_island,
             self.pa_base_addr + PA_TRIGGER_CONTROL,
             vec![trigger.0],
             false,
-        );
+        )
     }
 
     /// Starts the trigger if the Performance Analyzer is idle.
@@ -891,18 +911,19 @@ impl<'a> PerformanceAnalyzer<'a> {
     /// # Returns
     ///
     /// A mutable reference to `self`.
-    pub fn trigger_start(&mut self, active_states: u8, timeout: u8) {
+    #[allow(clippy::doc_overindented_list_items)]
+    pub fn trigger_start(&mut self, active_states: u8, timeout: u8) -> Result<(), NfpError> {
         let mut trigger = PATriggerControl(0);
         trigger.set_active_states(active_states as u32);
         trigger.set_timeout(timeout as u32);
         trigger.set_trigger_command(1);
         xpb_write(
-            self.exp_bar,
+            &mut *self.nfp.exp_bar()?,
             &self.cpp_island,
             self.pa_base_addr + PA_TRIGGER_CONTROL,
             vec![trigger.0],
             false,
-        );
+        )
     }
 
     /// Reads a number of 32-bit words from the Performance Analyzer FIFO.
@@ -913,10 +934,8 @@ impl<'a> PerformanceAnalyzer<'a> {
     ///
     /// # Parameters
     ///
-    /// * `nfp_bdf` - The PCIe Bus/Device/Function identifier.
-    /// * `island` - The island where the PA FIFO is located.
     /// * `num_words` - Number of 32-bit words to read from the Performance Analyzer FIFO. If set to zero,
-    ///                 all words in the FIFO are read.
+    ///   all words in the FIFO are read.
     ///
     /// # Returns
     ///
@@ -924,13 +943,15 @@ impl<'a> PerformanceAnalyzer<'a> {
     ///
     /// # Errors
     ///
-    /// This function will `panic!` in the following cases:
-    /// * If the FIFO buffer is empty.
-    /// * If `num_words` exceeds 4096.
-    pub fn read_fifo(&mut self, num_words: u32) -> Vec<u32> {
+    /// Returns `NfpError::InvalidArgument` if `num_words` exceeds the FIFO
+    /// size of 4096 words, or if the FIFO is empty.
+    pub fn read_fifo(&mut self, num_words: u32) -> Result<Vec<u32>, NfpError> {
         // Check if num_words exceeds the maximum FIFO size
-        if num_words > 4096 {
-            panic!("The maximum size of the FIFO is 4096 32-bit words.");
+        if num_words > PA_FIFO_SIZE {
+            return Err(NfpError::InvalidArgument(format!(
+                "Cannot read {} words, the maximum size of the FIFO is {} 32-bit words",
+                num_words, PA_FIFO_SIZE
+            )));
         }
 
         let mut fifo_words: Vec<u32> = Vec::new();
@@ -938,26 +959,33 @@ impl<'a> PerformanceAnalyzer<'a> {
         // Read FIFO control data
         let fifo_control = PAFifoControl(
             xpb_read(
-                self.exp_bar,
+                &mut *self.nfp.exp_bar()?,
                 &self.cpp_island,
                 self.pa_base_addr + PA_FIFO_CONTROL,
                 1,
                 false,
-            )[0],
+            )?[0],
         );
 
-        // Determine number of entries in the FIFO
-        let entries_in_fifo = if fifo_control.overflow() && !self.pa_configuration.journalling() {
-            4096
-        } else {
-            fifo_control.write_ptr() - fifo_control.read_ptr()
-        };
-
         // Check if the FIFO is empty
         if fifo_control.empty() {
-            panic!("FIFO buffer is empty");
+            return Err(NfpError::InvalidArgument(format!(
+                "The Performance Analyzer FIFO of island {} is empty",
+                self.cpp_island
+            )));
         }
 
+        // Determine number of entries in the FIFO. The pointers wrap around
+        // the FIFO, so the write pointer may be behind the read pointer.
+        let entries_in_fifo = if fifo_control.overflow() && !self.pa_configuration.journalling() {
+            PA_FIFO_SIZE
+        } else {
+            fifo_control
+                .write_ptr()
+                .wrapping_sub(fifo_control.read_ptr())
+                % PA_FIFO_SIZE
+        };
+
         // Adjust the number of words to read
         let words_to_read = if num_words != 0 && num_words < entries_in_fifo {
             num_words
@@ -969,16 +997,16 @@ impl<'a> PerformanceAnalyzer<'a> {
         for _ in 0..words_to_read {
             fifo_words.push(
                 xpb_read(
-                    self.exp_bar,
+                    &mut *self.nfp.exp_bar()?,
                     &self.cpp_island,
                     self.pa_base_addr + PA_FIFO_DATA,
                     1,
                     false,
-                )[0],
+                )?[0],
             );
         }
 
-        fifo_words
+        Ok(fifo_words)
     }
 
     /// Reads the current status of the trigger.
@@ -986,15 +1014,15 @@ impl<'a> PerformanceAnalyzer<'a> {
     /// # Returns
     ///
     /// A `PATriggerStatus` instance that contains the current status of the trigger.
-    pub fn read_trigger_status(&mut self) -> PATriggerStatus {
+    pub fn read_trigger_status(&mut self) -> Result<PATriggerStatus, NfpError> {
         let raw_val = xpb_read(
-            self.exp_bar,
+            &mut *self.nfp.exp_bar()?,
             &self.cpp_island,
             self.pa_base_addr + PA_TRIGGER_STATUS,
             1,
             false,
-        );
-        PATriggerStatus(raw_val[0])
+        )?;
+        Ok(PATriggerStatus(raw_val[0]))
     }
 
     /// Retrieves the current value of the Performance Analyzer Timer.
@@ -1003,14 +1031,14 @@ impl<'a> PerformanceAnalyzer<'a> {
     /// # Returns
     ///
     /// The current 32-bit timer value as a `u32`.
-    pub fn read_pa_timer(&mut self) -> u32 {
-        xpb_read(
-            self.exp_bar,
+    pub fn read_pa_timer(&mut self) -> Result<u32, NfpError> {
+        Ok(xpb_read(
+            &mut *self.nfp.exp_bar()?,
             &self.cpp_island,
             self.pa_base_addr + PA_TIMER,
             1,
             false,
-        )[0]
+        )?[0])
     }
 
     /// Retrieves the current value of one of the Performance Counters.
@@ -1025,17 +1053,17 @@ impl<'a> PerformanceAnalyzer<'a> {
     /// # Panics
     ///
     /// This function will panic if `counter_num` is not in the range 0-3.
-    pub fn read_perf_counter(&mut self, counter_num: u8) -> u32 {
+    pub fn read_perf_counter(&mut self, counter_num: u8) -> Result<u32, NfpError> {
         if counter_num >= (1 << 2) {
             panic!("counter_num can only be 2 bits maximum.");
         }
-        xpb_read(
-            self.exp_bar,
+        Ok(xpb_read(
+            &mut *self.nfp.exp_bar()?,
             &self.cpp_island,
             self.pa_base_addr + PA_PERFORMANCE_COUNTER[counter_num as usize],
             1,
             false,
-        )[0]
+        )?[0])
     }
 
     /// Retrieves the current value of one of the Trigger Counters.
@@ -1050,17 +1078,17 @@ impl<'a> PerformanceAnalyzer<'a> {
     /// # Panics
     ///
     /// This function will panic if `counter_num` is not in the range 0-1.
-    pub fn read_trigger_counter(&mut self, counter_num: u8) -> u32 {
+    pub fn read_trigger_counter(&mut self, counter_num: u8) -> Result<u32, NfpError> {
         if counter_num >= (1 << 1) {
             panic!("counter_num can only be 1 bit maximum.");
         }
-        xpb_read(
-            self.exp_bar,
+        Ok(xpb_read(
+            &mut *self.nfp.exp_bar()?,
             &self.cpp_island,
             self.pa_base_addr + PA_TRIGGER_COUNTER[counter_num as usize],
             1,
             false,
-        )[0]
+        )?[0])
     }
 
     /// Sets the restart value for a Trigger counter.
@@ -1072,17 +1100,64 @@ impl<'a> PerformanceAnalyzer<'a> {
     /// # Panics
     ///
     /// This function will panic if `counter_num` is not in the range 0-1.
-    pub fn set_trigger_counter_restart(&mut self, counter_num: u8, value: u32) {
+    pub fn set_trigger_counter_restart(
+        &mut self,
+        counter_num: u8,
+        value: u32,
+    ) -> Result<(), NfpError> {
         if counter_num >= (1 << 1) {
             panic!("counter_num can only be 1 bit maximum.");
         }
 
         xpb_write(
-            self.exp_bar,
+            &mut *self.nfp.exp_bar()?,
             &self.cpp_island,
             self.pa_base_addr + PA_TRIGGER_COUNTER_RESTART[counter_num as usize],
             vec![value],
             false,
-        );
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::chip_desc::IslandKind;
+    use crate::libs::sim_device::SimDevice;
+    use std::sync::Arc;
+
+    fn set_fifo_control(pa: &PerformanceAnalyzer, fifo_control: PAFifoControl) {
+        xpb_write(
+            &mut pa.nfp.exp_bar().unwrap(),
+            &pa.cpp_island,
+            pa.pa_base_addr + PA_FIFO_CONTROL,
+            vec![fifo_control.0],
+            false,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn fifo_reads_are_checked() {
+        let nfp = Nfp::new(Arc::new(SimDevice::new())).unwrap();
+        let mut pa =
+            PerformanceAnalyzer::new(nfp, CppIsland::first_of_kind(IslandKind::Rfpc)).unwrap();
+
+        let mut fifo_control = PAFifoControl(0);
+        fifo_control.set_empty(true);
+        set_fifo_control(&pa, fifo_control);
+        assert!(matches!(pa.read_fifo(0), Err(NfpError::InvalidArgument(_))));
+        assert!(matches!(
+            pa.read_fifo(PA_FIFO_SIZE + 1),
+            Err(NfpError::InvalidArgument(_))
+        ));
+
+        // The write pointer wrapped around past the read pointer.
+        let mut fifo_control = PAFifoControl(0);
+        fifo_control.set_read_ptr(PA_FIFO_SIZE - 2);
+        fifo_control.set_write_ptr(3);
+        set_fifo_control(&pa, fifo_control);
+        assert_eq!(pa.read_fifo(0).unwrap().len(), 5);
+        assert_eq!(pa.read_fifo(2).unwrap().len(), 2);
     }
 }
@@ -0,0 +1,643 @@
+#![allow(dead_code)]
+
+// Python extension module `rust_nfp_tools`, built with the `python` feature.
+
+use bytemuck::cast_slice;
+use clap::ValueEnum;
+use pyo3::buffer::PyBuffer;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyByteArray, PyBytes};
+use std::str::FromStr;
+
+use crate::libs::chip_desc::install_env_chip_desc;
+use crate::libs::cpp_bus::{CppBus, CppIsland, CppLength, CppTarget};
+use crate::libs::device_enum::DeviceEnumerator;
+use crate::libs::error::NfpError;
+use crate::libs::expansion_bar::ExpansionBar;
+use crate::libs::mem_access::{mem_read, mem_write, MemoryType, MuMemoryEngine};
+use crate::libs::nfp::{Nfp, PooledBar};
+use crate::libs::rfpc::{Rfpc, RfpcCsr, RfpcGpr, RfpcReg};
+use crate::libs::rfpc_debugger::{
+    rfpc_dbg_halt, rfpc_dbg_read_reg, rfpc_dbg_resume, rfpc_dbg_write_reg,
+};
+use crate::libs::virtual_terminal::VirtualTerminal;
+use crate::libs::xpb_bus::{xpb_read, xpb_write};
+
+mod exceptions {
+    use pyo3::create_exception;
+    use pyo3::exceptions::PyException;
+
+    create_exception!(
+        rust_nfp_tools,
+        NfpError,
+        PyException,
+        "Error of the NFP access stack."
+    );
+    create_exception!(rust_nfp_tools, LockContentionError, NfpError);
+    create_exception!(rust_nfp_tools, DeviceIoError, NfpError);
+    create_exception!(rust_nfp_tools, AddressOutOfRangeError, NfpError);
+    create_exception!(rust_nfp_tools, InvalidArgumentError, NfpError);
+    create_exception!(rust_nfp_tools, DeviceNotFoundError, NfpError);
+    create_exception!(rust_nfp_tools, DmCmdError, NfpError);
+    create_exception!(rust_nfp_tools, DeviceTimeoutError, NfpError);
+    create_exception!(rust_nfp_tools, VerifyFailedError, NfpError);
+    create_exception!(rust_nfp_tools, FirmwareNotLoadedError, NfpError);
+}
+
+use exceptions::{
+    AddressOutOfRangeError, DeviceIoError, DeviceNotFoundError, DeviceTimeoutError, DmCmdError,
+    FirmwareNotLoadedError, InvalidArgumentError, LockContentionError, VerifyFailedError,
+};
+
+impl From<NfpError> for PyErr {
+    fn from(error: NfpError) -> Self {
+        let message = error.to_string();
+        match error {
+            NfpError::LockContention(_) => LockContentionError::new_err(message),
+            NfpError::Io { .. } => DeviceIoError::new_err(message),
+            NfpError::AddressOutOfRange(_) => AddressOutOfRangeError::new_err(message),
+            NfpError::InvalidArgument(_) => InvalidArgumentError::new_err(message),
+            NfpError::DeviceNotFound(_) => DeviceNotFoundError::new_err(message),
+            NfpError::DmCmdErr(_) => DmCmdError::new_err(message),
+            NfpError::Timeout(_) => DeviceTimeoutError::new_err(message),
+            NfpError::VerifyFailed(_) => VerifyFailedError::new_err(message),
+            NfpError::FirmwareNotLoaded(_) => FirmwareNotLoadedError::new_err(message),
+        }
+    }
+}
+
+/// Island or CPP target, given by name or ID.
+#[derive(FromPyObject)]
+enum IdOrName {
+    Id(u8),
+    Name(String),
+}
+
+impl IdOrName {
+    fn parse<T: FromStr<Err = String>>(&self) -> Result<T, NfpError> {
+        let s = match self {
+            IdOrName::Id(id) => id.to_string(),
+            IdOrName::Name(name) => name.clone(),
+        };
+        T::from_str(&s).map_err(NfpError::InvalidArgument)
+    }
+}
+
+/// RFPC register, given by name ("x5", "mstatus") or debug module register
+/// number.
+#[derive(FromPyObject)]
+enum RegArg {
+    Addr(u16),
+    Name(String),
+}
+
+impl RegArg {
+    fn reg_addr(&self) -> Result<u64, NfpError> {
+        match self {
+            RegArg::Addr(addr) => Ok(*addr as u64),
+            RegArg::Name(name) => RfpcGpr::from_str(name, true)
+                .map(|gpr| gpr.reg_addr())
+                .or_else(|_| RfpcCsr::from_str(name, true).map(|csr| csr.reg_addr()))
+                .map_err(|_| NfpError::InvalidArgument(format!("No RFPC register {}", name))),
+        }
+    }
+}
+
+/// Parse the name of a clap value, e.g. a memory type.
+fn value_arg<T: ValueEnum>(value: &str, what: &str) -> Result<T, NfpError> {
+    T::from_str(value, true).map_err(|_| {
+        let values: Vec<_> = T::value_variants()
+            .iter()
+            .filter_map(|v| v.to_possible_value())
+            .map(|v| v.get_name().to_string())
+            .collect();
+        NfpError::InvalidArgument(format!(
+            "Invalid {} {} [possible values: {}]",
+            what,
+            value,
+            values.join(", ")
+        ))
+    })
+}
+
+/// Return 32-bit words as an `array.array('I')`, which can be indexed like a
+/// list and wrapped by numpy without a copy, e.g. with `numpy.frombuffer`.
+fn words_array<'py>(py: Python<'py>, words: &[u32]) -> PyResult<Bound<'py, PyAny>> {
+    py.import("array")?
+        .getattr("array")?
+        .call1(("I", PyBytes::new(py, cast_slice(words))))
+}
+
+/// Extract 32-bit words from a `uint32` buffer (numpy array,
+/// `array.array('I')`), little-endian bytes, or a sequence of integers.
+fn words_arg(words: &Bound<'_, PyAny>) -> PyResult<Vec<u32>> {
+    if words.is_instance_of::<PyBytes>() || words.is_instance_of::<PyByteArray>() {
+        let bytes = PyBuffer::<u8>::get(words)?.to_vec(words.py())?;
+        if !bytes.len().is_multiple_of(4) {
+            return Err(PyValueError::new_err(format!(
+                "{} bytes is not a whole number of 32-bit words",
+                bytes.len()
+            )));
+        }
+        return Ok(bytes
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect());
+    }
+    if let Ok(buffer) = PyBuffer::<u32>::get(words) {
+        return buffer.to_vec(words.py());
+    }
+    words.extract()
+}
+
+/// Handle of an NFP.
+///
+/// `Nfp("0000:65:00.0")` opens the NFP at a PCIe BDF, `Nfp(sim=True)` an
+/// in-process simulated NFP and `Nfp(remote="host:4242")` the NFP of an
+/// `nfp-cpp-server`, with the token in `NFP_REMOTE_TOKEN`.
+#[pyclass(name = "Nfp", module = "rust_nfp_tools")]
+struct PyNfp {
+    nfp: Nfp,
+}
+
+#[pymethods]
+impl PyNfp {
+    #[new]
+    #[pyo3(signature = (pci_bdf=None, *, remote=None, sim=false))]
+    fn new(
+        py: Python<'_>,
+        pci_bdf: Option<&str>,
+        remote: Option<&str>,
+        sim: bool,
+    ) -> PyResult<Self> {
+        if pci_bdf.is_none() && remote.is_none() && !sim {
+            return Err(PyValueError::new_err("Give a PCIe BDF, remote or sim=True"));
+        }
+        let nfp = py.detach(|| {
+            let pci_bdf = pci_bdf
+                .map(|pci_bdf| DeviceEnumerator::new().select(pci_bdf))
+                .transpose()?;
+            Nfp::open(pci_bdf.as_deref(), remote, sim)
+        })?;
+        Ok(PyNfp { nfp })
+    }
+
+    /// Name of the device, e.g. its PCIe BDF.
+    #[getter]
+    fn name(&self) -> &str {
+        self.nfp.name()
+    }
+
+    /// Take an expansion BAR for exclusive use, waiting if all of them are in
+    /// use. The BAR is returned by `free()` or at the end of a `with` block.
+    fn exp_bar(&self, py: Python<'_>) -> PyResult<PyExpansionBar> {
+        let bar = py.detach(|| self.nfp.exp_bar())?;
+        Ok(PyExpansionBar { bar: Some(bar) })
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Nfp('{}')", self.nfp.name())
+    }
+}
+
+/// Expansion BAR taken from an `Nfp`.
+#[pyclass(name = "ExpansionBar", module = "rust_nfp_tools")]
+struct PyExpansionBar {
+    bar: Option<PooledBar<ExpansionBar>>,
+}
+
+impl PyExpansionBar {
+    fn bar(&mut self) -> PyResult<&mut ExpansionBar> {
+        match &mut self.bar {
+            Some(bar) => Ok(bar),
+            None => Err(PyValueError::new_err("Expansion BAR was freed")),
+        }
+    }
+}
+
+#[pymethods]
+impl PyExpansionBar {
+    /// Size of the BAR window in bytes.
+    #[getter]
+    fn size(&mut self) -> PyResult<u64> {
+        Ok(self.bar()?.exp_bar_size)
+    }
+
+    /// Read `length` bytes at `offset` of the window as last configured.
+    fn read<'py>(
+        &mut self,
+        py: Python<'py>,
+        offset: u64,
+        length: u64,
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        let bar = self.bar()?;
+        let bytes = py.detach(|| bar.read(offset, length))?;
+        Ok(PyBytes::new(py, &bytes))
+    }
+
+    /// Write `data` at `offset` of the window as last configured.
+    fn write(&mut self, py: Python<'_>, data: &[u8], offset: u64) -> PyResult<()> {
+        let bar = self.bar()?;
+        Ok(py.detach(|| bar.write(data, offset))?)
+    }
+
+    /// Return the BAR to its `Nfp`.
+    fn free(&mut self) {
+        self.bar = None;
+    }
+
+    fn __enter__(slf: Py<Self>) -> Py<Self> {
+        slf
+    }
+
+    #[pyo3(signature = (*_args))]
+    fn __exit__(&mut self, _args: &Bound<'_, pyo3::types::PyTuple>) -> bool {
+        self.free();
+        false
+    }
+}
+
+/// CPP bus access through an `ExpansionBar`.
+#[pyclass(name = "CppBus", module = "rust_nfp_tools")]
+struct PyCppBus {
+    exp_bar: Py<PyExpansionBar>,
+}
+
+#[pymethods]
+impl PyCppBus {
+    #[new]
+    fn new(exp_bar: Py<PyExpansionBar>) -> Self {
+        PyCppBus { exp_bar }
+    }
+
+    /// Read `length_words` 32-bit words from the CPP bus.
+    ///
+    /// `cpp_len` is "len32", "len64" or "no-len".
+    #[allow(clippy::too_many_arguments)]
+    fn read<'py>(
+        &self,
+        py: Python<'py>,
+        island: IdOrName,
+        target: IdOrName,
+        action: u8,
+        token: u8,
+        cpp_len: &str,
+        address: u64,
+        length_words: u64,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let island: CppIsland = island.parse()?;
+        let target: CppTarget = target.parse()?;
+        let cpp_len: CppLength = value_arg(cpp_len, "CPP length")?;
+        let mut exp_bar = self.exp_bar.borrow_mut(py);
+        let bar = exp_bar.bar()?;
+        let words = py.detach(|| {
+            CppBus::new(bar).read(
+                island,
+                target,
+                action,
+                token,
+                cpp_len,
+                address,
+                length_words,
+            )
+        })?;
+        words_array(py, &words)
+    }
+
+    /// Write 32-bit words to the CPP bus.
+    #[allow(clippy::too_many_arguments)]
+    fn write(
+        &self,
+        py: Python<'_>,
+        island: IdOrName,
+        target: IdOrName,
+        action: u8,
+        token: u8,
+        cpp_len: &str,
+        address: u64,
+        words: &Bound<'_, PyAny>,
+    ) -> PyResult<()> {
+        let island: CppIsland = island.parse()?;
+        let target: CppTarget = target.parse()?;
+        let cpp_len: CppLength = value_arg(cpp_len, "CPP length")?;
+        let words = words_arg(words)?;
+        let mut exp_bar = self.exp_bar.borrow_mut(py);
+        let bar = exp_bar.bar()?;
+        Ok(py.detach(|| {
+            CppBus::new(bar).write(island, target, action, token, cpp_len, address, words)
+        })?)
+    }
+}
+
+/// Read `length` XPB registers starting at `address` of `island`, or of the
+/// XPB master of the island if `xpbm` is set.
+#[pyfunction(name = "xpb_read")]
+#[pyo3(signature = (exp_bar, island, address, length=1, xpbm=false))]
+fn py_xpb_read<'py>(
+    py: Python<'py>,
+    exp_bar: &mut PyExpansionBar,
+    island: IdOrName,
+    address: u32,
+    length: u64,
+    xpbm: bool,
+) -> PyResult<Bound<'py, PyAny>> {
+    let island: CppIsland = island.parse()?;
+    let bar = exp_bar.bar()?;
+    let words = py.detach(|| xpb_read(bar, &island, address, length, xpbm))?;
+    words_array(py, &words)
+}
+
+/// Write XPB registers starting at `address` of `island`.
+#[pyfunction(name = "xpb_write")]
+#[pyo3(signature = (exp_bar, island, address, words, xpbm=false))]
+fn py_xpb_write(
+    py: Python<'_>,
+    exp_bar: &mut PyExpansionBar,
+    island: IdOrName,
+    address: u32,
+    words: &Bound<'_, PyAny>,
+    xpbm: bool,
+) -> PyResult<()> {
+    let island: CppIsland = island.parse()?;
+    let words = words_arg(words)?;
+    let bar = exp_bar.bar()?;
+    Ok(py.detach(|| xpb_write(bar, &island, address, words, xpbm))?)
+}
+
+/// Return the memory type and engine of a memory access. The memory type
+/// defaults to the one the tools use for `island`.
+fn mem_args(
+    island: CppIsland,
+    mem_type: Option<&str>,
+    engine: &str,
+) -> Result<(MemoryType, MuMemoryEngine), NfpError> {
+    let mem_type = match mem_type {
+        Some(mem_type) => value_arg(mem_type, "memory type")?,
+        None => MemoryType::default_for_island(island),
+    };
+    Ok((mem_type, value_arg(engine, "memory engine")?))
+}
+
+/// Read `length` 32-bit words at byte `address` of a memory of `island`.
+#[pyfunction(name = "mem_read")]
+#[pyo3(signature = (exp_bar, island, address, length, *, mem_type=None, engine="bulk32"))]
+fn py_mem_read<'py>(
+    py: Python<'py>,
+    exp_bar: &mut PyExpansionBar,
+    island: IdOrName,
+    address: u64,
+    length: u64,
+    mem_type: Option<&str>,
+    engine: &str,
+) -> PyResult<Bound<'py, PyAny>> {
+    let island: CppIsland = island.parse()?;
+    let (mem_type, engine) = mem_args(island, mem_type, engine)?;
+    let bar = exp_bar.bar()?;
+    let words = py.detach(|| mem_read(bar, island, mem_type, engine, address, length))?;
+    words_array(py, &words)
+}
+
+/// Write 32-bit words at byte `address` of a memory of `island`.
+#[pyfunction(name = "mem_write")]
+#[pyo3(signature = (exp_bar, island, address, words, *, mem_type=None, engine="bulk32"))]
+fn py_mem_write(
+    py: Python<'_>,
+    exp_bar: &mut PyExpansionBar,
+    island: IdOrName,
+    address: u64,
+    words: &Bound<'_, PyAny>,
+    mem_type: Option<&str>,
+    engine: &str,
+) -> PyResult<()> {
+    let island: CppIsland = island.parse()?;
+    let (mem_type, engine) = mem_args(island, mem_type, engine)?;
+    let words = words_arg(words)?;
+    let bar = exp_bar.bar()?;
+    Ok(py.detach(|| mem_write(bar, island, mem_type, engine, address, words))?)
+}
+
+/// RFPC core, by island and cluster, group and core number.
+#[pyclass(name = "Rfpc", module = "rust_nfp_tools", frozen)]
+struct PyRfpc {
+    rfpc: Rfpc,
+}
+
+#[pymethods]
+impl PyRfpc {
+    #[new]
+    fn new(island: IdOrName, cluster: u8, group: u8, core: u8) -> PyResult<Self> {
+        let rfpc = Rfpc::new(island.parse()?, cluster, group, core)?;
+        Ok(PyRfpc { rfpc })
+    }
+
+    #[getter]
+    fn island(&self) -> String {
+        self.rfpc.island.to_string()
+    }
+
+    #[getter]
+    fn cluster(&self) -> u8 {
+        self.rfpc.cluster
+    }
+
+    #[getter]
+    fn group(&self) -> u8 {
+        self.rfpc.group
+    }
+
+    #[getter]
+    fn core(&self) -> u8 {
+        self.rfpc.core
+    }
+
+    /// Halt the core through its debug module.
+    fn halt(&self, py: Python<'_>, nfp: &PyNfp) -> PyResult<()> {
+        let mut expl_bar = py.detach(|| nfp.nfp.expl_bar())?;
+        Ok(py.detach(|| rfpc_dbg_halt(&mut expl_bar, &self.rfpc))?)
+    }
+
+    /// Resume the halted core.
+    fn resume(&self, py: Python<'_>, nfp: &PyNfp) -> PyResult<()> {
+        let mut expl_bar = py.detach(|| nfp.nfp.expl_bar())?;
+        Ok(py.detach(|| rfpc_dbg_resume(&mut expl_bar, &self.rfpc))?)
+    }
+
+    /// Read a register, given by name or debug module register number.
+    ///
+    /// With `halt`, the core is halted for the read and resumed afterwards,
+    /// as `nfp-rfpc-reg` does. Otherwise the core must already be halted.
+    #[pyo3(signature = (nfp, reg, *, halt=true))]
+    fn read_reg(&self, py: Python<'_>, nfp: &PyNfp, reg: RegArg, halt: bool) -> PyResult<u64> {
+        let reg_addr = reg.reg_addr()?;
+        Ok(py.detach(|| {
+            let mut expl_bar = nfp.nfp.expl_bar()?;
+            if halt {
+                rfpc_dbg_halt(&mut expl_bar, &self.rfpc)?;
+            }
+            let value = rfpc_dbg_read_reg(&mut expl_bar, &self.rfpc, reg_addr)?;
+            if halt {
+                rfpc_dbg_resume(&mut expl_bar, &self.rfpc)?;
+            }
+            Ok::<_, NfpError>(value)
+        })?)
+    }
+
+    /// Write a register. See `read_reg`.
+    #[pyo3(signature = (nfp, reg, value, *, halt=true))]
+    fn write_reg(
+        &self,
+        py: Python<'_>,
+        nfp: &PyNfp,
+        reg: RegArg,
+        value: u64,
+        halt: bool,
+    ) -> PyResult<()> {
+        let reg_addr = reg.reg_addr()?;
+        Ok(py.detach(|| {
+            let mut expl_bar = nfp.nfp.expl_bar()?;
+            if halt {
+                rfpc_dbg_halt(&mut expl_bar, &self.rfpc)?;
+            }
+            rfpc_dbg_write_reg(&mut expl_bar, &self.rfpc, reg_addr, value)?;
+            if halt {
+                rfpc_dbg_resume(&mut expl_bar, &self.rfpc)?;
+            }
+            Ok::<_, NfpError>(())
+        })?)
+    }
+
+    fn __str__(&self) -> String {
+        self.rfpc.to_string()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Rfpc('{}', {}, {}, {})",
+            self.rfpc.island, self.rfpc.cluster, self.rfpc.group, self.rfpc.core
+        )
+    }
+}
+
+/// Virtual terminal of RFPC firmware at `address` of a memory of `island`.
+#[pyclass(name = "VirtualTerminal", module = "rust_nfp_tools")]
+struct PyVirtualTerminal {
+    vtm: VirtualTerminal,
+}
+
+#[pymethods]
+impl PyVirtualTerminal {
+    #[new]
+    #[pyo3(signature = (nfp, island, address, *, mem_type=None))]
+    fn new(nfp: &PyNfp, island: IdOrName, address: u32, mem_type: Option<&str>) -> PyResult<Self> {
+        let island: CppIsland = island.parse()?;
+        let mem_type = match mem_type {
+            Some(mem_type) => value_arg(mem_type, "memory type")?,
+            None => MemoryType::default_for_island(island),
+        };
+        Ok(PyVirtualTerminal {
+            vtm: VirtualTerminal::new(nfp.nfp.clone(), island, mem_type, address),
+        })
+    }
+
+    /// Return whether the terminal lock is held by an RFPC.
+    fn is_locked(&mut self, py: Python<'_>) -> PyResult<bool> {
+        Ok(py.detach(|| self.vtm.is_locked())?)
+    }
+
+    /// Return the RFPC holding the terminal lock, if any.
+    fn holder(&mut self, py: Python<'_>) -> PyResult<Option<PyRfpc>> {
+        let holder = py.detach(|| self.vtm.holder())?;
+        Ok(holder.map(|rfpc| PyRfpc { rfpc }))
+    }
+
+    /// Return the number of bytes of data available.
+    fn data_available(&mut self, py: Python<'_>) -> PyResult<u32> {
+        Ok(py.detach(|| self.vtm.data_available())?)
+    }
+
+    /// Read the available data as bytes.
+    fn read_bytes<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let bytes = py.detach(|| self.vtm.read_bytes())?;
+        Ok(PyBytes::new(py, &bytes))
+    }
+
+    /// Read the available data as a string.
+    fn read_string(&mut self, py: Python<'_>) -> PyResult<String> {
+        Ok(py.detach(|| self.vtm.read_string())?)
+    }
+
+    /// Wait up to `timeout` seconds, or forever, for data to be available.
+    #[pyo3(signature = (timeout=None))]
+    fn wait_for_data(&mut self, py: Python<'_>, timeout: Option<u64>) -> PyResult<()> {
+        Ok(py.detach(|| self.vtm.wait_for_data(timeout))?)
+    }
+
+    /// Read data until `regexp` matches or no more data arrives.
+    #[pyo3(signature = (regexp, start_timeout=None, end_timeout=None))]
+    fn read_block(
+        &mut self,
+        py: Python<'_>,
+        regexp: &str,
+        start_timeout: Option<u64>,
+        end_timeout: Option<u64>,
+    ) -> PyResult<String> {
+        if let Err(e) = regex::Regex::new(regexp) {
+            return Err(PyValueError::new_err(e.to_string()));
+        }
+        Ok(py.detach(|| self.vtm.read_block(start_timeout, end_timeout, regexp))?)
+    }
+
+    /// Discard the pending data.
+    fn flush_one(&mut self, py: Python<'_>) -> PyResult<()> {
+        Ok(py.detach(|| self.vtm.flush_one())?)
+    }
+
+    /// Discard data until no more is written, for up to `timeout` seconds.
+    #[pyo3(signature = (timeout=None))]
+    fn flush(&mut self, py: Python<'_>, timeout: Option<u64>) -> PyResult<()> {
+        Ok(py.detach(|| self.vtm.flush(timeout))?)
+    }
+}
+
+/// Access to NFP devices: CPP bus, XPB, memories, RFPC registers and
+/// virtual terminals.
+///
+/// Reads return `array.array('I')`, which numpy wraps without a copy with
+/// `numpy.frombuffer(words, dtype=numpy.uint32)`. Writes take a uint32
+/// buffer such as a numpy array, little-endian bytes, or a list of ints.
+#[pymodule]
+fn rust_nfp_tools(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    let py = m.py();
+    install_env_chip_desc()?;
+    m.add_class::<PyNfp>()?;
+    m.add_class::<PyExpansionBar>()?;
+    m.add_class::<PyCppBus>()?;
+    m.add_class::<PyRfpc>()?;
+    m.add_class::<PyVirtualTerminal>()?;
+    m.add_function(wrap_pyfunction!(py_xpb_read, m)?)?;
+    m.add_function(wrap_pyfunction!(py_xpb_write, m)?)?;
+    m.add_function(wrap_pyfunction!(py_mem_read, m)?)?;
+    m.add_function(wrap_pyfunction!(py_mem_write, m)?)?;
+
+    m.add("NfpError", py.get_type::<exceptions::NfpError>())?;
+    m.add("LockContentionError", py.get_type::<LockContentionError>())?;
+    m.add("DeviceIoError", py.get_type::<DeviceIoError>())?;
+    m.add(
+        "AddressOutOfRangeError",
+        py.get_type::<AddressOutOfRangeError>(),
+    )?;
+    m.add(
+        "InvalidArgumentError",
+        py.get_type::<InvalidArgumentError>(),
+    )?;
+    m.add("DeviceNotFoundError", py.get_type::<DeviceNotFoundError>())?;
+    m.add("DmCmdError", py.get_type::<DmCmdError>())?;
+    m.add("DeviceTimeoutError", py.get_type::<DeviceTimeoutError>())?;
+    m.add("VerifyFailedError", py.get_type::<VerifyFailedError>())?;
+    m.add(
+        "FirmwareNotLoadedError",
+        py.get_type::<FirmwareNotLoadedError>(),
+    )?;
+    Ok(())
+}
@@ -0,0 +1,1199 @@
+#![allow(dead_code)]
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::libs::common::hex_bytes;
+use crate::libs::cpp_bus::{CppBus, CppIsland, CppLength, CppTarget};
+use crate::libs::device_backend::{BarWindow, DeviceBackend};
+use crate::libs::error::NfpError;
+use crate::libs::expansion_bar::{
+    MapType, BAR_CONFIG_BASE_CONFIG_SNOOP, EXPANSION_BAR_BASE_OFFSET, EXPANSION_BAR_CSR_OFFSET,
+    EXPANSION_BAR_PHYS_OFFSET,
+};
+use crate::libs::explicit_bar::{
+    ExplicitCmdFields, CSR_EXPL_BAR_OFFSET, CSR_EXPL_BASE_OFFSET, NUM_EXPL_BARS,
+};
+use crate::libs::nfp::Nfp;
+use crate::libs::xpb_bus::{xpb_read, xpb_write};
+
+// Version of the remote access protocol.
+const REMOTE_PROTOCOL_VERSION: u32 = 2;
+// Longest request accepted from a client before it is authenticated.
+const MAX_HELLO_LEN: u64 = 4096;
+// Size of the PCIe configuration space served to clients.
+const CONFIG_SPACE_SIZE: u64 = 4096;
+// Offset of the PCIe command register in the configuration space.
+const PCI_COMMAND: u64 = 4;
+// Memory space and bus master enable bits of the PCIe command register.
+const PCI_COMMAND_MEMORY_MASTER: u8 = 0x06;
+// Most 32-bit words a single CPP or XPB request may read, one 16 MiB
+// expansion BAR window.
+const MAX_REQUEST_WORDS: u64 = (16 << 20) / 4;
+// Longest request accepted from an authenticated client: a write of
+// `MAX_REQUEST_WORDS` words of up to 10 digits and a comma each, plus the
+// other fields of the request.
+const MAX_REQUEST_LEN: u64 = MAX_REQUEST_WORDS * 11 + MAX_HELLO_LEN;
+// Environment variable holding the token shared by the server and clients.
+pub const REMOTE_TOKEN_ENV: &str = "NFP_REMOTE_TOKEN";
+// Address `nfp-cpp-server` listens on by default.
+pub const DEFAULT_SERVER_ADDR: &str = "127.0.0.1:4242";
+
+/// Request sent by a client, one JSON object per line.
+///
+/// The first request of a connection must be `Hello`. The device requests
+/// forward the `DeviceBackend` operations of a `RemoteBackend`; BARs must be
+/// locked by the client before they are mapped, accessed or configured, and
+/// the configuration space may only be written at the BAR CSRs. The bus
+/// requests make a whole CPP, XPB or explicit access on the server in a
+/// single round trip, for clients other than `RemoteBackend`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum RemoteRequest {
+    Hello {
+        version: u32,
+        token: String,
+    },
+    ConfigRead {
+        offset: u64,
+        length: u64,
+    },
+    ConfigWrite {
+        offset: u64,
+        #[serde(with = "hex_bytes")]
+        data: Vec<u8>,
+    },
+    LockExpBar {
+        phys_bar: u8,
+        exp_bar: u8,
+    },
+    UnlockExpBar {
+        phys_bar: u8,
+        exp_bar: u8,
+    },
+    LockExplBar {
+        expl_bar: u32,
+    },
+    UnlockExplBar {
+        expl_bar: u32,
+    },
+    MapExpBar {
+        phys_bar: u8,
+        exp_bar: u8,
+    },
+    UnmapExpBar {
+        window: u32,
+    },
+    BarRead {
+        window: u32,
+        offset: u64,
+        length: u64,
+    },
+    BarWrite {
+        window: u32,
+        offset: u64,
+        #[serde(with = "hex_bytes")]
+        data: Vec<u8>,
+    },
+    CppRead {
+        island: u8,
+        target: u8,
+        action: u8,
+        token: u8,
+        cpp_len: CppLength,
+        address: u64,
+        length_words: u64,
+    },
+    CppWrite {
+        island: u8,
+        target: u8,
+        action: u8,
+        token: u8,
+        cpp_len: CppLength,
+        address: u64,
+        words: Vec<u32>,
+    },
+    XpbRead {
+        island: u8,
+        address: u32,
+        length: u64,
+        xpbm: bool,
+    },
+    XpbWrite {
+        island: u8,
+        address: u32,
+        words: Vec<u32>,
+        xpbm: bool,
+    },
+    ExplicitCmd {
+        island: u8,
+        target: u8,
+        action: u8,
+        token: u8,
+        address: u64,
+        length: u8,
+        fields: ExplicitCmdFields,
+        pull_data: Option<Vec<u32>>,
+        push_data_len: Option<u64>,
+    },
+}
+
+/// Kind of error returned to a client, mapped back onto `NfpError`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum RemoteErrorKind {
+    Unauthorized,
+    LockContention,
+    AddressOutOfRange,
+    InvalidArgument,
+    Timeout,
+    Io,
+}
+
+/// Response of the server to a request, one JSON object per line.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum RemoteResponse {
+    Ok,
+    Bytes {
+        #[serde(with = "hex_bytes")]
+        data: Vec<u8>,
+    },
+    Words {
+        words: Vec<u32>,
+    },
+    Window {
+        window: u32,
+        size: u64,
+    },
+    Error {
+        kind: RemoteErrorKind,
+        message: String,
+    },
+}
+
+impl RemoteResponse {
+    fn nfp_error(e: &NfpError) -> Self {
+        let (kind, message) = match e {
+            NfpError::LockContention(msg) => (RemoteErrorKind::LockContention, msg.clone()),
+            NfpError::AddressOutOfRange(msg) => (RemoteErrorKind::AddressOutOfRange, msg.clone()),
+            NfpError::InvalidArgument(msg) => (RemoteErrorKind::InvalidArgument, msg.clone()),
+            NfpError::Timeout(msg) => (RemoteErrorKind::Timeout, msg.clone()),
+            _ => (RemoteErrorKind::Io, e.to_string()),
+        };
+        RemoteResponse::Error { kind, message }
+    }
+
+    fn io_error(e: &io::Error) -> Self {
+        let kind = match e.kind() {
+            io::ErrorKind::WouldBlock => RemoteErrorKind::LockContention,
+            _ => RemoteErrorKind::Io,
+        };
+        RemoteResponse::Error {
+            kind,
+            message: e.to_string(),
+        }
+    }
+}
+
+/// Write `message` as a line of JSON.
+fn send<T: Serialize>(writer: &mut impl Write, message: &T) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, message).map_err(io::Error::other)?;
+    writer.write_all(b"\n")?;
+    writer.flush()
+}
+
+/// Read a line of JSON, or `None` once the peer closed the connection.
+fn receive<T: DeserializeOwned>(reader: &mut impl BufRead) -> io::Result<Option<T>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    serde_json::from_str(&line)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Compare tokens in constant time.
+fn token_matches(token: &str, expected: &str) -> bool {
+    token.len() == expected.len()
+        && token
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Connection of a `RemoteBackend` to the server.
+struct RemoteConnection {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+}
+
+impl RemoteConnection {
+    fn request(&mut self, request: &RemoteRequest) -> io::Result<RemoteResponse> {
+        send(&mut self.writer, request)?;
+        receive(&mut self.reader)?.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::UnexpectedEof, "Server closed the connection")
+        })
+    }
+
+    /// Make a `DeviceBackend` request, returning error responses as
+    /// `io::Error`s. Lock contention is returned as `WouldBlock`, as by the
+    /// local backends.
+    fn device_request(&mut self, request: &RemoteRequest) -> io::Result<RemoteResponse> {
+        match self.request(request)? {
+            RemoteResponse::Error {
+                kind: RemoteErrorKind::LockContention,
+                message,
+            } => Err(io::Error::new(io::ErrorKind::WouldBlock, message)),
+            RemoteResponse::Error { message, .. } => Err(io::Error::other(message)),
+            response => Ok(response),
+        }
+    }
+}
+
+fn unexpected_response(response: RemoteResponse) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Unexpected response {:?}", response),
+    )
+}
+
+/// Device backend for an NFP in another machine, reached through an
+/// `nfp-cpp-server`.
+///
+/// Every `DeviceBackend` operation is a round trip to the server, so all
+/// tools work unchanged with a remote NFP. The protocol is not encrypted;
+/// use it on a lab network or through an SSH tunnel.
+pub struct RemoteBackend {
+    addr: String,
+    connection: Arc<Mutex<RemoteConnection>>,
+}
+
+impl RemoteBackend {
+    /// Connect to the server at `addr` and authenticate with `token`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::Io` if the server cannot be reached or rejects
+    /// the token.
+    pub fn connect(addr: &str, token: &str) -> Result<Self, NfpError> {
+        let io_err = |e| NfpError::io(format!("Failed to connect to {}", addr), e);
+        let stream = TcpStream::connect(addr).map_err(io_err)?;
+        stream.set_nodelay(true).map_err(io_err)?;
+        let connection = RemoteConnection {
+            reader: BufReader::new(stream.try_clone().map_err(io_err)?),
+            writer: BufWriter::new(stream),
+        };
+
+        let backend = RemoteBackend {
+            addr: addr.to_string(),
+            connection: Arc::new(Mutex::new(connection)),
+        };
+        backend.call(&RemoteRequest::Hello {
+            version: REMOTE_PROTOCOL_VERSION,
+            token: token.to_string(),
+        })?;
+        Ok(backend)
+    }
+
+    /// Make a request, returning error responses as the `NfpError` the
+    /// server failed with.
+    fn call(&self, request: &RemoteRequest) -> Result<RemoteResponse, NfpError> {
+        let response = self
+            .connection
+            .lock()
+            .unwrap()
+            .request(request)
+            .map_err(|e| NfpError::io(format!("Request to {} failed", self.addr), e))?;
+
+        let RemoteResponse::Error { kind, message } = response else {
+            return Ok(response);
+        };
+        Err(match kind {
+            RemoteErrorKind::LockContention => NfpError::LockContention(message),
+            RemoteErrorKind::AddressOutOfRange => NfpError::AddressOutOfRange(message),
+            RemoteErrorKind::InvalidArgument => NfpError::InvalidArgument(message),
+            RemoteErrorKind::Timeout => NfpError::Timeout(message),
+            RemoteErrorKind::Unauthorized => NfpError::io(
+                format!("{} rejected the connection", self.addr),
+                io::Error::new(io::ErrorKind::PermissionDenied, message),
+            ),
+            RemoteErrorKind::Io => NfpError::io(
+                format!("Access through {} failed", self.addr),
+                io::Error::other(message),
+            ),
+        })
+    }
+}
+
+impl DeviceBackend for RemoteBackend {
+    fn name(&self) -> &str {
+        &self.addr
+    }
+
+    fn config_read(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let request = RemoteRequest::ConfigRead {
+            offset,
+            length: buf.len() as u64,
+        };
+        match self.connection.lock().unwrap().device_request(&request)? {
+            RemoteResponse::Bytes { data } if data.len() == buf.len() => {
+                buf.copy_from_slice(&data);
+                Ok(())
+            }
+            response => Err(unexpected_response(response)),
+        }
+    }
+
+    fn config_write(&self, offset: u64, data: &[u8]) -> io::Result<()> {
+        let request = RemoteRequest::ConfigWrite {
+            offset,
+            data: data.to_vec(),
+        };
+        self.connection
+            .lock()
+            .unwrap()
+            .device_request(&request)
+            .map(|_| ())
+    }
+
+    fn lock_exp_bar(&self, phys_bar: u8, exp_bar: u8) -> io::Result<()> {
+        let request = RemoteRequest::LockExpBar { phys_bar, exp_bar };
+        self.connection
+            .lock()
+            .unwrap()
+            .device_request(&request)
+            .map(|_| ())
+    }
+
+    fn unlock_exp_bar(&self, phys_bar: u8, exp_bar: u8) {
+        // The server also unlocks the BARs of a client when it disconnects.
+        let request = RemoteRequest::UnlockExpBar { phys_bar, exp_bar };
+        let _ = self.connection.lock().unwrap().device_request(&request);
+    }
+
+    fn lock_expl_bar(&self, expl_bar_index: u32) -> io::Result<()> {
+        let request = RemoteRequest::LockExplBar {
+            expl_bar: expl_bar_index,
+        };
+        self.connection
+            .lock()
+            .unwrap()
+            .device_request(&request)
+            .map(|_| ())
+    }
+
+    fn unlock_expl_bar(&self, expl_bar_index: u32) {
+        let request = RemoteRequest::UnlockExplBar {
+            expl_bar: expl_bar_index,
+        };
+        let _ = self.connection.lock().unwrap().device_request(&request);
+    }
+
+    fn map_exp_bar(&self, phys_bar: u8, exp_bar: u8) -> io::Result<Box<dyn BarWindow>> {
+        let request = RemoteRequest::MapExpBar { phys_bar, exp_bar };
+        match self.connection.lock().unwrap().device_request(&request)? {
+            RemoteResponse::Window { window, size } => Ok(Box::new(RemoteWindow {
+                addr: self.addr.clone(),
+                connection: Arc::clone(&self.connection),
+                window,
+                size,
+            })),
+            response => Err(unexpected_response(response)),
+        }
+    }
+}
+
+/// Expansion BAR window mapped by the server for a `RemoteBackend`.
+struct RemoteWindow {
+    addr: String,
+    connection: Arc<Mutex<RemoteConnection>>,
+    window: u32,
+    size: u64,
+}
+
+impl BarWindow for RemoteWindow {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read(&self, offset: u64, length: u64) -> io::Result<Vec<u8>> {
+        let request = RemoteRequest::BarRead {
+            window: self.window,
+            offset,
+            length,
+        };
+        let result = self.connection.lock().unwrap().device_request(&request);
+        match result {
+            Ok(RemoteResponse::Bytes { data }) if data.len() == length as usize => Ok(data),
+            Ok(response) => Err(self.error("read", unexpected_response(response))),
+            Err(e) => Err(self.error("read", e)),
+        }
+    }
+
+    fn write(&mut self, write_bytes: &[u8], offset: u64) -> io::Result<()> {
+        let request = RemoteRequest::BarWrite {
+            window: self.window,
+            offset,
+            data: write_bytes.to_vec(),
+        };
+        let result = self.connection.lock().unwrap().device_request(&request);
+        result.map(|_| ()).map_err(|e| self.error("write", e))
+    }
+}
+
+impl RemoteWindow {
+    /// Name the server in an error of a BAR access.
+    fn error(&self, access: &str, e: io::Error) -> io::Error {
+        io::Error::new(
+            e.kind(),
+            format!("BAR {} through {} failed: {}", access, self.addr, e),
+        )
+    }
+}
+
+impl Drop for RemoteWindow {
+    fn drop(&mut self) {
+        let request = RemoteRequest::UnmapExpBar {
+            window: self.window,
+        };
+        if let Ok(mut connection) = self.connection.lock() {
+            let _ = connection.device_request(&request);
+        }
+    }
+}
+
+/// BAR window mapped by a client of a `CppServer`.
+struct ServerWindow {
+    // Expansion BAR the window maps, as (physical BAR, expansion BAR).
+    bar: (u8, u8),
+    window: Box<dyn BarWindow>,
+}
+
+/// State of the connection of one client to a `CppServer`.
+struct ServerSession {
+    nfp: Nfp,
+    // Expansion BARs locked by the client, unlocked when it disconnects.
+    locks: HashSet<(u8, u8)>,
+    windows: HashMap<u32, ServerWindow>,
+    next_window: u32,
+    // Explicit BARs locked by the client, unlocked when it disconnects.
+    expl_locks: HashSet<u32>,
+}
+
+impl ServerSession {
+    fn new(nfp: Nfp) -> Self {
+        ServerSession {
+            nfp,
+            locks: HashSet::new(),
+            windows: HashMap::new(),
+            next_window: 0,
+            expl_locks: HashSet::new(),
+        }
+    }
+
+    /// Authenticate the client on `stream` with `token`, then serve its
+    /// requests until it disconnects.
+    fn run(&mut self, stream: TcpStream, token: &str) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = BufWriter::new(stream);
+
+        match receive(&mut (&mut reader).take(MAX_HELLO_LEN)) {
+            Ok(Some(RemoteRequest::Hello {
+                version,
+                token: client_token,
+            })) if token_matches(&client_token, token) => {
+                if version != REMOTE_PROTOCOL_VERSION {
+                    let message = format!(
+                        "Unsupported protocol version {}, the server speaks version {}",
+                        version, REMOTE_PROTOCOL_VERSION
+                    );
+                    let response = RemoteResponse::Error {
+                        kind: RemoteErrorKind::InvalidArgument,
+                        message: message.clone(),
+                    };
+                    send(&mut writer, &response)?;
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+                }
+                send(&mut writer, &RemoteResponse::Ok)?;
+            }
+            _ => {
+                let response = RemoteResponse::Error {
+                    kind: RemoteErrorKind::Unauthorized,
+                    message: "Invalid token".to_string(),
+                };
+                send(&mut writer, &response)?;
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "Client failed to authenticate",
+                ));
+            }
+        }
+
+        while let Some(request) = receive(&mut (&mut reader).take(MAX_REQUEST_LEN))? {
+            let response = self.handle(request);
+            send(&mut writer, &response)?;
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, request: RemoteRequest) -> RemoteResponse {
+        let backend = Arc::clone(self.nfp.backend());
+        let done = |result: io::Result<()>| match result {
+            Ok(()) => RemoteResponse::Ok,
+            Err(e) => RemoteResponse::io_error(&e),
+        };
+
+        match request {
+            RemoteRequest::Hello { .. } => RemoteResponse::Error {
+                kind: RemoteErrorKind::InvalidArgument,
+                message: "Client is already authenticated".to_string(),
+            },
+            RemoteRequest::ConfigRead { offset, length } => {
+                if offset
+                    .checked_add(length)
+                    .is_none_or(|end| end > CONFIG_SPACE_SIZE)
+                {
+                    return RemoteResponse::Error {
+                        kind: RemoteErrorKind::AddressOutOfRange,
+                        message: format!(
+                            "Config read of {:#x} bytes at {:#x} exceeds the config space of {:#x} bytes",
+                            length, offset, CONFIG_SPACE_SIZE
+                        ),
+                    };
+                }
+                let mut data = vec![0; length as usize];
+                match backend.config_read(offset, &mut data) {
+                    Ok(()) => RemoteResponse::Bytes { data },
+                    Err(e) => RemoteResponse::io_error(&e),
+                }
+            }
+            RemoteRequest::ConfigWrite { offset, data } => {
+                if offset == PCI_COMMAND && data.len() == 1 {
+                    // The server already enabled the device when opening
+                    // it. Clients may not disable it for everyone else.
+                    return if data[0] & PCI_COMMAND_MEMORY_MASTER == PCI_COMMAND_MEMORY_MASTER {
+                        RemoteResponse::Ok
+                    } else {
+                        RemoteResponse::Error {
+                            kind: RemoteErrorKind::InvalidArgument,
+                            message: "Clients may not disable the device".to_string(),
+                        }
+                    };
+                }
+                match self.check_config_write(offset, data.len() as u64) {
+                    Ok(()) => done(backend.config_write(offset, &data)),
+                    Err(response) => response,
+                }
+            }
+            RemoteRequest::LockExpBar { phys_bar, exp_bar } => {
+                let result = backend.lock_exp_bar(phys_bar, exp_bar);
+                if result.is_ok() {
+                    self.locks.insert((phys_bar, exp_bar));
+                }
+                done(result)
+            }
+            RemoteRequest::UnlockExpBar { phys_bar, exp_bar } => {
+                // Only release BARs of this client, not of the server or
+                // other clients.
+                if self.locks.remove(&(phys_bar, exp_bar)) {
+                    self.windows
+                        .retain(|_, window| window.bar != (phys_bar, exp_bar));
+                    backend.unlock_exp_bar(phys_bar, exp_bar);
+                }
+                RemoteResponse::Ok
+            }
+            RemoteRequest::LockExplBar { expl_bar } => {
+                let result = backend.lock_expl_bar(expl_bar);
+                if result.is_ok() {
+                    self.expl_locks.insert(expl_bar);
+                }
+                done(result)
+            }
+            RemoteRequest::UnlockExplBar { expl_bar } => {
+                if self.expl_locks.remove(&expl_bar) {
+                    backend.unlock_expl_bar(expl_bar);
+                }
+                RemoteResponse::Ok
+            }
+            RemoteRequest::MapExpBar { phys_bar, exp_bar } => {
+                if let Err(response) = self.check_locked((phys_bar, exp_bar)) {
+                    return response;
+                }
+                match backend.map_exp_bar(phys_bar, exp_bar) {
+                    Ok(window) => {
+                        let size = window.size();
+                        self.next_window += 1;
+                        self.windows.insert(
+                            self.next_window,
+                            ServerWindow {
+                                bar: (phys_bar, exp_bar),
+                                window,
+                            },
+                        );
+                        RemoteResponse::Window {
+                            window: self.next_window,
+                            size,
+                        }
+                    }
+                    Err(e) => RemoteResponse::io_error(&e),
+                }
+            }
+            RemoteRequest::UnmapExpBar { window } => {
+                self.windows.remove(&window);
+                RemoteResponse::Ok
+            }
+            RemoteRequest::BarRead {
+                window,
+                offset,
+                length,
+            } => match self.window(window, offset, length) {
+                Ok(window) => match window.read(offset, length) {
+                    Ok(data) => RemoteResponse::Bytes { data },
+                    Err(e) => RemoteResponse::io_error(&e),
+                },
+                Err(response) => response,
+            },
+            RemoteRequest::BarWrite {
+                window,
+                offset,
+                data,
+            } => match self.window(window, offset, data.len() as u64) {
+                Ok(window) => match window.write(&data, offset) {
+                    Ok(()) => RemoteResponse::Ok,
+                    Err(e) => RemoteResponse::io_error(&e),
+                },
+                Err(response) => response,
+            },
+            request => self
+                .bus_access(request)
+                .unwrap_or_else(|e| RemoteResponse::nfp_error(&e)),
+        }
+    }
+
+    /// Check that a config write of `length` bytes at `offset` only writes
+    /// the CSRs of a single expansion or explicit BAR locked by the client.
+    fn check_config_write(&self, offset: u64, length: u64) -> Result<(), RemoteResponse> {
+        let invalid = |kind, message| Err(RemoteResponse::Error { kind, message });
+        let Some(end) = offset
+            .checked_add(length)
+            .filter(|&end| end <= CONFIG_SPACE_SIZE)
+        else {
+            return invalid(
+                RemoteErrorKind::AddressOutOfRange,
+                format!(
+                    "Config write of {:#x} bytes at {:#x} exceeds the config space of {:#x} bytes",
+                    length, offset, CONFIG_SPACE_SIZE
+                ),
+            );
+        };
+
+        let exp_base = (BAR_CONFIG_BASE_CONFIG_SNOOP + EXPANSION_BAR_BASE_OFFSET) as u64;
+        let expl_base = (BAR_CONFIG_BASE_CONFIG_SNOOP + CSR_EXPL_BASE_OFFSET) as u64;
+        let expl_end = expl_base + (NUM_EXPL_BARS * CSR_EXPL_BAR_OFFSET) as u64;
+        if (exp_base..expl_base).contains(&offset) {
+            let csr = (offset - exp_base) / EXPANSION_BAR_CSR_OFFSET as u64;
+            let csr_end = exp_base + (csr + 1) * EXPANSION_BAR_CSR_OFFSET as u64;
+            let bars_per_phys = (EXPANSION_BAR_PHYS_OFFSET / EXPANSION_BAR_CSR_OFFSET) as u64;
+            if end <= csr_end {
+                let bar = ((csr / bars_per_phys) as u8, (csr % bars_per_phys) as u8);
+                return self.check_locked(bar);
+            }
+        } else if (expl_base..expl_end).contains(&offset) {
+            let expl_bar_index = ((offset - expl_base) / CSR_EXPL_BAR_OFFSET as u64) as u32;
+            let csr_end = expl_base + (expl_bar_index as u64 + 1) * CSR_EXPL_BAR_OFFSET as u64;
+            if end <= csr_end {
+                if self.expl_locks.contains(&expl_bar_index) {
+                    return Ok(());
+                }
+                return invalid(
+                    RemoteErrorKind::InvalidArgument,
+                    format!(
+                        "Explicit BAR {} is not locked by this client",
+                        expl_bar_index
+                    ),
+                );
+            }
+        }
+
+        invalid(
+            RemoteErrorKind::InvalidArgument,
+            format!(
+                "Config write of {:#x} bytes at {:#x} is not to the CSRs of a single BAR",
+                length, offset
+            ),
+        )
+    }
+
+    /// Check that the client holds the lock of expansion BAR `bar`, given
+    /// as (physical BAR, expansion BAR).
+    fn check_locked(&self, bar: (u8, u8)) -> Result<(), RemoteResponse> {
+        if self.locks.contains(&bar) {
+            Ok(())
+        } else {
+            Err(RemoteResponse::Error {
+                kind: RemoteErrorKind::InvalidArgument,
+                message: format!(
+                    "Expansion BAR {} of BAR {} is not locked by this client",
+                    bar.1, bar.0
+                ),
+            })
+        }
+    }
+
+    /// Find a window mapped by the client, checking that the client still
+    /// holds the lock of its BAR and that `length` bytes at `offset` lie
+    /// within it.
+    fn window(
+        &mut self,
+        window: u32,
+        offset: u64,
+        length: u64,
+    ) -> Result<&mut Box<dyn BarWindow>, RemoteResponse> {
+        let Some(bar) = self.windows.get(&window).map(|window| window.bar) else {
+            return Err(RemoteResponse::Error {
+                kind: RemoteErrorKind::InvalidArgument,
+                message: format!("No BAR window {} is mapped", window),
+            });
+        };
+        self.check_locked(bar)?;
+        let bar_window = &mut self.windows.get_mut(&window).unwrap().window;
+        if offset
+            .checked_add(length)
+            .is_none_or(|end| end > bar_window.size())
+        {
+            return Err(RemoteResponse::Error {
+                kind: RemoteErrorKind::AddressOutOfRange,
+                message: format!(
+                    "Access of {:#x} bytes at {:#x} exceeds BAR window of size {:#x}",
+                    length,
+                    offset,
+                    bar_window.size()
+                ),
+            });
+        }
+        Ok(bar_window)
+    }
+
+    /// Make a CPP, XPB or explicit access through the BARs of the server.
+    fn bus_access(&self, request: RemoteRequest) -> Result<RemoteResponse, NfpError> {
+        let check_length = |length_words: u64| {
+            if length_words > MAX_REQUEST_WORDS {
+                return Err(NfpError::InvalidArgument(format!(
+                    "Access of {} words exceeds the limit of {} words per request",
+                    length_words, MAX_REQUEST_WORDS
+                )));
+            }
+            Ok(())
+        };
+
+        match request {
+            RemoteRequest::CppRead {
+                island,
+                target,
+                action,
+                token,
+                cpp_len,
+                address,
+                length_words,
+            } => {
+                check_length(length_words)?;
+                let mut exp_bar = self.nfp.exp_bar()?;
+                let words = CppBus::with_map_type(&mut exp_bar, MapType::Fixed).read(
+                    CppIsland::from_id(island)?,
+                    CppTarget::from_id(target)?,
+                    action,
+                    token,
+                    cpp_len,
+                    address,
+                    length_words,
+                )?;
+                Ok(RemoteResponse::Words { words })
+            }
+            RemoteRequest::CppWrite {
+                island,
+                target,
+                action,
+                token,
+                cpp_len,
+                address,
+                words,
+            } => {
+                check_length(words.len() as u64)?;
+                let mut exp_bar = self.nfp.exp_bar()?;
+                CppBus::with_map_type(&mut exp_bar, MapType::Fixed).write(
+                    CppIsland::from_id(island)?,
+                    CppTarget::from_id(target)?,
+                    action,
+                    token,
+                    cpp_len,
+                    address,
+                    words,
+                )?;
+                Ok(RemoteResponse::Ok)
+            }
+            RemoteRequest::XpbRead {
+                island,
+                address,
+                length,
+                xpbm,
+            } => {
+                check_length(length)?;
+                let mut exp_bar = self.nfp.exp_bar()?;
+                let words = xpb_read(
+                    &mut exp_bar,
+                    &CppIsland::from_id(island)?,
+                    address,
+                    length,
+                    xpbm,
+                )?;
+                Ok(RemoteResponse::Words { words })
+            }
+            RemoteRequest::XpbWrite {
+                island,
+                address,
+                words,
+                xpbm,
+            } => {
+                check_length(words.len() as u64)?;
+                let mut exp_bar = self.nfp.exp_bar()?;
+                xpb_write(
+                    &mut exp_bar,
+                    &CppIsland::from_id(island)?,
+                    address,
+                    words,
+                    xpbm,
+                )?;
+                Ok(RemoteResponse::Ok)
+            }
+            RemoteRequest::ExplicitCmd {
+                island,
+                target,
+                action,
+                token,
+                address,
+                length,
+                fields,
+                pull_data,
+                push_data_len,
+            } => {
+                let mut expl_bar = self.nfp.expl_bar()?;
+                let push_data = expl_bar.explicit_cmd(
+                    CppIsland::from_id(island)?,
+                    CppTarget::from_id(target)?,
+                    action,
+                    token,
+                    address,
+                    length,
+                    &fields,
+                    pull_data,
+                    push_data_len,
+                )?;
+                Ok(match push_data {
+                    Some(words) => RemoteResponse::Words { words },
+                    None => RemoteResponse::Ok,
+                })
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Drop for ServerSession {
+    fn drop(&mut self) {
+        self.windows.clear();
+        for (phys_bar, exp_bar) in self.locks.drain() {
+            self.nfp.backend().unlock_exp_bar(phys_bar, exp_bar);
+        }
+        for expl_bar_index in self.expl_locks.drain() {
+            self.nfp.backend().unlock_expl_bar(expl_bar_index);
+        }
+    }
+}
+
+/// Connection event of a `CppServer`, reported to the caller of `serve`.
+#[derive(Debug)]
+pub enum ServerEvent {
+    Connected {
+        peer: String,
+    },
+    /// The client disconnected, or its session failed with `error`.
+    Disconnected {
+        peer: String,
+        error: Option<io::Error>,
+    },
+}
+
+/// TCP server giving clients that present the shared token access to an
+/// NFP, for `RemoteBackend`.
+pub struct CppServer {
+    nfp: Nfp,
+    token: String,
+}
+
+impl CppServer {
+    pub fn new(nfp: Nfp, token: &str) -> Self {
+        CppServer {
+            nfp,
+            token: token.to_string(),
+        }
+    }
+
+    /// Accept clients on `listener`, serving each on its own thread and
+    /// passing their connection events to `on_event`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::Io` if accepting a connection fails.
+    pub fn serve(
+        &self,
+        listener: TcpListener,
+        on_event: impl Fn(ServerEvent) + Send + Sync + 'static,
+    ) -> Result<(), NfpError> {
+        let on_event = Arc::new(on_event);
+        for stream in listener.incoming() {
+            let stream = stream.map_err(|e| NfpError::io("Failed to accept a client", e))?;
+            let peer = stream
+                .peer_addr()
+                .map_or("unknown".to_string(), |addr| addr.to_string());
+            let mut session = ServerSession::new(self.nfp.clone());
+            let token = self.token.clone();
+            let on_event = Arc::clone(&on_event);
+
+            thread::spawn(move || {
+                on_event(ServerEvent::Connected { peer: peer.clone() });
+                let error = session.run(stream, &token).err();
+                on_event(ServerEvent::Disconnected { peer, error });
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::chip_desc::IslandKind;
+    use crate::libs::sim_device::SimDevice;
+    use crate::libs::xpb_bus::xpb_explicit_write;
+
+    const TOKEN: &str = "loopback-token";
+
+    /// Serve `sim` on a loopback port, returning the address of the server.
+    fn serve(sim: &SimDevice) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = CppServer::new(Nfp::new(Arc::new(sim.clone())).unwrap(), TOKEN);
+        thread::spawn(move || server.serve(listener, |_| {}));
+        addr
+    }
+
+    #[test]
+    fn bus_access_over_loopback() {
+        let sim = SimDevice::new();
+        let addr = serve(&sim);
+        let island = CppIsland::first_of_kind(IslandKind::Emu);
+
+        let nfp = Nfp::new(Arc::new(RemoteBackend::connect(&addr, TOKEN).unwrap())).unwrap();
+        let mut exp_bar = nfp.exp_bar().unwrap();
+        CppBus::new(&mut exp_bar)
+            .write(
+                island,
+                CppTarget::mem(),
+                1,
+                0,
+                CppLength::Len64,
+                0x40,
+                vec![0x1234, 0x5678],
+            )
+            .unwrap();
+        xpb_write(&mut exp_bar, &island, 0x100, vec![0xabcd], false).unwrap();
+        drop(exp_bar);
+        let mut expl_bar = nfp.expl_bar().unwrap();
+        xpb_explicit_write(&mut expl_bar, &island, 0x104, vec![0x5a5a], false).unwrap();
+        drop(expl_bar);
+        drop(nfp);
+
+        // The accesses reached the device of the server.
+        let local = Nfp::new(Arc::new(sim)).unwrap();
+        let mut exp_bar = local.exp_bar().unwrap();
+        let words = CppBus::new(&mut exp_bar)
+            .read(island, CppTarget::mem(), 0, 0, CppLength::Len64, 0x40, 2)
+            .unwrap();
+        assert_eq!(words, [0x1234, 0x5678]);
+        let registers = xpb_read(&mut exp_bar, &island, 0x100, 2, false).unwrap();
+        assert_eq!(registers, [0xabcd, 0x5a5a]);
+
+        assert!(matches!(
+            RemoteBackend::connect(&addr, "wrong-token"),
+            Err(NfpError::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn server_checks_requests() {
+        let addr = serve(&SimDevice::new());
+        let client = RemoteBackend::connect(&addr, TOKEN).unwrap();
+
+        let cpp_read = |island, target, length_words| RemoteRequest::CppRead {
+            island,
+            target,
+            action: 0,
+            token: 0,
+            cpp_len: CppLength::Len32,
+            address: 0,
+            length_words,
+        };
+        let emu = CppIsland::first_of_kind(IslandKind::Emu).id();
+        assert!(matches!(
+            client.call(&cpp_read(emu, CppTarget::mem().id(), 1)),
+            Ok(RemoteResponse::Words { .. })
+        ));
+        assert!(matches!(
+            client.call(&cpp_read(0x7f, CppTarget::mem().id(), 1)),
+            Err(NfpError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            client.call(&cpp_read(emu, 0xff, 1)),
+            Err(NfpError::InvalidArgument(_))
+        ));
+
+        assert!(matches!(
+            client.call(&cpp_read(emu, CppTarget::mem().id(), 1 << 40)),
+            Err(NfpError::InvalidArgument(_))
+        ));
+        let too_many_words = vec![0; MAX_REQUEST_WORDS as usize + 1];
+        assert!(matches!(
+            client.call(&RemoteRequest::CppWrite {
+                island: emu,
+                target: CppTarget::mem().id(),
+                action: 1,
+                token: 0,
+                cpp_len: CppLength::Len32,
+                address: 0,
+                words: too_many_words.clone(),
+            }),
+            Err(NfpError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            client.call(&RemoteRequest::XpbWrite {
+                island: emu,
+                address: 0,
+                words: too_many_words,
+                xpbm: false,
+            }),
+            Err(NfpError::InvalidArgument(_))
+        ));
+
+        assert!(matches!(
+            client.call(&RemoteRequest::ConfigRead {
+                offset: 0,
+                length: 1 << 40,
+            }),
+            Err(NfpError::AddressOutOfRange(_))
+        ));
+
+        // The config space is only written at the CSRs of BARs the client
+        // holds.
+        let config_write = |offset, length| RemoteRequest::ConfigWrite {
+            offset,
+            data: vec![0; length],
+        };
+        assert!(matches!(
+            client.call(&config_write(0x10, 4)),
+            Err(NfpError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            client.call(&config_write(CONFIG_SPACE_SIZE - 4, 8)),
+            Err(NfpError::AddressOutOfRange(_))
+        ));
+        let bar_csr = (BAR_CONFIG_BASE_CONFIG_SNOOP + 7 * EXPANSION_BAR_CSR_OFFSET) as u64;
+        assert!(matches!(
+            client.call(&config_write(bar_csr, 8)),
+            Err(NfpError::InvalidArgument(_))
+        ));
+        let expl_csr = (BAR_CONFIG_BASE_CONFIG_SNOOP + CSR_EXPL_BASE_OFFSET) as u64;
+        let lock_expl = RemoteRequest::LockExplBar { expl_bar: 0 };
+        client.call(&lock_expl).unwrap();
+        client.call(&config_write(expl_csr, 16)).unwrap();
+        let other = RemoteBackend::connect(&addr, TOKEN).unwrap();
+        assert!(matches!(
+            other.call(&config_write(expl_csr, 16)),
+            Err(NfpError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            other.call(&lock_expl),
+            Err(NfpError::LockContention(_))
+        ));
+
+        // BARs are only mapped and accessed while the client locks them.
+        let map = RemoteRequest::MapExpBar {
+            phys_bar: 0,
+            exp_bar: 7,
+        };
+        assert!(matches!(
+            client.call(&map),
+            Err(NfpError::InvalidArgument(_))
+        ));
+        client
+            .call(&RemoteRequest::LockExpBar {
+                phys_bar: 0,
+                exp_bar: 7,
+            })
+            .unwrap();
+        client.call(&config_write(bar_csr, 8)).unwrap();
+        let Ok(RemoteResponse::Window { window, .. }) = client.call(&map) else {
+            panic!("Failed to map a locked BAR");
+        };
+        let read = RemoteRequest::BarRead {
+            window,
+            offset: 0,
+            length: 4,
+        };
+        assert!(matches!(
+            client.call(&read),
+            Ok(RemoteResponse::Bytes { .. })
+        ));
+        client
+            .call(&RemoteRequest::UnlockExpBar {
+                phys_bar: 0,
+                exp_bar: 7,
+            })
+            .unwrap();
+        assert!(matches!(
+            client.call(&read),
+            Err(NfpError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn server_drops_overlong_requests() {
+        let addr = serve(&SimDevice::new());
+        let stream = TcpStream::connect(&addr).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = BufWriter::new(stream);
+        let hello = RemoteRequest::Hello {
+            version: REMOTE_PROTOCOL_VERSION,
+            token: TOKEN.to_string(),
+        };
+        send(&mut writer, &hello).unwrap();
+        assert!(matches!(receive(&mut reader), Ok(Some(RemoteResponse::Ok))));
+
+        // A request line that never ends is cut off at the limit, and the
+        // server closes the connection instead of buffering it.
+        let padding = vec![b' '; MAX_REQUEST_LEN as usize + 1];
+        let _ = writer.write_all(&padding).and_then(|_| writer.flush());
+        assert!(!matches!(
+            receive::<RemoteResponse>(&mut reader),
+            Ok(Some(_))
+        ));
+    }
+}
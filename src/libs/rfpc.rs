@@ -2,7 +2,9 @@
 use clap::ValueEnum;
 use std::fmt::{Debug, Display, Formatter, Result};
 
+use crate::libs::chip_desc::{chip_desc, RfpcClusterDesc};
 use crate::libs::cpp_bus::CppIsland;
+use crate::libs::error::NfpError;
 
 pub trait RfpcReg: Display + Debug {
     fn reg_addr(&self) -> u64;
@@ -227,93 +229,135 @@ pub struct Rfpc {
 }
 
 impl Rfpc {
-    pub fn new(island: CppIsland, cluster: u8, group: u8, core: u8) -> Self {
-        if cluster > 2 {
-            panic!("Cluster number out of range")
-        } else if group > 3 {
-            panic!("Group number out of range")
-        } else if core > 7 {
-            panic!("Core number out of range")
+    /// Return core `core` of group `group` in cluster `cluster` of `island`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::InvalidArgument` if a number is out of range for
+    /// the chip.
+    pub fn new(
+        island: CppIsland,
+        cluster: u8,
+        group: u8,
+        core: u8,
+    ) -> std::result::Result<Self, NfpError> {
+        let rfpc_desc = &chip_desc().rfpc;
+        let out_of_range = if cluster as usize >= rfpc_desc.clusters.len() {
+            Some("Cluster")
+        } else if group >= rfpc_desc.groups_per_cluster {
+            Some("Group")
+        } else if core >= rfpc_desc.cores_per_group {
+            Some("Core")
+        } else {
+            None
         };
+        if let Some(number) = out_of_range {
+            return Err(NfpError::InvalidArgument(format!(
+                "{} number out of range",
+                number
+            )));
+        }
 
-        Rfpc {
+        Ok(Rfpc {
             island,
             cluster,
             group,
             core,
-        }
+        })
     }
 
-    pub fn from_island_group_core(island: CppIsland, group: u8, core: u8) -> Self {
-        let cluster = group / 4;
-        let group = group % 4;
-
-        Rfpc {
+    /// Like `new`, but with `group` numbering the groups of all clusters of
+    /// the island.
+    pub fn from_island_group_core(
+        island: CppIsland,
+        group: u8,
+        core: u8,
+    ) -> std::result::Result<Self, NfpError> {
+        let groups_per_cluster = chip_desc().rfpc.groups_per_cluster;
+        Rfpc::new(
             island,
-            cluster,
-            group,
+            group / groups_per_cluster,
+            group % groups_per_cluster,
             core,
-        }
+        )
     }
 
-    pub fn dm_xpb_base(&self) -> u32 {
-        match self.cluster {
-            0 => 0x240000,
-            1 => 0x320000,
-            2 => 0x400000,
-            _ => panic!("Invalid Cluster"),
-        }
+    fn cluster_desc(&self) -> std::result::Result<&'static RfpcClusterDesc, NfpError> {
+        chip_desc().rfpc_cluster(self.cluster).ok_or_else(|| {
+            NfpError::InvalidArgument(format!("Invalid cluster ID {}", self.cluster))
+        })
     }
 
-    pub fn group_ctl_xpb_base(&self) -> (u32, u32) {
-        let cluster = match self.cluster {
-            0 => 0x280000,
-            1 => 0x360000,
-            2 => 0x440000,
-            _ => panic!("Invalid cluster ID {}", self.cluster),
-        };
+    /// Return the XPB base address of the debug module of the cluster.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::InvalidArgument` if the cluster does not exist.
+    pub fn dm_xpb_base(&self) -> std::result::Result<u32, NfpError> {
+        Ok(self.cluster_desc()?.dm_xpb_base)
+    }
 
-        let group = match self.group {
-            0 => 0x000,
-            1 => 0x080,
-            2 => 0x100,
-            3 => 0x180,
-            _ => panic!("Invalid group ID {}", self.group),
-        };
+    /// Return the XPB base address of the group control registers of the
+    /// cluster, and the offset of those of the group.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::InvalidArgument` if the cluster or group does not
+    /// exist.
+    pub fn group_ctl_xpb_base(&self) -> std::result::Result<(u32, u32), NfpError> {
+        let rfpc_desc = &chip_desc().rfpc;
+        if self.group >= rfpc_desc.groups_per_cluster {
+            return Err(NfpError::InvalidArgument(format!(
+                "Invalid group ID {}",
+                self.group
+            )));
+        }
 
-        (cluster, group)
+        let cluster = self.cluster_desc()?.group_ctl_xpb_base;
+        let group = self.group as u32 * rfpc_desc.group_ctl_stride;
+
+        Ok((cluster, group))
     }
 
+    /// Return the hartsello and hartselhi fields selecting the core in the
+    /// debug module of the cluster, which numbers the cores of all groups of
+    /// the cluster consecutively.
     pub fn dm_hartsel(&self) -> (u32, u32) {
-        let hartsel: u32 = 8 * (self.group as u32) + (self.core as u32);
+        let cores_per_group = chip_desc().rfpc.cores_per_group as u32;
+        let hartsel: u32 = cores_per_group * (self.group as u32) + (self.core as u32);
         let hartsello: u32 = hartsel & 0x3FF;
         let hartselhi: u32 = (hartsel >> 10) & 0x3FF;
         (hartsello, hartselhi)
     }
 
-    pub fn imb_port(&self) -> u8 {
-        match self.cluster {
-            0 => match self.group {
-                0 | 1 => 4,
-                2 | 3 => 7,
-                _ => panic!("Invalid group"),
-            },
-            1 => match self.group {
-                0 | 1 => 8,
-                2 | 3 => 11,
-                _ => panic!("Invalid group"),
-            },
-            2 => match self.group {
-                0 | 1 => 12,
-                2 | 3 => 13,
-                _ => panic!("Invalid group"),
-            },
-            _ => panic!("Invalid cluster"),
-        }
+    /// Return the IMB port of the group.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::InvalidArgument` if the cluster or group does not
+    /// exist.
+    pub fn imb_port(&self) -> std::result::Result<u8, NfpError> {
+        self.cluster_desc()?
+            .imb_ports
+            .get(self.group as usize)
+            .copied()
+            .ok_or_else(|| NfpError::InvalidArgument(format!("Invalid group ID {}", self.group)))
     }
 
+    /// Return the number of the core among the cores of the groups that
+    /// share its IMB port, which number their cores consecutively.
     pub fn cpp_core_num(&self) -> u8 {
-        (8 * self.group + self.core) % 16
+        let cores_per_group = chip_desc().rfpc.cores_per_group;
+        let port_group = self
+            .cluster_desc()
+            .ok()
+            .and_then(|cluster| {
+                let port = cluster.imb_ports.get(self.group as usize)?;
+                let groups = &cluster.imb_ports[..self.group as usize];
+                Some(groups.iter().filter(|p| *p == port).count() as u8)
+            })
+            .unwrap_or(0);
+        port_group * cores_per_group + self.core
     }
 }
 
@@ -335,3 +379,21 @@ impl PartialEq for Rfpc {
             && self.core == other.core
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::chip_desc::IslandKind;
+
+    #[test]
+    fn core_numbers_follow_the_chip_description() {
+        let island = CppIsland::first_of_kind(IslandKind::Rfpc);
+        let rfpc = Rfpc::new(island, 1, 2, 3).unwrap();
+        assert_eq!(rfpc.dm_hartsel(), (19, 0));
+        // Groups 2 and 3 share an IMB port.
+        assert_eq!(rfpc.cpp_core_num(), 3);
+        let rfpc = Rfpc::new(island, 1, 3, 3).unwrap();
+        assert_eq!(rfpc.dm_hartsel(), (27, 0));
+        assert_eq!(rfpc.cpp_core_num(), 11);
+    }
+}
@@ -1,6 +1,7 @@
 #![allow(dead_code)]
 
-use crate::libs::expansion_bar::ExpansionBar;
+use crate::libs::error::NfpError;
+use crate::libs::nfp::Nfp;
 use crate::libs::performance_analyzer::{
     CaptureMethod, CaptureMode, CaptureStart, EventMethod, HistogramSource, PerfCounterAction,
     PerformanceAnalyzer, TcamCaptureSource, TcamCaptureType,
@@ -59,7 +60,7 @@ bitfield! {
 /// Configures the Performance Analyzer for tracing based on specified parameters.
 ///
 /// # Parameters
-/// - `exp_bar`: Reference to the expansion bar for configuration.
+/// - `nfp`: Handle to the NFP holding the RFPC.
 /// - `rfpc`: Reference to the RFPC structure holding core parameters.
 /// - `trace_pc`: Flag indicating whether to trace program counter.
 /// - `trace_seq`: Flag indicating whether to trace sequential instructions.
@@ -70,10 +71,12 @@ bitfield! {
 /// - `timestamp`: Flag indicating whether to include timestamps.
 ///
 /// # Returns
-/// A configured `PerformanceAnalyzer`.
-pub fn pa_trigger_on_uncomp_trace<'a>(
-    exp_bar: &'a mut ExpansionBar,
-    rfpc: &'a Rfpc,
+/// A configured `PerformanceAnalyzer`, or the error of the first failed XPB
+/// access.
+#[allow(clippy::too_many_arguments)]
+pub fn pa_trigger_on_uncomp_trace(
+    nfp: Nfp,
+    rfpc: &Rfpc,
     trace_pc: bool,
     trace_seq: bool,
     trace_bp: bool,
@@ -81,7 +84,7 @@ pub fn pa_trigger_on_uncomp_trace<'a>(
     bus_words: u32,
     word_index: u32,
     timestamp: bool,
-) -> PerformanceAnalyzer<'a> {
+) -> Result<PerformanceAnalyzer, NfpError> {
     // Determine the capture method based on bus_words and timestamp
     let capture_method = match bus_words {
         1 => {
@@ -103,7 +106,7 @@ pub fn pa_trigger_on_uncomp_trace<'a>(
     };
 
     // Build up the Performance Analyzer configuration and start it up.
-    let pa = PerformanceAnalyzer::new(exp_bar, rfpc.island)
+    let pa = PerformanceAnalyzer::new(nfp, rfpc.island)?
         .set_pa_global_config(
             false,
             false,
@@ -137,7 +140,7 @@ pub fn pa_trigger_on_uncomp_trace<'a>(
             0x01,
             false,
         )
-        .start_pa();
+        .start_pa()?;
 
     // Set up and enable trace output for specified RFPC core.
     let mut pa_mux = PerfMuxConfig(0);
@@ -146,12 +149,12 @@ pub fn pa_trigger_on_uncomp_trace<'a>(
     pa_mux.set_lane_select_hi(3);
 
     xpb_write(
-        pa.exp_bar,
+        &mut *pa.nfp.exp_bar()?,
         &pa.cpp_island,
         rfpc_perf_mux_config!(rfpc.cluster, rfpc.group),
         vec![pa_mux.0],
         false,
-    );
+    )?;
 
     let mut pa_control = PAControl(0);
     pa_control.set_enable(true);
@@ -163,14 +166,14 @@ pub fn pa_trigger_on_uncomp_trace<'a>(
     pa_control.set_trace_bkpt(trace_bp);
 
     xpb_write(
-        pa.exp_bar,
+        &mut *pa.nfp.exp_bar()?,
         &pa.cpp_island,
         rfpc_pa_control!(rfpc.cluster, rfpc.group),
         vec![pa_control.0],
         false,
-    );
+    )?;
 
-    pa
+    Ok(pa)
 }
 
 /// Applies the Performance Analyzer settings, initiates the Performance Analyzer trigger,
@@ -185,11 +188,11 @@ pub fn pa_trigger_on_uncomp_trace<'a>(
 /// # Returns
 ///
 /// A `Vec<u32>` containing the 32-bit samples read from the Performance Analyzer's FIFO.
-pub fn read_trace(pa: &mut PerformanceAnalyzer, num_words: u32) -> Vec<u32> {
+pub fn read_trace(pa: &mut PerformanceAnalyzer, num_words: u32) -> Result<Vec<u32>, NfpError> {
     // Set the trigger to an idle state to ensure it's ready for sampling.
-    pa.trigger_idle();
+    pa.trigger_idle()?;
     // Start the trigger without any active states.
-    pa.trigger_start(0, 0);
+    pa.trigger_start(0, 0)?;
 
     // Initialize a vector to store the FIFO samples.
     let mut fifo_samples: Vec<u32> = Vec::new();
@@ -198,16 +201,16 @@ pub fn read_trace(pa: &mut PerformanceAnalyzer, num_words: u32) -> Vec<u32> {
     while fifo_samples.len() < num_words as usize {
         let remaining_words: u32 = num_words - fifo_samples.len() as u32;
         // Read FIFO samples from the Performance Analyzer.
-        let samples = pa.read_fifo(remaining_words);
+        let samples = pa.read_fifo(remaining_words)?;
         fifo_samples.extend(samples);
     }
 
     // Halt the trigger after collecting samples.
-    pa.trigger_halt();
+    pa.trigger_halt()?;
 
     // Return only the requested number of samples.
     fifo_samples.truncate(num_words as usize);
-    fifo_samples
+    Ok(fifo_samples)
 }
 
 /// Formats uncompressed RFPC trace samples for display.
@@ -225,6 +228,8 @@ pub fn read_trace(pa: &mut PerformanceAnalyzer, num_words: u32) -> Vec<u32> {
 /// * `word_index`: The index of the first word to be read.
 /// * `timestamp`: A boolean indicating whether to include a timestamp column.
 /// * `words_per_sample`: The number of words that constitute a single sample.
+/// * `annotate`: Returns the symbol a sample word points to, if any. Words
+///   with a symbol are shown as `0x00000420 <symbol>`.
 ///
 /// # Returns
 ///
@@ -233,9 +238,9 @@ pub fn read_trace(pa: &mut PerformanceAnalyzer, num_words: u32) -> Vec<u32> {
 ///
 /// # Example
 ///
-/// ```
+/// ```ignore
 /// let samples = vec![0xDEADBEEF, 0xCAFEBABE, 0xB16B00B5];
-/// let formatted_lines = format_uncomp_trace(samples, 3, 0, true, 3);
+/// let formatted_lines = format_uncomp_trace(samples, 3, 0, true, 3, None);
 /// for line in formatted_lines {
 ///     println!("{}", line);
 /// }
@@ -247,9 +252,15 @@ pub fn format_uncomp_trace(
     word_index: u32,
     timestamp: bool,
     words_per_sample: usize,
+    annotate: Option<&dyn Fn(u32) -> Option<String>>,
 ) -> Vec<String> {
     let mut formatted_lines = Vec::new();
 
+    let format_word = |word: u32| match annotate.and_then(|annotate| annotate(word)) {
+        Some(symbol) => format!("{:#010x} <{}>", word, symbol),
+        None => format!("{:#010x}", word),
+    };
+
     // Initialize header_line and determine modulus
     let mut header_line = Vec::new();
     let modulus = bus_words + 1 + timestamp as u32;
@@ -297,11 +308,11 @@ pub fn format_uncomp_trace(
                     .filter(|(i, _)| *i != index) // Exclude the timestamp index
                     .map(|(_, &v)| v)
                     .collect();
-                sample_line.extend(sample_without_ts.iter().map(|&v| format!("{:#010x}", v)));
+                sample_line.extend(sample_without_ts.iter().map(|&v| format_word(v)));
             }
         } else {
             // No timestamp, just format the sample as hex
-            sample_line.extend(chunk.iter().map(|&v| format!("{:#010x}", v)));
+            sample_line.extend(chunk.iter().map(|&v| format_word(v)));
         }
 
         // Add formatted line
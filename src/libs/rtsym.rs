@@ -0,0 +1,542 @@
+#![allow(dead_code)]
+
+use std::fmt;
+
+use clap::ValueEnum;
+
+use crate::libs::chip_desc::{chip_desc, IslandKind};
+use crate::libs::cpp_bus::{CppBus, CppIsland, CppLength, CppTarget};
+use crate::libs::error::NfpError;
+use crate::libs::expansion_bar::ExpansionBar;
+use crate::libs::mem_access::{mem_read, mem_write, MemoryType, MuMemoryEngine};
+
+// The resource table sits at a fixed EMEM address. Each 32-byte entry names
+// a region of memory owned by the firmware, one of which is the Microcode
+// Information Page (MIP) that locates the run-time symbol table.
+const RESOURCE_TABLE_ADDRESS: u64 = 0x81_0000_0000;
+const RESOURCE_TABLE_ENTRIES: usize = 128;
+const RESOURCE_ENTRY_WORDS: usize = 8;
+const RESOURCE_NAME_LEN: usize = 8;
+const RESOURCE_NAME_MIP: &str = "nfp.mip";
+
+// MIP header fields, in 32-bit words.
+const MIP_SIGNATURE: u32 = 0x0050_494D; // "MIP\0"
+const MIP_VERSION: u32 = 1;
+const MIP_HEADER_WORDS: u64 = 24;
+const MIP_SYMTAB_ADDR: usize = 8;
+const MIP_SYMTAB_SIZE: usize = 9;
+const MIP_STRTAB_ADDR: usize = 10;
+const MIP_STRTAB_SIZE: usize = 11;
+
+// Size of a symbol table entry in bytes.
+const RTSYM_ENTRY_SIZE: usize = 16;
+
+// Special values of the target and island fields of a symbol table entry.
+const RTSYM_TARGET_NONE: u8 = 0x00;
+const RTSYM_TARGET_LMEM: u8 = 0xFF;
+const RTSYM_TARGET_EMU_CACHE: u8 = 0xF9;
+const RTSYM_ISLAND_NONE: u8 = 0xFF;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum RtsymType {
+    None,
+    Object,
+    Function,
+    Abs,
+}
+
+impl RtsymType {
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(RtsymType::None),
+            1 => Some(RtsymType::Object),
+            2 => Some(RtsymType::Function),
+            3 => Some(RtsymType::Abs),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RtsymType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RtsymType::None => write!(f, "none"),
+            RtsymType::Object => write!(f, "object"),
+            RtsymType::Function => write!(f, "function"),
+            RtsymType::Abs => write!(f, "abs"),
+        }
+    }
+}
+
+/// A symbol from the run-time symbol table of the firmware.
+#[derive(Clone, Debug)]
+pub struct Rtsym {
+    pub name: String,
+    pub sym_type: RtsymType,
+    /// CPP target ID, or one of the special RTSYM_TARGET_* values.
+    pub target: u8,
+    /// Island holding the symbol, if it is not a global symbol.
+    pub island: Option<CppIsland>,
+    /// Microengine or core number for symbols in core-local memory.
+    pub menum: u8,
+    /// Address of the symbol, or its value for absolute symbols.
+    pub address: u64,
+    pub size: u64,
+}
+
+impl Rtsym {
+    /// Parse a 16-byte symbol table entry, taking its name from `strtab`.
+    ///
+    /// The entry holds the type, target, island and address bits 39:32 in
+    /// bytes 0-3, address bits 31:0 in bytes 4-7, the name offset in bytes
+    /// 8-9, the core number and size bits 39:32 in bytes 10-11 and size bits
+    /// 31:0 in bytes 12-15.
+    fn parse(entry: &[u8], strtab: &[u8]) -> Result<Rtsym, NfpError> {
+        let name_offset = u16::from_le_bytes([entry[8], entry[9]]) as usize;
+        let name = strtab
+            .get(name_offset..)
+            .and_then(|name| name.split(|byte| *byte == 0).next())
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .ok_or_else(|| {
+                NfpError::InvalidArgument(format!(
+                    "Run-time symbol name offset {:#x} is outside the string table",
+                    name_offset
+                ))
+            })?;
+
+        let sym_type = RtsymType::from_id(entry[0]).ok_or_else(|| {
+            NfpError::InvalidArgument(format!(
+                "Run-time symbol {} has unknown type {}",
+                name, entry[0]
+            ))
+        })?;
+
+        let island = match entry[2] {
+            RTSYM_ISLAND_NONE => None,
+            id => Some(CppIsland::from_id(id).map_err(|_| {
+                NfpError::InvalidArgument(format!(
+                    "Run-time symbol {} is in unknown island {}",
+                    name, id
+                ))
+            })?),
+        };
+
+        let address_lo = u32::from_le_bytes(entry[4..8].try_into().unwrap()) as u64;
+        let size_lo = u32::from_le_bytes(entry[12..16].try_into().unwrap()) as u64;
+
+        Ok(Rtsym {
+            name,
+            sym_type,
+            target: entry[1],
+            island,
+            menum: entry[10],
+            address: ((entry[3] as u64) << 32) | address_lo,
+            size: ((entry[11] as u64) << 32) | size_lo,
+        })
+    }
+
+    /// Return the island and memory type holding the symbol, or `None` for
+    /// absolute symbols and symbols in memories the tools cannot access.
+    pub fn location(&self) -> Option<(CppIsland, MemoryType)> {
+        if self.sym_type == RtsymType::Abs {
+            return None;
+        }
+
+        // Global symbols without an island live in the first EMU.
+        let island = self
+            .island
+            .unwrap_or_else(|| CppIsland::first_of_kind(IslandKind::Emu));
+
+        let target = match self.target {
+            RTSYM_TARGET_NONE => return None,
+            RTSYM_TARGET_LMEM => return Some((island, MemoryType::Lmem)),
+            RTSYM_TARGET_EMU_CACHE => return Some((island, MemoryType::Imem)),
+            target => target,
+        };
+
+        let chip = chip_desc();
+        let memory = chip.memories.iter().find(|memory| {
+            memory.island_kind == island.kind()
+                && memory
+                    .target
+                    .as_deref()
+                    .and_then(|name| chip.target_by_name(name))
+                    .is_some_and(|desc| desc.id == target)
+        })?;
+
+        MemoryType::from_str(&memory.name, true)
+            .ok()
+            .map(|mem_type| (island, mem_type))
+    }
+
+    /// Describe where the symbol lives, e.g. "rfpc0.ctm" or "abs".
+    pub fn location_name(&self) -> String {
+        match (self.sym_type, self.location()) {
+            (RtsymType::Abs, _) => "abs".to_string(),
+            (_, Some((island, mem_type))) => {
+                format!("{}.{}", island, mem_type.to_string().to_lowercase())
+            }
+            (_, None) => format!("target{}", self.target),
+        }
+    }
+}
+
+/// The run-time symbol table published by the firmware.
+#[derive(Clone, Debug, Default)]
+pub struct RtsymTable {
+    symbols: Vec<Rtsym>,
+}
+
+/// Read `length` bytes of EMEM at `address` in the first EMU island.
+fn read_emem_bytes(
+    exp_bar: &mut ExpansionBar,
+    address: u64,
+    length: u64,
+) -> Result<Vec<u8>, NfpError> {
+    let words = mem_read(
+        exp_bar,
+        CppIsland::first_of_kind(IslandKind::Emu),
+        MemoryType::Emem,
+        MuMemoryEngine::Bulk32,
+        address,
+        length.div_ceil(4),
+    )?;
+    let mut bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    bytes.truncate(length as usize);
+    Ok(bytes)
+}
+
+/// Find the EMEM address of the MIP in the resource table.
+fn find_mip(exp_bar: &mut ExpansionBar) -> Result<u64, NfpError> {
+    // The table address is an MU direct access address outside the EMEM
+    // and IMEM address ranges, so read it as a plain MU bulk read.
+    let (action, token) = MuMemoryEngine::Bulk32.read_command();
+    let table = CppBus::new(exp_bar).read(
+        CppIsland::first_of_kind(IslandKind::Emu),
+        CppTarget::mem(),
+        action,
+        token,
+        CppLength::Len32,
+        RESOURCE_TABLE_ADDRESS,
+        (RESOURCE_TABLE_ENTRIES * RESOURCE_ENTRY_WORDS) as u64,
+    )?;
+
+    for entry in table.chunks_exact(RESOURCE_ENTRY_WORDS) {
+        // Words 0 and 1 hold the entry mutex, words 2 and 3 the name.
+        let name_bytes: Vec<u8> = entry[2..4]
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect();
+        let name_len = name_bytes
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(RESOURCE_NAME_LEN);
+        if &name_bytes[..name_len] != RESOURCE_NAME_MIP.as_bytes() {
+            continue;
+        }
+
+        // The CPP target sits in the top byte of word 5, and word 6 holds
+        // the address of the region in units of 256 bytes.
+        let target = (entry[5] >> 24) as u8;
+        if target != CppTarget::mem().id() {
+            return Err(NfpError::InvalidArgument(format!(
+                "MIP resource is on unsupported CPP target {}",
+                target
+            )));
+        }
+        return Ok((entry[6] as u64) << 8);
+    }
+
+    Err(NfpError::FirmwareNotLoaded(
+        "No MIP in the resource table".to_string(),
+    ))
+}
+
+impl RtsymTable {
+    /// Locate and read the run-time symbol table of the running firmware.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::FirmwareNotLoaded` if no firmware has published a
+    /// MIP, or `NfpError::InvalidArgument` if the MIP or the symbol table is
+    /// malformed.
+    pub fn read(exp_bar: &mut ExpansionBar) -> Result<Self, NfpError> {
+        let mip_address = find_mip(exp_bar)?;
+        let mip = mem_read(
+            exp_bar,
+            CppIsland::first_of_kind(IslandKind::Emu),
+            MemoryType::Emem,
+            MuMemoryEngine::Bulk32,
+            mip_address,
+            MIP_HEADER_WORDS,
+        )?;
+
+        if mip[0] != MIP_SIGNATURE || mip[1] != MIP_VERSION {
+            return Err(NfpError::InvalidArgument(format!(
+                "Invalid MIP at {:#x}: signature {:#010x}, version {}",
+                mip_address, mip[0], mip[1]
+            )));
+        }
+
+        let symtab = read_emem_bytes(
+            exp_bar,
+            mip[MIP_SYMTAB_ADDR] as u64,
+            mip[MIP_SYMTAB_SIZE] as u64,
+        )?;
+        let strtab = read_emem_bytes(
+            exp_bar,
+            mip[MIP_STRTAB_ADDR] as u64,
+            mip[MIP_STRTAB_SIZE] as u64,
+        )?;
+
+        RtsymTable::parse(&symtab, &strtab)
+    }
+
+    /// Parse a symbol table and its string table.
+    pub fn parse(symtab: &[u8], strtab: &[u8]) -> Result<Self, NfpError> {
+        if !symtab.len().is_multiple_of(RTSYM_ENTRY_SIZE) {
+            return Err(NfpError::InvalidArgument(format!(
+                "Run-time symbol table size {:#x} is not a multiple of {}",
+                symtab.len(),
+                RTSYM_ENTRY_SIZE
+            )));
+        }
+
+        let symbols = symtab
+            .chunks_exact(RTSYM_ENTRY_SIZE)
+            .map(|entry| Rtsym::parse(entry, strtab))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(RtsymTable { symbols })
+    }
+
+    pub fn symbols(&self) -> &[Rtsym] {
+        &self.symbols
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Rtsym> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+    }
+}
+
+/// Return the island and memory type of `symbol`, checking that the
+/// `length` words at `offset` lie within it.
+fn rtsym_region(
+    symbol: &Rtsym,
+    offset: u64,
+    length: u64,
+) -> Result<(CppIsland, MemoryType), NfpError> {
+    let location = symbol.location().ok_or_else(|| {
+        NfpError::InvalidArgument(format!(
+            "Symbol {} in {} cannot be accessed",
+            symbol.name,
+            symbol.location_name()
+        ))
+    })?;
+
+    let end = length
+        .checked_mul(4)
+        .and_then(|bytes| bytes.checked_add(offset));
+    if end.is_none_or(|end| end > symbol.size) {
+        return Err(NfpError::AddressOutOfRange(format!(
+            "Access of {:#x} words at offset {:#x} exceeds symbol {} of size {:#x}",
+            length, offset, symbol.name, symbol.size
+        )));
+    }
+
+    Ok(location)
+}
+
+/// Read `length` 32-bit words at `offset` in a run-time symbol.
+///
+/// # Errors
+///
+/// Returns `NfpError::InvalidArgument` if the symbol is absolute or in a
+/// memory the tools cannot access, or `NfpError::AddressOutOfRange` if the
+/// read goes past the end of the symbol.
+pub fn rtsym_read(
+    exp_bar: &mut ExpansionBar,
+    symbol: &Rtsym,
+    offset: u64,
+    length: u64,
+) -> Result<Vec<u32>, NfpError> {
+    let (island, mem_type) = rtsym_region(symbol, offset, length)?;
+    mem_read(
+        exp_bar,
+        island,
+        mem_type,
+        MuMemoryEngine::Bulk32,
+        symbol.address + offset,
+        length,
+    )
+}
+
+/// Write 32-bit words at `offset` in a run-time symbol.
+///
+/// # Errors
+///
+/// Same as `rtsym_read`.
+pub fn rtsym_write(
+    exp_bar: &mut ExpansionBar,
+    symbol: &Rtsym,
+    offset: u64,
+    values: Vec<u32>,
+) -> Result<(), NfpError> {
+    let (island, mem_type) = rtsym_region(symbol, offset, values.len() as u64)?;
+    mem_write(
+        exp_bar,
+        island,
+        mem_type,
+        MuMemoryEngine::Bulk32,
+        symbol.address + offset,
+        values,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::nfp::Nfp;
+    use crate::libs::sim_device::SimDevice;
+    use std::sync::Arc;
+
+    #[test]
+    fn no_firmware_is_reported() {
+        let nfp = Nfp::new(Arc::new(SimDevice::new())).unwrap();
+        let result = RtsymTable::read(&mut nfp.exp_bar().unwrap());
+        assert!(matches!(result, Err(NfpError::FirmwareNotLoaded(_))));
+    }
+
+    /// Build a symbol table entry, see `Rtsym::parse`.
+    fn entry(sym_type: u8, target: u8, island: u8, name_offset: u16, address: u64) -> Vec<u8> {
+        let mut entry = vec![sym_type, target, island, (address >> 32) as u8];
+        entry.extend((address as u32).to_le_bytes());
+        entry.extend(name_offset.to_le_bytes());
+        entry.extend([0, 0]);
+        entry.extend(0x40u32.to_le_bytes());
+        entry
+    }
+
+    const STRTAB: &[u8] = b"counters\0_entry\0lmem_var\0cache_var\0abs_val\0unused\0";
+
+    #[test]
+    fn parse_symbol_table() {
+        let rfpc0 = CppIsland::first_of_kind(IslandKind::Rfpc);
+        let emu = CppIsland::first_of_kind(IslandKind::Emu);
+        let mem = CppTarget::mem().id();
+        let symtab = [
+            entry(1, mem, RTSYM_ISLAND_NONE, 0, 0x12_0000_1000),
+            entry(2, mem, rfpc0.id(), 9, 0x200),
+            entry(1, RTSYM_TARGET_LMEM, rfpc0.id(), 16, 0x40),
+            entry(1, RTSYM_TARGET_EMU_CACHE, emu.id(), 25, 0x80),
+            entry(3, RTSYM_TARGET_NONE, RTSYM_ISLAND_NONE, 35, 42),
+            entry(1, RTSYM_TARGET_NONE, rfpc0.id(), 43, 0),
+        ]
+        .concat();
+        let table = RtsymTable::parse(&symtab, STRTAB).unwrap();
+        assert_eq!(table.symbols().len(), 6);
+
+        // Global symbols live in the first EMU.
+        let counters = table.get("counters").unwrap();
+        assert_eq!(counters.sym_type, RtsymType::Object);
+        assert_eq!(counters.address, 0x12_0000_1000);
+        assert_eq!(counters.size, 0x40);
+        assert_eq!(counters.location(), Some((emu, MemoryType::Emem)));
+
+        let entry_sym = table.get("_entry").unwrap();
+        assert_eq!(entry_sym.sym_type, RtsymType::Function);
+        assert_eq!(entry_sym.location(), Some((rfpc0, MemoryType::Ctm)));
+        assert_eq!(entry_sym.location_name(), "rfpc0.ctm");
+
+        let location = |name| table.get(name).unwrap().location();
+        assert_eq!(location("lmem_var"), Some((rfpc0, MemoryType::Lmem)));
+        assert_eq!(location("cache_var"), Some((emu, MemoryType::Imem)));
+        assert_eq!(location("abs_val"), None);
+        assert_eq!(table.get("abs_val").unwrap().location_name(), "abs");
+        assert_eq!(location("unused"), None);
+        assert_eq!(table.get("unused").unwrap().location_name(), "target0");
+        assert!(table.get("missing").is_none());
+    }
+
+    #[test]
+    fn parse_rejects_malformed_entries() {
+        let mem = CppTarget::mem().id();
+        let invalid = [
+            // Name offset beyond the string table.
+            entry(1, mem, RTSYM_ISLAND_NONE, STRTAB.len() as u16 + 1, 0),
+            // Unknown type.
+            entry(7, mem, RTSYM_ISLAND_NONE, 0, 0),
+            // Unknown island.
+            entry(1, mem, 0x50, 0, 0),
+            // Truncated entry.
+            entry(1, mem, RTSYM_ISLAND_NONE, 0, 0)[..12].to_vec(),
+        ];
+        for symtab in &invalid {
+            assert!(matches!(
+                RtsymTable::parse(symtab, STRTAB),
+                Err(NfpError::InvalidArgument(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn symbol_regions_are_bounded() {
+        let symtab = entry(1, CppTarget::mem().id(), RTSYM_ISLAND_NONE, 0, 0x1000);
+        let table = RtsymTable::parse(&symtab, STRTAB).unwrap();
+        let counters = table.get("counters").unwrap();
+
+        assert!(rtsym_region(counters, 0x3c, 1).is_ok());
+        for (offset, length) in [(0x3c, 2), (0, 0x4000000000000000), (u64::MAX, 1)] {
+            assert!(matches!(
+                rtsym_region(counters, offset, length),
+                Err(NfpError::AddressOutOfRange(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn read_finds_symbols_through_the_mip() {
+        let sim = Arc::new(SimDevice::new());
+        let nfp = Nfp::new(sim.clone()).unwrap();
+        let emu = CppIsland::first_of_kind(IslandKind::Emu).id();
+        let mem = CppTarget::mem();
+        let words_to_bytes =
+            |words: &[u32]| -> Vec<u8> { words.iter().flat_map(|w| w.to_le_bytes()).collect() };
+
+        let mip_address: u64 = 0x10000;
+        let symtab_address: u32 = 0x10100;
+        let strtab_address: u32 = 0x10200;
+
+        // A resource entry naming the MIP, after an unrelated one.
+        let mut resources = vec![0u32; 2 * RESOURCE_ENTRY_WORDS];
+        resources[2..4].copy_from_slice(&[u32::from_le_bytes(*b"nfp."), 0]);
+        resources[10..12]
+            .copy_from_slice(&[u32::from_le_bytes(*b"nfp."), u32::from_le_bytes(*b"mip\0")]);
+        resources[13] = (mem.id() as u32) << 24;
+        resources[14] = (mip_address >> 8) as u32;
+        sim.write_memory(
+            emu,
+            mem,
+            RESOURCE_TABLE_ADDRESS,
+            &words_to_bytes(&resources),
+        );
+
+        let symtab = entry(1, mem.id(), RTSYM_ISLAND_NONE, 0, 0x20000);
+        let mut mip = vec![0u32; MIP_HEADER_WORDS as usize];
+        mip[0] = MIP_SIGNATURE;
+        mip[1] = MIP_VERSION;
+        mip[MIP_SYMTAB_ADDR] = symtab_address;
+        mip[MIP_SYMTAB_SIZE] = symtab.len() as u32;
+        mip[MIP_STRTAB_ADDR] = strtab_address;
+        mip[MIP_STRTAB_SIZE] = STRTAB.len() as u32;
+        sim.write_memory(emu, mem, mip_address, &words_to_bytes(&mip));
+        sim.write_memory(emu, mem, symtab_address as u64, &symtab);
+        sim.write_memory(emu, mem, strtab_address as u64, STRTAB);
+        sim.write_memory(emu, mem, 0x20004, &words_to_bytes(&[0xcafe]));
+
+        let mut exp_bar = nfp.exp_bar().unwrap();
+        let table = RtsymTable::read(&mut exp_bar).unwrap();
+        let counters = table.get("counters").unwrap();
+        assert_eq!(counters.address, 0x20000);
+        assert_eq!(rtsym_read(&mut exp_bar, counters, 4, 1).unwrap(), [0xcafe]);
+    }
+}
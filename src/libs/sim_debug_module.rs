@@ -0,0 +1,637 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::libs::chip_desc::chip_desc;
+use crate::libs::error::{DmCmdErr, NfpError};
+use crate::libs::rfpc::{Rfpc, RfpcReg};
+use crate::libs::rfpc_debugger::{
+    RISCV_DBG_ABSTRACTCS, RISCV_DBG_ABSTRACTCS_BUSY, RISCV_DBG_ABSTRACTCS_CMDERR,
+    RISCV_DBG_COMMAND, RISCV_DBG_DATA0, RISCV_DBG_DATA1, RISCV_DBG_DMCONTROL,
+    RISCV_DBG_DMCONTROL_DMACTIVE, RISCV_DBG_DMCONTROL_HALTREQ, RISCV_DBG_DMCONTROL_HARTSELHI,
+    RISCV_DBG_DMCONTROL_HARTSELLO, RISCV_DBG_DMCONTROL_RESUMEREQ, RISCV_DBG_DMSTATUS,
+    RISCV_DBG_DMSTATUS_ALLHALTED, RISCV_DBG_DMSTATUS_ALLNONEXISTENT,
+    RISCV_DBG_DMSTATUS_ALLRESUMEACK, RISCV_DBG_DMSTATUS_ALLRUNNING, RISCV_DBG_DMSTATUS_ANYHALTED,
+    RISCV_DBG_DMSTATUS_ANYNONEXISTENT, RISCV_DBG_DMSTATUS_ANYRESUMEACK,
+    RISCV_DBG_DMSTATUS_ANYRUNNING, RISCV_DBG_DMSTATUS_AUTHENTICATED, RISCV_DBG_DMSTATUS_IMPEBREAK,
+    RISCV_DBG_PROGBUF0,
+};
+use crate::libs::sim_device::{SimDevice, XpbDevice};
+
+// Size of the debug module register block on the XPB bus.
+const SIM_DM_XPB_SIZE: u32 = 0x200;
+// Debug module version reported in dmstatus (0.13).
+const SIM_DM_VERSION: u32 = 2;
+// Number of data and program buffer registers implemented.
+const SIM_DM_DATACOUNT: u32 = 2;
+const SIM_DM_PROGBUFSIZE: u32 = 1;
+
+// Abstract command "Access Register" control fields.
+const COMMAND_AARSIZE_SHIFT: u32 = 20;
+const COMMAND_POSTEXEC: u32 = 1 << 18;
+const COMMAND_TRANSFER: u32 = 1 << 17;
+const COMMAND_WRITE: u32 = 1 << 16;
+const COMMAND_REGNO: u32 = 0xFFFF;
+
+// Register numbers of the GPRs in abstract commands.
+const REGNO_GPR_BASE: u16 = 0x1000;
+const REGNO_GPR_LAST: u16 = 0x101F;
+
+/// State of a single simulated RISC-V hart.
+#[derive(Default)]
+struct SimHart {
+    halted: bool,
+    resumeack: bool,
+    gprs: [u64; 32],
+    csrs: HashMap<u16, u64>,
+}
+
+/// Complete state of a simulated debug module.
+struct DmState {
+    dmcontrol: u32,
+    data: [u32; SIM_DM_DATACOUNT as usize],
+    progbuf0: u32,
+    cmderr: Option<DmCmdErr>,
+    busy_polls_left: u32,
+    harts: Vec<SimHart>,
+    // Hart memory, as 64-bit words keyed by word address.
+    memory: HashMap<u64, u64>,
+    // Fault injection.
+    injected_cmderr: Option<DmCmdErr>,
+    injected_cmderr_skip: u32,
+    injected_busy_polls: u32,
+    halt_timeout: bool,
+}
+
+impl DmState {
+    fn new(num_harts: usize) -> Self {
+        DmState {
+            dmcontrol: 0,
+            data: [0; SIM_DM_DATACOUNT as usize],
+            progbuf0: 0,
+            cmderr: None,
+            busy_polls_left: 0,
+            harts: (0..num_harts).map(|_| SimHart::default()).collect(),
+            memory: HashMap::new(),
+            injected_cmderr: None,
+            injected_cmderr_skip: 0,
+            injected_busy_polls: 0,
+            halt_timeout: false,
+        }
+    }
+
+    fn hartsel(&self) -> u32 {
+        let hartsello = (self.dmcontrol & RISCV_DBG_DMCONTROL_HARTSELLO) >> 16;
+        let hartselhi = (self.dmcontrol & RISCV_DBG_DMCONTROL_HARTSELHI) >> 6;
+        (hartselhi << 10) | hartsello
+    }
+
+    fn selected_hart(&mut self) -> Option<&mut SimHart> {
+        let hartsel = self.hartsel() as usize;
+        self.harts.get_mut(hartsel)
+    }
+
+    fn write_dmcontrol(&mut self, value: u32) {
+        // haltreq and resumereq are write-only and are not stored.
+        self.dmcontrol = value & !(RISCV_DBG_DMCONTROL_HALTREQ | RISCV_DBG_DMCONTROL_RESUMEREQ);
+
+        if value & RISCV_DBG_DMCONTROL_DMACTIVE == 0 {
+            // Deactivating the debug module resets it.
+            self.data = [0; SIM_DM_DATACOUNT as usize];
+            self.progbuf0 = 0;
+            self.cmderr = None;
+            self.busy_polls_left = 0;
+            return;
+        }
+
+        let halt_timeout = self.halt_timeout;
+        if let Some(hart) = self.selected_hart() {
+            if value & RISCV_DBG_DMCONTROL_HALTREQ != 0 && !halt_timeout {
+                hart.halted = true;
+            } else if value & RISCV_DBG_DMCONTROL_RESUMEREQ != 0 {
+                hart.halted = false;
+                hart.resumeack = true;
+            }
+        }
+    }
+
+    fn read_dmstatus(&mut self) -> u32 {
+        let mut dmstatus =
+            SIM_DM_VERSION | RISCV_DBG_DMSTATUS_AUTHENTICATED | RISCV_DBG_DMSTATUS_IMPEBREAK;
+
+        // Only the hart selected by hartsel is selected, the hart array
+        // mask is not implemented. A hart beyond the harts of the cluster
+        // does not exist, and reports neither running nor halted.
+        dmstatus |= match self.selected_hart() {
+            None => RISCV_DBG_DMSTATUS_ALLNONEXISTENT | RISCV_DBG_DMSTATUS_ANYNONEXISTENT,
+            Some(hart) => {
+                let mut status = if hart.halted {
+                    RISCV_DBG_DMSTATUS_ALLHALTED | RISCV_DBG_DMSTATUS_ANYHALTED
+                } else {
+                    RISCV_DBG_DMSTATUS_ALLRUNNING | RISCV_DBG_DMSTATUS_ANYRUNNING
+                };
+                if hart.resumeack {
+                    status |= RISCV_DBG_DMSTATUS_ALLRESUMEACK | RISCV_DBG_DMSTATUS_ANYRESUMEACK;
+                }
+                status
+            }
+        };
+
+        dmstatus
+    }
+
+    fn read_abstractcs(&mut self) -> u32 {
+        let mut abstractcs = (SIM_DM_PROGBUFSIZE << 24) | SIM_DM_DATACOUNT;
+        abstractcs |= (self.cmderr.map_or(0, |cmderr| cmderr.id()) as u32) << 8;
+
+        if self.busy_polls_left > 0 {
+            self.busy_polls_left -= 1;
+            abstractcs |= RISCV_DBG_ABSTRACTCS_BUSY;
+        }
+
+        abstractcs
+    }
+
+    fn write_abstractcs(&mut self, value: u32) {
+        // cmderr is write-1-to-clear.
+        let clear = ((value & RISCV_DBG_ABSTRACTCS_CMDERR) >> 8) as u8;
+        if let Some(cmderr) = self.cmderr {
+            let remaining = cmderr.id() & !clear;
+            self.cmderr = (remaining != 0).then(|| DmCmdErr::from_id(remaining));
+        }
+    }
+
+    fn write_command(&mut self, command: u32) {
+        if self.busy_polls_left > 0 {
+            self.cmderr = Some(DmCmdErr::Busy);
+            return;
+        }
+        // Commands are ignored until cmderr is cleared.
+        if self.cmderr.is_some() {
+            return;
+        }
+
+        self.busy_polls_left = self.injected_busy_polls;
+
+        if self.injected_cmderr.is_some() {
+            if self.injected_cmderr_skip > 0 {
+                self.injected_cmderr_skip -= 1;
+            } else {
+                self.cmderr = self.injected_cmderr.take();
+                return;
+            }
+        }
+
+        self.cmderr = self.run_command(command).err();
+    }
+
+    /// Execute an abstract command, returning the cmderr on failure.
+    fn run_command(&mut self, command: u32) -> Result<(), DmCmdErr> {
+        let cmdtype = command >> 24;
+        let control = command & 0xFFFFFF;
+
+        // Only the "Access Register" abstract command is supported.
+        if cmdtype != 0 {
+            return Err(DmCmdErr::NotSupported);
+        }
+
+        let aarsize = (control >> COMMAND_AARSIZE_SHIFT) & 0x7;
+        if aarsize != 2 && aarsize != 3 {
+            return Err(DmCmdErr::NotSupported);
+        }
+
+        let data = ((self.data[1] as u64) << 32) | self.data[0] as u64;
+        let hart = self.selected_hart().ok_or(DmCmdErr::HaltResume)?;
+        if !hart.halted {
+            return Err(DmCmdErr::HaltResume);
+        }
+
+        if control & COMMAND_TRANSFER != 0 {
+            let regno = (control & COMMAND_REGNO) as u16;
+            if control & COMMAND_WRITE != 0 {
+                let value = if aarsize == 2 {
+                    data & 0xFFFFFFFF
+                } else {
+                    data
+                };
+                hart_write_reg(hart, regno, value)?;
+            } else {
+                let value = hart_read_reg(hart, regno)?;
+                self.data[0] = value as u32;
+                if aarsize == 3 {
+                    self.data[1] = (value >> 32) as u32;
+                }
+            }
+        }
+
+        if control & COMMAND_POSTEXEC != 0 {
+            self.exec_progbuf()?;
+        }
+
+        Ok(())
+    }
+
+    /// Execute the single program buffer instruction on the selected hart.
+    /// Only the 64-bit loads and stores used by `rfpc_debugger` are modelled.
+    fn exec_progbuf(&mut self) -> Result<(), DmCmdErr> {
+        let insn = self.progbuf0;
+        let hartsel = self.hartsel() as usize;
+        let hart = self.harts.get_mut(hartsel).ok_or(DmCmdErr::HaltResume)?;
+        let opcode = insn & 0x7F;
+        let funct3 = (insn >> 12) & 0x7;
+        let rd = ((insn >> 7) & 0x1F) as usize;
+        let rs1 = ((insn >> 15) & 0x1F) as usize;
+        let rs2 = ((insn >> 20) & 0x1F) as usize;
+
+        match (opcode, funct3) {
+            // ld rd, imm(rs1)
+            (0x03, 3) => {
+                let imm = (insn as i32 >> 20) as i64;
+                let addr = hart.gprs[rs1].wrapping_add(imm as u64);
+                if !addr.is_multiple_of(8) {
+                    return Err(DmCmdErr::Exception);
+                }
+                let value = self.memory.get(&addr).copied().unwrap_or(0);
+                if rd != 0 {
+                    hart.gprs[rd] = value;
+                }
+                Ok(())
+            }
+            // sd rs2, imm(rs1)
+            (0x23, 3) => {
+                let imm = ((((insn >> 25) << 5) | ((insn >> 7) & 0x1F)) as i32) << 20 >> 20;
+                let addr = hart.gprs[rs1].wrapping_add(imm as i64 as u64);
+                if !addr.is_multiple_of(8) {
+                    return Err(DmCmdErr::Exception);
+                }
+                let value = hart.gprs[rs2];
+                self.memory.insert(addr, value);
+                Ok(())
+            }
+            // ebreak, or an empty program buffer.
+            (0x73, 0) if insn == 0x00100073 => Ok(()),
+            _ => Err(DmCmdErr::Exception),
+        }
+    }
+}
+
+/// Read register `regno` of a hart, using the abstract command register
+/// numbering (CSRs below 0x1000, GPRs at 0x1000-0x101f).
+fn hart_read_reg(hart: &SimHart, regno: u16) -> Result<u64, DmCmdErr> {
+    match regno {
+        REGNO_GPR_BASE..=REGNO_GPR_LAST => Ok(hart.gprs[(regno - REGNO_GPR_BASE) as usize]),
+        0..REGNO_GPR_BASE => Ok(hart.csrs.get(&regno).copied().unwrap_or(0)),
+        _ => Err(DmCmdErr::Exception),
+    }
+}
+
+/// Write register `regno` of a hart.
+fn hart_write_reg(hart: &mut SimHart, regno: u16, value: u64) -> Result<(), DmCmdErr> {
+    match regno {
+        REGNO_GPR_BASE..=REGNO_GPR_LAST => {
+            // x0 is hard wired to zero.
+            if regno != REGNO_GPR_BASE {
+                hart.gprs[(regno - REGNO_GPR_BASE) as usize] = value;
+            }
+        }
+        0..REGNO_GPR_BASE => {
+            hart.csrs.insert(regno, value);
+        }
+        _ => return Err(DmCmdErr::Exception),
+    }
+    Ok(())
+}
+
+/// Handle to the debug module state attached to the XPB bus.
+struct DmHandle(Arc<Mutex<DmState>>);
+
+impl XpbDevice for DmHandle {
+    fn xpb_read(&mut self, offset: u32) -> u32 {
+        let mut state = self.0.lock().unwrap();
+        match offset {
+            RISCV_DBG_DATA0 => state.data[0],
+            RISCV_DBG_DATA1 => state.data[1],
+            RISCV_DBG_DMCONTROL => state.dmcontrol,
+            RISCV_DBG_DMSTATUS => state.read_dmstatus(),
+            RISCV_DBG_ABSTRACTCS => state.read_abstractcs(),
+            RISCV_DBG_PROGBUF0 => state.progbuf0,
+            _ => 0,
+        }
+    }
+
+    fn xpb_write(&mut self, offset: u32, value: u32) {
+        let mut state = self.0.lock().unwrap();
+        match offset {
+            RISCV_DBG_DATA0 => state.data[0] = value,
+            RISCV_DBG_DATA1 => state.data[1] = value,
+            RISCV_DBG_DMCONTROL => state.write_dmcontrol(value),
+            RISCV_DBG_ABSTRACTCS => state.write_abstractcs(value),
+            RISCV_DBG_COMMAND => state.write_command(value),
+            RISCV_DBG_PROGBUF0 => state.progbuf0 = value,
+            _ => (),
+        }
+    }
+}
+
+/// Simulated RISC-V debug module, as specified in "RISC-V External Debug
+/// Support" version 0.13.2, for one RFPC cluster.
+///
+/// The module implements dmcontrol, dmstatus, abstractcs, command, data0-1
+/// and progbuf0 for the harts selected by `Rfpc::dm_hartsel()`. Abstract
+/// commands support register access, and the program buffer executes the
+/// 64-bit loads and stores `rfpc_debugger` uses to access hart memory.
+/// Faults can be injected to exercise the debugger's error paths.
+///
+/// `SimDevice::new` attaches a debug module to every RFPC cluster; get it
+/// with `SimDevice::debug_module`.
+#[derive(Clone)]
+pub struct SimDebugModule {
+    state: Arc<Mutex<DmState>>,
+}
+
+impl SimDebugModule {
+    /// Attach a debug module for the cluster of `rfpc` to a simulated NFP,
+    /// at `rfpc.dm_xpb_base()` in the XPB space of the RFPC island.
+    ///
+    /// All RFPCs of a cluster share the same debug module, so this only
+    /// needs to be called once per cluster.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::InvalidArgument` if the cluster does not exist.
+    pub fn attach(sim: &SimDevice, rfpc: &Rfpc) -> Result<Self, NfpError> {
+        let rfpc_desc = &chip_desc().rfpc;
+        let num_harts = rfpc_desc.groups_per_cluster as usize * rfpc_desc.cores_per_group as usize;
+        let state = Arc::new(Mutex::new(DmState::new(num_harts)));
+
+        sim.attach_xpb_device(
+            rfpc.island,
+            rfpc.dm_xpb_base()?,
+            SIM_DM_XPB_SIZE,
+            Box::new(DmHandle(Arc::clone(&state))),
+        );
+
+        Ok(SimDebugModule { state })
+    }
+
+    /// Fail the next abstract command with error `cmderr`.
+    pub fn inject_cmderr(&self, cmderr: DmCmdErr) {
+        self.inject_cmderr_after(0, cmderr);
+    }
+
+    /// Fail the abstract command after the next `commands` commands with
+    /// error `cmderr`.
+    pub fn inject_cmderr_after(&self, commands: u32, cmderr: DmCmdErr) {
+        let mut state = self.state.lock().unwrap();
+        state.injected_cmderr = Some(cmderr);
+        state.injected_cmderr_skip = commands;
+    }
+
+    /// Keep abstractcs.busy set for `polls` reads of abstractcs after every
+    /// abstract command. Zero disables the injection.
+    pub fn inject_busy(&self, polls: u32) {
+        self.state.lock().unwrap().injected_busy_polls = polls;
+    }
+
+    /// Make the harts ignore halt requests, so halting them times out.
+    pub fn inject_halt_timeout(&self, enable: bool) {
+        self.state.lock().unwrap().halt_timeout = enable;
+    }
+
+    /// Return whether the hart of `rfpc` is halted.
+    pub fn is_halted(&self, rfpc: &Rfpc) -> bool {
+        let state = self.state.lock().unwrap();
+        state
+            .harts
+            .get(hart_index(rfpc))
+            .is_some_and(|hart| hart.halted)
+    }
+
+    /// Read a register of the hart of `rfpc`.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the cluster has no hart for `rfpc`.
+    pub fn read_reg(&self, rfpc: &Rfpc, reg: &dyn RfpcReg) -> u64 {
+        let state = self.state.lock().unwrap();
+        hart_read_reg(hart(&state.harts, rfpc), reg.reg_addr() as u16).unwrap_or(0)
+    }
+
+    /// Write a register of the hart of `rfpc`.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the cluster has no hart for `rfpc`, or
+    /// `reg` is not a hart register.
+    pub fn write_reg(&self, rfpc: &Rfpc, reg: &dyn RfpcReg, value: u64) {
+        let mut state = self.state.lock().unwrap();
+        let index = hart_index(rfpc);
+        let hart = match state.harts.get_mut(index) {
+            Some(hart) => hart,
+            None => panic!("RFPC {} has no hart in the debug module", rfpc),
+        };
+        hart_write_reg(hart, reg.reg_addr() as u16, value)
+            .unwrap_or_else(|_| panic!("Invalid RFPC register {:#x}", reg.reg_addr()));
+    }
+
+    /// Read the 64-bit word of hart memory at `address` (8-byte aligned).
+    pub fn read_memory64(&self, address: u64) -> u64 {
+        let state = self.state.lock().unwrap();
+        state.memory.get(&(address & !0x7)).copied().unwrap_or(0)
+    }
+
+    /// Write the 64-bit word of hart memory at `address` (8-byte aligned).
+    pub fn write_memory64(&self, address: u64, value: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.memory.insert(address & !0x7, value);
+    }
+}
+
+/// Index into the harts of its debug module of the hart of `rfpc`.
+fn hart_index(rfpc: &Rfpc) -> usize {
+    let (hartsello, hartselhi) = rfpc.dm_hartsel();
+    ((hartselhi << 10) | hartsello) as usize
+}
+
+/// The hart of `rfpc`, panicking if the cluster has none.
+fn hart<'a>(harts: &'a [SimHart], rfpc: &Rfpc) -> &'a SimHart {
+    match harts.get(hart_index(rfpc)) {
+        Some(hart) => hart,
+        None => panic!("RFPC {} has no hart in the debug module", rfpc),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::chip_desc::IslandKind;
+    use crate::libs::cpp_bus::CppIsland;
+    use crate::libs::error::NfpError;
+    use crate::libs::nfp::Nfp;
+    use crate::libs::rfpc::RfpcGpr;
+    use crate::libs::rfpc_debugger::{
+        read_rfpc_reg, rfpc_dbg_halt, rfpc_dbg_read_memory, rfpc_dbg_read_reg,
+    };
+
+    fn sim_nfp() -> (SimDevice, Nfp) {
+        let sim = SimDevice::new();
+        let nfp = Nfp::new(Arc::new(sim.clone())).unwrap();
+        (sim, nfp)
+    }
+
+    fn rfpc(island: CppIsland, cluster: u8, group: u8, core: u8) -> Rfpc {
+        Rfpc::new(island, cluster, group, core).unwrap()
+    }
+
+    #[test]
+    fn halt_and_read_reg() {
+        let (sim, nfp) = sim_nfp();
+        let rfpc = rfpc(CppIsland::first_of_kind(IslandKind::Rfpc), 1, 2, 3);
+        let dm = sim.debug_module(&rfpc).unwrap();
+        dm.write_reg(&rfpc, &RfpcGpr::X5, 0x0123_4567_89AB_CDEF);
+
+        let mut expl_bar = nfp.expl_bar().unwrap();
+        rfpc_dbg_halt(&mut expl_bar, &rfpc).unwrap();
+        assert!(dm.is_halted(&rfpc));
+
+        let value = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, RfpcGpr::X5.reg_addr()).unwrap();
+        assert_eq!(value, 0x0123_4567_89AB_CDEF);
+    }
+
+    #[test]
+    fn debug_modules_are_per_island() {
+        let (sim, nfp) = sim_nfp();
+        let rfpc0 = rfpc(CppIsland::from_id(9).unwrap(), 0, 0, 0);
+        let rfpc1 = rfpc(CppIsland::from_id(10).unwrap(), 0, 0, 0);
+
+        rfpc_dbg_halt(&mut nfp.expl_bar().unwrap(), &rfpc1).unwrap();
+        assert!(!sim.debug_module(&rfpc0).unwrap().is_halted(&rfpc0));
+        assert!(sim.debug_module(&rfpc1).unwrap().is_halted(&rfpc1));
+    }
+
+    #[test]
+    fn read_memory() {
+        let (sim, nfp) = sim_nfp();
+        let rfpc = rfpc(CppIsland::first_of_kind(IslandKind::Rfpc), 0, 0, 1);
+        let dm = sim.debug_module(&rfpc).unwrap();
+        dm.write_memory64(0x1000, 0x1111_2222_3333_4444);
+        dm.write_memory64(0x1008, 0x5555_6666_7777_8888);
+        dm.write_reg(&rfpc, &RfpcGpr::X10, 0xA0);
+
+        let mut expl_bar = nfp.expl_bar().unwrap();
+        rfpc_dbg_halt(&mut expl_bar, &rfpc).unwrap();
+        let words = rfpc_dbg_read_memory(&mut expl_bar, &rfpc, 0x1000, 4).unwrap();
+        assert_eq!(
+            words,
+            vec![0x3333_4444, 0x1111_2222, 0x7777_8888, 0x5555_6666]
+        );
+        // a0 is restored after the read.
+        assert_eq!(dm.read_reg(&rfpc, &RfpcGpr::X10), 0xA0);
+    }
+
+    #[test]
+    fn command_on_running_hart_fails() {
+        let (_sim, nfp) = sim_nfp();
+        let rfpc = rfpc(CppIsland::first_of_kind(IslandKind::Rfpc), 0, 0, 0);
+
+        let result = rfpc_dbg_read_reg(&mut nfp.expl_bar().unwrap(), &rfpc, 0x1001);
+        assert!(matches!(
+            result,
+            Err(NfpError::DmCmdErr(DmCmdErr::HaltResume))
+        ));
+    }
+
+    #[test]
+    fn injected_cmderr_is_reported_and_cleared() {
+        let (sim, nfp) = sim_nfp();
+        let rfpc = rfpc(CppIsland::first_of_kind(IslandKind::Rfpc), 0, 1, 0);
+        let dm = sim.debug_module(&rfpc).unwrap();
+        let mut expl_bar = nfp.expl_bar().unwrap();
+        rfpc_dbg_halt(&mut expl_bar, &rfpc).unwrap();
+
+        dm.inject_cmderr(DmCmdErr::Bus);
+        let result = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, 0x1001);
+        assert!(matches!(result, Err(NfpError::DmCmdErr(DmCmdErr::Bus))));
+
+        // The debugger clears cmderr, so the next command succeeds.
+        assert!(rfpc_dbg_read_reg(&mut expl_bar, &rfpc, 0x1001).is_ok());
+    }
+
+    #[test]
+    fn failed_register_read_resumes_hart() {
+        let (sim, nfp) = sim_nfp();
+        let rfpc = rfpc(CppIsland::first_of_kind(IslandKind::Rfpc), 0, 0, 1);
+        let dm = sim.debug_module(&rfpc).unwrap();
+
+        dm.inject_cmderr(DmCmdErr::Bus);
+        let reg: Box<dyn RfpcReg> = Box::new(RfpcGpr::X1);
+        let result = read_rfpc_reg(&mut nfp.expl_bar().unwrap(), &rfpc, &reg);
+        assert!(matches!(result, Err(NfpError::DmCmdErr(DmCmdErr::Bus))));
+        assert!(!dm.is_halted(&rfpc));
+    }
+
+    #[test]
+    fn failed_memory_read_restores_a0() {
+        let (sim, nfp) = sim_nfp();
+        let rfpc = rfpc(CppIsland::first_of_kind(IslandKind::Rfpc), 0, 0, 0);
+        let dm = sim.debug_module(&rfpc).unwrap();
+        dm.write_reg(&rfpc, &RfpcGpr::X10, 0x1234);
+        let mut expl_bar = nfp.expl_bar().unwrap();
+        rfpc_dbg_halt(&mut expl_bar, &rfpc).unwrap();
+
+        // Fail the load, after a0 has been saved.
+        dm.inject_cmderr_after(1, DmCmdErr::Exception);
+        let result = rfpc_dbg_read_memory(&mut expl_bar, &rfpc, 0x100, 2);
+        assert!(matches!(
+            result,
+            Err(NfpError::DmCmdErr(DmCmdErr::Exception))
+        ));
+        assert_eq!(dm.read_reg(&rfpc, &RfpcGpr::X10), 0x1234);
+    }
+
+    #[test]
+    fn injected_busy_is_waited_for() {
+        let (sim, nfp) = sim_nfp();
+        let rfpc = rfpc(CppIsland::first_of_kind(IslandKind::Rfpc), 0, 0, 2);
+        let dm = sim.debug_module(&rfpc).unwrap();
+        dm.write_reg(&rfpc, &RfpcGpr::X1, 42);
+        let mut expl_bar = nfp.expl_bar().unwrap();
+        rfpc_dbg_halt(&mut expl_bar, &rfpc).unwrap();
+
+        dm.inject_busy(2);
+        let value = rfpc_dbg_read_reg(&mut expl_bar, &rfpc, RfpcGpr::X1.reg_addr()).unwrap();
+        assert_eq!(value, 42);
+    }
+
+    #[test]
+    fn injected_halt_timeout() {
+        let (sim, nfp) = sim_nfp();
+        let rfpc = rfpc(CppIsland::first_of_kind(IslandKind::Rfpc), 0, 0, 0);
+        let dm = sim.debug_module(&rfpc).unwrap();
+
+        dm.inject_halt_timeout(true);
+        let result = rfpc_dbg_halt(&mut nfp.expl_bar().unwrap(), &rfpc);
+        assert!(matches!(result, Err(NfpError::Timeout(_))));
+        assert!(!dm.is_halted(&rfpc));
+    }
+
+    #[test]
+    fn nonexistent_hart() {
+        let mut state = DmState::new(32);
+        state.write_dmcontrol((32 << 16) | RISCV_DBG_DMCONTROL_DMACTIVE);
+        let dmstatus = state.read_dmstatus();
+        assert_ne!(dmstatus & RISCV_DBG_DMSTATUS_ANYNONEXISTENT, 0);
+        assert_ne!(dmstatus & RISCV_DBG_DMSTATUS_ALLNONEXISTENT, 0);
+        assert_eq!(
+            dmstatus & (RISCV_DBG_DMSTATUS_ANYHALTED | RISCV_DBG_DMSTATUS_ANYRUNNING),
+            0
+        );
+
+        // Commands and the program buffer fail without touching any hart.
+        state.progbuf0 = 0x00053503;
+        state.write_command(0x37100a);
+        assert_eq!(state.cmderr, Some(DmCmdErr::HaltResume));
+        assert_eq!(state.exec_progbuf(), Err(DmCmdErr::HaltResume));
+    }
+}
@@ -0,0 +1,716 @@
+#![allow(dead_code)]
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use crate::libs::chip_desc::{chip_desc, IslandKind};
+use crate::libs::cpp_bus::{CppIsland, CppTarget};
+use crate::libs::device_backend::{BarWindow, DeviceBackend, EXPANSION_BARS_PER_PHYS_BAR};
+use crate::libs::expansion_bar::{
+    MapType, BAR_CONFIG_BASE_CONFIG_SNOOP, EXPANSION_BAR_BASE_OFFSET, EXPANSION_BAR_CSR_OFFSET,
+    EXPANSION_BAR_PHYS_OFFSET,
+};
+use crate::libs::explicit_bar::{
+    CSR_EXPL_BAR_OFFSET, CSR_EXPL_BASE_OFFSET, NUM_EXPL_BARS, PCIE_INT_SRAM_BASE,
+    SRAM_DATA_BASE_OFFSET, SRAM_DATA_EXPL_BAR_OFFSET,
+};
+use crate::libs::mem_access::MuAtomicOp;
+use crate::libs::rfpc::Rfpc;
+use crate::libs::sim_debug_module::SimDebugModule;
+
+// Size of the simulated PCIe configuration space.
+const SIM_CONFIG_SPACE_SIZE: usize = 4096;
+// Size of every simulated physical BAR (8 expansion BARs of 16 MiB).
+const SIM_PHYS_BAR_SIZE: u64 = 128 << 20;
+// Granularity of the sparse simulated memories.
+const SIM_PAGE_SIZE: u64 = 4096;
+// PCIe vendor and device IDs reported by the simulated device.
+const SIM_VENDOR_ID: u16 = 0x1da8;
+const SIM_DEVICE_ID: u16 = 0x7000;
+// CPP target ID of the PCIe island internal target (holds the PCIe SRAM).
+const CPP_TARGET_PCIE_INTERNAL: u8 = 0;
+
+/// A single CPP bus command, as decoded from an expansion or explicit BAR
+/// configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CppCommand {
+    pub island: u8,
+    pub target: u8,
+    pub action: u8,
+    pub token: u8,
+    pub address: u64,
+}
+
+/// Decode the expansion BAR CSR words produced by
+/// `ExpansionBar::expansion_bar_cfg`.
+///
+/// # Returns
+///
+/// The map type and the CPP command addressing the first byte of the
+/// expansion BAR window, or `None` if the CSRs select one of the reserved
+/// map types 5-7. Only Fixed mappings carry a CPP action; the other map
+/// types reuse the action (and token) fields for base address bits, so the
+/// decoded action is 0 for them.
+pub fn decode_exp_bar_cfg(cfg0: u32, cfg1: u32) -> Option<(MapType, CppCommand)> {
+    let map_type = MapType::from_id(((cfg0 >> 20) & 0x7) as u8).ok()?;
+    let island = ((cfg0 >> 24) & 0x7F) as u8;
+    let field_target = ((cfg0 >> 12) & 0xF) as u64;
+    let field_token = ((cfg0 >> 8) & 0x3) as u64;
+    let field_action = (cfg0 & 0x3F) as u64;
+    let cfg1 = cfg1 as u64;
+
+    let (target, action, token, address) = match map_type {
+        MapType::Fixed => (field_target, field_action, field_token, cfg1 << 16),
+        MapType::Bulk => (
+            field_target,
+            0,
+            field_token,
+            (field_action << 42) | (cfg1 << 10),
+        ),
+        MapType::Target => (
+            field_target,
+            0,
+            0,
+            (field_token << 46) | (field_action << 40) | (cfg1 << 8),
+        ),
+        // The target field holds address bits [47:44] in General mapping,
+        // which only reaches the PCIe internal target on the simulator.
+        MapType::General | MapType::Explicit => (
+            CPP_TARGET_PCIE_INTERNAL as u64,
+            0,
+            0,
+            (field_target << 44) | (field_token << 42) | (field_action << 36) | (cfg1 << 4),
+        ),
+    };
+
+    Some((
+        map_type,
+        CppCommand {
+            island,
+            target: target as u8,
+            action: action as u8,
+            token: token as u8,
+            address: address & 0xFFFF_FFFF_FFFF,
+        },
+    ))
+}
+
+/// Decode the four explicit BAR CSR words produced by
+/// `ExplicitBar::explicit_bar_cfg`.
+///
+/// # Returns
+///
+/// The CPP command addressing the start of the explicit BAR window and the
+/// length of the command in 32-bit words.
+pub fn decode_expl_bar_cfg(cfg: [u32; 4]) -> (CppCommand, u64) {
+    let command = CppCommand {
+        island: ((cfg[2] >> 16) & 0x7F) as u8,
+        target: ((cfg[1] >> 28) & 0xF) as u8,
+        action: ((cfg[0] >> 20) & 0x3F) as u8,
+        token: ((cfg[0] >> 16) & 0x3) as u8,
+        address: (cfg[3] as u64) << 16,
+    };
+    let length_words = ((cfg[0] >> 8) & 0x1F) as u64 + 1;
+
+    (command, length_words)
+}
+
+/// Return whether a CPP command writes (pulls data) rather than reads.
+pub fn is_write_command(target: u8, action: u8) -> bool {
+    if target == CppTarget::mem().id() {
+        // MU write commands (Bulk64, Atomic32 and Bulk32 writes) and the
+        // plain MU atomic operations.
+        matches!(action, 1 | 4 | 5 | 6 | 7 | 9 | 31)
+    } else {
+        action == 1
+    }
+}
+
+/// Offset into the PCIe configuration space of the CSRs of expansion BAR
+/// `exp_bar` of physical BAR `phys_bar`.
+pub(crate) fn exp_bar_csr_addr(phys_bar: u8, exp_bar: u8) -> u32 {
+    BAR_CONFIG_BASE_CONFIG_SNOOP
+        + EXPANSION_BAR_BASE_OFFSET
+        + (phys_bar as u32) * EXPANSION_BAR_PHYS_OFFSET
+        + (exp_bar as u32) * EXPANSION_BAR_CSR_OFFSET
+}
+
+/// Offset into the PCIe configuration space of the CSRs of explicit BAR
+/// `expl_bar_index`.
+pub(crate) fn expl_bar_csr_addr(expl_bar_index: u32) -> u32 {
+    BAR_CONFIG_BASE_CONFIG_SNOOP + CSR_EXPL_BASE_OFFSET + expl_bar_index * CSR_EXPL_BAR_OFFSET
+}
+
+/// CPP address of byte `offset` into a BAR window of `window_size` bytes
+/// configured with `base_addr`. The window offset replaces the low address
+/// bits, as on the hardware.
+pub(crate) fn window_address(base_addr: u64, window_size: u64, offset: u64) -> u64 {
+    (base_addr & !(window_size - 1)) | offset
+}
+
+/// Sparse byte-addressed memory, allocated in pages on first write.
+#[derive(Default)]
+struct SparseMemory {
+    pages: HashMap<u64, Vec<u8>>,
+}
+
+impl SparseMemory {
+    fn read(&self, address: u64, length: u64) -> Vec<u8> {
+        let mut data = Vec::with_capacity(length as usize);
+        let mut addr = address;
+        let end = address + length;
+        while addr < end {
+            let page_offset = addr % SIM_PAGE_SIZE;
+            let chunk = (SIM_PAGE_SIZE - page_offset).min(end - addr);
+            match self.pages.get(&(addr / SIM_PAGE_SIZE)) {
+                Some(page) => data
+                    .extend_from_slice(&page[page_offset as usize..(page_offset + chunk) as usize]),
+                None => data.resize(data.len() + chunk as usize, 0),
+            }
+            addr += chunk;
+        }
+        data
+    }
+
+    fn write(&mut self, address: u64, data: &[u8]) {
+        let mut addr = address;
+        let mut written = 0;
+        while written < data.len() {
+            let page_offset = (addr % SIM_PAGE_SIZE) as usize;
+            let chunk = (SIM_PAGE_SIZE as usize - page_offset).min(data.len() - written);
+            let page = self
+                .pages
+                .entry(addr / SIM_PAGE_SIZE)
+                .or_insert_with(|| vec![0; SIM_PAGE_SIZE as usize]);
+            page[page_offset..page_offset + chunk].copy_from_slice(&data[written..written + chunk]);
+            addr += chunk as u64;
+            written += chunk;
+        }
+    }
+}
+
+/// Register block on the simulated XPB bus, such as a peripheral with
+/// side effects on register access.
+pub trait XpbDevice: Send {
+    /// Read the 32-bit register at `offset` into the device.
+    fn xpb_read(&mut self, offset: u32) -> u32;
+
+    /// Write the 32-bit register at `offset` into the device.
+    fn xpb_write(&mut self, offset: u32, value: u32);
+}
+
+/// XPB device attached to the simulated XPB bus.
+struct XpbMapping {
+    island: u8,
+    base: u32,
+    size: u32,
+    device: Box<dyn XpbDevice>,
+}
+
+/// Complete state of a simulated NFP.
+struct SimState {
+    config: Vec<u8>,
+    exp_bar_locks: HashSet<(u8, u8)>,
+    expl_bar_locks: HashSet<u32>,
+    // Memories and XPB register space, keyed by (island, CPP target).
+    memories: HashMap<(u8, u8), SparseMemory>,
+    xpb_devices: Vec<XpbMapping>,
+}
+
+impl SimState {
+    fn new() -> Self {
+        let mut config = vec![0u8; SIM_CONFIG_SPACE_SIZE];
+        config[0..2].copy_from_slice(&SIM_VENDOR_ID.to_le_bytes());
+        config[2..4].copy_from_slice(&SIM_DEVICE_ID.to_le_bytes());
+
+        SimState {
+            config,
+            exp_bar_locks: HashSet::new(),
+            expl_bar_locks: HashSet::new(),
+            memories: HashMap::new(),
+            xpb_devices: Vec::new(),
+        }
+    }
+
+    fn config_word(&self, offset: u32) -> u32 {
+        let offset = offset as usize;
+        u32::from_le_bytes(self.config[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn exp_bar_cfg(&self, phys_bar: u8, exp_bar: u8) -> (u32, u32) {
+        let csr_addr = exp_bar_csr_addr(phys_bar, exp_bar);
+        (self.config_word(csr_addr), self.config_word(csr_addr + 4))
+    }
+
+    fn expl_bar_cfg(&self, expl_bar_index: u32) -> [u32; 4] {
+        let csr_addr = expl_bar_csr_addr(expl_bar_index);
+        [
+            self.config_word(csr_addr),
+            self.config_word(csr_addr + 4),
+            self.config_word(csr_addr + 8),
+            self.config_word(csr_addr + 12),
+        ]
+    }
+
+    /// Map a CPP command onto the island and address it accesses.
+    fn route(&self, command: &CppCommand) -> (u8, u64) {
+        if command.target == CppTarget::ct().id() {
+            // XPB: the register island is given in the address (see
+            // `xpb_cpp_address`), global (XPBM) accesses reach it through
+            // the chip exec island.
+            let xpb_addr = command.address as u32;
+            let island = ((xpb_addr >> 24) & 0x7F) as u8;
+            (island, (xpb_addr & 0x00FFFFFF) as u64)
+        } else {
+            (command.island, command.address)
+        }
+    }
+
+    /// Find the XPB device, if any, claiming an XPB register.
+    fn xpb_device(&mut self, island: u8, address: u64) -> Option<(&mut Box<dyn XpbDevice>, u32)> {
+        let address = address as u32;
+        self.xpb_devices
+            .iter_mut()
+            .find(|mapping| {
+                mapping.island == island
+                    && (mapping.base..mapping.base + mapping.size).contains(&address)
+            })
+            .map(|mapping| (&mut mapping.device, address - mapping.base))
+    }
+
+    fn cpp_read(&mut self, command: &CppCommand, length: u64) -> Vec<u8> {
+        if command.target == CppTarget::mem().id() {
+            if let Some((op, true)) = MuAtomicOp::from_command(command.action, command.token) {
+                if op.is_immediate() {
+                    // Immediate test atomics pull nothing and push back the
+                    // previous value of each word, like a read.
+                    let mut push_data = Vec::new();
+                    for word in 0..length.div_ceil(4) {
+                        let word_command = CppCommand {
+                            address: command.address + word * 4,
+                            ..*command
+                        };
+                        push_data.extend(self.mu_atomic(&word_command, op, &[]).to_le_bytes());
+                    }
+                    push_data.truncate(length as usize);
+                    return push_data;
+                }
+            }
+        }
+
+        let (island, address) = self.route(command);
+
+        if command.target == CppTarget::ct().id() {
+            if let Some((device, offset)) = self.xpb_device(island, address) {
+                return (0..length.div_ceil(4) as u32)
+                    .flat_map(|word| device.xpb_read(offset + word * 4).to_le_bytes())
+                    .take(length as usize)
+                    .collect();
+            }
+        }
+
+        let memory = self.memories.entry((island, command.target)).or_default();
+        memory.read(address, length)
+    }
+
+    /// Apply MU atomic `op` to the word at the command address, returning
+    /// the previous value of the word.
+    fn mu_atomic(&mut self, command: &CppCommand, op: MuAtomicOp, pull_data: &[u32]) -> u32 {
+        let (island, address) = self.route(command);
+        let memory = self.memories.entry((island, command.target)).or_default();
+        let previous = u32::from_le_bytes(memory.read(address, 4).try_into().unwrap());
+        memory.write(address, &op.apply(previous, pull_data).to_le_bytes());
+        previous
+    }
+
+    fn cpp_write(&mut self, command: &CppCommand, data: &[u8]) {
+        if command.target == CppTarget::mem().id() {
+            if let Some((op, false)) = MuAtomicOp::from_command(command.action, command.token) {
+                // Plain atomics operate on each word with its own pull data.
+                for (word, chunk) in data.chunks(4).enumerate() {
+                    let mut value = [0u8; 4];
+                    value[..chunk.len()].copy_from_slice(chunk);
+                    let word_command = CppCommand {
+                        address: command.address + word as u64 * 4,
+                        ..*command
+                    };
+                    self.mu_atomic(&word_command, op, &[u32::from_le_bytes(value)]);
+                }
+                return;
+            }
+        }
+
+        let (island, address) = self.route(command);
+
+        if command.target == CppTarget::ct().id() {
+            if let Some((device, offset)) = self.xpb_device(island, address) {
+                for (word, chunk) in data.chunks(4).enumerate() {
+                    let mut value = [0u8; 4];
+                    value[..chunk.len()].copy_from_slice(chunk);
+                    device.xpb_write(offset + word as u32 * 4, u32::from_le_bytes(value));
+                }
+                return;
+            }
+        }
+
+        let memory = self.memories.entry((island, command.target)).or_default();
+        memory.write(address, data);
+    }
+
+    /// Execute the explicit command selected by a read of the explicit
+    /// trigger window, returning the data pushed back on the trigger read.
+    fn explicit_trigger(&mut self, window_size: u64, offset: u64, length: u64) -> Vec<u8> {
+        let expl_bar_size = window_size / NUM_EXPL_BARS as u64;
+        let expl_bar_index = (offset / expl_bar_size) as u32;
+
+        let (mut command, length_words) = decode_expl_bar_cfg(self.expl_bar_cfg(expl_bar_index));
+        command.address = window_address(command.address, expl_bar_size, offset % expl_bar_size);
+
+        // Explicit command data is staged in the PCIe SRAM.
+        let sram_command = CppCommand {
+            island: 0,
+            target: CPP_TARGET_PCIE_INTERNAL,
+            action: 0,
+            token: 0,
+            address: (PCIE_INT_SRAM_BASE
+                + SRAM_DATA_BASE_OFFSET
+                + expl_bar_index * SRAM_DATA_EXPL_BAR_OFFSET) as u64,
+        };
+
+        if command.target == CppTarget::mem().id() {
+            if let Some((op, true)) = MuAtomicOp::from_command(command.action, command.token) {
+                // Test atomics pull their operands, unless immediate, and
+                // push the previous value.
+                let pull_data: Vec<u32> = if op.is_immediate() {
+                    Vec::new()
+                } else {
+                    self.cpp_read(&sram_command, length_words * 4)
+                        .chunks(4)
+                        .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+                        .collect()
+                };
+                let previous = self.mu_atomic(&command, op, &pull_data).to_le_bytes();
+                self.cpp_write(&sram_command, &previous);
+
+                // Without pull data the push data also completes the
+                // trigger read.
+                let mut push_data = if op.is_immediate() {
+                    previous.to_vec()
+                } else {
+                    Vec::new()
+                };
+                push_data.resize(length as usize, 0);
+                return push_data;
+            }
+        }
+
+        if is_write_command(command.target, command.action) {
+            let pull_data = self.cpp_read(&sram_command, length_words * 4);
+            self.cpp_write(&command, &pull_data);
+            vec![0; length as usize]
+        } else {
+            let mut push_data = self.cpp_read(&command, length_words * 4);
+            self.cpp_write(&sram_command, &push_data);
+            push_data.resize(length as usize, 0);
+            push_data
+        }
+    }
+
+    fn bar_read(
+        &mut self,
+        phys_bar: u8,
+        exp_bar: u8,
+        window_size: u64,
+        offset: u64,
+        length: u64,
+    ) -> Vec<u8> {
+        let (cfg0, cfg1) = self.exp_bar_cfg(phys_bar, exp_bar);
+        match decode_exp_bar_cfg(cfg0, cfg1) {
+            // A BAR with a reserved map type makes no CPP command; the read
+            // completes with all ones, as an unclaimed PCIe read does.
+            None => vec![0xFF; length as usize],
+            Some((MapType::Explicit, _)) => self.explicit_trigger(window_size, offset, length),
+            Some((_, mut command)) => {
+                command.address = window_address(command.address, window_size, offset);
+                self.cpp_read(&command, length)
+            }
+        }
+    }
+
+    fn bar_write(
+        &mut self,
+        phys_bar: u8,
+        exp_bar: u8,
+        window_size: u64,
+        write_bytes: &[u8],
+        offset: u64,
+    ) {
+        let (cfg0, cfg1) = self.exp_bar_cfg(phys_bar, exp_bar);
+        match decode_exp_bar_cfg(cfg0, cfg1) {
+            // Writes to the explicit trigger window, or through a BAR with a
+            // reserved map type, have no effect.
+            None | Some((MapType::Explicit, _)) => (),
+            Some((_, mut command)) => {
+                command.address = window_address(command.address, window_size, offset);
+                self.cpp_write(&command, write_bytes);
+            }
+        }
+    }
+}
+
+/// In-process software model of an NFP.
+///
+/// The simulated device decodes the expansion BAR and explicit BAR CSRs
+/// written to its configuration space, and routes BAR window accesses to
+/// sparse per-island memories (EMEM, CTM, CLS, PCIe SRAM, ...) and XPB
+/// register space. All memory reads as zero until written. Every RFPC
+/// cluster has a simulated RISC-V debug module.
+#[derive(Clone)]
+pub struct SimDevice {
+    state: Arc<Mutex<SimState>>,
+    // Debug module of each RFPC cluster, keyed by (island, cluster).
+    debug_modules: Arc<HashMap<(u8, u8), SimDebugModule>>,
+}
+
+impl SimDevice {
+    pub fn new() -> Self {
+        let mut sim = SimDevice {
+            state: Arc::new(Mutex::new(SimState::new())),
+            debug_modules: Arc::new(HashMap::new()),
+        };
+
+        let mut debug_modules = HashMap::new();
+        let rfpc_islands = chip_desc()
+            .islands
+            .iter()
+            .filter(|island| island.kind == IslandKind::Rfpc);
+        for island in rfpc_islands {
+            for cluster in 0..chip_desc().rfpc.clusters.len() as u8 {
+                // The island and cluster come from the chip description, so
+                // attaching cannot fail.
+                let debug_module = CppIsland::from_id(island.id)
+                    .and_then(|island| Rfpc::new(island, cluster, 0, 0))
+                    .and_then(|rfpc| SimDebugModule::attach(&sim, &rfpc));
+                if let Ok(debug_module) = debug_module {
+                    debug_modules.insert((island.id, cluster), debug_module);
+                }
+            }
+        }
+        sim.debug_modules = Arc::new(debug_modules);
+
+        sim
+    }
+
+    /// Debug module of the RFPC cluster of `rfpc`, to inspect the harts and
+    /// inject faults.
+    pub fn debug_module(&self, rfpc: &Rfpc) -> Option<&SimDebugModule> {
+        self.debug_modules.get(&(rfpc.island.id(), rfpc.cluster))
+    }
+
+    /// Read simulated memory directly, bypassing the BARs.
+    pub fn read_memory(&self, island: u8, target: CppTarget, address: u64, length: u64) -> Vec<u8> {
+        let command = CppCommand {
+            island,
+            target: target.id(),
+            action: 0,
+            token: 0,
+            address,
+        };
+        self.state.lock().unwrap().cpp_read(&command, length)
+    }
+
+    /// Write simulated memory directly, bypassing the BARs.
+    pub fn write_memory(&self, island: u8, target: CppTarget, address: u64, data: &[u8]) {
+        let command = CppCommand {
+            island,
+            target: target.id(),
+            action: 0,
+            token: 0,
+            address,
+        };
+        self.state.lock().unwrap().cpp_write(&command, data);
+    }
+
+    /// Attach a device to the simulated XPB bus. Accesses to the `size`
+    /// bytes of XPB address space at `base` are forwarded to the device
+    /// instead of plain register storage.
+    ///
+    /// # Parameters
+    ///
+    /// * `island` - Island holding the device. The device is reached by
+    ///   both local and global (XPBM) accesses to the island.
+    /// * `base` - 24-bit XPB address of the device.
+    /// * `size` - Size of the device register block in bytes.
+    /// * `device` - Device model servicing the accesses.
+    pub fn attach_xpb_device(
+        &self,
+        island: CppIsland,
+        base: u32,
+        size: u32,
+        device: Box<dyn XpbDevice>,
+    ) {
+        self.state.lock().unwrap().xpb_devices.push(XpbMapping {
+            island: island.id(),
+            base: base & 0x00FFFFFF,
+            size,
+            device,
+        });
+    }
+}
+
+impl Default for SimDevice {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeviceBackend for SimDevice {
+    fn name(&self) -> &str {
+        "sim"
+    }
+
+    fn config_read(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let state = self.state.lock().unwrap();
+        let end = offset as usize + buf.len();
+        if end > state.config.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Config space access out of range",
+            ));
+        }
+        buf.copy_from_slice(&state.config[offset as usize..end]);
+        Ok(())
+    }
+
+    fn config_write(&self, offset: u64, data: &[u8]) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let end = offset as usize + data.len();
+        if end > state.config.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Config space access out of range",
+            ));
+        }
+        state.config[offset as usize..end].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn lock_exp_bar(&self, phys_bar: u8, exp_bar: u8) -> io::Result<()> {
+        if self
+            .state
+            .lock()
+            .unwrap()
+            .exp_bar_locks
+            .insert((phys_bar, exp_bar))
+        {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                format!("exp_bar{}-{} is already locked", phys_bar, exp_bar),
+            ))
+        }
+    }
+
+    fn unlock_exp_bar(&self, phys_bar: u8, exp_bar: u8) {
+        self.state
+            .lock()
+            .unwrap()
+            .exp_bar_locks
+            .remove(&(phys_bar, exp_bar));
+    }
+
+    fn lock_expl_bar(&self, expl_bar_index: u32) -> io::Result<()> {
+        if self
+            .state
+            .lock()
+            .unwrap()
+            .expl_bar_locks
+            .insert(expl_bar_index)
+        {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                format!("expl_bar{} is already locked", expl_bar_index),
+            ))
+        }
+    }
+
+    fn unlock_expl_bar(&self, expl_bar_index: u32) {
+        self.state
+            .lock()
+            .unwrap()
+            .expl_bar_locks
+            .remove(&expl_bar_index);
+    }
+
+    fn map_exp_bar(&self, phys_bar: u8, exp_bar: u8) -> io::Result<Box<dyn BarWindow>> {
+        Ok(Box::new(SimWindow {
+            state: Arc::clone(&self.state),
+            phys_bar,
+            exp_bar,
+            size: SIM_PHYS_BAR_SIZE / EXPANSION_BARS_PER_PHYS_BAR,
+        }))
+    }
+}
+
+/// Expansion BAR window of a simulated NFP.
+struct SimWindow {
+    state: Arc<Mutex<SimState>>,
+    phys_bar: u8,
+    exp_bar: u8,
+    size: u64,
+}
+
+impl BarWindow for SimWindow {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read(&self, offset: u64, length: u64) -> io::Result<Vec<u8>> {
+        Ok(self.state.lock().unwrap().bar_read(
+            self.phys_bar,
+            self.exp_bar,
+            self.size,
+            offset,
+            length,
+        ))
+    }
+
+    fn write(&mut self, write_bytes: &[u8], offset: u64) -> io::Result<()> {
+        self.state.lock().unwrap().bar_write(
+            self.phys_bar,
+            self.exp_bar,
+            self.size,
+            write_bytes,
+            offset,
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserved_map_type_reads_all_ones() {
+        let sim = SimDevice::new();
+        let csr_addr = exp_bar_csr_addr(2, 0) as u64;
+        // Map type 5 is reserved.
+        sim.config_write(csr_addr, &(5u32 << 20).to_le_bytes())
+            .unwrap();
+        assert_eq!(decode_exp_bar_cfg(5 << 20, 0), None);
+
+        let mut window = sim.map_exp_bar(2, 0).unwrap();
+        window.write(&[0; 4], 0).unwrap();
+        assert_eq!(window.read(0, 4).unwrap(), vec![0xFF; 4]);
+
+        // The device is still usable after the access.
+        sim.config_write(csr_addr, &0u32.to_le_bytes()).unwrap();
+        assert_eq!(window.read(0, 4).unwrap(), vec![0; 4]);
+    }
+}
@@ -4,8 +4,9 @@ use std::thread::sleep;
 use std::time::{Duration, Instant};
 
 use crate::libs::cpp_bus::CppIsland;
-use crate::libs::expansion_bar::ExpansionBar;
+use crate::libs::error::NfpError;
 use crate::libs::mem_access::{mem_read, mem_write, MemoryType, MuMemoryEngine};
+use crate::libs::nfp::Nfp;
 use crate::libs::rfpc::Rfpc;
 use bitfield::bitfield;
 use bytemuck::cast_slice;
@@ -33,39 +34,36 @@ bitfield! {
 ///
 /// Represents a single instance of the virtual terminal interface on
 /// a specified NFP device, at a specified location in memory.
-pub struct VirtualTerminal<'a> {
-    exp_bar: &'a mut ExpansionBar,
+pub struct VirtualTerminal {
+    nfp: Nfp,
     island: CppIsland,
     mem_type: MemoryType,
     address: u32,
 }
 
-impl<'a> VirtualTerminal<'a> {
+impl VirtualTerminal {
     /// Creates a new `VirtualTerminal` instance.
     ///
     /// # Parameters
     ///
-    /// - `exp_bar`: `&mut ExpansionBar`
-    ///   A mutable reference to the `ExpansionBar` instance, which provides access to the expansion memory region.
+    /// - `nfp`: `Nfp`
+    ///   Handle to the NFP holding the virtual terminal.
     ///
     /// - `island`: `CppIsland`
-    ///   The island in which the virtual terminal resides. Determines the memory type used (`Emem` or `Ctm`).
+    ///   The island in which the virtual terminal resides.
+    ///
+    /// - `mem_type`: `MemoryType`
+    ///   The memory holding the virtual terminal, see `MemoryType::default_for_island`.
     ///
     /// - `address`: `u32`
     ///   The base address of the virtual terminal memory region in the selected island.
     ///
     /// # Returns
     ///
-    /// A new `VirtualTerminal` instance configured with the specified `ExpansionBar`, `CppIsland`, and memory address.
-    pub fn new(exp_bar: &'a mut ExpansionBar, island: CppIsland, address: u32) -> Self {
-        let mem_type = if island == CppIsland::Emu0 {
-            MemoryType::Emem
-        } else {
-            MemoryType::Ctm
-        };
-
+    /// A new `VirtualTerminal` instance configured with the specified `Nfp`, `CppIsland`, memory type and memory address.
+    pub fn new(nfp: Nfp, island: CppIsland, mem_type: MemoryType, address: u32) -> Self {
         VirtualTerminal {
-            exp_bar,
+            nfp,
             island,
             mem_type,
             address,
@@ -73,35 +71,35 @@ impl<'a> VirtualTerminal<'a> {
     }
 
     /// Return whether the virtual terminal lock is held by an RFPC.
-    pub fn is_locked(&mut self) -> bool {
+    pub fn is_locked(&mut self) -> Result<bool, NfpError> {
         let lock_word = mem_read(
-            self.exp_bar,
+            &mut *self.nfp.exp_bar()?,
             self.island,
             self.mem_type,
             MuMemoryEngine::Atomic32,
             (self.address + LOCK_OFFSET).into(),
             1,
-        )[0];
+        )?[0];
 
-        lock_word == 0
+        Ok(lock_word == 0)
     }
 
     /// Return the identifying information of the locking RFPC.
-    pub fn holder(&mut self) -> Option<Rfpc> {
+    pub fn holder(&mut self) -> Result<Option<Rfpc>, NfpError> {
         // If the virtual terminal is not locked, then the holder is
         // invalid regardless of data presence.
-        if !self.is_locked() {
-            return None;
+        if !self.is_locked()? {
+            return Ok(None);
         }
 
         let meta_word = mem_read(
-            self.exp_bar,
+            &mut *self.nfp.exp_bar()?,
             self.island,
             self.mem_type,
             MuMemoryEngine::Atomic32,
             (self.address + METADATA_OFFSET).into(),
             1,
-        )[0];
+        )?[0];
 
         let meta = VtmMetadata(meta_word);
 
@@ -110,85 +108,88 @@ impl<'a> VirtualTerminal<'a> {
             // Island ID is invalid, indicating that the metadata is
             // invalid. This typically occurs on startup before the lock
             // is acquired for the first time.
-            return None;
+            return Ok(None);
         }
 
         let group = meta.group() % 4;
         let cluster = group / 4;
 
-        Some(Rfpc {
-            island: CppIsland::from_id(meta.island() as u8),
+        Ok(Some(Rfpc {
+            island: CppIsland::from_id(meta.island() as u8)?,
             cluster: cluster as u8,
             group: group as u8,
             core: meta.core() as u8,
-        })
+        }))
     }
 
     /// Return the number of bytes of data available in the virtual
     /// terminal memory.
-    pub fn data_available(&mut self) -> u32 {
-        match self.holder() {
+    pub fn data_available(&mut self) -> Result<u32, NfpError> {
+        match self.holder()? {
             Some(_) => {
                 // Read the length of available data from the specified offset.
-                mem_read(
-                    self.exp_bar,
+                Ok(mem_read(
+                    &mut *self.nfp.exp_bar()?,
                     self.island,
                     self.mem_type,
                     MuMemoryEngine::Atomic32,
                     (self.address + LENGTH_OFFSET).into(),
                     1,
-                )[0]
+                )?[0])
             }
-            None => 0, // If no holder, return 0.
+            None => Ok(0), // If no holder, return 0.
         }
     }
 
     /// Read the available data from the virtual terminal as bytes.
-    pub fn read_bytes(&mut self) -> Vec<u8> {
+    pub fn read_bytes(&mut self) -> Result<Vec<u8>, NfpError> {
         let mut data_bytes: Vec<u8> = Vec::new();
         // Get length of available data.
-        let data_len = self.data_available();
+        let data_len = self.data_available()?;
         if data_len == 0 {
-            return data_bytes;
+            return Ok(data_bytes);
         }
 
         // Read available data from memory.
         let data_words = mem_read(
-            self.exp_bar,
+            &mut *self.nfp.exp_bar()?,
             self.island,
             self.mem_type,
             MuMemoryEngine::Bulk32,
             (self.address + DATA_OFFSET).into(),
             data_len as u64,
-        );
+        )?;
 
         // Clear the length word to indicate to the sender that the data
         // has been received, and it's clear to send more data.
         mem_write(
-            self.exp_bar,
+            &mut *self.nfp.exp_bar()?,
             self.island,
             self.mem_type,
             MuMemoryEngine::Atomic32,
             (self.address + LENGTH_OFFSET).into(),
             vec![0],
-        );
+        )?;
 
         let converted_bytes: Vec<u8> = cast_slice(&data_words).to_vec();
         data_bytes.extend(converted_bytes);
 
-        data_bytes
+        Ok(data_bytes)
     }
 
     /// Read the available data from the virtual terminal as a string.
-    pub fn read_string(&mut self) -> String {
-        String::from_utf8(self.read_bytes()).expect("Invalid UTF-8 sequence")
+    ///
+    /// Invalid UTF-8 sequences in the data are replaced with U+FFFD; use
+    /// `read_bytes` to get the data as sent.
+    pub fn read_string(&mut self) -> Result<String, NfpError> {
+        Ok(String::from_utf8_lossy(&self.read_bytes()?).into_owned())
     }
 
     /// Waits for data to become available or until a specified timeout is reached.
     ///
     /// This function continuously checks if data is available using the `data_available`
     /// method. If data becomes available, the function exits. If a timeout is provided,
-    /// the function will panic with an error message if the time limit is exceeded before
+    /// the function returns `NfpError::Timeout` if the time limit is exceeded before
     /// data is available.
     ///
     /// # Arguments
@@ -196,23 +197,26 @@ impl<'a> VirtualTerminal<'a> {
     /// * `timeout` - An optional duration in seconds to wait for data. If `None`, the function
     ///   will wait indefinitely.
     ///
-    /// # Panics
+    /// # Errors
     ///
-    /// This function will panic if the timeout is exceeded while waiting for data.
-    pub fn wait_for_data(&mut self, timeout: Option<u64>) {
+    /// Returns `NfpError::Timeout` if the timeout is exceeded while waiting for data.
+    #[allow(clippy::manual_map)]
+    pub fn wait_for_data(&mut self, timeout: Option<u64>) -> Result<(), NfpError> {
         let end_time = match timeout {
             Some(t) => Some(Instant::now() + Duration::from_secs(t)),
             None => None,
         };
 
         loop {
-            if self.data_available() != 0 {
-                break;
+            if self.data_available()? != 0 {
+                return Ok(());
             }
 
             if let Some(end_time) = end_time {
                 if Instant::now() > end_time {
-                    panic!("Time limit exceeded waiting for virtual terminal data.");
+                    return Err(NfpError::Timeout(
+                        "Time limit exceeded waiting for virtual terminal data.".to_string(),
+                    ));
                 }
             }
 
@@ -250,31 +254,31 @@ impl<'a> VirtualTerminal<'a> {
         start_timeout: Option<u64>,
         end_timeout: Option<u64>,
         regexp: &str,
-    ) -> String {
+    ) -> Result<String, NfpError> {
         if let Some(timeout) = start_timeout {
             // Wait for data to be available (or timeout exceeded).
-            self.wait_for_data(Some(timeout));
+            self.wait_for_data(Some(timeout))?;
         }
 
         let mut block = String::new();
         let regex = Regex::new(regexp).unwrap();
 
         loop {
-            if self.data_available() == 0 {
+            if self.data_available()? == 0 {
                 // No more data available - return what's received so far.
-                return block;
+                return Ok(block);
             }
 
-            block.push_str(&self.read_string());
+            block.push_str(&self.read_string()?);
 
             // Check for regex match
             if let Some(captures) = regex.captures(&block) {
                 // Return the full match (group 0)
-                return captures[0].to_string();
+                return Ok(captures[0].to_string());
             }
 
             if let Some(timeout) = end_timeout {
-                self.wait_for_data(Some(timeout));
+                self.wait_for_data(Some(timeout))?;
             } else {
                 sleep(Duration::from_millis(100));
             }
@@ -286,15 +290,15 @@ impl<'a> VirtualTerminal<'a> {
     /// data has been processed/consumed, and it's free to send more
     /// data (overwrite the current data), but don't actually read the
     /// pending data.
-    pub fn flush_one(&mut self) {
+    pub fn flush_one(&mut self) -> Result<(), NfpError> {
         mem_write(
-            self.exp_bar,
+            &mut *self.nfp.exp_bar()?,
             self.island,
             self.mem_type,
             MuMemoryEngine::Atomic32,
             (self.address + LENGTH_OFFSET).into(),
             vec![0],
-        );
+        )
     }
 
     /// Flushes all pending data from the virtual terminal interface,
@@ -308,25 +312,27 @@ impl<'a> VirtualTerminal<'a> {
     ///   If `None`, the virtual terminal will be continually flushed
     ///   until no more data is pushed to it.
     ///
-    /// # Panics
+    /// # Errors
     ///
-    /// This function will panic if the timeout is exceeded while flushing data.
-    pub fn flush(&mut self, timeout: Option<u64>) {
+    /// Returns `NfpError::Timeout` if the timeout is exceeded while flushing data.
+    pub fn flush(&mut self, timeout: Option<u64>) -> Result<(), NfpError> {
         let mut end_time: Option<Instant> = None;
 
         if let Some(timeout) = timeout {
             end_time = Some(Instant::now() + Duration::from_secs(timeout));
         }
 
-        while self.data_available() != 0 {
+        while self.data_available()? != 0 {
             if let Some(end_time) = end_time {
                 if Instant::now() > end_time {
-                    panic!("Time limit exceeded while flushing data from virtual terminal interface. Data still pending in memory.");
+                    return Err(NfpError::Timeout("Time limit exceeded while flushing data from virtual terminal interface. Data still pending in memory.".to_string()));
                 }
             }
 
-            self.flush_one();
+            self.flush_one()?;
             sleep(Duration::from_millis(100));
         }
+
+        Ok(())
     }
 }
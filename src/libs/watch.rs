@@ -0,0 +1,200 @@
+#![allow(dead_code)]
+
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::libs::error::NfpError;
+
+// Longest sleep between checks for Ctrl-C.
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+fn write_err(e: std::io::Error) -> NfpError {
+    NfpError::io("Failed to write output", e)
+}
+
+/// Format the time since the watch started, in seconds.
+fn timestamp(start: Instant) -> String {
+    format!("[{:10.3}]", start.elapsed().as_secs_f64())
+}
+
+/// Sleep for `interval`, returning early once `running` is cleared.
+fn sleep_while_running(interval: Duration, running: &AtomicBool) {
+    let end = Instant::now() + interval;
+    while running.load(Ordering::SeqCst) {
+        let now = Instant::now();
+        if now >= end {
+            break;
+        }
+        sleep(WATCH_POLL_INTERVAL.min(end - now));
+    }
+}
+
+/// Return a flag that is cleared when Ctrl-C is pressed, for `watch_words`.
+///
+/// This sets the Ctrl-C handler of the process, which can only be done once.
+///
+/// # Errors
+///
+/// Returns `NfpError::Io` if the Ctrl-C handler cannot be set.
+pub fn ctrlc_running_flag() -> Result<Arc<AtomicBool>, NfpError> {
+    let running = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+    ctrlc::set_handler(move || {
+        r.store(false, Ordering::SeqCst);
+    })
+    .map_err(|e| NfpError::io("Failed to set Ctrl-C handler", std::io::Error::other(e)))?;
+    Ok(running)
+}
+
+/// Repeatedly read a region of 32-bit words and print the words that
+/// changed, until `running` is cleared.
+///
+/// The first read is printed in full. After that, each changed word is
+/// printed with the time since the watch started and its old and new value.
+///
+/// # Parameters
+///
+/// * `out`: Destination of the changes, usually stdout.
+/// * `interval`: Time between reads.
+/// * `address`: Address of the first word, used to label the words.
+/// * `read`: Reads the words of the region.
+/// * `annotate`: Returns the symbol at an address, if any.
+/// * `running`: Cleared to stop watching, e.g. by `ctrlc_running_flag`.
+///
+/// # Errors
+///
+/// Returns any error of `read`, or `NfpError::Io` if writing to `out` fails.
+pub fn watch_words(
+    out: &mut dyn Write,
+    interval: Duration,
+    address: u64,
+    read: &mut dyn FnMut() -> Result<Vec<u32>, NfpError>,
+    annotate: Option<&dyn Fn(u64) -> Option<String>>,
+    running: &AtomicBool,
+) -> Result<(), NfpError> {
+    let symbol_suffix = |address: u64| {
+        annotate
+            .and_then(|annotate| annotate(address))
+            .map_or(String::new(), |symbol| format!(" <{}>", symbol))
+    };
+
+    let start = Instant::now();
+    let mut previous = read()?;
+    for (index, value) in previous.iter().enumerate() {
+        let address = address + index as u64 * 4;
+        writeln!(
+            out,
+            "{} address 0x{:08x}: 0x{:08x}{}",
+            timestamp(start),
+            address,
+            value,
+            symbol_suffix(address)
+        )
+        .map_err(write_err)?;
+    }
+    out.flush().map_err(write_err)?;
+
+    loop {
+        sleep_while_running(interval, running);
+        if !running.load(Ordering::SeqCst) {
+            break;
+        }
+
+        let current = read()?;
+        for (index, (old, new)) in previous.iter().zip(&current).enumerate() {
+            if old == new {
+                continue;
+            }
+            let address = address + index as u64 * 4;
+            writeln!(
+                out,
+                "{} address 0x{:08x}: 0x{:08x} -> 0x{:08x}{}",
+                timestamp(start),
+                address,
+                old,
+                new,
+                symbol_suffix(address)
+            )
+            .map_err(write_err)?;
+        }
+        out.flush().map_err(write_err)?;
+        previous = current;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_changed_words_are_printed() {
+        let running = AtomicBool::new(true);
+        let mut script = vec![vec![1, 2, 3], vec![1, 2, 3], vec![1, 5, 3], vec![0, 5, 4]]
+            .into_iter()
+            .peekable();
+        let mut read = || {
+            let words = script.next().unwrap();
+            if script.peek().is_none() {
+                running.store(false, Ordering::SeqCst);
+            }
+            Ok(words)
+        };
+        let annotate = |address: u64| (address == 0x1008).then(|| "count".to_string());
+
+        let mut out = Vec::new();
+        watch_words(
+            &mut out,
+            Duration::ZERO,
+            0x1000,
+            &mut read,
+            Some(&annotate),
+            &running,
+        )
+        .unwrap();
+
+        // Drop the timestamps, which depend on how fast the test runs.
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out
+            .lines()
+            .map(|line| line.split_once("] ").unwrap().1)
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "address 0x00001000: 0x00000001",
+                "address 0x00001004: 0x00000002",
+                "address 0x00001008: 0x00000003 <count>",
+                "address 0x00001004: 0x00000002 -> 0x00000005",
+                "address 0x00001000: 0x00000001 -> 0x00000000",
+                "address 0x00001008: 0x00000003 -> 0x00000004 <count>",
+            ]
+        );
+        assert!(script.next().is_none());
+    }
+
+    #[test]
+    fn read_errors_stop_the_watch() {
+        let running = AtomicBool::new(true);
+        let mut reads = 0;
+        let mut read = || {
+            reads += 1;
+            match reads {
+                1 => Ok(vec![7]),
+                _ => Err(NfpError::InvalidArgument("gone".to_string())),
+            }
+        };
+
+        let mut out = Vec::new();
+        assert!(matches!(
+            watch_words(&mut out, Duration::ZERO, 0, &mut read, None, &running),
+            Err(NfpError::InvalidArgument(_))
+        ));
+        assert_eq!(reads, 2);
+        assert_eq!(String::from_utf8(out).unwrap().lines().count(), 1);
+    }
+}
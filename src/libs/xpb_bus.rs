@@ -1,9 +1,10 @@
 #![allow(dead_code)]
 
-use crate::libs::common::split_addr48;
 use crate::libs::cpp_bus::{CppBus, CppIsland, CppLength, CppTarget};
+use crate::libs::error::NfpError;
 use crate::libs::expansion_bar::{ExpansionBar, MapType};
-use crate::libs::explicit_bar::ExplicitBar;
+use crate::libs::explicit_bar::{ExplicitBar, ExplicitCmdFields, EXPL_MAX_DATA_WORDS};
+use crate::libs::nfp::{Nfp, PooledBar};
 
 pub fn xpb_read(
     exp_bar: &mut ExpansionBar,
@@ -11,34 +12,21 @@ pub fn xpb_read(
     address: u32,
     length: u64,
     xpbm: bool,
-) -> Vec<u32> {
-    // Ensure expansion BAR gets configured with Bulk mapping.
-    if exp_bar.exp_bar_map != MapType::Bulk {
-        exp_bar.exp_bar_map = MapType::Bulk;
-    }
-
-    let mut xpb_addr = address & 0x00FFFFFF;
-    xpb_addr |= (island.id() as u32 & 0x7F) << 24;
-    let mut tgt_island = *island;
-    if xpbm == true {
-        xpb_addr |= 1 << 31; // Set global bit
-        tgt_island = CppIsland::ChipExec;
-    }
+) -> Result<Vec<u32>, NfpError> {
+    let (tgt_island, xpb_addr) = xpb_cpp_address(island, address, length, xpbm)?;
 
-    // Instantiate Cpp bus with allocated expansion BAR.
-    let mut cpp_bus = CppBus::new(exp_bar);
+    // XPB accesses are made with a Bulk mapping.
+    let mut cpp_bus = CppBus::with_map_type(exp_bar, MapType::Bulk);
 
-    let read_words = cpp_bus.read(
+    cpp_bus.read(
         tgt_island,
-        CppTarget::Ct,
+        CppTarget::ct(),
         0,
         0,
         CppLength::Len32,
-        xpb_addr as u64,
+        xpb_addr,
         length,
-    );
-
-    read_words
+    )
 }
 
 pub fn xpb_write(
@@ -47,74 +35,145 @@ pub fn xpb_write(
     address: u32,
     write_words: Vec<u32>,
     xpbm: bool,
-) {
-    // Ensure expansion BAR gets configured with Bulk mapping.
-    if exp_bar.exp_bar_map != MapType::Bulk {
-        exp_bar.exp_bar_map = MapType::Bulk;
-    }
-
-    let mut xpb_addr = address & 0x00FFFFFF;
-    let mut tgt_island = *island;
-    if xpbm == true {
-        xpb_addr |= 1 << 31; // Set global bit
-        tgt_island = CppIsland::ChipExec;
-    } else {
-        xpb_addr |= (island.id() as u32 & 0x7F) << 24;
-    }
+) -> Result<(), NfpError> {
+    let (tgt_island, xpb_addr) = xpb_cpp_address(island, address, write_words.len() as u64, xpbm)?;
 
-    // Instantiate Cpp bus with allocated expansion BAR.
-    let mut cpp_bus = CppBus::new(exp_bar);
+    // XPB accesses are made with a Bulk mapping.
+    let mut cpp_bus = CppBus::with_map_type(exp_bar, MapType::Bulk);
 
     cpp_bus.write(
         tgt_island,
-        CppTarget::Ct,
+        CppTarget::ct(),
         0,
         0,
         CppLength::Len32,
-        xpb_addr as u64,
+        xpb_addr,
         write_words,
-    );
+    )
 }
 
-pub fn xpb_explicit_write32(
-    expl_bar: &mut ExplicitBar,
+/// CPP island and address of `length` consecutive XPB registers from
+/// `address` of `island`.
+///
+/// The island is given in bits [30:24] of the XPB address. Global (XPBM)
+/// accesses also set bit 31 and are made to the chip exec island, which
+/// holds the XPB master.
+///
+/// # Errors
+///
+/// Returns `NfpError::AddressOutOfRange` if any of the registers lies beyond
+/// 24 bits of address.
+pub(crate) fn xpb_cpp_address(
     island: &CppIsland,
     address: u32,
-    write_words: Vec<u32>,
+    length: u64,
     xpbm: bool,
-) {
+) -> Result<(CppIsland, u64), NfpError> {
     if address.leading_zeros() < 8 {
-        panic!("XPB address {:#08x} is wider than 24 bits.", address);
+        return Err(NfpError::AddressOutOfRange(format!(
+            "XPB address {:#08x} is wider than 24 bits.",
+            address
+        )));
+    }
+    let end = length
+        .checked_mul(4)
+        .and_then(|bytes| bytes.checked_add(address as u64));
+    if end.is_none_or(|end| end > 0x1000000) {
+        return Err(NfpError::AddressOutOfRange(format!(
+            "Access of {:#x} XPB registers at {:#08x} runs past 24 bits of address.",
+            length, address
+        )));
     }
 
     let mut xpb_addr = address & 0x00FFFFFF;
-    let mut tgt_island = island.id();
-    if xpbm == true {
+    xpb_addr |= (island.id() as u32 & 0x7F) << 24;
+    let mut tgt_island = *island;
+    if xpbm {
         xpb_addr |= 1 << 31; // Set global bit
-        tgt_island = CppIsland::ChipExec.id();
-    } else {
-        xpb_addr |= (tgt_island as u32 & 0x7F) << 24;
+        tgt_island = CppIsland::chip_exec();
     }
 
-    let (base_addr, offset) = split_addr48(xpb_addr as u64, expl_bar.size() as u64);
+    Ok((tgt_island, xpb_addr as u64))
+}
 
-    expl_bar.explicit_bar_cfg(
-        tgt_island,
-        CppTarget::Ct.id(),
-        1,
-        0,
-        base_addr,
-        Some(1),
-        0,
-        0xFF,
-        None,
-        None,
-        None,
-        None,
-        None,
-    );
-
-    expl_bar.run_explicit_cmd(offset, Some(write_words), None, false);
+/// Write `write_words` to consecutive XPB registers with explicit commands.
+///
+/// Each command writes up to `EXPL_MAX_DATA_WORDS` registers.
+pub fn xpb_explicit_write(
+    expl_bar: &mut ExplicitBar,
+    island: &CppIsland,
+    address: u32,
+    write_words: Vec<u32>,
+    xpbm: bool,
+) -> Result<(), NfpError> {
+    // Check the whole range before writing any register.
+    xpb_cpp_address(island, address, write_words.len() as u64, xpbm)?;
+    for (index, chunk) in write_words.chunks(EXPL_MAX_DATA_WORDS as usize).enumerate() {
+        let chunk_address = address + (index as u64 * EXPL_MAX_DATA_WORDS * 4) as u32;
+        let (tgt_island, xpb_addr) =
+            xpb_cpp_address(island, chunk_address, chunk.len() as u64, xpbm)?;
+        expl_bar.explicit_cmd(
+            tgt_island,
+            CppTarget::ct(),
+            1,
+            0,
+            xpb_addr,
+            (chunk.len() - 1) as u8,
+            &ExplicitCmdFields::default(),
+            Some(chunk.to_vec()),
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+/// Read `length` consecutive XPB registers with explicit commands.
+///
+/// Each command reads up to `EXPL_MAX_DATA_WORDS` registers.
+pub fn xpb_explicit_read(
+    expl_bar: &mut ExplicitBar,
+    island: &CppIsland,
+    address: u32,
+    length: u64,
+    xpbm: bool,
+) -> Result<Vec<u32>, NfpError> {
+    xpb_cpp_address(island, address, length, xpbm)?;
+    let mut read_words = Vec::with_capacity(length as usize);
+    while (read_words.len() as u64) < length {
+        let chunk_len = (length - read_words.len() as u64).min(EXPL_MAX_DATA_WORDS);
+        let chunk_address = address + (read_words.len() * 4) as u32;
+        let (tgt_island, xpb_addr) = xpb_cpp_address(island, chunk_address, chunk_len, xpbm)?;
+        match expl_bar.explicit_cmd(
+            tgt_island,
+            CppTarget::ct(),
+            0,
+            0,
+            xpb_addr,
+            (chunk_len - 1) as u8,
+            &ExplicitCmdFields::default(),
+            None,
+            Some(chunk_len),
+        )? {
+            Some(words) => read_words.extend(words),
+            None => {
+                return Err(NfpError::InvalidArgument(format!(
+                    "No push data returned for XPB address {:#010x}",
+                    chunk_address
+                )))
+            }
+        }
+    }
+    Ok(read_words)
+}
+
+pub fn xpb_explicit_write32(
+    expl_bar: &mut ExplicitBar,
+    island: &CppIsland,
+    address: u32,
+    write_words: Vec<u32>,
+    xpbm: bool,
+) -> Result<(), NfpError> {
+    xpb_explicit_write(expl_bar, island, address, write_words, xpbm)
 }
 
 pub fn xpb_explicit_read32(
@@ -122,41 +181,105 @@ pub fn xpb_explicit_read32(
     island: &CppIsland,
     address: u32,
     xpbm: bool,
-) -> u32 {
-    if address.leading_zeros() < 8 {
-        panic!("XPB address {:#08x} is wider than 24 bits.", address);
+) -> Result<u32, NfpError> {
+    Ok(xpb_explicit_read(expl_bar, island, address, 1, xpbm)?[0])
+}
+
+/// BAR that XPB accesses are made through.
+pub enum XpbBar {
+    /// Bulk CPP commands through an expansion BAR.
+    Expansion(PooledBar<ExpansionBar>),
+    /// Explicit commands through an explicit BAR.
+    Explicit(PooledBar<ExplicitBar>),
+}
+
+impl XpbBar {
+    /// Take a BAR for XPB accesses from `nfp`, an explicit BAR if
+    /// `explicit` is set.
+    pub fn new(nfp: &Nfp, explicit: bool) -> Result<Self, NfpError> {
+        if explicit {
+            Ok(XpbBar::Explicit(nfp.expl_bar()?))
+        } else {
+            Ok(XpbBar::Expansion(nfp.exp_bar()?))
+        }
     }
 
-    let mut xpb_addr = address & 0x00FFFFFF;
-    let mut tgt_island = island.id();
-    if xpbm == true {
-        xpb_addr |= 1 << 31; // Set global bit
-        tgt_island = CppIsland::ChipExec.id();
-    } else {
-        xpb_addr |= (tgt_island as u32 & 0x7F) << 24;
+    /// Read `length` consecutive XPB registers.
+    pub fn read(
+        &mut self,
+        island: &CppIsland,
+        address: u32,
+        length: u64,
+        xpbm: bool,
+    ) -> Result<Vec<u32>, NfpError> {
+        match self {
+            XpbBar::Expansion(exp_bar) => xpb_read(exp_bar, island, address, length, xpbm),
+            XpbBar::Explicit(expl_bar) => {
+                xpb_explicit_read(expl_bar, island, address, length, xpbm)
+            }
+        }
+    }
+
+    /// Write `write_words` to consecutive XPB registers.
+    pub fn write(
+        &mut self,
+        island: &CppIsland,
+        address: u32,
+        write_words: Vec<u32>,
+        xpbm: bool,
+    ) -> Result<(), NfpError> {
+        match self {
+            XpbBar::Expansion(exp_bar) => xpb_write(exp_bar, island, address, write_words, xpbm),
+            XpbBar::Explicit(expl_bar) => {
+                xpb_explicit_write(expl_bar, island, address, write_words, xpbm)
+            }
+        }
     }
+}
 
-    let (base_addr, offset) = split_addr48(xpb_addr as u64, expl_bar.size() as u64);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::chip_desc::IslandKind;
+    use crate::libs::sim_device::SimDevice;
+    use std::sync::Arc;
 
-    expl_bar.explicit_bar_cfg(
-        tgt_island,
-        CppTarget::Ct.id(),
-        0,
-        0,
-        base_addr,
-        Some(1),
-        0,
-        0xFF,
-        None,
-        None,
-        None,
-        None,
-        None,
-    );
-
-    if let Some(vec) = expl_bar.run_explicit_cmd(offset, None, Some(1), false) {
-        vec[0]
-    } else {
-        panic!("Failed to read from XPB address");
+    #[test]
+    fn wide_addresses_are_rejected() {
+        let nfp = Nfp::new(Arc::new(SimDevice::new())).unwrap();
+        let island = CppIsland::first_of_kind(IslandKind::Emu);
+
+        for explicit in [false, true] {
+            let mut xpb_bar = XpbBar::new(&nfp, explicit).unwrap();
+            xpb_bar
+                .write(&island, 0xfffffc, vec![0x1234], false)
+                .unwrap();
+            assert_eq!(xpb_bar.read(&island, 0xfffffc, 1, false).unwrap(), [0x1234]);
+
+            // Bit 24 would otherwise select another island.
+            assert!(matches!(
+                xpb_bar.read(&island, 0x1000000, 1, false),
+                Err(NfpError::AddressOutOfRange(_))
+            ));
+            assert!(matches!(
+                xpb_bar.write(&island, 0x1fffffc, vec![0], false),
+                Err(NfpError::AddressOutOfRange(_))
+            ));
+
+            // Multi-word accesses may not cross into the next island either.
+            assert!(matches!(
+                xpb_bar.read(&island, 0xfffffc, 2, false),
+                Err(NfpError::AddressOutOfRange(_))
+            ));
+            assert!(matches!(
+                xpb_bar.read(&island, 0xffff80, 40, false),
+                Err(NfpError::AddressOutOfRange(_))
+            ));
+            assert!(matches!(
+                xpb_bar.write(&island, 0xffff80, vec![0x5678; 33], false),
+                Err(NfpError::AddressOutOfRange(_))
+            ));
+            assert_eq!(xpb_bar.read(&island, 0xfffffc, 1, false).unwrap(), [0x1234]);
+        }
     }
 }
@@ -0,0 +1,709 @@
+#![allow(dead_code)]
+
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use crate::libs::chip_desc::{chip_desc, name_matches, IslandKind};
+use crate::libs::common::hex_parser;
+use crate::libs::cpp_bus::CppIsland;
+use crate::libs::error::NfpError;
+
+/// Environment variable naming a register description file to use instead
+/// of the bundled one.
+pub const REG_DESC_ENV: &str = "NFP_REG_DESC";
+
+// Register description bundled with the tools.
+const BUNDLED_REG_DESC: &str = include_str!("../../chips/merlin_regs.toml");
+
+static REG_DESC: OnceLock<RegDesc> = OnceLock::new();
+
+/// Access type of a register or field.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RegAccess {
+    /// Read-write.
+    #[default]
+    Rw,
+    /// Read-only.
+    Ro,
+    /// Write-only. Reads return no meaningful value.
+    Wo,
+    /// Read, and write 1 to clear.
+    W1c,
+}
+
+impl RegAccess {
+    pub fn readable(&self) -> bool {
+        *self != RegAccess::Wo
+    }
+
+    pub fn writable(&self) -> bool {
+        *self != RegAccess::Ro
+    }
+}
+
+impl fmt::Display for RegAccess {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegAccess::Rw => write!(f, "rw"),
+            RegAccess::Ro => write!(f, "ro"),
+            RegAccess::Wo => write!(f, "wo"),
+            RegAccess::W1c => write!(f, "w1c"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct FieldDesc {
+    pub name: String,
+    /// Least significant bit of the field.
+    pub lsb: u8,
+    // Most significant bit of the field, the same as `lsb` if not given.
+    #[serde(default)]
+    msb: Option<u8>,
+    /// Access type of the field, if it differs from the register's.
+    #[serde(default)]
+    pub access: Option<RegAccess>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+impl FieldDesc {
+    /// Most significant bit of the field.
+    pub fn msb(&self) -> u8 {
+        self.msb.unwrap_or(self.lsb)
+    }
+
+    /// Number of bits in the field.
+    pub fn width(&self) -> u32 {
+        (self.msb() - self.lsb) as u32 + 1
+    }
+
+    /// Mask of the field's bits in the register.
+    pub fn mask(&self) -> u32 {
+        (u32::MAX >> (32 - self.width())) << self.lsb
+    }
+
+    /// Return the value of the field in register value `value`.
+    pub fn extract(&self, value: u32) -> u32 {
+        (value & self.mask()) >> self.lsb
+    }
+
+    /// Format the bits of the field, e.g. `[8]` or `[3:1]`.
+    pub fn bits(&self) -> String {
+        if self.width() == 1 {
+            format!("[{}]", self.lsb)
+        } else {
+            format!("[{}:{}]", self.msb(), self.lsb)
+        }
+    }
+}
+
+fn default_count() -> u32 {
+    1
+}
+
+fn default_stride() -> u32 {
+    4
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RegisterDesc {
+    pub name: String,
+    /// Offset of the register in its block.
+    pub offset: u32,
+    #[serde(default)]
+    pub access: RegAccess,
+    #[serde(default)]
+    pub reset: u32,
+    /// Number of registers in the array, 1 for a single register.
+    #[serde(default = "default_count")]
+    pub count: u32,
+    /// Distance between the registers of an array.
+    #[serde(default = "default_stride")]
+    pub stride: u32,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub fields: Vec<FieldDesc>,
+}
+
+impl RegisterDesc {
+    /// Return the name of register `index` of the array, or the register's
+    /// name if it is not an array.
+    pub fn element_name(&self, index: u32) -> String {
+        if self.count == 1 {
+            self.name.clone()
+        } else if self.name.ends_with(|c: char| c.is_ascii_digit()) {
+            format!("{}_{}", self.name, index)
+        } else {
+            format!("{}{}", self.name, index)
+        }
+    }
+
+    /// Return the access type of `field`.
+    pub fn field_access(&self, field: &FieldDesc) -> RegAccess {
+        field.access.unwrap_or(self.access)
+    }
+
+    pub fn field(&self, name: &str) -> Option<&FieldDesc> {
+        self.fields
+            .iter()
+            .find(|field| name_matches(&field.name, name))
+    }
+
+    /// Split a register value into the values of its fields.
+    pub fn decode(&self, value: u32) -> Vec<(&FieldDesc, u32)> {
+        self.fields
+            .iter()
+            .map(|field| (field, field.extract(value)))
+            .collect()
+    }
+
+    /// Set fields of a register value, as read before a read-modify-write.
+    ///
+    /// Write-only and write-1-to-clear fields that are not assigned are
+    /// written as zero, so that the write does not repeat a command or clear
+    /// a status bit.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::InvalidArgument` if the register has no field of
+    /// an assigned name, the field is read-only, or the value does not fit
+    /// in it.
+    pub fn apply_fields(
+        &self,
+        value: u32,
+        assignments: &[FieldAssignment],
+    ) -> Result<u32, NfpError> {
+        let mut value = value;
+
+        for field in &self.fields {
+            let assigned = assignments
+                .iter()
+                .any(|assignment| name_matches(&field.name, &assignment.name));
+            let access = self.field_access(field);
+            if !assigned && (access == RegAccess::Wo || access == RegAccess::W1c) {
+                value &= !field.mask();
+            }
+        }
+
+        for assignment in assignments {
+            let field = self.field(&assignment.name).ok_or_else(|| {
+                NfpError::InvalidArgument(format!(
+                    "Register {} has no field {}",
+                    self.name, assignment.name
+                ))
+            })?;
+            if !self.field_access(field).writable() {
+                return Err(NfpError::InvalidArgument(format!(
+                    "Field {} of register {} is read-only",
+                    field.name, self.name
+                )));
+            }
+            if assignment.value > field.mask() >> field.lsb {
+                return Err(NfpError::InvalidArgument(format!(
+                    "Value {:#x} does not fit in the {}-bit field {}",
+                    assignment.value,
+                    field.width(),
+                    field.name
+                )));
+            }
+            value = (value & !field.mask()) | (assignment.value << field.lsb);
+        }
+
+        Ok(value)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct BlockInstanceDesc {
+    pub name: String,
+    /// XPB base address of the instance. Taken from the `xpb_blocks` of the
+    /// island in the chip description if not given.
+    #[serde(default)]
+    pub base: Option<u32>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct BlockDesc {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Kind of island holding instances of the block.
+    pub island_kind: IslandKind,
+    /// Whether the block is reached through the XPB master.
+    #[serde(default)]
+    pub xpbm: bool,
+    pub instances: Vec<BlockInstanceDesc>,
+    #[serde(default)]
+    pub registers: Vec<RegisterDesc>,
+}
+
+impl BlockDesc {
+    /// Return the XPB base address of `instance` in island `island_id`, if
+    /// the island has the instance.
+    pub fn instance_base(&self, instance: &BlockInstanceDesc, island_id: u8) -> Option<u32> {
+        instance
+            .base
+            .or_else(|| chip_desc().xpb_block_base(island_id, &self.name))
+    }
+}
+
+/// An XPB register of a block instance in an island.
+#[derive(Clone, Debug)]
+pub struct XpbRegister<'a> {
+    /// Full name of the register, e.g. `rfpc0.cl0.grp0.PAControl`.
+    pub name: String,
+    pub island: CppIsland,
+    /// XPB address of the register.
+    pub address: u32,
+    /// Whether the register is reached through the XPB master.
+    pub xpbm: bool,
+    pub desc: &'a RegisterDesc,
+}
+
+/// Description of the XPB registers of an NFP chip.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RegDesc {
+    pub name: String,
+    #[serde(default)]
+    pub blocks: Vec<BlockDesc>,
+}
+
+impl RegDesc {
+    /// The register description bundled with the tools.
+    pub fn bundled() -> RegDesc {
+        RegDesc::from_toml(BUNDLED_REG_DESC).expect("Invalid bundled register description")
+    }
+
+    /// Parse and validate a register description in TOML format.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::InvalidArgument` if the description cannot be
+    /// parsed, a register is not word aligned or has duplicate names, or a
+    /// field lies outside its register or overlaps another field.
+    pub fn from_toml(contents: &str) -> Result<RegDesc, NfpError> {
+        let regs: RegDesc = toml::from_str(contents).map_err(|e| {
+            NfpError::InvalidArgument(format!("Invalid register description: {}", e))
+        })?;
+        regs.validate()?;
+        Ok(regs)
+    }
+
+    /// Load a register description from a TOML file.
+    pub fn load(path: &Path) -> Result<RegDesc, NfpError> {
+        let contents = fs::read_to_string(path).map_err(|e| {
+            NfpError::io(
+                format!("Failed to read register description {}", path.display()),
+                e,
+            )
+        })?;
+        RegDesc::from_toml(&contents)
+    }
+
+    fn validate(&self) -> Result<(), NfpError> {
+        let invalid = |msg: String| {
+            Err(NfpError::InvalidArgument(format!(
+                "Invalid register description {}: {}",
+                self.name, msg
+            )))
+        };
+
+        for block in &self.blocks {
+            if let Some(instance) = block
+                .instances
+                .iter()
+                .find(|instance| instance.base.is_some_and(|base| base > 0x00FF_FFFF))
+            {
+                return invalid(format!(
+                    "instance {} of block {} is beyond the 24-bit XPB space",
+                    instance.name, block.name
+                ));
+            }
+
+            let mut names: Vec<String> = Vec::new();
+            for register in &block.registers {
+                if !register.offset.is_multiple_of(4)
+                    || register.count == 0
+                    || !register.stride.is_multiple_of(4)
+                {
+                    return invalid(format!(
+                        "register {} of block {} is not word aligned",
+                        register.name, block.name
+                    ));
+                }
+
+                for index in 0..register.count {
+                    let name = register.element_name(index);
+                    if names.iter().any(|other| name_matches(other, &name)) {
+                        return invalid(format!(
+                            "duplicate register {} in block {}",
+                            name, block.name
+                        ));
+                    }
+                    names.push(name);
+                }
+
+                let mut used: u32 = 0;
+                for field in &register.fields {
+                    if field.msb() > 31 || field.msb() < field.lsb {
+                        return invalid(format!(
+                            "field {} of register {} has bits [{}:{}]",
+                            field.name,
+                            register.name,
+                            field.msb(),
+                            field.lsb
+                        ));
+                    }
+                    if used & field.mask() != 0 {
+                        return invalid(format!(
+                            "field {} of register {} overlaps another field",
+                            field.name, register.name
+                        ));
+                    }
+                    used |= field.mask();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Return the registers of `block` instance `instance` in `island`.
+    fn instance_registers<'a>(
+        &'a self,
+        island: CppIsland,
+        block: &'a BlockDesc,
+        instance: &BlockInstanceDesc,
+    ) -> Result<Vec<XpbRegister<'a>>, NfpError> {
+        let base = block.instance_base(instance, island.id()).ok_or_else(|| {
+            NfpError::InvalidArgument(format!("Island {} has no {} block", island, block.name))
+        })?;
+
+        let mut registers = Vec::new();
+        for register in &block.registers {
+            for index in 0..register.count {
+                registers.push(XpbRegister {
+                    name: format!(
+                        "{}.{}.{}",
+                        island,
+                        instance.name,
+                        register.element_name(index)
+                    ),
+                    island,
+                    address: base + register.offset + index * register.stride,
+                    xpbm: block.xpbm,
+                    desc: register,
+                });
+            }
+        }
+        Ok(registers)
+    }
+
+    /// Look up registers by name.
+    ///
+    /// `name` is `<island>.<instance>.<register>` for a single register, or
+    /// `<island>.<instance>` or `<island>.<block>` for every register of a
+    /// block instance or of every instance of a block. Names are matched
+    /// ignoring case, '-' and '_'.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::InvalidArgument` if the island is unknown, no
+    /// register matches `name`, or the island has no instance of the block.
+    pub fn lookup(&self, name: &str) -> Result<Vec<XpbRegister<'_>>, NfpError> {
+        let unknown = || NfpError::InvalidArgument(format!("Unknown register {}", name));
+
+        let (island_name, path) = name.split_once('.').ok_or_else(unknown)?;
+        let island = CppIsland::from_str(island_name).map_err(NfpError::InvalidArgument)?;
+        let kind = chip_desc().island(island.id()).map(|island| island.kind);
+
+        for block in self
+            .blocks
+            .iter()
+            .filter(|block| Some(block.island_kind) == kind)
+        {
+            if name_matches(&block.name, path) {
+                let mut registers = Vec::new();
+                for instance in &block.instances {
+                    registers.extend(self.instance_registers(island, block, instance)?);
+                }
+                return Ok(registers);
+            }
+
+            for instance in &block.instances {
+                if name_matches(&instance.name, path) {
+                    return self.instance_registers(island, block, instance);
+                }
+
+                let Some(register) = path
+                    .get(..instance.name.len())
+                    .filter(|prefix| name_matches(prefix, &instance.name))
+                    .and_then(|_| path[instance.name.len()..].strip_prefix('.'))
+                else {
+                    continue;
+                };
+                let registers = self.instance_registers(island, block, instance)?;
+                if let Some(found) = registers.into_iter().find(|found| {
+                    name_matches(found.name.rsplit('.').next().unwrap_or_default(), register)
+                }) {
+                    return Ok(vec![found]);
+                }
+            }
+        }
+
+        Err(unknown())
+    }
+
+    /// Return every register of every block instance in `island`.
+    pub fn island_registers(&self, island: CppIsland) -> Vec<XpbRegister<'_>> {
+        let kind = chip_desc().island(island.id()).map(|island| island.kind);
+        self.blocks
+            .iter()
+            .filter(|block| Some(block.island_kind) == kind)
+            .flat_map(|block| {
+                block.instances.iter().filter_map(move |instance| {
+                    self.instance_registers(island, block, instance).ok()
+                })
+            })
+            .flatten()
+            .collect()
+    }
+}
+
+/// A field assignment given on the command line, e.g. `trace_en=1`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FieldAssignment {
+    pub name: String,
+    pub value: u32,
+}
+
+impl FromStr for FieldAssignment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid field assignment {}, expected NAME=VALUE", s))?;
+        let value = hex_parser(value.trim())
+            .map_err(|_| format!("Invalid value in field assignment {}", s))?;
+        Ok(FieldAssignment {
+            name: name.trim().to_string(),
+            value,
+        })
+    }
+}
+
+/// Return the register description used by the tools.
+///
+/// This is the description installed with `install_reg_desc` or
+/// `install_env_reg_desc`, or the bundled description if none was installed
+/// before the first call.
+pub fn reg_desc() -> &'static RegDesc {
+    REG_DESC.get_or_init(RegDesc::bundled)
+}
+
+/// Use `regs` as the register description for the rest of the process.
+///
+/// # Errors
+///
+/// Returns `NfpError::InvalidArgument` if a register description is already
+/// in use, i.e. `reg_desc` has been called before.
+pub fn install_reg_desc(regs: RegDesc) -> Result<(), NfpError> {
+    REG_DESC.set(regs).map_err(|_| {
+        NfpError::InvalidArgument("A register description is already in use".to_string())
+    })
+}
+
+/// Load the register description named by the `NFP_REG_DESC` environment
+/// variable, if it is set, and use it for the rest of the process.
+///
+/// # Errors
+///
+/// Returns `NfpError::Io` if the file cannot be read, and
+/// `NfpError::InvalidArgument` if it is not a valid register description or
+/// a register description is already in use.
+pub fn install_env_reg_desc() -> Result<(), NfpError> {
+    let Some(path) = std::env::var_os(REG_DESC_ENV) else {
+        return Ok(());
+    };
+    let regs = RegDesc::load(Path::new(&path)).map_err(|e| match e {
+        NfpError::InvalidArgument(msg) => {
+            NfpError::InvalidArgument(format!("{} ({}={:?})", msg, REG_DESC_ENV, path))
+        }
+        e => e,
+    })?;
+    install_reg_desc(regs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_REG_DESC: &str = r#"
+name = "test"
+
+[[blocks]]
+name = "blk"
+island_kind = "rfpc"
+instances = [{ name = "blk", base = 0x1000 }]
+
+[[blocks.registers]]
+name = "Ctl"
+offset = 0x0
+fields = [
+    { name = "enable", lsb = 0 },
+    { name = "mode", lsb = 4, msb = 7 },
+    { name = "go", lsb = 8, access = "wo" },
+    { name = "err", lsb = 9, access = "w1c" },
+    { name = "busy", lsb = 31, access = "ro" },
+]
+"#;
+
+    fn assignment(name: &str, value: u32) -> FieldAssignment {
+        FieldAssignment {
+            name: name.to_string(),
+            value,
+        }
+    }
+
+    #[test]
+    fn fields_mask_and_extract() {
+        let regs = RegDesc::from_toml(TEST_REG_DESC).unwrap();
+        let ctl = &regs.blocks[0].registers[0];
+
+        let mode = ctl.field("mode").unwrap();
+        assert_eq!(mode.width(), 4);
+        assert_eq!(mode.mask(), 0xF0);
+        assert_eq!(mode.extract(0x1234_5678), 0x7);
+        assert_eq!(mode.bits(), "[7:4]");
+
+        let busy = ctl.field("busy").unwrap();
+        assert_eq!(busy.mask(), 0x8000_0000);
+        assert_eq!(busy.extract(0x8000_0000), 1);
+        assert_eq!(busy.bits(), "[31]");
+
+        let whole = FieldDesc {
+            name: "whole".to_string(),
+            lsb: 0,
+            msb: Some(31),
+            access: None,
+            description: None,
+        };
+        assert_eq!(whole.mask(), u32::MAX);
+        assert_eq!(whole.extract(0xdead_beef), 0xdead_beef);
+    }
+
+    #[test]
+    fn apply_fields_read_modify_write() {
+        let regs = RegDesc::from_toml(TEST_REG_DESC).unwrap();
+        let ctl = &regs.blocks[0].registers[0];
+
+        // Unassigned write-only and write-1-to-clear fields are written as
+        // zero, the other fields keep the value read.
+        let value = ctl
+            .apply_fields(0x8000_0301, &[assignment("mode", 0xA)])
+            .unwrap();
+        assert_eq!(value, 0x8000_00A1);
+        let value = ctl
+            .apply_fields(0x0000_0300, &[assignment("go", 1), assignment("err", 1)])
+            .unwrap();
+        assert_eq!(value, 0x0000_0300);
+
+        assert!(matches!(
+            ctl.apply_fields(0, &[assignment("busy", 0)]),
+            Err(NfpError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            ctl.apply_fields(0, &[assignment("mode", 0x10)]),
+            Err(NfpError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            ctl.apply_fields(0, &[assignment("nonexistent", 0)]),
+            Err(NfpError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn lookup_registers_instances_and_blocks() {
+        let regs = RegDesc::bundled();
+
+        let found = regs.lookup("rfpc0.cl1.grp2.PAControl").unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].name, "rfpc0.cl1.grp2.PAControl");
+        assert_eq!(found[0].address, 0x360120);
+        assert!(!found[0].xpbm);
+
+        // Names match ignoring case, '-' and '_'.
+        let found = regs.lookup("RFPC0.CL0.DM.DMCONTROL").unwrap();
+        assert_eq!(found[0].address, 0x240040);
+        assert!(found[0].xpbm);
+        let found = regs.lookup("rfpc0.pa.pa_config").unwrap();
+        assert_eq!(found[0].address, 0x0F0000);
+
+        // Register arrays are named with their index.
+        let found = regs.lookup("rfpc0.cl0.dm.data11").unwrap();
+        assert_eq!(found[0].address, 0x240000 + 0x10 + 11 * 4);
+
+        let instance = regs.lookup("rfpc0.cl0.grp1").unwrap();
+        assert_eq!(instance.len(), 2);
+        assert!(instance
+            .iter()
+            .all(|r| r.name.starts_with("rfpc0.cl0.grp1.")));
+        let block = regs.lookup("rfpc0.rfpc_group").unwrap();
+        assert_eq!(block.len(), 24);
+
+        for name in ["rfpc0.cl0.grp0.NoSuchReg", "rfpc0", "nbi0.pa", "bogus.pa"] {
+            assert!(matches!(
+                regs.lookup(name),
+                Err(NfpError::InvalidArgument(_))
+            ));
+        }
+        // Only rfpc0 gives the base of the pa block.
+        assert!(matches!(
+            regs.lookup("rfpc1.pa.PAConfig"),
+            Err(NfpError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn validate_rejects_bad_fields_and_names() {
+        let invalid = [
+            // Fields overlapping.
+            TEST_REG_DESC.replace("lsb = 4, msb = 7", "lsb = 0, msb = 3"),
+            // Fields with their bits reversed or beyond the register.
+            TEST_REG_DESC.replace("lsb = 4, msb = 7", "lsb = 7, msb = 4"),
+            TEST_REG_DESC.replace("lsb = 4, msb = 7", "lsb = 30, msb = 32"),
+            // Registers not word aligned.
+            TEST_REG_DESC.replace("offset = 0x0", "offset = 0x2"),
+            // Duplicate register names, including those of an array.
+            format!(
+                "{}\n[[blocks.registers]]\nname = \"ctl\"\noffset = 0x4\n",
+                TEST_REG_DESC
+            ),
+            format!(
+                "{}\n[[blocks.registers]]\nname = \"Ctl\"\noffset = 0x4\ncount = 2\n\n\
+                 [[blocks.registers]]\nname = \"Ctl1\"\noffset = 0x10\n",
+                TEST_REG_DESC
+            ),
+            // Instances beyond the 24-bit XPB space.
+            TEST_REG_DESC.replace("base = 0x1000", "base = 0x1000000"),
+        ];
+        for contents in &invalid {
+            assert!(
+                matches!(
+                    RegDesc::from_toml(contents),
+                    Err(NfpError::InvalidArgument(_))
+                ),
+                "accepted {}",
+                contents
+            );
+        }
+    }
+}
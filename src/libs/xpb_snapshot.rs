@@ -0,0 +1,417 @@
+#![allow(dead_code)]
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::libs::cpp_batch::CppBatch;
+use crate::libs::cpp_bus::CppIsland;
+use crate::libs::error::NfpError;
+use crate::libs::xpb_bus::XpbBar;
+use crate::libs::xpb_regs::{reg_desc, RegisterDesc, XpbRegister};
+
+// Version of the snapshot file format.
+const XPB_SNAPSHOT_VERSION: u32 = 1;
+
+/// The value of one XPB register in a snapshot.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct XpbSnapshotEntry {
+    /// Name of the island, as in the chip description.
+    pub island: String,
+    pub address: u32,
+    #[serde(default)]
+    pub xpbm: bool,
+    /// Name of the register in the register description, if it has one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub value: u32,
+}
+
+impl XpbSnapshotEntry {
+    /// Key identifying the register the entry holds the value of.
+    fn key(&self) -> (String, bool, u32) {
+        (self.island.clone(), self.xpbm, self.address)
+    }
+
+    /// Return the name of the register, or its island and address.
+    pub fn label(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => format!(
+                "{}{}:0x{:08x}",
+                self.island,
+                if self.xpbm { " xpbm" } else { "" },
+                self.address
+            ),
+        }
+    }
+
+    /// Return the description of the register, if it has a name.
+    pub fn desc(&self) -> Option<&'static RegisterDesc> {
+        let name = self.name.as_deref()?;
+        let registers = reg_desc().lookup(name).ok()?;
+        match registers.as_slice() {
+            [register] => Some(register.desc),
+            _ => None,
+        }
+    }
+}
+
+/// XPB register values read from a device at one point in time.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct XpbSnapshot {
+    pub version: u32,
+    /// Device the snapshot was taken from.
+    pub device: String,
+    /// Time the snapshot was taken at, in seconds since the Unix epoch.
+    pub time: u64,
+    pub entries: Vec<XpbSnapshotEntry>,
+}
+
+/// A register whose value differs between two snapshots.
+#[derive(Clone, Debug)]
+pub enum XpbSnapshotDiff {
+    /// The register has another value in the second snapshot.
+    Changed { entry: XpbSnapshotEntry, value: u32 },
+    /// The register is only in the first snapshot.
+    Removed(XpbSnapshotEntry),
+    /// The register is only in the second snapshot.
+    Added(XpbSnapshotEntry),
+}
+
+impl XpbSnapshot {
+    pub fn new(device: &str, entries: Vec<XpbSnapshotEntry>) -> Self {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        XpbSnapshot {
+            version: XPB_SNAPSHOT_VERSION,
+            device: device.to_string(),
+            time,
+            entries,
+        }
+    }
+
+    /// Save the snapshot to a JSON file.
+    pub fn save(&self, path: &Path) -> Result<(), NfpError> {
+        let io_err = |e| NfpError::io(format!("Failed to write {}", path.display()), e);
+        let file = File::create(path).map_err(io_err)?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, self)
+            .map_err(|e| io_err(std::io::Error::other(e)))?;
+        writeln!(writer).map_err(io_err)?;
+        writer.flush().map_err(io_err)
+    }
+
+    /// Load a snapshot saved by `save`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NfpError::Io` if the file cannot be read, or
+    /// `NfpError::InvalidArgument` if it is not a snapshot of a supported
+    /// version.
+    pub fn load(path: &Path) -> Result<Self, NfpError> {
+        let file = File::open(path)
+            .map_err(|e| NfpError::io(format!("Failed to open {}", path.display()), e))?;
+        let snapshot: XpbSnapshot = serde_json::from_reader(BufReader::new(file)).map_err(|e| {
+            NfpError::InvalidArgument(format!("Invalid snapshot {}: {}", path.display(), e))
+        })?;
+        if snapshot.version != XPB_SNAPSHOT_VERSION {
+            return Err(NfpError::InvalidArgument(format!(
+                "Snapshot {} has unsupported version {}",
+                path.display(),
+                snapshot.version
+            )));
+        }
+        Ok(snapshot)
+    }
+
+    /// Read the registers of this snapshot again from a device.
+    pub fn reread(&self, xpb_bar: &mut XpbBar, device: &str) -> Result<Self, NfpError> {
+        let registers = self
+            .entries
+            .iter()
+            .map(|entry| {
+                let island =
+                    CppIsland::from_str(&entry.island).map_err(NfpError::InvalidArgument)?;
+                Ok((island, entry.address, entry.xpbm))
+            })
+            .collect::<Result<Vec<_>, NfpError>>()?;
+        let values = read_registers(xpb_bar, &registers)?;
+
+        let entries = self
+            .entries
+            .iter()
+            .zip(values)
+            .map(|(entry, value)| XpbSnapshotEntry {
+                value,
+                ..entry.clone()
+            })
+            .collect();
+        Ok(XpbSnapshot::new(device, entries))
+    }
+
+    /// Compare this snapshot with `other`.
+    ///
+    /// # Returns
+    ///
+    /// The registers that differ, in the order of this snapshot, followed by
+    /// the registers only in `other`.
+    pub fn diff(&self, other: &XpbSnapshot) -> Vec<XpbSnapshotDiff> {
+        let others: BTreeMap<_, _> = other
+            .entries
+            .iter()
+            .map(|entry| (entry.key(), entry))
+            .collect();
+        let mut diffs = Vec::new();
+
+        for entry in &self.entries {
+            match others.get(&entry.key()) {
+                Some(other) if other.value != entry.value => diffs.push(XpbSnapshotDiff::Changed {
+                    entry: entry.clone(),
+                    value: other.value,
+                }),
+                Some(_) => {}
+                None => diffs.push(XpbSnapshotDiff::Removed(entry.clone())),
+            }
+        }
+
+        let keys: BTreeSet<_> = self.entries.iter().map(XpbSnapshotEntry::key).collect();
+        diffs.extend(
+            other
+                .entries
+                .iter()
+                .filter(|entry| !keys.contains(&entry.key()))
+                .map(|entry| XpbSnapshotDiff::Added(entry.clone())),
+        );
+        diffs
+    }
+}
+
+/// Read the described registers `registers` for a snapshot. Write-only
+/// registers are skipped.
+pub fn snapshot_registers(
+    xpb_bar: &mut XpbBar,
+    registers: &[XpbRegister],
+) -> Result<Vec<XpbSnapshotEntry>, NfpError> {
+    let registers: Vec<_> = registers
+        .iter()
+        .filter(|r| r.desc.access.readable())
+        .collect();
+    let addresses: Vec<_> = registers
+        .iter()
+        .map(|register| (register.island, register.address, register.xpbm))
+        .collect();
+    let values = read_registers(xpb_bar, &addresses)?;
+
+    Ok(registers
+        .into_iter()
+        .zip(values)
+        .map(|(register, value)| XpbSnapshotEntry {
+            island: register.island.to_string(),
+            address: register.address,
+            xpbm: register.xpbm,
+            name: Some(register.name.clone()),
+            value,
+        })
+        .collect())
+}
+
+/// Read the single XPB registers `registers`, given as (island, address,
+/// xpbm).
+///
+/// Through an expansion BAR the reads are made as one `CppBatch`, so that
+/// the BAR is configured once per XPB window rather than once per register.
+fn read_registers(
+    xpb_bar: &mut XpbBar,
+    registers: &[(CppIsland, u32, bool)],
+) -> Result<Vec<u32>, NfpError> {
+    match xpb_bar {
+        XpbBar::Expansion(exp_bar) => {
+            let mut batch = CppBatch::new();
+            for (island, address, xpbm) in registers {
+                batch.xpb_read(island, *address, 1, *xpbm)?;
+            }
+            let results = batch.execute(&mut [&mut **exp_bar])?;
+            Ok(results
+                .into_iter()
+                .flatten()
+                .map(|words| words[0])
+                .collect())
+        }
+        XpbBar::Explicit(_) => registers
+            .iter()
+            .map(|(island, address, xpbm)| Ok(xpb_bar.read(island, *address, 1, *xpbm)?[0]))
+            .collect(),
+    }
+}
+
+/// Read the XPB addresses `start..end` of `island` for a snapshot.
+///
+/// Addresses of registers in the register description are named after the
+/// register.
+///
+/// # Errors
+///
+/// Returns `NfpError::InvalidArgument` if the range is empty or not word
+/// aligned, or any error of the read.
+pub fn snapshot_range(
+    xpb_bar: &mut XpbBar,
+    island: CppIsland,
+    start: u32,
+    end: u32,
+    xpbm: bool,
+) -> Result<Vec<XpbSnapshotEntry>, NfpError> {
+    if start >= end || !start.is_multiple_of(4) || !end.is_multiple_of(4) {
+        return Err(NfpError::InvalidArgument(format!(
+            "Invalid XPB range {:#x}-{:#x}",
+            start, end
+        )));
+    }
+
+    let names: BTreeMap<u32, String> = reg_desc()
+        .island_registers(island)
+        .into_iter()
+        .filter(|register| register.xpbm == xpbm)
+        .map(|register| (register.address, register.name))
+        .collect();
+
+    let values = xpb_bar.read(&island, start, ((end - start) / 4) as u64, xpbm)?;
+    Ok(values
+        .into_iter()
+        .enumerate()
+        .map(|(index, value)| {
+            let address = start + index as u32 * 4;
+            XpbSnapshotEntry {
+                island: island.to_string(),
+                address,
+                xpbm,
+                name: names.get(&address).cloned(),
+                value,
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::chip_desc::IslandKind;
+    use crate::libs::nfp::Nfp;
+    use crate::libs::sim_device::SimDevice;
+    use std::sync::Arc;
+
+    #[test]
+    fn snapshots_diff_against_the_device() {
+        let nfp = Nfp::new(Arc::new(SimDevice::new())).unwrap();
+        let island = CppIsland::first_of_kind(IslandKind::Rfpc);
+        let mut xpb_bar = XpbBar::new(&nfp, false).unwrap();
+        xpb_bar
+            .write(&island, 0x1000, vec![1, 2, 3, 4], false)
+            .unwrap();
+
+        let before = XpbSnapshot::new(
+            "sim",
+            snapshot_range(&mut xpb_bar, island, 0x1000, 0x100c, false).unwrap(),
+        );
+        assert_eq!(before.entries.len(), 3);
+        assert_eq!(before.entries[1].value, 2);
+
+        xpb_bar.write(&island, 0x1004, vec![0x20], false).unwrap();
+        let after = XpbSnapshot::new(
+            "sim",
+            snapshot_range(&mut xpb_bar, island, 0x1004, 0x1010, false).unwrap(),
+        );
+
+        let diffs = before.diff(&after);
+        assert_eq!(diffs.len(), 3);
+        assert!(matches!(
+            &diffs[0],
+            XpbSnapshotDiff::Removed(entry) if entry.address == 0x1000
+        ));
+        assert!(matches!(
+            &diffs[1],
+            XpbSnapshotDiff::Changed { entry, value: 0x20 } if entry.address == 0x1004 && entry.value == 2
+        ));
+        assert!(matches!(
+            &diffs[2],
+            XpbSnapshotDiff::Added(entry) if entry.address == 0x100c && entry.value == 4
+        ));
+        assert!(after.diff(&after).is_empty());
+
+        // Rereading the first snapshot finds the changed register.
+        let reread = before.reread(&mut xpb_bar, "sim").unwrap();
+        assert_eq!(reread.entries[1].value, 0x20);
+        assert_eq!(before.diff(&reread).len(), 1);
+    }
+
+    #[test]
+    fn described_registers_are_named() {
+        let nfp = Nfp::new(Arc::new(SimDevice::new())).unwrap();
+        let island = CppIsland::first_of_kind(IslandKind::Rfpc);
+        let mut xpb_bar = XpbBar::new(&nfp, false).unwrap();
+
+        let entries = snapshot_range(&mut xpb_bar, island, 0x280020, 0x280028, false).unwrap();
+        assert_eq!(entries[0].name.as_deref(), Some("rfpc0.cl0.grp0.PAControl"));
+        assert_eq!(entries[0].label(), "rfpc0.cl0.grp0.PAControl");
+        assert!(entries[0].desc().is_some());
+        assert_eq!(
+            entries[1].name.as_deref(),
+            Some("rfpc0.cl0.grp0.PerfMuxConfig")
+        );
+
+        let registers = reg_desc().lookup("rfpc0.cl0.grp0").unwrap();
+        let entries = snapshot_registers(&mut xpb_bar, &registers).unwrap();
+        assert_eq!(entries.len(), registers.len());
+    }
+
+    #[test]
+    fn snapshot_range_rejects_unaligned_ranges() {
+        let nfp = Nfp::new(Arc::new(SimDevice::new())).unwrap();
+        let island = CppIsland::first_of_kind(IslandKind::Rfpc);
+        let mut xpb_bar = XpbBar::new(&nfp, false).unwrap();
+
+        for (start, end) in [
+            (0x1002, 0x1010),
+            (0x1000, 0x100e),
+            (0x1010, 0x1000),
+            (0x1000, 0x1000),
+        ] {
+            assert!(matches!(
+                snapshot_range(&mut xpb_bar, island, start, end, false),
+                Err(NfpError::InvalidArgument(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn saved_snapshots_load_back() {
+        let path = std::env::temp_dir().join(format!("nfp-xpb-snapshot-{}", std::process::id()));
+        let entry = XpbSnapshotEntry {
+            island: "rfpc0".to_string(),
+            address: 0x1000,
+            xpbm: true,
+            name: None,
+            value: 0xdead_beef,
+        };
+        assert_eq!(entry.label(), "rfpc0 xpbm:0x00001000");
+        let snapshot = XpbSnapshot::new("sim", vec![entry]);
+
+        snapshot.save(&path).unwrap();
+        let loaded = XpbSnapshot::load(&path).unwrap();
+        assert_eq!(loaded.device, "sim");
+        assert_eq!(loaded.time, snapshot.time);
+        assert_eq!(loaded.entries, snapshot.entries);
+
+        let mut future = snapshot.clone();
+        future.version = XPB_SNAPSHOT_VERSION + 1;
+        future.save(&path).unwrap();
+        let result = XpbSnapshot::load(&path);
+        let _ = std::fs::remove_file(&path);
+        assert!(matches!(result, Err(NfpError::InvalidArgument(_))));
+    }
+}